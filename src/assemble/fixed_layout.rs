@@ -0,0 +1,277 @@
+use super::{add_assets_recursive, add_stylesheets, asset_embed, metadata_build};
+use crate::epub::fixed_layout::{self, LAYOUT_PROPERTY, Viewport};
+use crate::epub::resources::Resources;
+use crate::epub::writer::xml_escape;
+use crate::epub::{EpubBook, EpubVersion, ManifestItem, NavPoint, Navigation, SpineItem, url};
+use anyhow::Context;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+const XHTML_MEDIA_TYPE: &str = "application/xhtml+xml";
+
+/// Assemble a folder of page images and XHTML pages into a pre-paginated
+/// book.
+///
+/// Pages are read from `pages/` in file name order. XHTML pages are used as
+/// they are, with a viewport meta added when they lack one; each image
+/// becomes an XHTML page that shows it at the size of `viewport`. Metadata,
+/// styles and assets are read as for a Markdown book.
+pub fn assemble_fixed_layout_book(
+    dir: &Path,
+    viewport: Option<Viewport>,
+    version: EpubVersion,
+) -> anyhow::Result<EpubBook> {
+    let mut metadata = metadata_build::read_metadata(dir)
+        .with_context(|| format!("reading metadata.yml from {}", dir.display()))?;
+    metadata
+        .custom
+        .insert(LAYOUT_PROPERTY.to_string(), "pre-paginated".to_string());
+
+    let pages = page_files(&dir.join("pages"))?;
+    if pages.is_empty() {
+        anyhow::bail!("no pages in {}", dir.join("pages").display());
+    }
+
+    let mut manifest: Vec<ManifestItem> = Vec::new();
+    let mut spine: Vec<SpineItem> = Vec::new();
+    let mut toc: Vec<NavPoint> = Vec::new();
+    let mut resources = Resources::default();
+
+    add_stylesheets(dir, &mut manifest, &mut resources)?;
+
+    for (index, path) in pages.iter().enumerate() {
+        let number = index + 1;
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let media_type = asset_embed::infer_media_type(path);
+
+        let (href, title, xhtml) = if media_type == XHTML_MEDIA_TYPE {
+            let xhtml = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let xhtml = if fixed_layout::read_viewport(&xhtml).is_some() {
+                xhtml
+            } else {
+                let viewport = viewport.with_context(|| {
+                    format!(
+                        "{} declares no viewport; pass --viewport WIDTHxHEIGHT",
+                        path.display()
+                    )
+                })?;
+                fixed_layout::insert_viewport(&xhtml, viewport)
+                    .with_context(|| format!("{} has no <head>", path.display()))?
+            };
+            let title = page_title(&xhtml).unwrap_or_else(|| format!("Page {number}"));
+            (format!("pages/{filename}"), title, xhtml)
+        } else if media_type.starts_with("image/") {
+            let viewport = viewport.with_context(|| {
+                format!(
+                    "image page {} needs --viewport WIDTHxHEIGHT",
+                    path.display()
+                )
+            })?;
+            let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+            if pages.contains(&path.with_extension("xhtml")) {
+                anyhow::bail!(
+                    "image page {} conflicts with {stem}.xhtml in pages/",
+                    path.display()
+                );
+            }
+
+            let image_href = format!("pages/{filename}");
+            let image_id = format!("image-{}", slug::slugify(&filename));
+            let data =
+                std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+            resources.insert(image_href.clone(), data);
+            // The first page is the cover
+            let cover = index == 0 && metadata.cover_id.is_none();
+            if cover {
+                metadata.cover_id = Some(image_id.clone());
+            }
+            manifest.push(ManifestItem {
+                id: image_id,
+                href: image_href,
+                media_type: media_type.to_string(),
+                properties: (cover && version == EpubVersion::V3)
+                    .then(|| "cover-image".to_string()),
+                ..Default::default()
+            });
+
+            let title = format!("Page {number}");
+            let xhtml = image_page(&filename, &title, viewport, version);
+            (format!("pages/{stem}.xhtml"), title, xhtml)
+        } else {
+            anyhow::bail!(
+                "unsupported page {}: expected XHTML or an image",
+                path.display()
+            );
+        };
+
+        let id = format!("page-{number:03}");
+        let properties =
+            (version == EpubVersion::V3 && xhtml.contains("<svg")).then(|| "svg".to_string());
+        resources.insert(href.clone(), xhtml.into_bytes());
+        manifest.push(ManifestItem {
+            id: id.clone(),
+            href: href.clone(),
+            media_type: XHTML_MEDIA_TYPE.to_string(),
+            properties,
+            ..Default::default()
+        });
+        spine.push(SpineItem {
+            idref: id,
+            ..Default::default()
+        });
+        toc.push(NavPoint {
+            label: title,
+            href,
+            ..Default::default()
+        });
+    }
+
+    let assets_dir = dir.join("assets");
+    if assets_dir.is_dir() {
+        add_assets_recursive(&assets_dir, "assets", &mut manifest, &mut resources)?;
+    }
+
+    Ok(EpubBook {
+        metadata,
+        manifest,
+        spine,
+        navigation: Navigation {
+            toc,
+            ..Default::default()
+        },
+        resources,
+        ..Default::default()
+    })
+}
+
+/// Files in `pages/`, sorted by name
+fn page_files(pages_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let entries =
+        std::fs::read_dir(pages_dir).with_context(|| format!("reading {}", pages_dir.display()))?;
+    let mut pages = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() {
+            pages.push(path);
+        }
+    }
+    pages.sort();
+    Ok(pages)
+}
+
+/// Text of a page's `<title>`, if not empty
+fn page_title(xhtml: &str) -> Option<String> {
+    let title_re = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").expect("valid regex");
+    let title = title_re.captures(xhtml)?[1].trim().to_string();
+    let title = quick_xml::escape::unescape(&title)
+        .map(|t| t.into_owned())
+        .unwrap_or(title);
+    (!title.is_empty()).then_some(title)
+}
+
+/// An XHTML page showing one image across the whole viewport
+fn image_page(image_href: &str, title: &str, viewport: Viewport, version: EpubVersion) -> String {
+    let head = match version {
+        EpubVersion::V2 => concat!(
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd\">\n",
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\">\n",
+            "<head>\n",
+            "  <meta http-equiv=\"Content-Type\" content=\"application/xhtml+xml; charset=UTF-8\"/>\n",
+        ),
+        EpubVersion::V3 => concat!(
+            "<!DOCTYPE html>\n",
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n",
+            "<head>\n",
+            "  <meta charset=\"UTF-8\"/>\n",
+        ),
+    };
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "{head}",
+            "  <meta name=\"viewport\" content=\"{viewport}\"/>\n",
+            "  <title>{title}</title>\n",
+            "  <style type=\"text/css\">\n",
+            "    html, body {{ margin: 0; padding: 0; }}\n",
+            "    img {{ display: block; width: {width}px; height: {height}px; }}\n",
+            "  </style>\n",
+            "</head>\n",
+            "<body>\n",
+            "  <img src=\"{src}\" alt=\"{title}\"/>\n",
+            "</body>\n",
+            "</html>\n",
+        ),
+        head = head,
+        viewport = viewport.meta_content(),
+        title = xml_escape(title),
+        width = viewport.width,
+        height = viewport.height,
+        src = xml_escape(&url::escape_href(image_href)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport() -> Viewport {
+        Viewport {
+            width: 600,
+            height: 800,
+        }
+    }
+
+    fn book_dir() -> tempfile::TempDir {
+        let tmp = tempfile::TempDir::new().unwrap();
+        std::fs::write(tmp.path().join("metadata.yml"), "title: Comic\n").unwrap();
+        std::fs::create_dir(tmp.path().join("pages")).unwrap();
+        tmp
+    }
+
+    #[test]
+    fn test_image_pages_are_wrapped() {
+        let tmp = book_dir();
+        std::fs::write(tmp.path().join("pages/001.png"), b"png").unwrap();
+        std::fs::write(tmp.path().join("pages/002.jpg"), b"jpg").unwrap();
+
+        let book =
+            assemble_fixed_layout_book(tmp.path(), Some(viewport()), EpubVersion::V3).unwrap();
+        assert_eq!(
+            book.metadata.custom[LAYOUT_PROPERTY].as_str(),
+            "pre-paginated"
+        );
+        assert_eq!(book.spine.len(), 2);
+        assert_eq!(book.metadata.cover_id.as_deref(), Some("image-001-png"));
+
+        let page = book.resources.get_string("pages/001.xhtml").unwrap();
+        assert_eq!(fixed_layout::read_viewport(&page), Some(viewport()));
+        assert!(page.contains("<img src=\"001.png\""));
+        assert_eq!(book.navigation.toc[1].label, "Page 2");
+    }
+
+    #[test]
+    fn test_xhtml_page_gets_viewport() {
+        let tmp = book_dir();
+        std::fs::write(
+            tmp.path().join("pages/01.xhtml"),
+            "<html><head><title>Opening</title></head><body/></html>",
+        )
+        .unwrap();
+
+        let book =
+            assemble_fixed_layout_book(tmp.path(), Some(viewport()), EpubVersion::V3).unwrap();
+        let page = book.resources.get_string("pages/01.xhtml").unwrap();
+        assert_eq!(fixed_layout::read_viewport(&page), Some(viewport()));
+        assert_eq!(book.navigation.toc[0].label, "Opening");
+    }
+
+    #[test]
+    fn test_image_page_requires_viewport() {
+        let tmp = book_dir();
+        std::fs::write(tmp.path().join("pages/001.png"), b"png").unwrap();
+        let err = assemble_fixed_layout_book(tmp.path(), None, EpubVersion::V3).unwrap_err();
+        assert!(err.to_string().contains("--viewport"), "{err}");
+    }
+}
@@ -1,8 +1,15 @@
+use crate::epub::EpubVersion;
 use pulldown_cmark::{Options, Parser, html};
 use regex::Regex;
 
-/// Convert Markdown to EPUB 3.3 XHTML
-pub fn markdown_to_xhtml(md: &str, title: &str, stylesheet: Option<&str>) -> String {
+/// Convert Markdown to XHTML: EPUB 3.3 content documents, or XHTML 1.1 for
+/// EPUB 2
+pub fn markdown_to_xhtml(
+    md: &str,
+    title: &str,
+    stylesheet: Option<&str>,
+    version: EpubVersion,
+) -> String {
     let options = Options::ENABLE_TABLES
         | Options::ENABLE_FOOTNOTES
         | Options::ENABLE_STRIKETHROUGH
@@ -20,13 +27,25 @@ pub fn markdown_to_xhtml(md: &str, title: &str, stylesheet: Option<&str>) -> Str
         .map(|href| format!("<link rel=\"stylesheet\" type=\"text/css\" href=\"{href}\"/>"))
         .unwrap_or_default();
 
-    format!(
-        concat!(
-            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+    let head = match version {
+        EpubVersion::V2 => concat!(
+            "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd\">\n",
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\">\n",
+            "<head>\n",
+            "  <meta http-equiv=\"Content-Type\" content=\"application/xhtml+xml; charset=UTF-8\"/>\n",
+        ),
+        EpubVersion::V3 => concat!(
             "<!DOCTYPE html>\n",
             "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n",
             "<head>\n",
             "  <meta charset=\"UTF-8\"/>\n",
+        ),
+    };
+
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "{head}",
             "  <title>{title}</title>\n",
             "  {css}\n",
             "</head>\n",
@@ -35,6 +54,7 @@ pub fn markdown_to_xhtml(md: &str, title: &str, stylesheet: Option<&str>) -> Str
             "</body>\n",
             "</html>\n",
         ),
+        head = head,
         title = xml_escape(title),
         css = css_link,
         body = body_html,
@@ -60,32 +80,32 @@ mod tests {
 
     #[test]
     fn test_basic_markdown_to_xhtml() {
-        let xhtml = markdown_to_xhtml("# Hello\n\nWorld", "Test", None);
+        let xhtml = markdown_to_xhtml("# Hello\n\nWorld", "Test", None, EpubVersion::V3);
         assert!(xhtml.contains("<h1>Hello</h1>"));
         assert!(xhtml.contains("<p>World</p>"));
     }
 
     #[test]
     fn test_with_stylesheet() {
-        let xhtml = markdown_to_xhtml("text", "Title", Some("styles.css"));
+        let xhtml = markdown_to_xhtml("text", "Title", Some("styles.css"), EpubVersion::V3);
         assert!(xhtml.contains(r#"<link rel="stylesheet" type="text/css" href="styles.css"/>"#));
     }
 
     #[test]
     fn test_without_stylesheet() {
-        let xhtml = markdown_to_xhtml("text", "Title", None);
+        let xhtml = markdown_to_xhtml("text", "Title", None, EpubVersion::V3);
         assert!(!xhtml.contains("stylesheet"));
     }
 
     #[test]
     fn test_title_escaping() {
-        let xhtml = markdown_to_xhtml("text", "A<B>&C", None);
+        let xhtml = markdown_to_xhtml("text", "A<B>&C", None, EpubVersion::V3);
         assert!(xhtml.contains("<title>A&lt;B&gt;&amp;C</title>"));
     }
 
     #[test]
     fn test_heading_attributes() {
-        let xhtml = markdown_to_xhtml("## Section {#sec1}\n\nText", "Test", None);
+        let xhtml = markdown_to_xhtml("## Section {#sec1}\n\nText", "Test", None, EpubVersion::V3);
         assert!(
             xhtml.contains(r#"id="sec1""#),
             "heading attribute not preserved: {xhtml}"
@@ -94,7 +114,7 @@ mod tests {
 
     #[test]
     fn test_pandoc_span_conversion() {
-        let xhtml = markdown_to_xhtml("[]{#anchor1}\n\nText", "Test", None);
+        let xhtml = markdown_to_xhtml("[]{#anchor1}\n\nText", "Test", None, EpubVersion::V3);
         assert!(
             xhtml.contains(r#"id="anchor1""#),
             "pandoc span not converted to anchor: {xhtml}"
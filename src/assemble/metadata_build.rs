@@ -2,6 +2,30 @@ use crate::epub::EpubMetadata;
 use crate::extract::frontmatter::BookMetadataYaml;
 use std::path::Path;
 
+/// Read metadata.yml and convert to EpubMetadata
+pub fn read_metadata(dir: &Path) -> anyhow::Result<EpubMetadata> {
+    let meta_path = dir.join("metadata.yml");
+    let content = std::fs::read_to_string(&meta_path)?;
+    let yaml: BookMetadataYaml = serde_yaml_ng::from_str(&content)?;
+
+    Ok(EpubMetadata {
+        titles: yaml.titles(),
+        creators: yaml.creators,
+        contributors: yaml.contributors,
+        identifiers: yaml.identifiers,
+        languages: yaml.languages,
+        publishers: yaml.publishers,
+        dates: yaml.dates,
+        description: yaml.description,
+        subjects: yaml.subjects,
+        rights: yaml.rights,
+        modified: None,
+        cover_id: None,
+        custom: yaml.custom,
+        accessibility: yaml.accessibility,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -30,8 +54,8 @@ rights: CC-BY
         .unwrap();
 
         let meta = read_metadata(tmp.path()).unwrap();
-        assert_eq!(meta.titles, vec!["Test Book"]);
-        assert_eq!(meta.creators, vec!["Author Name"]);
+        assert_eq!(meta.title(), Some("Test Book"));
+        assert_eq!(meta.creator_names(), vec!["Author Name"]);
         assert_eq!(meta.languages, vec!["en"]);
     }
 
@@ -41,25 +65,3 @@ rights: CC-BY
         assert!(read_metadata(tmp.path()).is_err());
     }
 }
-
-/// Read metadata.yml and convert to EpubMetadata
-pub fn read_metadata(dir: &Path) -> anyhow::Result<EpubMetadata> {
-    let meta_path = dir.join("metadata.yml");
-    let content = std::fs::read_to_string(&meta_path)?;
-    let yaml: BookMetadataYaml = serde_yaml_ng::from_str(&content)?;
-
-    Ok(EpubMetadata {
-        titles: yaml.title.into_iter().collect(),
-        creators: yaml.creators,
-        identifiers: yaml.identifiers,
-        languages: yaml.languages,
-        publishers: yaml.publishers,
-        dates: yaml.dates,
-        description: yaml.description,
-        subjects: yaml.subjects,
-        rights: yaml.rights,
-        modified: None,
-        cover_id: None,
-        custom: yaml.custom,
-    })
-}
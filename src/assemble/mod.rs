@@ -1,15 +1,18 @@
 pub mod asset_embed;
+pub mod fixed_layout;
 pub mod md_to_xhtml;
 pub mod metadata_build;
 pub mod package;
 pub mod spine_build;
 
-use crate::epub::{EpubBook, ManifestItem, SpineItem};
+use crate::epub::resources::Resources;
+use crate::epub::{EpubBook, EpubVersion, ManifestItem, SpineItem};
 use anyhow::Context;
 use std::path::Path;
 
-/// Assemble a Markdown directory into an EpubBook
-pub fn assemble_book(dir: &Path) -> anyhow::Result<EpubBook> {
+/// Assemble a Markdown directory into an EpubBook whose chapters are
+/// written for `version`
+pub fn assemble_book(dir: &Path, version: EpubVersion) -> anyhow::Result<EpubBook> {
     // Read metadata
     let metadata = metadata_build::read_metadata(dir)
         .with_context(|| format!("reading metadata.yml from {}", dir.display()))?;
@@ -22,38 +25,10 @@ pub fn assemble_book(dir: &Path) -> anyhow::Result<EpubBook> {
 
     let mut manifest: Vec<ManifestItem> = Vec::new();
     let mut spine: Vec<SpineItem> = Vec::new();
-    let mut resources: std::collections::HashMap<String, Vec<u8>> =
-        std::collections::HashMap::new();
+    let mut resources = Resources::default();
 
     // Determine stylesheet (check styles/ directory)
-    let styles_dir = dir.join("styles");
-    let stylesheet_href = if styles_dir.is_dir() {
-        let mut css_file = None;
-        if let Ok(entries) = std::fs::read_dir(&styles_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().is_some_and(|e| e == "css") {
-                    let filename = path.file_name().unwrap().to_string_lossy().to_string();
-                    let href = format!("styles/{filename}");
-                    let data = std::fs::read(&path)
-                        .with_context(|| format!("reading {}", path.display()))?;
-                    resources.insert(href.clone(), data);
-                    manifest.push(ManifestItem {
-                        id: format!("style-{}", slug::slugify(&filename)),
-                        href: href.clone(),
-                        media_type: "text/css".to_string(),
-                        properties: None,
-                    });
-                    if css_file.is_none() {
-                        css_file = Some(href);
-                    }
-                }
-            }
-        }
-        css_file
-    } else {
-        None
-    };
+    let stylesheet_href = add_stylesheets(dir, &mut manifest, &mut resources)?;
 
     // Convert chapters
     for (index, chapter_file) in chapter_order.iter().enumerate() {
@@ -73,7 +48,7 @@ pub fn assemble_book(dir: &Path) -> anyhow::Result<EpubBook> {
 
         // Convert to XHTML
         let css_rel = stylesheet_href.as_deref();
-        let xhtml = md_to_xhtml::markdown_to_xhtml(md_body, &title, css_rel);
+        let xhtml = md_to_xhtml::markdown_to_xhtml(md_body, &title, css_rel, version);
 
         // Create XHTML filename
         let xhtml_name = chapter_file.strip_suffix(".md").unwrap_or(chapter_file);
@@ -87,12 +62,14 @@ pub fn assemble_book(dir: &Path) -> anyhow::Result<EpubBook> {
             href: xhtml_href,
             media_type: "application/xhtml+xml".to_string(),
             properties: None,
+            ..Default::default()
         });
 
         spine.push(SpineItem {
             idref: item_id,
             linear: true,
             properties: None,
+            ..Default::default()
         });
     }
 
@@ -108,9 +85,46 @@ pub fn assemble_book(dir: &Path) -> anyhow::Result<EpubBook> {
         spine,
         navigation,
         resources,
+        ..Default::default()
     })
 }
 
+/// Add the stylesheets in `styles/`, returning the href of the first one
+fn add_stylesheets(
+    dir: &Path,
+    manifest: &mut Vec<ManifestItem>,
+    resources: &mut Resources,
+) -> anyhow::Result<Option<String>> {
+    let styles_dir = dir.join("styles");
+    if !styles_dir.is_dir() {
+        return Ok(None);
+    }
+    let mut css_file = None;
+    if let Ok(entries) = std::fs::read_dir(&styles_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "css") {
+                let filename = path.file_name().unwrap().to_string_lossy().to_string();
+                let href = format!("styles/{filename}");
+                let data =
+                    std::fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+                resources.insert(href.clone(), data);
+                manifest.push(ManifestItem {
+                    id: format!("style-{}", slug::slugify(&filename)),
+                    href: href.clone(),
+                    media_type: "text/css".to_string(),
+                    properties: None,
+                    ..Default::default()
+                });
+                if css_file.is_none() {
+                    css_file = Some(href);
+                }
+            }
+        }
+    }
+    Ok(css_file)
+}
+
 /// Strip YAML frontmatter (--- ... ---) from markdown content
 fn strip_frontmatter(content: &str) -> &str {
     if !content.starts_with("---") {
@@ -148,7 +162,7 @@ fn add_assets_recursive(
     dir: &Path,
     prefix: &str,
     manifest: &mut Vec<ManifestItem>,
-    resources: &mut std::collections::HashMap<String, Vec<u8>>,
+    resources: &mut Resources,
 ) -> anyhow::Result<()> {
     let entries = std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
 
@@ -174,6 +188,7 @@ fn add_assets_recursive(
                 href,
                 media_type: media_type.to_string(),
                 properties: None,
+                ..Default::default()
             });
         }
     }
@@ -1,9 +1,34 @@
-use crate::epub::writer;
+use crate::epub::fixed_layout::Viewport;
+use crate::epub::{EpubVersion, writer};
+use crate::util;
 use std::path::Path;
 
-/// Assemble a directory into an EPUB file
-pub fn package_epub(dir: &Path, output: &Path) -> anyhow::Result<()> {
-    let book = super::assemble_book(dir)?;
-    writer::write_epub(&book, output)?;
+/// Options for assembling a directory
+#[derive(Debug, Default, Clone)]
+pub struct AssembleOptions {
+    /// Write embedded fonts with IDPF obfuscation
+    pub obfuscate_fonts: bool,
+    pub version: EpubVersion,
+    /// Build a pre-paginated book from `pages/` instead of a Markdown one
+    pub fixed_layout: bool,
+    /// Viewport of fixed-layout pages that do not declare their own
+    pub viewport: Option<Viewport>,
+}
+
+/// Assemble a directory into an EPUB file, or stdout for `-`
+pub fn package_epub(dir: &Path, output: &Path, options: &AssembleOptions) -> anyhow::Result<()> {
+    let mut book = if options.fixed_layout {
+        super::fixed_layout::assemble_fixed_layout_book(dir, options.viewport, options.version)?
+    } else {
+        super::assemble_book(dir, options.version)?
+    };
+    if options.obfuscate_fonts {
+        book.obfuscate_fonts();
+    }
+    let write_options = writer::WriteOptions {
+        version: Some(options.version),
+        ..Default::default()
+    };
+    util::write_epub_output(&book, output, &write_options)?;
     Ok(())
 }
@@ -37,10 +37,8 @@ pub fn parse_summary(dir: &Path) -> anyhow::Result<(Vec<String>, Navigation)> {
                     list_depth.saturating_sub(1),
                 ));
             }
-            Event::Text(text) => {
-                if in_link {
-                    current_label.push_str(&text);
-                }
+            Event::Text(text) if in_link => {
+                current_label.push_str(&text);
             }
             _ => {}
         }
@@ -10,6 +10,9 @@ pub enum AssetCommand {
         /// Filter by asset type
         #[arg(long, value_parser = ["image", "css", "font", "audio"])]
         r#type: Option<String>,
+        /// Only list assets nothing in the book references
+        #[arg(long)]
+        unused: bool,
     },
     /// Extract a single asset
     Extract {
@@ -38,6 +41,9 @@ pub enum AssetCommand {
         /// Media type override
         #[arg(long)]
         media_type: Option<String>,
+        /// Obfuscate the font (IDPF algorithm)
+        #[arg(long)]
+        obfuscate: bool,
     },
     /// Remove an asset from an EPUB
     Remove {
@@ -45,5 +51,11 @@ pub enum AssetCommand {
         file: PathBuf,
         /// Asset path within the EPUB
         asset_path: String,
+        /// Remove the asset even if content still references it
+        #[arg(long, conflicts_with = "cascade")]
+        force: bool,
+        /// Also remove the elements, links and CSS rules referencing it
+        #[arg(long)]
+        cascade: bool,
     },
 }
@@ -1,3 +1,7 @@
+use crate::epub::EpubVersion;
+use crate::epub::fixed_layout::Viewport;
+use crate::extract::FixedLayoutMode;
+use crate::validate::report::{FailOn, ReportFormat};
 use clap::Subcommand;
 use std::path::PathBuf;
 
@@ -10,6 +14,10 @@ pub enum BookCommand {
         /// Output directory
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// Fixed-layout pages: passthrough (copy the XHTML to pages/) or
+        /// skip
+        #[arg(long, default_value = "passthrough", value_name = "MODE")]
+        fixed_layout: FixedLayoutMode,
     },
     /// Assemble a Markdown directory into an EPUB
     Assemble {
@@ -18,6 +26,20 @@ pub enum BookCommand {
         /// Output EPUB file path
         #[arg(short, long)]
         output: Option<PathBuf>,
+        /// Obfuscate embedded fonts (IDPF algorithm)
+        #[arg(long)]
+        obfuscate_fonts: bool,
+        /// EPUB version to write: 3 or 2 (EPUB 2.0.1)
+        #[arg(long, default_value = "3")]
+        epub_version: EpubVersion,
+        /// Build a fixed-layout (pre-paginated) book from the images and
+        /// XHTML pages in pages/, in file name order
+        #[arg(long)]
+        fixed_layout: bool,
+        /// Viewport of fixed-layout pages that do not declare one, e.g.
+        /// 1200x1600
+        #[arg(long, requires = "fixed_layout", value_name = "WIDTHxHEIGHT")]
+        viewport: Option<Viewport>,
     },
     /// Show information about an EPUB file
     Info {
@@ -28,5 +50,53 @@ pub enum BookCommand {
     Validate {
         /// Path to the EPUB file
         file: PathBuf,
+        /// Report format: text, json, sarif or junit (default: text, or
+        /// json with --json)
+        #[arg(long, value_name = "FORMAT")]
+        format: Option<ReportFormat>,
+        /// Exit with an error when an issue of this severity or worse is
+        /// found: warning or error
+        #[arg(long, value_name = "SEVERITY")]
+        fail_on: Option<FailOn>,
+        /// Repair common defects in place and list each fix, then report
+        /// what is left
+        #[arg(long, conflicts_with = "format")]
+        fix: bool,
+        /// With --fix, also make risky repairs: deleting unlisted files of
+        /// unknown type, adding an empty nav document and renaming duplicate
+        /// IDs
+        #[arg(long, requires = "fix")]
+        aggressive: bool,
+    },
+    /// Recover a damaged EPUB and write a conformant copy
+    Repair {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// Output EPUB file path (default: repair in place)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert an EPUB 2 book to EPUB 3 and list every change made
+    Upgrade {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// Output EPUB file path (default: upgrade in place)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Report accessibility metadata and audit the content for problems
+    A11y {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// Write the suggested accessibility metadata back to the book
+        #[arg(long)]
+        write: bool,
+        /// Accessibility summary to suggest and write
+        #[arg(long)]
+        summary: Option<String>,
+        /// Conformance claim to suggest and write, e.g.
+        /// "EPUB Accessibility 1.1 - WCAG 2.1 Level AA"
+        #[arg(long, value_name = "CLAIM")]
+        conforms_to: Option<String>,
     },
 }
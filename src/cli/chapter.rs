@@ -38,6 +38,15 @@ pub enum ChapterCommand {
         /// Chapter ID or index
         id: String,
     },
+    /// Move a chapter to a new path and update every reference to it
+    Rename {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// Chapter ID or index
+        id: String,
+        /// New href, relative to the OPF
+        new_href: String,
+    },
     /// Reorder a chapter in an EPUB
     Reorder {
         /// Path to the EPUB file
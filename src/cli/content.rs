@@ -34,6 +34,15 @@ pub enum ContentCommand {
         #[arg(long)]
         dry_run: bool,
     },
+    /// Check every hyperlink in the XHTML, nav and NCX: missing targets and
+    /// fragments, links to resources outside the spine and external URLs
+    Links {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// List every link, not only the ones flagged
+        #[arg(long)]
+        all: bool,
+    },
     /// List or restructure headings
     Headings {
         /// Path to the EPUB file
@@ -0,0 +1,33 @@
+use clap::Subcommand;
+use std::path::PathBuf;
+
+#[derive(Subcommand, Debug)]
+pub enum MediaOverlayCommand {
+    /// List media overlays with the documents they narrate and their durations
+    List {
+        /// Path to the EPUB file
+        file: PathBuf,
+    },
+    /// Extract the sync points of an overlay: text fragment, audio file,
+    /// clip begin and end
+    Extract {
+        /// Path to the EPUB file
+        file: PathBuf,
+        /// Overlay ID, or the ID of a content document it narrates
+        id: String,
+        /// Output file path (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check that text fragments exist, audio clips are in range and
+    /// media:duration matches the clips
+    Validate {
+        /// Path to the EPUB file
+        file: PathBuf,
+    },
+    /// Recompute media:duration metadata from the clip times
+    UpdateDurations {
+        /// Path to the EPUB file
+        file: PathBuf,
+    },
+}
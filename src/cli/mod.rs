@@ -2,6 +2,7 @@ pub mod asset;
 pub mod book;
 pub mod chapter;
 pub mod content;
+pub mod media_overlay;
 pub mod metadata;
 pub mod output;
 pub mod spine;
@@ -13,7 +14,8 @@ use clap::{Parser, Subcommand};
 #[command(
     name = "epx",
     version,
-    about = "Extract, manipulate, and assemble EPUB files"
+    about = "Extract, manipulate, and assemble EPUB files",
+    after_help = "Any EPUB path can be - for stdin; a book modified from stdin is written to stdout, as is output given as -o -."
 )]
 pub struct Cli {
     #[command(subcommand)]
@@ -31,6 +33,21 @@ pub struct Cli {
     #[arg(long, short, global = true)]
     pub quiet: bool,
 
+    /// Rendition to operate on in multi-rendition EPUBs: an index, OPF path,
+    /// label, layout or language
+    #[arg(long, global = true, value_name = "SELECTOR")]
+    pub rendition: Option<String>,
+
+    /// Read damaged EPUBs: recover a truncated ZIP, a misplaced mimetype,
+    /// a missing container.xml and manifest hrefs that miss their entries
+    #[arg(long, global = true)]
+    pub lenient: bool,
+
+    /// When writing, re-encode XHTML, NCX and OPF documents in legacy
+    /// encodings (ISO-8859-1, Windows-1252, UTF-16, ...) as UTF-8
+    #[arg(long, global = true)]
+    pub transcode_utf8: bool,
+
     /// Disable colored output
     #[arg(long, global = true, env = "NO_COLOR")]
     pub no_color: bool,
@@ -38,12 +55,13 @@ pub struct Cli {
 
 #[derive(Subcommand, Debug)]
 pub enum Resource {
-    /// Whole-book operations: extract, assemble, info, validate
+    /// Whole-book operations: extract, assemble, info, validate, repair,
+    /// upgrade, a11y
     Book {
         #[command(subcommand)]
         command: book::BookCommand,
     },
-    /// Chapter operations: list, extract, add, remove, reorder
+    /// Chapter operations: list, extract, add, remove, rename, reorder
     Chapter {
         #[command(subcommand)]
         command: chapter::ChapterCommand,
@@ -68,9 +86,15 @@ pub enum Resource {
         #[command(subcommand)]
         command: asset::AssetCommand,
     },
-    /// Content operations: search, replace, headings
+    /// Content operations: search, replace, links, headings
     Content {
         #[command(subcommand)]
         command: content::ContentCommand,
     },
+    /// Media overlay (SMIL) operations: list, extract, validate,
+    /// update-durations
+    MediaOverlay {
+        #[command(subcommand)]
+        command: media_overlay::MediaOverlayCommand,
+    },
 }
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::io::{self, IsTerminal, Write};
 
 pub struct OutputConfig {
@@ -5,6 +6,8 @@ pub struct OutputConfig {
     pub verbose: bool,
     pub quiet: bool,
     pub no_color: bool,
+    /// Set once stdout carries an EPUB; messages then go to stderr
+    stdout_reserved: Cell<bool>,
 }
 
 impl OutputConfig {
@@ -15,6 +18,21 @@ impl OutputConfig {
             verbose,
             quiet,
             no_color,
+            stdout_reserved: Cell::new(false),
+        }
+    }
+
+    /// Keep stdout for an EPUB written there, sending messages to stderr
+    pub fn reserve_stdout(&self) {
+        self.stdout_reserved.set(true);
+    }
+
+    /// Print a line of command output, to stderr when stdout is reserved
+    pub fn println(&self, msg: &str) {
+        if self.stdout_reserved.get() {
+            eprintln!("{msg}");
+        } else {
+            println!("{msg}");
         }
     }
 
@@ -25,20 +43,24 @@ impl OutputConfig {
     /// Print a status/confirmation message (suppressed in quiet mode).
     pub fn status(&self, msg: &str) {
         if !self.quiet {
-            println!("{msg}");
+            self.println(msg);
         }
     }
 
     /// Print extra detail (only shown in verbose mode, suppressed in quiet mode).
     pub fn detail(&self, msg: &str) {
         if self.verbose && !self.quiet {
-            println!("{msg}");
+            self.println(msg);
         }
     }
 
     pub fn print_json<T: serde::Serialize>(&self, value: &T) -> anyhow::Result<()> {
         let json = serde_json::to_string_pretty(value)?;
-        writeln!(io::stdout(), "{json}")?;
+        if self.stdout_reserved.get() {
+            writeln!(io::stderr(), "{json}")?;
+        } else {
+            writeln!(io::stdout(), "{json}")?;
+        }
         Ok(())
     }
 
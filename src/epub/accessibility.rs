@@ -0,0 +1,578 @@
+use crate::epub::{Accessibility, EpubBook};
+use crate::validate::line_column;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use serde::Serialize;
+
+pub const ACCESS_MODE: &str = "schema:accessMode";
+pub const ACCESS_MODE_SUFFICIENT: &str = "schema:accessModeSufficient";
+pub const FEATURE: &str = "schema:accessibilityFeature";
+pub const HAZARD: &str = "schema:accessibilityHazard";
+pub const SUMMARY: &str = "schema:accessibilitySummary";
+pub const CONFORMS_TO: &str = "dcterms:conformsTo";
+pub const CERTIFIED_BY: &str = "a11y:certifiedBy";
+
+/// Modelled properties, in the order they are written
+const PROPERTIES: [&str; 7] = [
+    ACCESS_MODE,
+    ACCESS_MODE_SUFFICIENT,
+    FEATURE,
+    HAZARD,
+    SUMMARY,
+    CONFORMS_TO,
+    CERTIFIED_BY,
+];
+
+/// Accessibility metadata of a book and the problems its content shows
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessibilityReport {
+    /// Metadata as declared in the package
+    pub declared: Accessibility,
+    /// The declared metadata, corrected and completed from the content
+    pub suggested: Accessibility,
+    pub issues: Vec<AccessibilityIssue>,
+}
+
+/// A problem found by [`audit`]: missing metadata is located in the
+/// package document, content problems at the element in their document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccessibilityIssue {
+    /// ZIP path of the document the issue is in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl AccessibilityIssue {
+    fn new(path: Option<&str>, message: String) -> Self {
+        Self {
+            path: path.map(str::to_string),
+            line: None,
+            column: None,
+            message,
+        }
+    }
+
+    fn at_position(mut self, (line, column): (usize, usize)) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+}
+
+/// `EPUB/ch1.xhtml:3:5: image 'a.png' has no alt text`
+impl std::fmt::Display for AccessibilityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{path}")?;
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, ":{line}:{column}")?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// What the content documents contain, summed over the spine
+#[derive(Debug, Default)]
+struct ContentStats {
+    images: usize,
+    images_without_alt: usize,
+    headings: usize,
+    skipped_headings: usize,
+    math: usize,
+    page_breaks: usize,
+    media: usize,
+}
+
+impl Accessibility {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The full property name for `name`, which may omit the prefix, e.g.
+    /// `accessibilityFeature`; `None` for properties not modelled here
+    pub fn property_name(name: &str) -> Option<&'static str> {
+        PROPERTIES
+            .into_iter()
+            .find(|p| *p == name || p.split_once(':').is_some_and(|(_, local)| local == name))
+    }
+
+    /// Add a value of `property`.
+    ///
+    /// Returns `false` for properties not modelled here and for a second
+    /// value of a single-valued property, which are then kept as raw XML.
+    pub fn push(&mut self, property: &str, value: String) -> bool {
+        let single = |slot: &mut Option<String>| {
+            if slot.is_some() {
+                return false;
+            }
+            *slot = Some(value.clone());
+            true
+        };
+        match property {
+            ACCESS_MODE => self.access_modes.push(value),
+            ACCESS_MODE_SUFFICIENT => self.access_modes_sufficient.push(value),
+            FEATURE => self.features.push(value),
+            HAZARD => self.hazards.push(value),
+            CONFORMS_TO => self.conforms_to.push(value),
+            SUMMARY => return single(&mut self.summary),
+            CERTIFIED_BY => return single(&mut self.certified_by),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Set `property`: add the value to a list unless already there, or
+    /// replace a single value. Returns `false` for properties not modelled.
+    pub fn set(&mut self, property: &str, value: &str) -> bool {
+        let value = value.to_string();
+        match property {
+            SUMMARY => self.summary = Some(value),
+            CERTIFIED_BY => self.certified_by = Some(value),
+            _ => match self.list_mut(property) {
+                Some(list) => add_unique(list, &value),
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// Remove every value of `property`. Returns `false` for properties not
+    /// modelled.
+    pub fn remove(&mut self, property: &str) -> bool {
+        match property {
+            SUMMARY => self.summary = None,
+            CERTIFIED_BY => self.certified_by = None,
+            _ => match self.list_mut(property) {
+                Some(list) => list.clear(),
+                None => return false,
+            },
+        }
+        true
+    }
+
+    /// `(property, value)` pairs in the order they are written
+    pub fn properties(&self) -> Vec<(&'static str, &str)> {
+        let mut properties = Vec::new();
+        let lists = [
+            (ACCESS_MODE, &self.access_modes),
+            (ACCESS_MODE_SUFFICIENT, &self.access_modes_sufficient),
+            (FEATURE, &self.features),
+            (HAZARD, &self.hazards),
+        ];
+        for (property, values) in lists {
+            properties.extend(values.iter().map(|v| (property, v.as_str())));
+        }
+        properties.extend(self.summary.as_deref().map(|v| (SUMMARY, v)));
+        properties.extend(self.conforms_to.iter().map(|v| (CONFORMS_TO, v.as_str())));
+        properties.extend(self.certified_by.as_deref().map(|v| (CERTIFIED_BY, v)));
+        properties
+    }
+
+    fn list_mut(&mut self, property: &str) -> Option<&mut Vec<String>> {
+        match property {
+            ACCESS_MODE => Some(&mut self.access_modes),
+            ACCESS_MODE_SUFFICIENT => Some(&mut self.access_modes_sufficient),
+            FEATURE => Some(&mut self.features),
+            HAZARD => Some(&mut self.hazards),
+            CONFORMS_TO => Some(&mut self.conforms_to),
+            _ => None,
+        }
+    }
+}
+
+/// Audit the accessibility metadata and content of a book.
+///
+/// Content checks cover images without alt text, documents without a
+/// language, skipped heading levels and tables without header cells.
+pub fn audit(book: &EpubBook) -> AccessibilityReport {
+    let declared = &book.metadata.accessibility;
+    let opf_path = book.source.as_ref().map(|source| source.opf_path.as_str());
+    let in_opf = |message| AccessibilityIssue::new(opf_path, message);
+    let mut issues = Vec::new();
+
+    let required = [
+        (ACCESS_MODE, declared.access_modes.is_empty()),
+        (
+            ACCESS_MODE_SUFFICIENT,
+            declared.access_modes_sufficient.is_empty(),
+        ),
+        (FEATURE, declared.features.is_empty()),
+        (HAZARD, declared.hazards.is_empty()),
+        (SUMMARY, declared.summary.is_none()),
+    ];
+    for (property, missing) in required {
+        if missing {
+            issues.push(in_opf(format!("no {property} declared")));
+        }
+    }
+    if declared.conforms_to.is_empty() {
+        issues.push(in_opf(format!("no {CONFORMS_TO} conformance claim")));
+    }
+
+    let stats = scan_content(book, &mut issues);
+
+    for feature in ["pageBreakMarkers", "pageNavigation"] {
+        if declared.features.iter().any(|f| f == feature) && book.navigation.page_list.is_empty() {
+            issues.push(in_opf(format!(
+                "{FEATURE} {feature} is claimed but the navigation has no page list"
+            )));
+        }
+    }
+    if declared.features.iter().any(|f| f == "alternativeText") && stats.images_without_alt > 0 {
+        issues.push(in_opf(format!(
+            "{FEATURE} alternativeText is claimed but {} image(s) have no alt text",
+            stats.images_without_alt
+        )));
+    }
+
+    AccessibilityReport {
+        declared: declared.clone(),
+        suggested: suggest(book, &stats),
+        issues,
+    }
+}
+
+/// Scan the spine documents, recording content issues
+fn scan_content(book: &EpubBook, issues: &mut Vec<AccessibilityIssue>) -> ContentStats {
+    let mut stats = ContentStats::default();
+
+    for spine_item in &book.spine {
+        let Some(item) = book.manifest.iter().find(|m| m.id == spine_item.idref) else {
+            continue;
+        };
+        if item.media_type != "application/xhtml+xml" {
+            continue;
+        }
+        let path = book.resource_key(&item.href);
+        let Some(xhtml) = book.resources.get_string(&path) else {
+            continue;
+        };
+        scan_document(&path, &xhtml, &mut stats, issues);
+    }
+
+    stats
+}
+
+/// Scan one content document; one that is not well-formed is scanned up to
+/// the error
+fn scan_document(
+    path: &str,
+    xhtml: &str,
+    stats: &mut ContentStats,
+    issues: &mut Vec<AccessibilityIssue>,
+) {
+    let issue_at = |offset: usize, message: String| {
+        AccessibilityIssue::new(Some(path), message).at_position(line_column(xhtml, offset))
+    };
+    let mut reader = Reader::from_str(xhtml);
+    // Offset of <html> and whether it declares a language
+    let mut html: Option<(usize, bool)> = None;
+    let mut previous_heading: Option<u8> = None;
+    // Offset of each open table and whether it has a header cell
+    let mut tables: Vec<(usize, bool)> = Vec::new();
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        let (e, empty) = match reader.read_event() {
+            Ok(Event::Start(e)) => (e, false),
+            Ok(Event::Empty(e)) => (e, true),
+            Ok(Event::End(e)) => {
+                if e.local_name().as_ref() == b"table"
+                    && let Some((offset, false)) = tables.pop()
+                {
+                    issues.push(issue_at(offset, "table has no header cells".to_string()));
+                }
+                continue;
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => continue,
+        };
+        let attrs = attributes(&e);
+        let value = |name: &str| {
+            attrs
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        match e.local_name().as_ref() {
+            b"html" if html.is_none() => {
+                let has_lang = ["lang", "xml:lang"]
+                    .iter()
+                    .any(|name| value(name).is_some_and(|lang| !lang.trim().is_empty()));
+                html = Some((start, has_lang));
+            }
+            b"img" => {
+                stats.images += 1;
+                if value("alt").is_none() {
+                    stats.images_without_alt += 1;
+                    let src = value("src").unwrap_or_default();
+                    issues.push(issue_at(start, format!("image '{src}' has no alt text")));
+                }
+            }
+            &[b'h', level @ b'1'..=b'6'] => {
+                let level = level - b'0';
+                stats.headings += 1;
+                if let Some(previous) = previous_heading
+                    && level > previous + 1
+                {
+                    stats.skipped_headings += 1;
+                    issues.push(issue_at(
+                        start,
+                        format!("heading level skips from h{previous} to h{level}"),
+                    ));
+                }
+                previous_heading = Some(level);
+            }
+            b"table" if empty => {
+                issues.push(issue_at(start, "table has no header cells".to_string()));
+            }
+            b"table" => tables.push((start, false)),
+            b"th" => {
+                if let Some(table) = tables.last_mut() {
+                    table.1 = true;
+                }
+            }
+            b"math" => stats.math += 1,
+            b"audio" | b"video" => stats.media += 1,
+            _ => {}
+        }
+
+        let is_page_break = attrs.iter().any(|(key, value)| {
+            let tokens = || value.split_ascii_whitespace();
+            (key.ends_with(":type") && tokens().any(|token| token == "pagebreak"))
+                || (key == "role" && tokens().any(|token| token == "doc-pagebreak"))
+        });
+        if is_page_break {
+            stats.page_breaks += 1;
+        }
+    }
+
+    match html {
+        Some((_, true)) => {}
+        Some((offset, false)) => issues.push(issue_at(
+            offset,
+            "no lang or xml:lang on <html>".to_string(),
+        )),
+        None => issues.push(AccessibilityIssue::new(
+            Some(path),
+            "no lang or xml:lang on <html>".to_string(),
+        )),
+    }
+}
+
+fn attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr
+                .unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+            (key, value)
+        })
+        .collect()
+}
+
+/// The declared metadata corrected by what the content shows: access modes
+/// and features the content confirms are added, features it refutes are
+/// dropped, and undeclared hazards become `unknown`
+fn suggest(book: &EpubBook, stats: &ContentStats) -> Accessibility {
+    let mut suggested = book.metadata.accessibility.clone();
+
+    add_unique(&mut suggested.access_modes, "textual");
+    if stats.images > 0 {
+        add_unique(&mut suggested.access_modes, "visual");
+    }
+    if stats.media > 0 {
+        add_unique(&mut suggested.access_modes, "auditory");
+    }
+
+    if stats.images == 0 {
+        add_unique(&mut suggested.access_modes_sufficient, "textual");
+    } else {
+        add_unique(&mut suggested.access_modes_sufficient, "textual,visual");
+        if stats.images_without_alt == 0 {
+            add_unique(&mut suggested.access_modes_sufficient, "textual");
+        } else {
+            suggested.access_modes_sufficient.retain(|m| m != "textual");
+        }
+    }
+
+    // Features the content can confirm or refute
+    let verifiable = [
+        ("tableOfContents", !book.navigation.toc.is_empty()),
+        ("readingOrder", !book.spine.is_empty()),
+        (
+            "structuralNavigation",
+            stats.headings > 0 && stats.skipped_headings == 0,
+        ),
+        (
+            "alternativeText",
+            stats.images > 0 && stats.images_without_alt == 0,
+        ),
+        ("pageNavigation", !book.navigation.page_list.is_empty()),
+        ("pageBreakMarkers", stats.page_breaks > 0),
+        ("MathML", stats.math > 0),
+        (
+            "synchronizedAudioText",
+            book.manifest.iter().any(|item| item.is_media_overlay()),
+        ),
+    ];
+    suggested
+        .features
+        .retain(|feature| verifiable.iter().all(|(name, _)| name != feature));
+    for (feature, present) in verifiable {
+        if present {
+            add_unique(&mut suggested.features, feature);
+        }
+    }
+
+    if suggested.hazards.is_empty() {
+        suggested.hazards.push("unknown".to_string());
+    }
+
+    suggested
+}
+
+fn add_unique(values: &mut Vec<String>, value: &str) {
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::{ManifestItem, NavPoint, SpineItem};
+
+    fn book_with_page(xhtml: &str, features: &[&str]) -> EpubBook {
+        let mut book = EpubBook::default();
+        book.manifest.push(ManifestItem {
+            id: "ch1".to_string(),
+            href: "ch1.xhtml".to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            ..Default::default()
+        });
+        book.spine.push(SpineItem {
+            idref: "ch1".to_string(),
+            ..Default::default()
+        });
+        book.navigation.toc.push(NavPoint {
+            label: "One".to_string(),
+            href: "ch1.xhtml".to_string(),
+            ..Default::default()
+        });
+        book.resources
+            .insert("ch1.xhtml".to_string(), xhtml.as_bytes().to_vec());
+        book.metadata.accessibility.features = features.iter().map(|f| f.to_string()).collect();
+        book
+    }
+
+    #[test]
+    fn property_names() {
+        assert_eq!(
+            Accessibility::property_name("accessMode"),
+            Some(ACCESS_MODE)
+        );
+        assert_eq!(Accessibility::property_name(CONFORMS_TO), Some(CONFORMS_TO));
+        assert_eq!(Accessibility::property_name("rendition:layout"), None);
+    }
+
+    #[test]
+    fn single_valued_properties_take_one_value() {
+        let mut a11y = Accessibility::default();
+        assert!(a11y.push(SUMMARY, "First".to_string()));
+        assert!(!a11y.push(SUMMARY, "Second".to_string()));
+        assert!(a11y.push(FEATURE, "MathML".to_string()));
+        assert!(!a11y.push("schema:unknown", "x".to_string()));
+        assert_eq!(
+            a11y.properties(),
+            vec![(FEATURE, "MathML"), (SUMMARY, "First")]
+        );
+    }
+
+    #[test]
+    fn audit_reports_content_issues() {
+        let xhtml = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body>
+<h1>One</h1><h3>Deep</h3>
+<img src="a.png"/><img src="b.png" alt="" data-alt="x"/><img src="c.png" data-alt="c"/>
+<table><tr><td>1</td></tr></table>
+</body></html>"#;
+        let book = book_with_page(xhtml, &["alternativeText", "pageBreakMarkers"]);
+        let report = audit(&book);
+        let has = |text: &str| report.issues.iter().any(|i| i.to_string().contains(text));
+        assert!(has("ch1.xhtml:3:1: image 'a.png' has no alt text"));
+        assert!(has("ch1.xhtml:3:57: image 'c.png' has no alt text"));
+        assert!(has("ch1.xhtml:1:1: no lang or xml:lang"));
+        assert!(has("ch1.xhtml:2:13: heading level skips from h1 to h3"));
+        assert!(has("ch1.xhtml:4:1: table has no header cells"));
+        assert!(has(
+            "pageBreakMarkers is claimed but the navigation has no page list"
+        ));
+        assert!(has("alternativeText is claimed but 2 image(s)"));
+
+        let suggested = &report.suggested;
+        assert_eq!(suggested.access_modes, vec!["textual", "visual"]);
+        assert_eq!(suggested.access_modes_sufficient, vec!["textual,visual"]);
+        assert_eq!(suggested.features, vec!["tableOfContents", "readingOrder"]);
+        assert_eq!(suggested.hazards, vec!["unknown"]);
+    }
+
+    #[test]
+    fn audit_confirms_accessible_content() {
+        let xhtml = r#"<html xml:lang="en"><body>
+<h1>One</h1><h2>Two</h2><img src="a.png" alt="A"/>
+<table><tr><th>H</th></tr></table><span epub:type="pagebreak" id="p1"/>
+</body></html>"#;
+        let mut book = book_with_page(xhtml, &["ARIA"]);
+        book.metadata.accessibility.hazards = vec!["none".to_string()];
+        let report = audit(&book);
+        assert!(
+            report
+                .issues
+                .iter()
+                .all(|i| i.path.as_deref() != Some("ch1.xhtml"))
+        );
+        assert_eq!(
+            report.suggested.features,
+            vec![
+                "ARIA",
+                "tableOfContents",
+                "readingOrder",
+                "structuralNavigation",
+                "alternativeText",
+                "pageBreakMarkers"
+            ]
+        );
+        assert_eq!(
+            report.suggested.access_modes_sufficient,
+            vec!["textual,visual", "textual"]
+        );
+        assert_eq!(report.suggested.hazards, vec!["none"]);
+    }
+
+    #[test]
+    fn nested_tables_need_their_own_header_cells() {
+        let xhtml = r#"<html lang="en"><body>
+<table><tr><th>H</th><td><table><tr><td>1</td></tr></table></td></tr></table>
+<table><tr><td><table><tr><th>H</th></tr></table></td></tr></table>
+</body></html>"#;
+        let report = audit(&book_with_page(xhtml, &[]));
+        let tables: Vec<_> = report
+            .issues
+            .iter()
+            .filter(|i| i.message == "table has no header cells")
+            .map(|i| (i.line, i.column))
+            .collect();
+        assert_eq!(tables, vec![(Some(2), Some(26)), (Some(3), Some(1))]);
+    }
+}
@@ -1,23 +1,88 @@
 use crate::error::{EpxError, Result};
 use quick_xml::Reader;
 use quick_xml::events::Event;
+use serde::Serialize;
 
-/// Parse META-INF/container.xml to find the OPF rootfile path
-pub fn parse_container(xml: &str) -> Result<String> {
+/// A `rootfile` of `container.xml`: one rendition of the publication
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Rendition {
+    /// Full ZIP path of the rendition's OPF
+    pub full_path: String,
+    pub media_type: String,
+    /// `rendition:layout`, e.g. `pre-paginated`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// `rendition:language`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// `rendition:media`, a CSS media query
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+    /// `rendition:accessMode`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_mode: Option<String>,
+    /// `rendition:label`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl Rendition {
+    pub fn new(full_path: impl Into<String>) -> Self {
+        Self {
+            full_path: full_path.into(),
+            media_type: "application/oebps-package+xml".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Selection attributes as `name=value` pairs, for display
+    pub fn selection_attributes(&self) -> Vec<String> {
+        [
+            ("layout", &self.layout),
+            ("language", &self.language),
+            ("media", &self.media),
+            ("accessMode", &self.access_mode),
+            ("label", &self.label),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| format!("{name}={v}")))
+        .collect()
+    }
+}
+
+/// Parse META-INF/container.xml into its renditions, in document order.
+///
+/// The first rendition is the default one.
+pub fn parse_container(xml: &str) -> Result<Vec<Rendition>> {
     let mut reader = Reader::from_str(xml);
     let mut buf = Vec::new();
+    let mut renditions = Vec::new();
 
     loop {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Empty(ref e)) | Ok(Event::Start(ref e))
                 if e.local_name().as_ref() == b"rootfile" =>
             {
+                let mut rendition = Rendition::default();
                 for attr in e.attributes().flatten() {
-                    if attr.key.as_ref() == b"full-path" {
-                        let path = String::from_utf8_lossy(&attr.value).into_owned();
-                        return Ok(path);
+                    let value = attr
+                        .unescape_value()
+                        .map(|v| v.into_owned())
+                        .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+                    match attr.key.as_ref() {
+                        b"full-path" => rendition.full_path = value,
+                        b"media-type" => rendition.media_type = value,
+                        b"rendition:layout" => rendition.layout = Some(value),
+                        b"rendition:language" => rendition.language = Some(value),
+                        b"rendition:media" => rendition.media = Some(value),
+                        b"rendition:accessMode" => rendition.access_mode = Some(value),
+                        b"rendition:label" => rendition.label = Some(value),
+                        _ => {}
                     }
                 }
+                if !rendition.full_path.is_empty() {
+                    renditions.push(rendition);
+                }
             }
             Ok(Event::Eof) => break,
             Err(e) => return Err(EpxError::Xml(e)),
@@ -26,9 +91,31 @@ pub fn parse_container(xml: &str) -> Result<String> {
         buf.clear();
     }
 
-    Err(EpxError::InvalidEpub(
-        "no rootfile found in container.xml".into(),
-    ))
+    if renditions.is_empty() {
+        return Err(EpxError::InvalidEpub(
+            "no rootfile found in container.xml".into(),
+        ));
+    }
+    Ok(renditions)
+}
+
+/// Pick a rendition by selector: a zero-based index, the OPF path, or the
+/// value of its `rendition:label`, `rendition:layout` or
+/// `rendition:language`
+pub fn select_rendition<'a>(renditions: &'a [Rendition], selector: &str) -> Result<&'a Rendition> {
+    if let Ok(index) = selector.parse::<usize>() {
+        return renditions
+            .get(index)
+            .ok_or_else(|| EpxError::RenditionNotFound(selector.to_string()));
+    }
+    let matches = |value: &Option<String>| value.as_deref() == Some(selector);
+    renditions
+        .iter()
+        .find(|r| r.full_path == selector)
+        .or_else(|| renditions.iter().find(|r| matches(&r.label)))
+        .or_else(|| renditions.iter().find(|r| matches(&r.layout)))
+        .or_else(|| renditions.iter().find(|r| matches(&r.language)))
+        .ok_or_else(|| EpxError::RenditionNotFound(selector.to_string()))
 }
 
 #[cfg(test)]
@@ -43,7 +130,10 @@ mod tests {
     <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
   </rootfiles>
 </container>"#;
-        assert_eq!(parse_container(xml).unwrap(), "OEBPS/content.opf");
+        assert_eq!(
+            parse_container(xml).unwrap()[0].full_path,
+            "OEBPS/content.opf"
+        );
     }
 
     #[test]
@@ -54,7 +144,7 @@ mod tests {
     <rootfile full-path="content.opf" media-type="application/oebps-package+xml"/>
   </rootfiles>
 </container>"#;
-        assert_eq!(parse_container(xml).unwrap(), "content.opf");
+        assert_eq!(parse_container(xml).unwrap()[0].full_path, "content.opf");
     }
 
     #[test]
@@ -72,4 +162,40 @@ mod tests {
         let xml = "<container><not-closed>";
         assert!(parse_container(xml).is_err());
     }
+
+    const MULTI_RENDITION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"
+    xmlns:rendition="http://www.idpf.org/2013/rendition">
+  <rootfiles>
+    <rootfile full-path="EPUB/fixed.opf" media-type="application/oebps-package+xml"
+        rendition:layout="pre-paginated" rendition:label="Fixed"/>
+    <rootfile full-path="EPUB/reflow.opf" media-type="application/oebps-package+xml"
+        rendition:layout="reflowable" rendition:language="fr"
+        rendition:media="(max-width: 600px)"/>
+  </rootfiles>
+</container>"#;
+
+    #[test]
+    fn parse_container_multiple_renditions() {
+        let renditions = parse_container(MULTI_RENDITION).unwrap();
+        assert_eq!(renditions.len(), 2);
+        assert_eq!(renditions[0].layout.as_deref(), Some("pre-paginated"));
+        assert_eq!(renditions[0].label.as_deref(), Some("Fixed"));
+        assert_eq!(renditions[1].full_path, "EPUB/reflow.opf");
+        assert_eq!(renditions[1].language.as_deref(), Some("fr"));
+        assert_eq!(renditions[1].media.as_deref(), Some("(max-width: 600px)"));
+    }
+
+    #[test]
+    fn select_rendition_by_selector() {
+        let renditions = parse_container(MULTI_RENDITION).unwrap();
+        let path = |selector| &select_rendition(&renditions, selector).unwrap().full_path;
+        assert_eq!(path("1"), "EPUB/reflow.opf");
+        assert_eq!(path("EPUB/fixed.opf"), "EPUB/fixed.opf");
+        assert_eq!(path("Fixed"), "EPUB/fixed.opf");
+        assert_eq!(path("reflowable"), "EPUB/reflow.opf");
+        assert_eq!(path("fr"), "EPUB/reflow.opf");
+        assert!(select_rendition(&renditions, "2").is_err());
+        assert!(select_rendition(&renditions, "de").is_err());
+    }
 }
@@ -0,0 +1,168 @@
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use regex::Regex;
+use regex::bytes::Regex as BytesRegex;
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+/// How far into a document to look for an encoding declaration
+const SNIFF_LEN: usize = 1024;
+
+static XML_DECL_BYTES: LazyLock<BytesRegex> = LazyLock::new(|| {
+    BytesRegex::new(r#"^\s*<\?xml[^>]*?\bencoding\s*=\s*["']([A-Za-z0-9._:-]+)["']"#)
+        .expect("valid regex")
+});
+static META_CHARSET_BYTES: LazyLock<BytesRegex> = LazyLock::new(|| {
+    BytesRegex::new(r#"(?i)<meta\b[^>]*?\bcharset\s*=\s*["']?([A-Za-z0-9._:-]+)"#)
+        .expect("valid regex")
+});
+static XML_DECL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^\s*<\?xml[^>]*?\bencoding\s*=\s*["']([A-Za-z0-9._:-]+)["']"#)
+        .expect("valid regex")
+});
+static META_CHARSET: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)<meta\b[^>]*?\bcharset\s*=\s*["']?([A-Za-z0-9._:-]+)"#).expect("valid regex")
+});
+
+/// The encoding of an XML or XHTML document and the length of its byte
+/// order mark.
+///
+/// EPUB 3 requires UTF-8 or UTF-16, but EPUB 2 books often declare a legacy
+/// charset. The byte order mark wins, then the XML declaration, then an HTML
+/// `<meta charset>`; without any of them the document is UTF-8.
+pub fn detect(bytes: &[u8]) -> (&'static Encoding, usize) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        return (encoding, bom_len);
+    }
+    // UTF-16 without a BOM still starts with "<?" (XML 1.0, appendix F)
+    if bytes.starts_with(&[b'<', 0, b'?', 0]) {
+        return (UTF_16LE, 0);
+    }
+    if bytes.starts_with(&[0, b'<', 0, b'?']) {
+        return (UTF_16BE, 0);
+    }
+
+    let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+    let declared = XML_DECL_BYTES
+        .captures(head)
+        .or_else(|| META_CHARSET_BYTES.captures(head))
+        .and_then(|caps| Encoding::for_label(&caps[1]));
+    // A document that made it into 8-bit form cannot be UTF-16, whatever it
+    // claims
+    match declared {
+        Some(encoding) if encoding != UTF_16LE && encoding != UTF_16BE => (encoding, 0),
+        _ => (UTF_8, 0),
+    }
+}
+
+/// Decode a document to a string.
+///
+/// Returns `None` if the bytes are malformed for the detected encoding.
+/// Text decoded from anything but UTF-8 has its declaration rewritten to
+/// UTF-8, so the result stays correct when written back as UTF-8.
+pub fn decode(bytes: &[u8]) -> Option<Cow<'_, str>> {
+    let (encoding, bom_len) = detect(bytes);
+    let body = &bytes[bom_len..];
+    if encoding == UTF_8 {
+        return std::str::from_utf8(body).ok().map(Cow::Borrowed);
+    }
+    let text = encoding.decode_without_bom_handling_and_without_replacement(body)?;
+    Some(Cow::Owned(declare_utf8(&text)))
+}
+
+/// Re-encode a document in a legacy encoding or UTF-16 as UTF-8.
+///
+/// Returns `None` if the document already is UTF-8 or cannot be decoded.
+pub fn transcode_to_utf8(bytes: &[u8]) -> Option<Vec<u8>> {
+    if detect(bytes).0 == UTF_8 {
+        return None;
+    }
+    decode(bytes).map(|text| text.into_owned().into_bytes())
+}
+
+/// Point the XML declaration or `<meta charset>` at UTF-8
+fn declare_utf8(text: &str) -> String {
+    let head_end = text
+        .char_indices()
+        .nth(SNIFF_LEN)
+        .map_or(text.len(), |(i, _)| i);
+    let head = &text[..head_end];
+    let declaration = XML_DECL
+        .captures(head)
+        .or_else(|| META_CHARSET.captures(head))
+        .and_then(|caps| caps.get(1));
+    match declaration {
+        Some(value) => format!("{}UTF-8{}", &text[..value.start()], &text[value.end()..]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_utf8_default() {
+        let (encoding, bom) = detect(b"<?xml version=\"1.0\"?><a/>");
+        assert_eq!(encoding, UTF_8);
+        assert_eq!(bom, 0);
+    }
+
+    #[test]
+    fn decode_strips_bom() {
+        assert_eq!(decode(b"\xEF\xBB\xBF<a/>").unwrap(), "<a/>");
+    }
+
+    #[test]
+    fn decode_xml_declared_latin1() {
+        let bytes = b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?><p>caf\xE9</p>";
+        assert_eq!(
+            decode(bytes).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><p>caf\u{e9}</p>"
+        );
+    }
+
+    #[test]
+    fn decode_meta_charset_windows_1252() {
+        let bytes =
+            b"<html><head><meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1252\"/></head><body>\x93hi\x94</body></html>";
+        let text = decode(bytes).unwrap();
+        assert!(text.contains("charset=UTF-8"));
+        assert!(text.contains("\u{201c}hi\u{201d}"));
+    }
+
+    #[test]
+    fn decode_utf16_with_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        for unit in "<?xml version=\"1.0\" encoding=\"UTF-16\"?><p>\u{e9}</p>".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(
+            decode(&bytes).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><p>\u{e9}</p>"
+        );
+    }
+
+    #[test]
+    fn decode_utf16be_without_bom() {
+        let mut bytes = Vec::new();
+        for unit in "<?xml version=\"1.0\"?><p/>".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_be_bytes());
+        }
+        assert_eq!(decode(&bytes).unwrap(), "<?xml version=\"1.0\"?><p/>");
+    }
+
+    #[test]
+    fn decode_invalid_utf8_fails() {
+        assert!(decode(b"<p>caf\xE9</p>").is_none());
+    }
+
+    #[test]
+    fn transcode_only_legacy_documents() {
+        assert!(transcode_to_utf8(b"<?xml version=\"1.0\" encoding=\"utf-8\"?><a/>").is_none());
+        let bytes = b"<?xml version='1.0' encoding='latin1'?><a>\xE9</a>";
+        assert_eq!(
+            transcode_to_utf8(bytes).unwrap(),
+            "<?xml version='1.0' encoding='UTF-8'?><a>\u{e9}</a>".as_bytes()
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use crate::epub::url;
+use crate::error::{EpxError, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use sha1::{Digest, Sha1};
+
+pub const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+
+/// Algorithm URI of the IDPF font obfuscation (OCF 3.0 §4.3)
+pub const IDPF_ALGORITHM: &str = "http://www.idpf.org/2008/embedding";
+/// Algorithm URI of the older Adobe font mangling
+pub const ADOBE_ALGORITHM: &str = "http://ns.adobe.com/pdf/enc#RC";
+
+/// Font obfuscation method
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontObfuscation {
+    /// SHA-1 of the unique identifier, applied to the first 1040 bytes
+    Idpf,
+    /// UUID bytes of the identifier, applied to the first 1024 bytes
+    Adobe,
+}
+
+/// An obfuscation method together with the key derived for one package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obfuscation {
+    pub method: FontObfuscation,
+    key: Vec<u8>,
+}
+
+/// A `<EncryptedData>` entry from `encryption.xml`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedEntry {
+    /// Full ZIP path of the encrypted resource
+    pub path: String,
+    pub algorithm: String,
+}
+
+impl FontObfuscation {
+    pub fn from_algorithm(uri: &str) -> Option<Self> {
+        match uri {
+            IDPF_ALGORITHM => Some(Self::Idpf),
+            ADOBE_ALGORITHM => Some(Self::Adobe),
+            _ => None,
+        }
+    }
+
+    pub fn algorithm(self) -> &'static str {
+        match self {
+            Self::Idpf => IDPF_ALGORITHM,
+            Self::Adobe => ADOBE_ALGORITHM,
+        }
+    }
+
+    fn header_len(self) -> usize {
+        match self {
+            Self::Idpf => 1040,
+            Self::Adobe => 1024,
+        }
+    }
+}
+
+impl Obfuscation {
+    /// Derive the key for `method` from the package unique identifier.
+    ///
+    /// Returns `None` for the Adobe method when the identifier is not a UUID.
+    pub fn new(method: FontObfuscation, unique_identifier: &str) -> Option<Self> {
+        let key = match method {
+            FontObfuscation::Idpf => {
+                let stripped: String = unique_identifier
+                    .chars()
+                    .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+                    .collect();
+                Sha1::digest(stripped.as_bytes()).to_vec()
+            }
+            FontObfuscation::Adobe => adobe_key(unique_identifier)?,
+        };
+        Some(Self { method, key })
+    }
+
+    /// Derive the key for a package.
+    ///
+    /// The unique identifier is tried first; Adobe mangling falls back to the
+    /// first other identifier that is a UUID.
+    pub fn for_package(
+        method: FontObfuscation,
+        unique_identifier: &str,
+        identifiers: &[String],
+    ) -> Option<Self> {
+        Self::new(method, unique_identifier)
+            .or_else(|| identifiers.iter().find_map(|id| Self::new(method, id)))
+    }
+
+    /// XOR the obfuscated header of `data` with the key.
+    ///
+    /// The operation is its own inverse, so it both obfuscates and restores.
+    pub fn apply(&self, data: &mut [u8]) {
+        let len = data.len().min(self.method.header_len());
+        for (i, byte) in data[..len].iter_mut().enumerate() {
+            *byte ^= self.key[i % self.key.len()];
+        }
+    }
+}
+
+fn adobe_key(identifier: &str) -> Option<Vec<u8>> {
+    let hex: String = identifier
+        .trim()
+        .trim_start_matches("urn:uuid:")
+        .chars()
+        .filter(|c| *c != '-')
+        .collect();
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    (0..32)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parse `META-INF/encryption.xml` into the list of encrypted resources.
+///
+/// `CipherReference` URIs are relative to the container root and are
+/// percent-decoded into ZIP paths.
+pub fn parse_encryption(xml: &str) -> Result<Vec<EncryptedEntry>> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut entries = Vec::new();
+    let mut algorithm: Option<String> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => match e.local_name().as_ref() {
+                b"EncryptedData" => algorithm = None,
+                b"EncryptionMethod" => {
+                    algorithm = attr_value(e, b"Algorithm");
+                }
+                b"CipherReference" => {
+                    if let Some(uri) = attr_value(e, b"URI") {
+                        entries.push(EncryptedEntry {
+                            path: url::percent_decode(uri.trim_start_matches('/')).into_owned(),
+                            algorithm: algorithm.clone().unwrap_or_default(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(EpxError::Xml(e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(entries)
+}
+
+/// Generate `encryption.xml` for obfuscated fonts, given full ZIP paths
+pub fn generate_encryption_xml(entries: &[(String, FontObfuscation)]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<encryption xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\" xmlns:enc=\"http://www.w3.org/2001/04/xmlenc#\">\n");
+    for (path, method) in entries {
+        xml.push_str("  <enc:EncryptedData>\n");
+        xml.push_str(&format!(
+            "    <enc:EncryptionMethod Algorithm=\"{}\"/>\n",
+            method.algorithm()
+        ));
+        xml.push_str(&format!(
+            "    <enc:CipherData><enc:CipherReference URI=\"{}\"/></enc:CipherData>\n",
+            crate::epub::writer::xml_escape(&url::encode_path(path))
+        ));
+        xml.push_str("  </enc:EncryptedData>\n");
+    }
+    xml.push_str("</encryption>\n");
+    xml
+}
+
+fn attr_value(e: &quick_xml::events::BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == key)
+        .map(|a| {
+            a.unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&a.value).into_owned())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: &str = "urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d";
+
+    #[test]
+    fn idpf_key_ignores_whitespace() {
+        let a = Obfuscation::new(FontObfuscation::Idpf, UID).unwrap();
+        let b = Obfuscation::new(FontObfuscation::Idpf, &format!(" {UID}\n")).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.key.len(), 20);
+    }
+
+    #[test]
+    fn adobe_key_needs_uuid() {
+        let key = Obfuscation::new(FontObfuscation::Adobe, UID).unwrap();
+        assert_eq!(key.key[0], 0xfe);
+        assert_eq!(key.key.len(), 16);
+        assert!(Obfuscation::new(FontObfuscation::Adobe, "isbn:12345").is_none());
+    }
+
+    #[test]
+    fn apply_is_reversible_and_limited_to_header() {
+        let original: Vec<u8> = (0..2000u32).map(|i| (i % 251) as u8).collect();
+        for method in [FontObfuscation::Idpf, FontObfuscation::Adobe] {
+            let obfuscation = Obfuscation::new(method, UID).unwrap();
+            let mut data = original.clone();
+            obfuscation.apply(&mut data);
+            assert_ne!(data[..16], original[..16]);
+            assert_eq!(data[1040..], original[1040..]);
+            obfuscation.apply(&mut data);
+            assert_eq!(data, original);
+        }
+    }
+
+    #[test]
+    fn parse_encryption_entries() {
+        let xml = r#"<?xml version="1.0"?>
+<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.idpf.org/2008/embedding"/>
+    <enc:CipherData><enc:CipherReference URI="EPUB/fonts/a.otf"/></enc:CipherData>
+  </enc:EncryptedData>
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+    <enc:CipherData><enc:CipherReference URI="EPUB/ch1.xhtml"/></enc:CipherData>
+  </enc:EncryptedData>
+</encryption>"#;
+        let entries = parse_encryption(xml).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "EPUB/fonts/a.otf");
+        assert_eq!(
+            FontObfuscation::from_algorithm(&entries[0].algorithm),
+            Some(FontObfuscation::Idpf)
+        );
+        assert_eq!(FontObfuscation::from_algorithm(&entries[1].algorithm), None);
+    }
+
+    #[test]
+    fn generate_encryption_roundtrip() {
+        let xml = generate_encryption_xml(&[("OEBPS/f.ttf".to_string(), FontObfuscation::Adobe)]);
+        let entries = parse_encryption(&xml).unwrap();
+        assert_eq!(entries[0].path, "OEBPS/f.ttf");
+        assert_eq!(entries[0].algorithm, ADOBE_ALGORITHM);
+    }
+
+    #[test]
+    fn cipher_reference_uris_are_percent_encoded() {
+        let path = "OEBPS/Fonts/My Font 100%.ttf".to_string();
+        let xml = generate_encryption_xml(&[(path.clone(), FontObfuscation::Idpf)]);
+        assert!(xml.contains("URI=\"OEBPS/Fonts/My%20Font%20100%25.ttf\""));
+        let entries = parse_encryption(&xml).unwrap();
+        assert_eq!(entries[0].path, path);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::epub::{EpubBook, SpineItem};
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+/// Metadata property setting the layout of the whole book
+pub const LAYOUT_PROPERTY: &str = "rendition:layout";
+
+static VIEWPORT_META: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<meta\b[^>]*\bname\s*=\s*["']viewport["'][^>]*>"#).expect("valid regex")
+});
+static HEAD_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)<head\b[^>]*>").expect("valid regex"));
+static CONTENT_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)\bcontent\s*=\s*["']([^"']*)["']"#).expect("valid regex"));
+
+/// `rendition:layout` of a book or a single spine item
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenditionLayout {
+    #[default]
+    Reflowable,
+    PrePaginated,
+}
+
+/// The initial containing block of a fixed-layout page, in CSS pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A fixed-layout page of the spine
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FixedLayoutPage {
+    pub index: usize,
+    pub idref: String,
+    /// ZIP path of the content document
+    pub path: String,
+    pub media_type: String,
+    /// `page-spread-left`, `page-spread-right` or `page-spread-center`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spread: Option<String>,
+    /// Viewport declared by the page, `None` for pages without one and for
+    /// SVG pages, which size themselves with `viewBox`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub viewport: Option<Viewport>,
+}
+
+impl std::fmt::Display for RenditionLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenditionLayout::Reflowable => write!(f, "reflowable"),
+            RenditionLayout::PrePaginated => write!(f, "pre-paginated"),
+        }
+    }
+}
+
+impl Viewport {
+    /// Parse the `content` of a viewport meta, e.g. `width=1200, height=1600`.
+    ///
+    /// Both dimensions must be given in pixels; `device-width` and scale
+    /// settings do not make a fixed-layout viewport.
+    pub fn parse_meta_content(content: &str) -> Option<Self> {
+        let mut width = None;
+        let mut height = None;
+        for setting in content.split([',', ';']) {
+            let Some((key, value)) = setting.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_end_matches("px").parse().ok();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "width" => width = value,
+                "height" => height = value,
+                _ => {}
+            }
+        }
+        Some(Self {
+            width: width.filter(|w| *w > 0)?,
+            height: height.filter(|h| *h > 0)?,
+        })
+    }
+
+    /// The `content` of a viewport meta for this viewport
+    pub fn meta_content(&self) -> String {
+        format!("width={}, height={}", self.width, self.height)
+    }
+}
+
+impl std::fmt::Display for Viewport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl std::str::FromStr for Viewport {
+    type Err = String;
+
+    /// Parse `WIDTHxHEIGHT`, e.g. `1200x1600`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid viewport '{s}' (expected WIDTHxHEIGHT, e.g. 1200x1600)");
+        let (width, height) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+        let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { width, height })
+    }
+}
+
+/// The viewport an XHTML page declares in its `<meta name="viewport">`
+pub fn read_viewport(xhtml: &str) -> Option<Viewport> {
+    let meta = VIEWPORT_META.find(xhtml)?;
+    let content = CONTENT_ATTR.captures(meta.as_str())?;
+    Viewport::parse_meta_content(&content[1])
+}
+
+/// Add a viewport meta to the `<head>` of a page that lacks one
+pub fn insert_viewport(xhtml: &str, viewport: Viewport) -> Option<String> {
+    if VIEWPORT_META.is_match(xhtml) {
+        return None;
+    }
+    let end = HEAD_TAG.find(xhtml)?.end();
+    Some(format!(
+        "{}\n  <meta name=\"viewport\" content=\"{}\"/>{}",
+        &xhtml[..end],
+        viewport.meta_content(),
+        &xhtml[end..]
+    ))
+}
+
+impl SpineItem {
+    /// Whether the itemref lists `property` in its `properties`
+    pub fn has_property(&self, property: &str) -> bool {
+        self.properties
+            .as_deref()
+            .is_some_and(|p| p.split_whitespace().any(|p| p == property))
+    }
+
+    /// The `page-spread-*` property of the itemref, if any
+    pub fn page_spread(&self) -> Option<&str> {
+        self.properties.as_deref().and_then(|p| {
+            p.split_whitespace()
+                .find(|p| p.starts_with("page-spread-") || p.starts_with("rendition:page-spread-"))
+        })
+    }
+}
+
+impl EpubBook {
+    /// Layout of the book as a whole, from the `rendition:layout` metadata
+    pub fn layout(&self) -> RenditionLayout {
+        match self.metadata.custom.get(LAYOUT_PROPERTY).map(|v| v.trim()) {
+            Some("pre-paginated") => RenditionLayout::PrePaginated,
+            _ => RenditionLayout::Reflowable,
+        }
+    }
+
+    /// Layout of one spine item: its `rendition:layout-*` property, else the
+    /// layout of the book
+    pub fn spine_layout(&self, item: &SpineItem) -> RenditionLayout {
+        if item.has_property("rendition:layout-pre-paginated") {
+            RenditionLayout::PrePaginated
+        } else if item.has_property("rendition:layout-reflowable") {
+            RenditionLayout::Reflowable
+        } else {
+            self.layout()
+        }
+    }
+
+    /// Whether the book or any of its spine items is pre-paginated
+    pub fn has_fixed_layout(&self) -> bool {
+        self.spine
+            .iter()
+            .any(|item| self.spine_layout(item) == RenditionLayout::PrePaginated)
+    }
+
+    /// The pre-paginated spine items with their spreads and viewports
+    pub fn fixed_layout_pages(&self) -> Vec<FixedLayoutPage> {
+        self.spine
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| self.spine_layout(item) == RenditionLayout::PrePaginated)
+            .filter_map(|(index, item)| {
+                let manifest_item = self.manifest.iter().find(|m| m.id == item.idref)?;
+                let path = self.resource_key(&manifest_item.href);
+                let viewport = if manifest_item.media_type == "application/xhtml+xml" {
+                    self.resources
+                        .get_string(&path)
+                        .and_then(|xhtml| read_viewport(&xhtml))
+                } else {
+                    None
+                };
+                Some(FixedLayoutPage {
+                    index,
+                    idref: item.idref.clone(),
+                    path,
+                    media_type: manifest_item.media_type.clone(),
+                    spread: item.page_spread().map(str::to_string),
+                    viewport,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_viewport_meta() {
+        let xhtml = r#"<html><head>
+  <meta name="viewport" content="width=1200, height=1600"/>
+</head><body/></html>"#;
+        assert_eq!(
+            read_viewport(xhtml),
+            Some(Viewport {
+                width: 1200,
+                height: 1600
+            })
+        );
+    }
+
+    #[test]
+    fn parse_viewport_meta_attribute_order() {
+        let xhtml = r#"<head><meta content="height=800;width=600px" name='viewport'></head>"#;
+        assert_eq!(
+            read_viewport(xhtml),
+            Some(Viewport {
+                width: 600,
+                height: 800
+            })
+        );
+    }
+
+    #[test]
+    fn device_width_is_not_a_fixed_viewport() {
+        let xhtml = r#"<meta name="viewport" content="width=device-width, initial-scale=1"/>"#;
+        assert_eq!(read_viewport(xhtml), None);
+    }
+
+    #[test]
+    fn parse_viewport_argument() {
+        let viewport: Viewport = "1200x1600".parse().unwrap();
+        assert_eq!(viewport.to_string(), "1200x1600");
+        assert_eq!(viewport.meta_content(), "width=1200, height=1600");
+        assert!("1200".parse::<Viewport>().is_err());
+        assert!("0x10".parse::<Viewport>().is_err());
+    }
+
+    #[test]
+    fn insert_viewport_into_head() {
+        let viewport = Viewport {
+            width: 10,
+            height: 20,
+        };
+        let xhtml = insert_viewport("<html><head><title/></head></html>", viewport).unwrap();
+        assert_eq!(read_viewport(&xhtml), Some(viewport));
+        assert_eq!(insert_viewport(&xhtml, viewport), None);
+    }
+
+    #[test]
+    fn spine_item_overrides_book_layout() {
+        let mut book = EpubBook::default();
+        book.metadata
+            .custom
+            .insert(LAYOUT_PROPERTY.to_string(), "pre-paginated".to_string());
+        let page = SpineItem {
+            idref: "p1".to_string(),
+            properties: Some("page-spread-left".to_string()),
+            ..Default::default()
+        };
+        let text = SpineItem {
+            idref: "t1".to_string(),
+            properties: Some("rendition:layout-reflowable".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(book.spine_layout(&page), RenditionLayout::PrePaginated);
+        assert_eq!(book.spine_layout(&text), RenditionLayout::Reflowable);
+        assert_eq!(page.page_spread(), Some("page-spread-left"));
+        assert_eq!(text.page_spread(), None);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::{EpxError, Result};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use serde::Serialize;
+use std::io::{Read, Seek};
+use zip::ZipArchive;
+
+pub const CONTAINER_PATH: &str = "META-INF/container.xml";
+pub const SIGNATURES_PATH: &str = "META-INF/signatures.xml";
+pub const DISPLAY_OPTIONS_PATH: &str = "META-INF/com.apple.ibooks.display-options.xml";
+
+/// Files under `META-INF/` other than `container.xml`.
+///
+/// `encryption.xml`, `signatures.xml`, `rights.xml`, `metadata.xml`,
+/// `manifest.xml` and vendor files are kept as opaque bytes and written back
+/// unchanged.
+#[derive(Debug, Default, Clone)]
+pub struct MetaInf {
+    pub files: Vec<MetaInfFile>,
+}
+
+/// A single preserved `META-INF/` entry
+#[derive(Debug, Clone)]
+pub struct MetaInfFile {
+    /// Full ZIP path, e.g. `META-INF/encryption.xml`
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Apple Books display options (`com.apple.ibooks.display-options.xml`)
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DisplayOptions {
+    pub platforms: Vec<DisplayPlatform>,
+}
+
+/// Options for one platform, `*` meaning all platforms
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct DisplayPlatform {
+    pub name: String,
+    pub options: Vec<DisplayOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisplayOption {
+    pub name: String,
+    pub value: String,
+}
+
+impl MetaInf {
+    pub fn get(&self, path: &str) -> Option<&[u8]> {
+        self.files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.data.as_slice())
+    }
+
+    /// Whether the package carries digital signatures
+    pub fn has_signatures(&self) -> bool {
+        self.get(SIGNATURES_PATH).is_some()
+    }
+
+    /// Parse the Apple Books display options file, if present
+    pub fn display_options(&self) -> Option<Result<DisplayOptions>> {
+        let data = self.get(DISPLAY_OPTIONS_PATH)?;
+        Some(
+            std::str::from_utf8(data)
+                .map_err(|e| {
+                    EpxError::InvalidEpub(format!("invalid UTF-8 in display options: {e}"))
+                })
+                .and_then(parse_display_options),
+        )
+    }
+}
+
+/// Read every `META-INF/` entry except `container.xml`, in archive order
+pub fn read_meta_inf<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<MetaInf> {
+    let mut meta_inf = MetaInf::default();
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let path = file.name().to_string();
+        if file.is_dir() || !path.starts_with("META-INF/") || path == CONTAINER_PATH {
+            continue;
+        }
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        meta_inf.files.push(MetaInfFile { path, data });
+    }
+    Ok(meta_inf)
+}
+
+/// Parse `com.apple.ibooks.display-options.xml`
+pub fn parse_display_options(xml: &str) -> Result<DisplayOptions> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut options = DisplayOptions::default();
+    let mut current_option: Option<DisplayOption> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e))
+                if e.local_name().as_ref() == b"platform" =>
+            {
+                options.platforms.push(DisplayPlatform {
+                    name: attr_value(e, b"name").unwrap_or_else(|| "*".to_string()),
+                    options: Vec::new(),
+                });
+            }
+            Ok(Event::Start(ref e)) if e.local_name().as_ref() == b"option" => {
+                current_option = Some(DisplayOption {
+                    name: attr_value(e, b"name").unwrap_or_default(),
+                    value: String::new(),
+                });
+            }
+            Ok(Event::Text(ref e)) => {
+                if let Some(ref mut option) = current_option {
+                    option
+                        .value
+                        .push_str(e.unescape().unwrap_or_default().trim());
+                }
+            }
+            Ok(Event::End(ref e)) if e.local_name().as_ref() == b"option" => {
+                if let Some(option) = current_option.take() {
+                    // Options outside a <platform> apply to all platforms
+                    if options.platforms.is_empty() {
+                        options.platforms.push(DisplayPlatform {
+                            name: "*".to_string(),
+                            options: Vec::new(),
+                        });
+                    }
+                    if let Some(platform) = options.platforms.last_mut() {
+                        platform.options.push(option);
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(EpxError::Xml(e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(options)
+}
+
+fn attr_value(e: &quick_xml::events::BytesStart, key: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key.as_ref() == key)
+        .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_display_options_platforms() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<display_options>
+  <platform name="*">
+    <option name="specified-fonts">true</option>
+  </platform>
+  <platform name="iphone">
+    <option name="orientation-lock">landscape-only</option>
+  </platform>
+</display_options>"#;
+        let options = parse_display_options(xml).unwrap();
+        assert_eq!(options.platforms.len(), 2);
+        assert_eq!(options.platforms[0].name, "*");
+        assert_eq!(options.platforms[0].options[0].name, "specified-fonts");
+        assert_eq!(options.platforms[0].options[0].value, "true");
+        assert_eq!(options.platforms[1].options[0].value, "landscape-only");
+    }
+
+    #[test]
+    fn parse_display_options_without_platform() {
+        let xml = r#"<display_options><option name="fixed-layout">true</option></display_options>"#;
+        let options = parse_display_options(xml).unwrap();
+        assert_eq!(options.platforms.len(), 1);
+        assert_eq!(options.platforms[0].name, "*");
+        assert_eq!(options.platforms[0].options[0].name, "fixed-layout");
+    }
+
+    #[test]
+    fn meta_inf_lookup() {
+        let meta_inf = MetaInf {
+            files: vec![MetaInfFile {
+                path: SIGNATURES_PATH.to_string(),
+                data: b"<signatures/>".to_vec(),
+            }],
+        };
+        assert!(meta_inf.has_signatures());
+        assert!(meta_inf.display_options().is_none());
+    }
+}
@@ -1,10 +1,24 @@
+pub mod accessibility;
 pub mod container;
+pub mod encoding;
+pub mod encryption;
+pub mod fixed_layout;
+pub mod meta_inf;
 pub mod navigation;
 pub mod opf;
 pub mod reader;
+pub mod references;
+pub mod repair;
+pub mod resources;
+pub mod smil;
+pub mod url;
 pub mod writer;
 pub mod zip_utils;
 
+use container::Rendition;
+use encryption::FontObfuscation;
+use meta_inf::MetaInf;
+use resources::Resources;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -15,16 +29,50 @@ pub struct EpubBook {
     pub manifest: Vec<ManifestItem>,
     pub spine: Vec<SpineItem>,
     pub navigation: Navigation,
-    pub resources: HashMap<String, Vec<u8>>,
+    pub opf_extras: OpfExtras,
+    pub resources: Resources,
+    /// Preserved `META-INF/` files besides `container.xml` and `encryption.xml`
+    pub meta_inf: MetaInf,
+    /// Every rendition `container.xml` lists, including the one this book
+    /// was read from. Empty for books built in memory.
+    pub renditions: Vec<Rendition>,
+    /// Fonts to obfuscate on write, keyed by resource path.
+    ///
+    /// Resources hold the plain font data; the writer re-obfuscates with the
+    /// key of the package it writes and generates `encryption.xml`.
+    pub obfuscated_fonts: HashMap<String, FontObfuscation>,
+    /// The package as read from disk, `None` for books built in memory
+    pub source: Option<SourcePackage>,
+    /// Problems a lenient read worked around; writing the book fixes them
+    pub repairs: Vec<String>,
+}
+
+/// Layout and parsed state of a package as it was read.
+///
+/// In-place edits use this to write back to the original paths and to tell
+/// which parts of the book were actually changed.
+#[derive(Debug, Default, Clone)]
+pub struct SourcePackage {
+    /// Full ZIP path of the OPF, as given by `container.xml`
+    pub opf_path: String,
+    pub version: EpubVersion,
+    pub unique_identifier: Option<String>,
+    pub metadata: EpubMetadata,
+    pub manifest: Vec<ManifestItem>,
+    pub spine: Vec<SpineItem>,
+    pub opf_extras: OpfExtras,
+    pub navigation: Navigation,
 }
 
 /// Dublin Core metadata fields
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct EpubMetadata {
     pub identifiers: Vec<String>,
-    pub titles: Vec<String>,
+    pub titles: Vec<Title>,
     pub languages: Vec<String>,
-    pub creators: Vec<String>,
+    pub creators: Vec<Contributor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contributors: Vec<Contributor>,
     pub publishers: Vec<String>,
     pub dates: Vec<String>,
     pub description: Option<String>,
@@ -34,28 +82,188 @@ pub struct EpubMetadata {
     pub cover_id: Option<String>,
     #[serde(default)]
     pub custom: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Accessibility::is_empty")]
+    pub accessibility: Accessibility,
+}
+
+/// schema.org accessibility metadata and the conformance claim
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Accessibility {
+    /// `schema:accessMode`, e.g. `textual`, `visual`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub access_modes: Vec<String>,
+    /// `schema:accessModeSufficient`, each a comma-separated set of modes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub access_modes_sufficient: Vec<String>,
+    /// `schema:accessibilityFeature`, e.g. `alternativeText`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// `schema:accessibilityHazard`, e.g. `none`, `flashing`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hazards: Vec<String>,
+    /// `schema:accessibilitySummary`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// `dcterms:conformsTo`, e.g. `EPUB Accessibility 1.1 - WCAG 2.1 Level AA`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conforms_to: Vec<String>,
+    /// `a11y:certifiedBy`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certified_by: Option<String>,
+}
+
+/// A `dc:title` with its EPUB 3 refinements.
+///
+/// Serializes as a plain string when nothing but the value is set.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct Title {
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// `title-type`: main, subtitle, short, collection, edition or expanded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_as: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_script: Vec<AlternateScript>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_seq: Option<u32>,
+}
+
+/// A `dc:creator` or `dc:contributor` with its refinements.
+///
+/// Serializes as a plain string when nothing but the name is set.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct Contributor {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// MARC relator codes, e.g. `aut`, `edt`, `ill`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Sort name (`file-as`), e.g. "Carroll, Lewis"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_as: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternate_script: Vec<AlternateScript>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_seq: Option<u32>,
+    /// Attributes of the `role` and `file-as` refinements as read, e.g.
+    /// `id` or `xml:lang`, matched by property and text. Refinements
+    /// written with the default attributes are not listed.
+    #[serde(skip)]
+    pub refinement_attributes: Vec<ElementAttributes>,
+}
+
+/// A name or title rendered in another language or script
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlternateScript {
+    pub lang: String,
+    pub value: String,
 }
 
 /// An item in the EPUB manifest
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct ManifestItem {
     pub id: String,
     pub href: String,
     pub media_type: String,
     pub properties: Option<String>,
+    /// ID of the fallback item for foreign resources
+    pub fallback: Option<String>,
+    /// ID of the SMIL media overlay for this item
+    pub media_overlay: Option<String>,
+    /// Attributes without a field, e.g. vendor `data-*` ones, written back
+    /// as is
+    pub attributes: Vec<(String, String)>,
 }
 
 /// A spine item reference
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SpineItem {
     pub idref: String,
     pub linear: bool,
     pub properties: Option<String>,
+    pub id: Option<String>,
+    /// Attributes without a field, written back as is
+    pub attributes: Vec<(String, String)>,
+}
+
+/// Package document content without a dedicated model.
+///
+/// Kept so regenerating the OPF does not drop what the parser skipped over.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OpfExtras {
+    /// `<package>` attributes besides `version` and `unique-identifier`,
+    /// e.g. `prefix`, `xml:lang` and `dir`
+    pub package_attributes: Vec<(String, String)>,
+    /// ID referenced by `package@unique-identifier`
+    pub unique_identifier_id: Option<String>,
+    /// `<metadata>` attributes, mostly namespace declarations
+    pub metadata_attributes: Vec<(String, String)>,
+    /// Attributes of modelled metadata elements that have no field, e.g.
+    /// `opf:scheme` on an identifier
+    pub element_attributes: Vec<ElementAttributes>,
+    /// Unmodelled metadata elements as raw XML, in document order
+    pub metadata_elements: Vec<String>,
+    pub page_progression_direction: Option<String>,
+    /// `<spine>` attributes besides `toc` and `page-progression-direction`
+    pub spine_attributes: Vec<(String, String)>,
+    /// Package children after `<spine>`, e.g. `<guide>`, `<bindings>` and
+    /// `<collection>`, as raw XML
+    pub trailing_elements: Vec<String>,
+    /// Whether the package has a `<guide>`; one is only added to a package
+    /// without when the landmarks change
+    pub has_guide: bool,
+    /// XML comments, with where they are written back
+    pub comments: Vec<OpfComment>,
+}
+
+/// An XML comment of the package document
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpfComment {
+    pub position: CommentPosition,
+    /// The comment as written, e.g. `<!-- cover -->`
+    pub text: String,
+}
+
+/// Where a comment of the package document goes when the OPF is
+/// regenerated. Comments before an element follow it around; those next to
+/// regenerated markup stay in its section.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommentPosition {
+    /// Before `<package>`
+    Prolog,
+    /// Before a child of `<package>`, by local name, e.g. `manifest`
+    Before(String),
+    /// In `<metadata>`, whose elements are written in an order of their own;
+    /// kept at its start
+    Metadata,
+    /// Before the manifest item with this ID
+    Item(String),
+    /// Before the spine itemref with this idref
+    ItemRef(String),
+    /// Before the end tag of `<package>` or one of its children, by local
+    /// name
+    End(String),
+    /// After `</package>`
+    Epilogue,
+}
+
+/// Extra attributes of one metadata element, matched by element and text
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementAttributes {
+    /// Local name, e.g. `identifier`
+    pub element: String,
+    pub value: String,
+    pub attributes: Vec<(String, String)>,
 }
 
 /// Navigation structure
-#[derive(Debug, Default)]
-#[allow(dead_code)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Navigation {
     pub toc: Vec<NavPoint>,
     pub landmarks: Vec<NavPoint>,
@@ -63,27 +271,287 @@ pub struct Navigation {
     pub epub_version: EpubVersion,
 }
 
-/// A navigation point in the TOC tree
-#[derive(Debug, Clone)]
+/// A navigation point in the TOC tree, landmarks or page list
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct NavPoint {
     pub label: String,
     pub href: String,
     pub children: Vec<NavPoint>,
+    /// `epub:type` of a landmark, such as `cover` or `bodymatter`
+    pub epub_type: Option<String>,
 }
 
 /// EPUB version
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum EpubVersion {
     V2,
     #[default]
     V3,
 }
 
+impl Default for SpineItem {
+    fn default() -> Self {
+        Self {
+            idref: String::new(),
+            linear: true,
+            properties: None,
+            id: None,
+            attributes: Vec::new(),
+        }
+    }
+}
+
+impl OpfExtras {
+    /// Preserved attributes of the metadata element `element` with text `value`
+    pub fn attributes_for(&self, element: &str, value: &str) -> &[(String, String)] {
+        self.element_attributes
+            .iter()
+            .find(|e| e.element == element && e.value == value)
+            .map_or(&[], |e| e.attributes.as_slice())
+    }
+}
+
+impl ManifestItem {
+    /// Whether this item is the EPUB 3 navigation document
+    pub fn is_nav(&self) -> bool {
+        self.properties
+            .as_deref()
+            .is_some_and(|p| p.split_whitespace().any(|p| p == "nav"))
+    }
+
+    /// Whether this item is a font
+    pub fn is_font(&self) -> bool {
+        self.media_type.contains("font") || self.media_type == "application/vnd.ms-opentype"
+    }
+
+    /// Whether this item is an NCX table of contents
+    pub fn is_ncx(&self) -> bool {
+        self.media_type == "application/x-dtbncx+xml"
+    }
+
+    /// Add `property` to the item's properties; returns `false` if it was set
+    pub fn add_property(&mut self, property: &str) -> bool {
+        match &mut self.properties {
+            Some(properties) if properties.split_whitespace().any(|p| p == property) => false,
+            Some(properties) if !properties.trim().is_empty() => {
+                properties.push(' ');
+                properties.push_str(property);
+                true
+            }
+            properties => {
+                *properties = Some(property.to_string());
+                true
+            }
+        }
+    }
+
+    /// Remove `property` from the item's properties, dropping them when none
+    /// are left
+    pub fn remove_property(&mut self, property: &str) {
+        if let Some(properties) = &self.properties {
+            let rest: Vec<&str> = properties
+                .split_whitespace()
+                .filter(|p| *p != property)
+                .collect();
+            self.properties = (!rest.is_empty()).then(|| rest.join(" "));
+        }
+    }
+}
+
+/// `base`, or `base-2`, `base-3`... whichever is not `taken` yet
+pub fn unique_id(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut id = base.to_string();
+    let mut n = 2;
+    while taken(&id) {
+        id = format!("{base}-{n}");
+        n += 1;
+    }
+    id
+}
+
+impl EpubMetadata {
+    /// The main title: the first one typed `main`, else the first one
+    pub fn title(&self) -> Option<&str> {
+        self.main_title_index()
+            .map(|index| self.titles[index].value.as_str())
+    }
+
+    pub fn main_title_index(&self) -> Option<usize> {
+        self.titles
+            .iter()
+            .position(|t| t.title_type.as_deref() == Some("main"))
+            .or((!self.titles.is_empty()).then_some(0))
+    }
+
+    /// Creator names in document order
+    pub fn creator_names(&self) -> Vec<&str> {
+        self.creators.iter().map(|c| c.name.as_str()).collect()
+    }
+}
+
+impl Title {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether only the value is set
+    pub fn is_plain(&self) -> bool {
+        *self == Self::new(self.value.clone())
+    }
+}
+
+impl Contributor {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether only the name is set
+    pub fn is_plain(&self) -> bool {
+        *self == Self::new(self.name.clone())
+    }
+
+    /// Attributes of the `property` refinement with text `value` as read,
+    /// if they were not the default ones
+    pub fn refinement_attributes_for(
+        &self,
+        property: &str,
+        value: &str,
+    ) -> Option<&[(String, String)]> {
+        self.refinement_attributes
+            .iter()
+            .find(|e| e.element == property && e.value == value)
+            .map(|e| e.attributes.as_slice())
+    }
+}
+
+// Plain entries serialize as a bare string; the derived (remote) impls
+// handle the full map form.
+
+impl Serialize for Title {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.is_plain() {
+            s.serialize_str(&self.value)
+        } else {
+            Title::serialize(self, s)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Title {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Plain(String),
+            Full(#[serde(with = "Title")] Title),
+        }
+        Ok(match Repr::deserialize(d)? {
+            Repr::Plain(value) => Title::new(value),
+            Repr::Full(title) => title,
+        })
+    }
+}
+
+impl Serialize for Contributor {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.is_plain() {
+            s.serialize_str(&self.name)
+        } else {
+            Contributor::serialize(self, s)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Contributor {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Plain(String),
+            Full(#[serde(with = "Contributor")] Contributor),
+        }
+        Ok(match Repr::deserialize(d)? {
+            Repr::Plain(name) => Contributor::new(name),
+            Repr::Full(contributor) => contributor,
+        })
+    }
+}
+
+impl SourcePackage {
+    /// Directory prefix of the OPF, with a trailing slash unless empty
+    pub fn opf_dir(&self) -> &str {
+        match self.opf_path.rfind('/') {
+            Some(idx) => &self.opf_path[..=idx],
+            None => "",
+        }
+    }
+}
+
 impl EpubBook {
+    /// The version of the package the book was read from, or of its
+    /// navigation for books built in memory
+    pub fn version(&self) -> EpubVersion {
+        self.source
+            .as_ref()
+            .map_or(self.navigation.epub_version, |source| source.version)
+    }
+
+    /// Mark every font in the manifest for IDPF obfuscation on write
+    pub fn obfuscate_fonts(&mut self) {
+        let opf_dir = self.detect_opf_dir();
+        for item in self.manifest.iter().filter(|item| item.is_font()) {
+            self.obfuscated_fonts
+                .insert(url::resolve(&opf_dir, &item.href), FontObfuscation::Idpf);
+        }
+    }
+
+    /// Add a manifest item for a nav document, which the writer generates
+    /// from the book's navigation. Returns its href, or `None` if the book
+    /// has a nav document already.
+    pub fn add_nav_item(&mut self) -> Option<String> {
+        if self.manifest.iter().any(|item| item.is_nav()) {
+            return None;
+        }
+        let mut href = "nav.xhtml".to_string();
+        let mut n = 2;
+        while self.resources.contains_key(&self.resource_key(&href))
+            || self.manifest.iter().any(|item| item.href == href)
+        {
+            href = format!("nav-{n}.xhtml");
+            n += 1;
+        }
+        let id = unique_id("nav", |id| self.manifest.iter().any(|item| item.id == id));
+        self.manifest.push(ManifestItem {
+            id,
+            href: href.clone(),
+            media_type: "application/xhtml+xml".to_string(),
+            properties: Some("nav".to_string()),
+            ..Default::default()
+        });
+        Some(href)
+    }
+
+    /// ZIP path of the resource an href from the OPF points at.
+    ///
+    /// Manifest, spine and navigation hrefs are all relative to the OPF.
+    pub fn resource_key(&self, href: &str) -> String {
+        url::resolve(&self.detect_opf_dir(), href)
+    }
+
     /// Detect the OPF directory prefix from loaded resources.
     ///
-    /// Checks for a `.opf` file first, then falls back to common prefixes.
+    /// Uses the OPF path the book was read from when known, otherwise checks
+    /// for a `.opf` file first, then falls back to common prefixes.
     pub fn detect_opf_dir(&self) -> String {
+        if let Some(source) = &self.source {
+            return source.opf_dir().to_string();
+        }
         for key in self.resources.keys() {
             if key.ends_with(".opf") {
                 if let Some(idx) = key.rfind('/') {
@@ -111,6 +579,19 @@ impl std::fmt::Display for EpubVersion {
     }
 }
 
+impl std::str::FromStr for EpubVersion {
+    type Err = String;
+
+    /// Parse "2", "2.0", "2.0.1", "3", "3.0" and the like
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split('.').next() {
+            Some("2") => Ok(EpubVersion::V2),
+            Some("3") => Ok(EpubVersion::V3),
+            _ => Err(format!("unsupported EPUB version '{s}' (expected 2 or 3)")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,58 +1,131 @@
-use crate::epub::{EpubVersion, ManifestItem, NavPoint, Navigation};
+use crate::epub::{EpubVersion, ManifestItem, NavPoint, Navigation, url};
 use crate::error::{EpxError, Result};
 use quick_xml::Reader;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 
 /// Try to parse navigation from manifest items and content.
 /// Prefers EPUB 3 nav.xhtml, falls back to NCX.
+///
+/// `get_content` is called with ZIP paths. Hrefs in the result are rebased
+/// from the navigation document to the OPF at `opf_path`, so like manifest
+/// hrefs they are relative to the OPF.
 pub fn parse_navigation(
     manifest: &[ManifestItem],
+    opf_path: &str,
     get_content: &dyn Fn(&str) -> Option<String>,
 ) -> Result<Navigation> {
+    let ncx_path = manifest
+        .iter()
+        .find(|item| item.is_ncx())
+        .map(|item| url::resolve(opf_path, &item.href));
+    let ncx = ncx_path.as_deref().and_then(get_content);
+
     // Try EPUB 3 nav.xhtml first
-    if let Some(nav_item) = manifest.iter().find(|item| {
-        item.properties
-            .as_deref()
-            .is_some_and(|p| p.contains("nav"))
-    }) && let Some(content) = get_content(&nav_item.href)
-        && let Ok(nav) = parse_nav_xhtml(&content)
-    {
-        return Ok(Navigation {
-            epub_version: EpubVersion::V3,
-            ..nav
-        });
+    if let Some(nav_item) = manifest.iter().find(|item| item.is_nav()) {
+        let nav_path = url::resolve(opf_path, &nav_item.href);
+        if let Some(content) = get_content(&nav_path)
+            && let Ok(mut nav) = parse_nav_xhtml(&content)
+        {
+            rebase_navigation(&mut nav, &nav_path, opf_path);
+            // Books made for both versions sometimes only map print pages in
+            // the NCX
+            if nav.page_list.is_empty()
+                && let (Some(ncx), Some(ncx_path)) = (&ncx, &ncx_path)
+                && let Ok((_, mut page_list)) = parse_ncx(ncx)
+            {
+                map_points(&mut page_list, &|href| {
+                    url::rebase(href, ncx_path, opf_path)
+                });
+                nav.page_list = page_list;
+            }
+            return Ok(Navigation {
+                epub_version: EpubVersion::V3,
+                ..nav
+            });
+        }
     }
 
     // Fall back to NCX
-    if let Some(ncx_item) = manifest
-        .iter()
-        .find(|item| item.media_type == "application/x-dtbncx+xml")
-        && let Some(content) = get_content(&ncx_item.href)
-    {
-        let toc = parse_ncx(&content)?;
-        return Ok(Navigation {
+    if let (Some(content), Some(ncx_path)) = (ncx, ncx_path) {
+        let (toc, page_list) = parse_ncx(&content)?;
+        let mut nav = Navigation {
             toc,
             landmarks: Vec::new(),
-            page_list: Vec::new(),
+            page_list,
             epub_version: EpubVersion::V2,
-        });
+        };
+        rebase_navigation(&mut nav, &ncx_path, opf_path);
+        return Ok(nav);
     }
 
     Ok(Navigation::default())
 }
 
+/// Rebase every href of the navigation from the document at `from` to the
+/// document at `to`, see [`url::rebase`]
+pub(crate) fn rebase_navigation(navigation: &mut Navigation, from: &str, to: &str) {
+    map_hrefs(navigation, &|href| url::rebase(href, from, to));
+}
+
+/// Replace every href of the navigation with `f(href)`
+pub(crate) fn map_hrefs(navigation: &mut Navigation, f: &dyn Fn(&str) -> String) {
+    for points in [
+        &mut navigation.toc,
+        &mut navigation.landmarks,
+        &mut navigation.page_list,
+    ] {
+        map_points(points, f);
+    }
+}
+
+fn map_points(points: &mut [NavPoint], f: &dyn Fn(&str) -> String) {
+    for point in points {
+        if !point.href.is_empty() {
+            point.href = f(&point.href);
+        }
+        map_points(&mut point.children, f);
+    }
+}
+
+/// The `epub:type` attribute of an element, whatever the namespace prefix
+fn epub_type(e: &BytesStart) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|attr| attr.key.prefix().is_some() && attr.key.local_name().as_ref() == b"type")
+        .map(|attr| String::from_utf8_lossy(&attr.value).into_owned())
+}
+
+/// Which list of [`Navigation`] a `<nav>` element fills
+#[derive(Clone, Copy, PartialEq)]
+enum NavKind {
+    Toc,
+    Landmarks,
+    PageList,
+}
+
+impl NavKind {
+    fn from_epub_type(value: &str) -> Option<Self> {
+        value.split_whitespace().find_map(|t| match t {
+            "toc" => Some(Self::Toc),
+            "landmarks" => Some(Self::Landmarks),
+            "page-list" => Some(Self::PageList),
+            _ => None,
+        })
+    }
+}
+
 fn parse_nav_xhtml(html: &str) -> Result<Navigation> {
-    // Simplified parsing: extract nav[epub:type="toc"] list items
-    let mut toc = Vec::new();
+    let mut navigation = Navigation {
+        epub_version: EpubVersion::V3,
+        ..Default::default()
+    };
 
     // Use quick-xml to parse the XHTML
     let mut reader = Reader::from_str(html);
     let mut buf = Vec::new();
-    let mut in_nav_toc = false;
-    let mut depth: usize = 0;
+    let mut current_nav: Option<NavKind> = None;
     let mut stack: Vec<Vec<NavPoint>> = vec![Vec::new()];
-    let mut current_href = String::new();
-    let mut current_label = String::new();
+    let mut current = NavPoint::default();
     let mut in_a = false;
 
     loop {
@@ -60,59 +133,59 @@ fn parse_nav_xhtml(html: &str) -> Result<Navigation> {
             Ok(Event::Start(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
                 if local == "nav" {
-                    for attr in e.attributes().flatten() {
-                        let key = String::from_utf8_lossy(attr.key.as_ref());
-                        if key.ends_with("type") {
-                            let val = String::from_utf8_lossy(&attr.value);
-                            if val == "toc" {
-                                in_nav_toc = true;
-                            }
-                        }
-                    }
-                } else if in_nav_toc {
+                    current_nav = epub_type(e).and_then(|t| NavKind::from_epub_type(&t));
+                    stack = vec![Vec::new()];
+                } else if current_nav.is_some() {
                     if local == "ol" {
-                        depth += 1;
                         stack.push(Vec::new());
                     } else if local == "a" {
                         in_a = true;
-                        current_label.clear();
-                        current_href.clear();
+                        current = NavPoint {
+                            epub_type: epub_type(e),
+                            ..Default::default()
+                        };
                         for attr in e.attributes().flatten() {
                             if attr.key.as_ref() == b"href" {
-                                current_href = String::from_utf8_lossy(&attr.value).into_owned();
+                                current.href = String::from_utf8_lossy(&attr.value).into_owned();
                             }
                         }
                     }
                 }
             }
-            Ok(Event::Text(ref e)) => {
-                if in_a && in_nav_toc {
-                    current_label.push_str(&e.unescape().unwrap_or_default());
-                }
+            Ok(Event::Text(ref e)) if in_a && current_nav.is_some() => {
+                current.label.push_str(&e.unescape().unwrap_or_default());
             }
             Ok(Event::End(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-                if local == "nav" && in_nav_toc {
-                    in_nav_toc = false;
-                } else if in_nav_toc {
+                if local == "nav"
+                    && let Some(kind) = current_nav.take()
+                {
+                    let points = std::mem::take(&mut stack).into_iter().next();
+                    let target = match kind {
+                        NavKind::Toc => &mut navigation.toc,
+                        NavKind::Landmarks => &mut navigation.landmarks,
+                        NavKind::PageList => &mut navigation.page_list,
+                    };
+                    // Only the first nav of each kind counts
+                    if target.is_empty() {
+                        *target = points.unwrap_or_default();
+                    }
+                } else if current_nav.is_some() {
                     if local == "a" {
                         in_a = false;
-                        if let Some(current) = stack.last_mut() {
-                            current.push(NavPoint {
-                                label: current_label.trim().to_string(),
-                                href: current_href.clone(),
-                                children: Vec::new(),
-                            });
+                        if let Some(level) = stack.last_mut() {
+                            let mut point = std::mem::take(&mut current);
+                            point.label = point.label.trim().to_string();
+                            level.push(point);
                         }
                     } else if local == "ol" {
-                        depth = depth.saturating_sub(1);
                         let children = stack.pop().unwrap_or_default();
                         if let Some(parent_list) = stack.last_mut() {
                             if let Some(parent) = parent_list.last_mut() {
                                 parent.children = children;
                             } else {
                                 // Top level
-                                stack.last_mut().unwrap().extend(children);
+                                parent_list.extend(children);
                             }
                         }
                     }
@@ -125,22 +198,15 @@ fn parse_nav_xhtml(html: &str) -> Result<Navigation> {
         buf.clear();
     }
 
-    if let Some(items) = stack.into_iter().next() {
-        toc = items;
-    }
-
-    Ok(Navigation {
-        toc,
-        landmarks: Vec::new(),
-        page_list: Vec::new(),
-        epub_version: EpubVersion::V3,
-    })
+    Ok(navigation)
 }
 
-fn parse_ncx(xml: &str) -> Result<Vec<NavPoint>> {
+/// Parse the NCX `navMap` and `pageList`
+pub(crate) fn parse_ncx(xml: &str) -> Result<(Vec<NavPoint>, Vec<NavPoint>)> {
     let mut reader = Reader::from_str(xml);
     let mut buf = Vec::new();
     let mut nav_points = Vec::new();
+    let mut page_targets = Vec::new();
     let mut stack: Vec<NavPoint> = Vec::new();
     let mut in_text = false;
     let mut current_label = String::new();
@@ -149,12 +215,8 @@ fn parse_ncx(xml: &str) -> Result<Vec<NavPoint>> {
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-                if local == "navPoint" {
-                    stack.push(NavPoint {
-                        label: String::new(),
-                        href: String::new(),
-                        children: Vec::new(),
-                    });
+                if local == "navPoint" || local == "pageTarget" {
+                    stack.push(NavPoint::default());
                 } else if local == "text" {
                     in_text = true;
                     current_label.clear();
@@ -172,10 +234,8 @@ fn parse_ncx(xml: &str) -> Result<Vec<NavPoint>> {
                     }
                 }
             }
-            Ok(Event::Text(ref e)) => {
-                if in_text {
-                    current_label.push_str(&e.unescape().unwrap_or_default());
-                }
+            Ok(Event::Text(ref e)) if in_text => {
+                current_label.push_str(&e.unescape().unwrap_or_default());
             }
             Ok(Event::End(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
@@ -191,6 +251,10 @@ fn parse_ncx(xml: &str) -> Result<Vec<NavPoint>> {
                     } else {
                         nav_points.push(point);
                     }
+                } else if local == "pageTarget"
+                    && let Some(target) = stack.pop()
+                {
+                    page_targets.push(target);
                 }
             }
             Ok(Event::Eof) => break,
@@ -200,7 +264,34 @@ fn parse_ncx(xml: &str) -> Result<Vec<NavPoint>> {
         buf.clear();
     }
 
-    Ok(nav_points)
+    Ok((nav_points, page_targets))
+}
+
+/// Map a `<guide>` reference type to the landmark `epub:type` it stands for
+pub(crate) fn guide_type_to_epub_type(guide_type: &str) -> String {
+    match guide_type {
+        "text" => "bodymatter",
+        "title-page" => "titlepage",
+        "acknowledgements" => "acknowledgments",
+        other => other,
+    }
+    .to_string()
+}
+
+/// Map a landmark `epub:type` to the `<guide>` reference type for EPUB 2
+/// reading systems; types without a guide equivalent become `other.*`
+pub(crate) fn epub_type_to_guide_type(epub_type: &str) -> String {
+    match epub_type {
+        "bodymatter" => "text".to_string(),
+        "titlepage" => "title-page".to_string(),
+        "acknowledgments" => "acknowledgements".to_string(),
+        "cover" | "toc" | "index" | "glossary" | "loi" | "lot" | "preface" | "bibliography"
+        | "colophon" | "copyright-page" | "dedication" | "epigraph" | "foreword" | "notes" => {
+            epub_type.to_string()
+        }
+        other if other.starts_with("other.") => other.to_string(),
+        other => format!("other.{other}"),
+    }
 }
 
 #[cfg(test)]
@@ -227,9 +318,10 @@ mod tests {
             href: "toc.xhtml".to_string(),
             media_type: "application/xhtml+xml".to_string(),
             properties: Some("nav".to_string()),
+            ..Default::default()
         }];
 
-        let nav = parse_navigation(&manifest, &|href| {
+        let nav = parse_navigation(&manifest, "content.opf", &|href| {
             if href == "toc.xhtml" {
                 Some(nav_html.to_string())
             } else {
@@ -244,6 +336,36 @@ mod tests {
         assert_eq!(nav.toc[1].label, "Chapter 2");
     }
 
+    #[test]
+    fn parse_navigation_rebases_hrefs_to_opf() {
+        let nav_html = r##"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+<nav epub:type="toc"><ol>
+<li><a href="../Text/Chapter%201.xhtml#start">Chapter 1</a></li>
+</ol></nav>
+<nav epub:type="landmarks"><ol>
+<li><a epub:type="toc" href="#toc">Contents</a></li>
+</ol></nav>
+</body>
+</html>"##;
+
+        let manifest = vec![ManifestItem {
+            id: "nav".to_string(),
+            href: "Nav/nav.xhtml".to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            properties: Some("nav".to_string()),
+            ..Default::default()
+        }];
+
+        let nav = parse_navigation(&manifest, "OEBPS/content.opf", &|path| {
+            (path == "OEBPS/Nav/nav.xhtml").then(|| nav_html.to_string())
+        })
+        .unwrap();
+
+        assert_eq!(nav.toc[0].href, "Text/Chapter%201.xhtml#start");
+        assert_eq!(nav.landmarks[0].href, "Nav/nav.xhtml#toc");
+    }
+
     #[test]
     fn parse_ncx_basic() {
         let ncx_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -265,9 +387,10 @@ mod tests {
             href: "toc.ncx".to_string(),
             media_type: "application/x-dtbncx+xml".to_string(),
             properties: None,
+            ..Default::default()
         }];
 
-        let nav = parse_navigation(&manifest, &|href| {
+        let nav = parse_navigation(&manifest, "content.opf", &|href| {
             if href == "toc.ncx" {
                 Some(ncx_xml.to_string())
             } else {
@@ -288,9 +411,10 @@ mod tests {
             href: "ch1.xhtml".to_string(),
             media_type: "application/xhtml+xml".to_string(),
             properties: None,
+            ..Default::default()
         }];
 
-        let nav = parse_navigation(&manifest, &|_| None).unwrap();
+        let nav = parse_navigation(&manifest, "content.opf", &|_| None).unwrap();
         assert!(nav.toc.is_empty());
     }
 
@@ -317,9 +441,10 @@ mod tests {
             href: "nav.xhtml".to_string(),
             media_type: "application/xhtml+xml".to_string(),
             properties: Some("nav".to_string()),
+            ..Default::default()
         }];
 
-        let nav = parse_navigation(&manifest, &|href| {
+        let nav = parse_navigation(&manifest, "content.opf", &|href| {
             if href == "nav.xhtml" {
                 Some(nav_html.to_string())
             } else {
@@ -352,17 +477,19 @@ mod tests {
                 href: "nav.xhtml".to_string(),
                 media_type: "application/xhtml+xml".to_string(),
                 properties: Some("nav".to_string()),
+                ..Default::default()
             },
             ManifestItem {
                 id: "ncx".to_string(),
                 href: "toc.ncx".to_string(),
                 media_type: "application/x-dtbncx+xml".to_string(),
                 properties: None,
+                ..Default::default()
             },
         ];
 
         // nav.xhtml content is invalid/missing, so falls back to NCX
-        let nav = parse_navigation(&manifest, &|href| {
+        let nav = parse_navigation(&manifest, "content.opf", &|href| {
             if href == "toc.ncx" {
                 Some(ncx_xml.to_string())
             } else {
@@ -373,4 +500,72 @@ mod tests {
 
         assert_eq!(nav.toc[0].label, "From NCX");
     }
+
+    #[test]
+    fn parse_nav_landmarks_and_page_list() {
+        let nav_html = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+<nav epub:type="toc"><ol><li><a href="ch1.xhtml">Chapter 1</a></li></ol></nav>
+<nav epub:type="landmarks" hidden="">
+<h2>Guide</h2>
+<ol>
+<li><a epub:type="cover" href="cover.xhtml">Cover</a></li>
+<li><a epub:type="bodymatter" href="ch1.xhtml">Start</a></li>
+</ol>
+</nav>
+<nav epub:type="page-list" hidden="">
+<ol>
+<li><a href="ch1.xhtml#p1">1</a></li>
+<li><a href="ch1.xhtml#p2">2</a></li>
+</ol>
+</nav>
+</body>
+</html>"#;
+
+        let nav = parse_nav_xhtml(nav_html).unwrap();
+        assert_eq!(nav.toc.len(), 1);
+        assert!(nav.toc[0].epub_type.is_none());
+        assert_eq!(nav.landmarks.len(), 2);
+        assert_eq!(nav.landmarks[0].epub_type.as_deref(), Some("cover"));
+        assert_eq!(nav.landmarks[1].label, "Start");
+        assert_eq!(nav.page_list.len(), 2);
+        assert_eq!(nav.page_list[1].label, "2");
+        assert_eq!(nav.page_list[1].href, "ch1.xhtml#p2");
+    }
+
+    #[test]
+    fn parse_ncx_page_list() {
+        let ncx_xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+<navMap>
+<navPoint id="np1" playOrder="1">
+  <navLabel><text>Chapter 1</text></navLabel>
+  <content src="ch1.xhtml"/>
+</navPoint>
+</navMap>
+<pageList>
+<navLabel><text>Pages</text></navLabel>
+<pageTarget id="p1" type="normal" value="1" playOrder="2">
+  <navLabel><text>1</text></navLabel>
+  <content src="ch1.xhtml#p1"/>
+</pageTarget>
+</pageList>
+</ncx>"#;
+
+        let (toc, page_list) = parse_ncx(ncx_xml).unwrap();
+        assert_eq!(toc.len(), 1);
+        assert_eq!(page_list.len(), 1);
+        assert_eq!(page_list[0].label, "1");
+        assert_eq!(page_list[0].href, "ch1.xhtml#p1");
+    }
+
+    #[test]
+    fn guide_type_mapping() {
+        assert_eq!(guide_type_to_epub_type("text"), "bodymatter");
+        assert_eq!(guide_type_to_epub_type("cover"), "cover");
+        assert_eq!(epub_type_to_guide_type("bodymatter"), "text");
+        assert_eq!(epub_type_to_guide_type("titlepage"), "title-page");
+        assert_eq!(epub_type_to_guide_type("frontmatter"), "other.frontmatter");
+    }
 }
@@ -1,7 +1,11 @@
-use crate::epub::{EpubMetadata, EpubVersion, ManifestItem, SpineItem};
+use crate::epub::{
+    Accessibility, AlternateScript, CommentPosition, Contributor, ElementAttributes, EpubMetadata,
+    EpubVersion, ManifestItem, NavPoint, OpfComment, OpfExtras, SpineItem, Title, accessibility,
+    navigation,
+};
 use crate::error::{EpxError, Result};
 use quick_xml::Reader;
-use quick_xml::events::Event;
+use quick_xml::events::{BytesStart, Event};
 
 #[allow(dead_code)]
 pub struct OpfData {
@@ -9,159 +13,265 @@ pub struct OpfData {
     pub manifest: Vec<ManifestItem>,
     pub spine: Vec<SpineItem>,
     pub version: EpubVersion,
+    /// Value of the identifier referenced by `package@unique-identifier`
+    pub unique_identifier: Option<String>,
+    pub extras: OpfExtras,
+    /// EPUB 2 `<guide>` references as landmarks
+    pub guide: Vec<NavPoint>,
+}
+
+/// Scheme of the role codes modelled in [`Contributor::roles`]
+pub(crate) const MARC_RELATORS: &str = "marc:relators";
+/// Attributes a `role` refinement is written with unless others were read
+const ROLE_ATTRIBUTES: &[(&str, &str)] = &[("scheme", MARC_RELATORS)];
+
+/// A `<meta refines="#id">` element, applied once all metadata is read
+struct Refinement {
+    target: String,
+    property: String,
+    lang: Option<String>,
+    value: String,
+    /// Attributes besides `refines` and `property`
+    attributes: Vec<(String, String)>,
+    /// The element as written, kept when the refinement is not modelled
+    raw: String,
+}
+
+/// Attributes of the metadata element being read
+#[derive(Default)]
+struct ElementAttrs {
+    id: String,
+    property: String,
+    refines: String,
+    lang: Option<String>,
+    /// EPUB 2 `opf:role`
+    role: Option<String>,
+    /// EPUB 2 `opf:file-as`
+    file_as: Option<String>,
+    all: Vec<(String, String)>,
+}
+
+impl ElementAttrs {
+    fn read(e: &BytesStart) -> Self {
+        let mut attrs = Self {
+            all: attributes(e),
+            ..Default::default()
+        };
+        for (key, value) in &attrs.all {
+            let value = value.clone();
+            match key.as_str() {
+                "id" => attrs.id = value,
+                "property" => attrs.property = value,
+                "refines" => attrs.refines = value,
+                "xml:lang" => attrs.lang = Some(value),
+                "opf:role" => attrs.role = Some(value),
+                "opf:file-as" => attrs.file_as = Some(value),
+                _ => {}
+            }
+        }
+        attrs
+    }
+
+    /// Attributes other than `modelled`
+    fn unmodelled(&self, modelled: &[&str]) -> Vec<(String, String)> {
+        self.all
+            .iter()
+            .filter(|(key, _)| !modelled.contains(&key.as_str()))
+            .cloned()
+            .collect()
+    }
 }
 
 pub fn parse_opf(xml: &str) -> Result<OpfData> {
     let mut reader = Reader::from_str(xml);
     let mut buf = Vec::new();
+    let mut skip_buf = Vec::new();
 
     let mut metadata = EpubMetadata::default();
     let mut manifest = Vec::new();
     let mut spine = Vec::new();
+    let mut guide = Vec::new();
+    let mut extras = OpfExtras::default();
     let mut version = EpubVersion::V3;
+    let mut unique_identifier = None;
 
+    // Element depth: 1 inside <package>, 2 inside <metadata>
+    let mut depth = 0;
     let mut in_metadata = false;
+    let mut in_guide = false;
     let mut current_element = String::new();
     let mut current_text = String::new();
-    let mut current_meta_property = String::new();
+    let mut current = ElementAttrs::default();
+    let mut current_start = 0;
+    let mut refinements = Vec::new();
+    let mut package_seen = false;
+    // Comments waiting for the element they precede
+    let mut pending_comments: Vec<String> = Vec::new();
 
     loop {
+        let event_start = reader.buffer_position() as usize;
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                depth += 1;
 
-                if local == "package" {
-                    for attr in e.attributes().flatten() {
-                        if attr.key.as_ref() == b"version" {
-                            let v = String::from_utf8_lossy(&attr.value);
-                            version = if v.starts_with('2') {
-                                EpubVersion::V2
-                            } else {
-                                EpubVersion::V3
-                            };
-                        }
-                    }
-                } else if local == "metadata" {
+                if depth == 2 && PACKAGE_CHILDREN.contains(&local.as_str()) {
+                    let position = CommentPosition::Before(local.clone());
+                    place_comments(&mut extras, &mut pending_comments, position);
+                }
+                if depth == 1 && local == "package" {
+                    package_seen = true;
+                    read_package_attributes(e, &mut version, &mut extras);
+                } else if depth == 2 && local == "metadata" {
                     in_metadata = true;
-                } else if in_metadata {
-                    current_element = local.clone();
+                    extras.metadata_attributes = attributes(e);
+                } else if depth == 2 && local == "spine" {
+                    read_spine_attributes(e, &mut extras);
+                } else if depth == 2 && local == "guide" {
+                    in_guide = true;
+                    extras.has_guide = true;
+                } else if in_guide && local == "reference" {
+                    guide.push(read_guide_reference(e));
+                } else if depth == 2 && local != "manifest" {
+                    // <bindings>, <collection>: kept verbatim
+                    reader.read_to_end_into(e.name(), &mut skip_buf)?;
+                    depth -= 1;
+                    let end = reader.buffer_position() as usize;
+                    extras
+                        .trailing_elements
+                        .push(with_comments(&mut pending_comments, &xml[event_start..end]));
+                } else if in_metadata && depth == 3 {
+                    current_element = local;
                     current_text.clear();
-                    current_meta_property.clear();
-                    if local == "meta" {
-                        for attr in e.attributes().flatten() {
-                            if attr.key.as_ref() == b"property" {
-                                current_meta_property =
-                                    String::from_utf8_lossy(&attr.value).into_owned();
-                            }
-                        }
-                    }
+                    current = ElementAttrs::read(e);
+                    current_start = event_start;
                 }
             }
             Ok(Event::End(ref e)) => {
-                let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
-
-                if local == "metadata" {
+                depth -= 1;
+                if depth <= 1 {
+                    let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                    let position = CommentPosition::End(local);
+                    place_comments(&mut extras, &mut pending_comments, position);
+                }
+                if in_metadata && depth == 1 {
                     in_metadata = false;
-                } else if in_metadata && !current_text.is_empty() {
+                } else if in_guide && depth == 1 {
+                    in_guide = false;
+                } else if in_metadata && depth == 2 {
+                    let raw = &xml[current_start..reader.buffer_position() as usize];
                     let text = current_text.trim().to_string();
-                    match current_element.as_str() {
-                        "identifier" => metadata.identifiers.push(text),
-                        "title" => metadata.titles.push(text),
-                        "language" => metadata.languages.push(text),
-                        "creator" => metadata.creators.push(text),
-                        "publisher" => metadata.publishers.push(text),
-                        "date" => metadata.dates.push(text),
-                        "description" => metadata.description = Some(text),
-                        "subject" => metadata.subjects.push(text),
-                        "rights" => metadata.rights = Some(text),
-                        "meta" if !current_meta_property.is_empty() => {
-                            match current_meta_property.as_str() {
-                                "dcterms:modified" => metadata.modified = Some(text),
-                                _ => {
-                                    metadata.custom.insert(current_meta_property.clone(), text);
-                                }
-                            }
-                        }
-                        _ => {}
+                    let element = std::mem::take(&mut current_element);
+                    let attrs = std::mem::take(&mut current);
+
+                    if element == "meta" && !attrs.property.is_empty() && !attrs.refines.is_empty()
+                    {
+                        refinements.push(Refinement {
+                            target: attrs.refines.trim_start_matches('#').to_string(),
+                            attributes: attrs.unmodelled(&["refines", "property"]),
+                            property: attrs.property,
+                            lang: attrs.lang,
+                            value: text,
+                            raw: raw.to_string(),
+                        });
+                    } else if !read_metadata_element(
+                        &element,
+                        text,
+                        attrs,
+                        &mut metadata,
+                        &mut extras,
+                        &mut unique_identifier,
+                    ) {
+                        extras.metadata_elements.push(raw.to_string());
                     }
-                    current_text.clear();
-                    current_element.clear();
-                    current_meta_property.clear();
                 }
             }
-            Ok(Event::Text(ref e)) => {
-                if in_metadata {
-                    current_text.push_str(&e.unescape().unwrap_or_default());
+            Ok(Event::Text(ref e)) if in_metadata => {
+                current_text.push_str(&e.unescape().unwrap_or_default());
+            }
+            Ok(Event::Comment(_)) => {
+                let text = xml[event_start..reader.buffer_position() as usize].to_string();
+                if depth == 0 {
+                    let position = if package_seen {
+                        CommentPosition::Epilogue
+                    } else {
+                        CommentPosition::Prolog
+                    };
+                    extras.comments.push(OpfComment { position, text });
+                } else if in_metadata {
+                    // Comments inside an element are part of its raw XML
+                    if depth == 2 {
+                        extras.comments.push(OpfComment {
+                            position: CommentPosition::Metadata,
+                            text,
+                        });
+                    }
+                } else {
+                    pending_comments.push(text);
                 }
             }
             Ok(Event::Empty(ref e)) => {
                 let local = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                let raw = || xml[event_start..reader.buffer_position() as usize].to_string();
 
                 if local == "item" {
-                    let mut item = ManifestItem {
-                        id: String::new(),
-                        href: String::new(),
-                        media_type: String::new(),
-                        properties: None,
-                    };
-                    for attr in e.attributes().flatten() {
-                        match attr.key.as_ref() {
-                            b"id" => item.id = String::from_utf8_lossy(&attr.value).into_owned(),
-                            b"href" => {
-                                item.href = String::from_utf8_lossy(&attr.value).into_owned()
-                            }
-                            b"media-type" => {
-                                item.media_type = String::from_utf8_lossy(&attr.value).into_owned()
-                            }
-                            b"properties" => {
-                                item.properties =
-                                    Some(String::from_utf8_lossy(&attr.value).into_owned())
-                            }
-                            _ => {}
+                    let mut item = ManifestItem::default();
+                    for (key, value) in attributes(e) {
+                        match key.as_str() {
+                            "id" => item.id = value,
+                            "href" => item.href = value,
+                            "media-type" => item.media_type = value,
+                            "properties" => item.properties = Some(value),
+                            "fallback" => item.fallback = Some(value),
+                            "media-overlay" => item.media_overlay = Some(value),
+                            _ => item.attributes.push((key, value)),
                         }
                     }
+                    let position = CommentPosition::Item(item.id.clone());
+                    place_comments(&mut extras, &mut pending_comments, position);
                     manifest.push(item);
                 } else if local == "itemref" {
-                    let mut spine_item = SpineItem {
-                        idref: String::new(),
-                        linear: true,
-                        properties: None,
-                    };
-                    for attr in e.attributes().flatten() {
-                        match attr.key.as_ref() {
-                            b"idref" => {
-                                spine_item.idref = String::from_utf8_lossy(&attr.value).into_owned()
-                            }
-                            b"linear" => {
-                                spine_item.linear = String::from_utf8_lossy(&attr.value) != "no"
-                            }
-                            b"properties" => {
-                                spine_item.properties =
-                                    Some(String::from_utf8_lossy(&attr.value).into_owned())
-                            }
-                            _ => {}
+                    let mut spine_item = SpineItem::default();
+                    for (key, value) in attributes(e) {
+                        match key.as_str() {
+                            "idref" => spine_item.idref = value,
+                            "linear" => spine_item.linear = value != "no",
+                            "properties" => spine_item.properties = Some(value),
+                            "id" => spine_item.id = Some(value),
+                            _ => spine_item.attributes.push((key, value)),
                         }
                     }
+                    let position = CommentPosition::ItemRef(spine_item.idref.clone());
+                    place_comments(&mut extras, &mut pending_comments, position);
                     spine.push(spine_item);
-                } else if in_metadata && local == "meta" {
-                    // Handle EPUB 2 <meta name="cover" content="cover-image"/>
-                    let mut name = String::new();
-                    let mut content = String::new();
-                    for attr in e.attributes().flatten() {
-                        match attr.key.as_ref() {
-                            b"name" => name = String::from_utf8_lossy(&attr.value).into_owned(),
-                            b"content" => {
-                                content = String::from_utf8_lossy(&attr.value).into_owned()
-                            }
-                            b"property" => name = String::from_utf8_lossy(&attr.value).into_owned(),
-                            _ => {}
-                        }
+                } else if in_guide && local == "reference" {
+                    guide.push(read_guide_reference(e));
+                } else if depth == 1 && PACKAGE_CHILDREN.contains(&local.as_str()) {
+                    let position = CommentPosition::Before(local.clone());
+                    place_comments(&mut extras, &mut pending_comments, position);
+                    match local.as_str() {
+                        "metadata" => extras.metadata_attributes = attributes(e),
+                        "spine" => read_spine_attributes(e, &mut extras),
+                        "guide" => extras.has_guide = true,
+                        _ => {}
                     }
-                    if name == "cover" {
-                        metadata.cover_id = Some(content);
-                    } else if name == "dcterms:modified" {
-                        // EPUB 3 modified timestamp stored in content attr won't be here,
-                        // it uses text content — handled in Start/End events
+                } else if depth == 1 {
+                    extras
+                        .trailing_elements
+                        .push(with_comments(&mut pending_comments, &raw()));
+                } else if in_metadata && depth == 2 {
+                    // EPUB 2 <meta name="cover" content="cover-image"/>
+                    let attrs = attributes(e);
+                    let value_of = |name: &str| {
+                        attrs
+                            .iter()
+                            .find(|(key, _)| key == name)
+                            .map(|(_, value)| value.clone())
+                    };
+                    if local == "meta" && value_of("name").as_deref() == Some("cover") {
+                        metadata.cover_id = value_of("content");
+                    } else if !read_accessibility_element(&local, &attrs, &mut metadata) {
+                        extras.metadata_elements.push(raw());
                     }
                 }
             }
@@ -170,6 +280,13 @@ pub fn parse_opf(xml: &str) -> Result<OpfData> {
             _ => {}
         }
         buf.clear();
+        skip_buf.clear();
+    }
+
+    for refinement in refinements {
+        if !apply_refinement(&mut metadata, &refinement) {
+            extras.metadata_elements.push(refinement.raw);
+        }
     }
 
     Ok(OpfData {
@@ -177,9 +294,326 @@ pub fn parse_opf(xml: &str) -> Result<OpfData> {
         manifest,
         spine,
         version,
+        unique_identifier,
+        extras,
+        guide,
     })
 }
 
+/// Package children the OPF is regenerated from; comments before them are
+/// placed by name
+const PACKAGE_CHILDREN: &[&str] = &["metadata", "manifest", "spine", "guide"];
+
+fn place_comments(extras: &mut OpfExtras, pending: &mut Vec<String>, position: CommentPosition) {
+    for text in pending.drain(..) {
+        extras.comments.push(OpfComment {
+            position: position.clone(),
+            text,
+        });
+    }
+}
+
+/// A raw element with the comments before it, which it is written back with
+fn with_comments(pending: &mut Vec<String>, raw: &str) -> String {
+    let mut out = String::new();
+    for comment in pending.drain(..) {
+        out.push_str(&comment);
+        out.push_str("\n  ");
+    }
+    out.push_str(raw);
+    out
+}
+
+/// A guide `<reference>` as a landmark
+fn read_guide_reference(e: &BytesStart) -> NavPoint {
+    let mut point = NavPoint::default();
+    for (key, value) in attributes(e) {
+        match key.as_str() {
+            "type" => point.epub_type = Some(navigation::guide_type_to_epub_type(&value)),
+            "title" => point.label = value,
+            "href" => point.href = value,
+            _ => {}
+        }
+    }
+    point
+}
+
+/// Store a metadata element in the model.
+///
+/// Returns `false` for elements the model has no place for, which are then
+/// kept as raw XML.
+fn read_metadata_element(
+    element: &str,
+    text: String,
+    attrs: ElementAttrs,
+    metadata: &mut EpubMetadata,
+    extras: &mut OpfExtras,
+    unique_identifier: &mut Option<String>,
+) -> bool {
+    if text.is_empty() {
+        return false;
+    }
+    let modelled: &[&str] = match element {
+        "title" => &["id"],
+        "creator" | "contributor" => &["id", "opf:role", "opf:file-as"],
+        "identifier" | "language" | "publisher" | "date" | "description" | "subject" | "rights" => {
+            &[]
+        }
+        "meta" => {
+            if attrs.property == "dcterms:modified" {
+                metadata.modified = Some(text);
+                return true;
+            }
+            // Metas with ids or schemes may be the target of refinements or
+            // carry meaning the flat custom map cannot hold
+            if attrs.property.is_empty() || attrs.all.len() > 1 {
+                return false;
+            }
+            if Accessibility::property_name(&attrs.property) == Some(attrs.property.as_str()) {
+                return metadata.accessibility.push(&attrs.property, text);
+            }
+            if metadata.custom.contains_key(&attrs.property) {
+                return false;
+            }
+            metadata.custom.insert(attrs.property, text);
+            return true;
+        }
+        _ => return false,
+    };
+
+    let unmodelled = attrs.unmodelled(modelled);
+    if !unmodelled.is_empty() {
+        extras.element_attributes.push(ElementAttributes {
+            element: element.to_string(),
+            value: text.clone(),
+            attributes: unmodelled,
+        });
+    }
+
+    match element {
+        "identifier" => {
+            if !attrs.id.is_empty() && extras.unique_identifier_id.as_ref() == Some(&attrs.id) {
+                *unique_identifier = Some(text.clone());
+            }
+            metadata.identifiers.push(text)
+        }
+        "title" => metadata.titles.push(Title {
+            value: text,
+            id: non_empty(&attrs.id),
+            ..Default::default()
+        }),
+        "creator" | "contributor" => {
+            let contributor = Contributor {
+                name: text,
+                id: non_empty(&attrs.id),
+                roles: attrs.role.into_iter().collect(),
+                file_as: attrs.file_as,
+                ..Default::default()
+            };
+            if element == "creator" {
+                metadata.creators.push(contributor);
+            } else {
+                metadata.contributors.push(contributor);
+            }
+        }
+        "language" => metadata.languages.push(text),
+        "publisher" => metadata.publishers.push(text),
+        "date" => metadata.dates.push(text),
+        "description" => metadata.description = Some(text),
+        "subject" => metadata.subjects.push(text),
+        "rights" => metadata.rights = Some(text),
+        _ => unreachable!(),
+    }
+    true
+}
+
+/// Read an empty accessibility element into the model: the EPUB 2
+/// `<meta name="schema:accessMode" content="textual"/>` form or a
+/// `<link rel="dcterms:conformsTo" href="..."/>` conformance claim. Elements
+/// with any other attributes stay raw.
+fn read_accessibility_element(
+    element: &str,
+    attrs: &[(String, String)],
+    metadata: &mut EpubMetadata,
+) -> bool {
+    let (key, value) = match element {
+        "meta" => ("name", "content"),
+        "link" => ("rel", "href"),
+        _ => return false,
+    };
+    let [(k1, v1), (k2, v2)] = attrs else {
+        return false;
+    };
+    let (property, value) = if k1 == key && k2 == value {
+        (v1, v2)
+    } else if k2 == key && k1 == value {
+        (v2, v1)
+    } else {
+        return false;
+    };
+    if element == "link" && property != accessibility::CONFORMS_TO {
+        return false;
+    }
+    match Accessibility::property_name(property) {
+        Some(name) if name == property => metadata.accessibility.push(name, value.clone()),
+        _ => false,
+    }
+}
+
+fn read_package_attributes(e: &BytesStart, version: &mut EpubVersion, extras: &mut OpfExtras) {
+    for (key, value) in attributes(e) {
+        match key.as_str() {
+            "version" => {
+                *version = if value.starts_with('2') {
+                    EpubVersion::V2
+                } else {
+                    EpubVersion::V3
+                };
+            }
+            "unique-identifier" => extras.unique_identifier_id = Some(value),
+            // The default namespace is always written
+            "xmlns" => {}
+            _ => extras.package_attributes.push((key, value)),
+        }
+    }
+}
+
+fn read_spine_attributes(e: &BytesStart, extras: &mut OpfExtras) {
+    for (key, value) in attributes(e) {
+        match key.as_str() {
+            // The NCX reference is regenerated from the manifest
+            "toc" => {}
+            "page-progression-direction" => extras.page_progression_direction = Some(value),
+            _ => extras.spine_attributes.push((key, value)),
+        }
+    }
+}
+
+/// All attributes of an element, unescaped, in document order
+fn attributes(e: &BytesStart) -> Vec<(String, String)> {
+    e.attributes()
+        .flatten()
+        .map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr
+                .unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+            (key, value)
+        })
+        .collect()
+}
+
+/// Attach a refinement to the title or contributor it refines.
+///
+/// Returns `false` for refinements of other elements or with properties not
+/// modelled here.
+fn apply_refinement(metadata: &mut EpubMetadata, refinement: &Refinement) -> bool {
+    let target = Some(refinement.target.as_str());
+    if let Some(title) = metadata
+        .titles
+        .iter_mut()
+        .find(|t| t.id.as_deref() == target)
+    {
+        refine_title(title, refinement)
+    } else if let Some(contributor) = metadata
+        .creators
+        .iter_mut()
+        .chain(metadata.contributors.iter_mut())
+        .find(|c| c.id.as_deref() == target)
+    {
+        refine_contributor(contributor, refinement)
+    } else {
+        false
+    }
+}
+
+fn refine_title(title: &mut Title, refinement: &Refinement) -> bool {
+    let value = refinement.value.clone();
+    match refinement.property.as_str() {
+        "title-type" => title.title_type = Some(value),
+        "file-as" => title.file_as = Some(value),
+        "alternate-script" => title.alternate_script.push(refinement.alternate_script()),
+        "display-seq" => match value.parse() {
+            Ok(seq) => title.display_seq = Some(seq),
+            Err(_) => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+fn refine_contributor(contributor: &mut Contributor, refinement: &Refinement) -> bool {
+    let value = refinement.value.clone();
+    match refinement.property.as_str() {
+        // Roles from other schemes than MARC relators are kept as written
+        "role" => {
+            if refinement
+                .attribute("scheme")
+                .is_some_and(|scheme| scheme != MARC_RELATORS)
+            {
+                return false;
+            }
+            contributor.roles.push(value);
+            keep_refinement_attributes(contributor, refinement, ROLE_ATTRIBUTES);
+        }
+        // A second file-as is kept as written
+        "file-as" if contributor.file_as.is_none() => {
+            contributor.file_as = Some(value);
+            keep_refinement_attributes(contributor, refinement, &[]);
+        }
+        "alternate-script" => contributor
+            .alternate_script
+            .push(refinement.alternate_script()),
+        "display-seq" => match value.parse() {
+            Ok(seq) => contributor.display_seq = Some(seq),
+            Err(_) => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+/// Record the attributes of a role or file-as refinement on the
+/// contributor, unless they are `default`
+fn keep_refinement_attributes(
+    contributor: &mut Contributor,
+    refinement: &Refinement,
+    default: &[(&str, &str)],
+) {
+    let is_default = refinement.attributes.len() == default.len()
+        && default
+            .iter()
+            .all(|(key, value)| refinement.attribute(key) == Some(value));
+    if !is_default {
+        contributor.refinement_attributes.push(ElementAttributes {
+            element: refinement.property.clone(),
+            value: refinement.value.clone(),
+            attributes: refinement.attributes.clone(),
+        });
+    }
+}
+
+impl Refinement {
+    fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn alternate_script(&self) -> AlternateScript {
+        AlternateScript {
+            lang: self.lang.clone().unwrap_or_default(),
+            value: self.value.clone(),
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,18 +659,130 @@ mod tests {
         assert!(matches!(data.version, EpubVersion::V2));
     }
 
+    #[test]
+    fn parse_opf_unique_identifier() {
+        let opf = minimal_opf(
+            "3.0",
+            r#"<dc:identifier id="isbn">978-0-00-000000-0</dc:identifier>"#,
+            "",
+            "",
+        );
+        let data = parse_opf(&opf).unwrap();
+        assert_eq!(data.metadata.identifiers.len(), 2);
+        assert_eq!(data.unique_identifier.as_deref(), Some("urn:uuid:test"));
+    }
+
     #[test]
     fn parse_opf_metadata_titles() {
         let opf = minimal_opf("3.0", "", "", "");
         let data = parse_opf(&opf).unwrap();
-        assert_eq!(data.metadata.titles, vec!["Test Book"]);
+        assert_eq!(data.metadata.title(), Some("Test Book"));
     }
 
     #[test]
     fn parse_opf_metadata_creators() {
         let opf = minimal_opf("3.0", "<dc:creator>Jane Doe</dc:creator>", "", "");
         let data = parse_opf(&opf).unwrap();
-        assert_eq!(data.metadata.creators, vec!["Jane Doe"]);
+        assert_eq!(data.metadata.creator_names(), vec!["Jane Doe"]);
+    }
+
+    #[test]
+    fn parse_opf_creator_refinements() {
+        let opf = minimal_opf(
+            "3.0",
+            r##"<dc:creator id="c1">Lewis Carroll</dc:creator>
+    <meta refines="#c1" property="role" scheme="marc:relators">aut</meta>
+    <meta refines="#c1" property="role" scheme="marc:relators">ill</meta>
+    <meta refines="#c1" property="file-as">Carroll, Lewis</meta>
+    <meta refines="#c1" property="alternate-script" xml:lang="ja">ルイス・キャロル</meta>
+    <meta refines="#c1" property="display-seq">1</meta>
+    <dc:contributor id="c2">John Tenniel</dc:contributor>
+    <meta refines="#c2" property="role" scheme="marc:relators">ill</meta>"##,
+            "",
+            "",
+        );
+        let data = parse_opf(&opf).unwrap();
+        let creator = &data.metadata.creators[0];
+        assert_eq!(creator.id.as_deref(), Some("c1"));
+        assert_eq!(creator.roles, vec!["aut", "ill"]);
+        assert_eq!(creator.file_as.as_deref(), Some("Carroll, Lewis"));
+        assert_eq!(creator.alternate_script[0].lang, "ja");
+        assert_eq!(creator.alternate_script[0].value, "ルイス・キャロル");
+        assert_eq!(creator.display_seq, Some(1));
+        assert_eq!(data.metadata.contributors[0].roles, vec!["ill"]);
+        assert!(data.metadata.custom.is_empty());
+    }
+
+    #[test]
+    fn parse_opf_keeps_refinement_attributes() {
+        let opf = minimal_opf(
+            "3.0",
+            r##"<dc:creator id="c1">Lewis Carroll</dc:creator>
+    <meta refines="#c1" property="role" scheme="marc:relators" id="r1">aut</meta>
+    <meta refines="#c1" property="role" scheme="marc:relators">ill</meta>
+    <meta refines="#c1" property="role" scheme="onix:codelist17">A01</meta>
+    <meta refines="#c1" property="file-as" xml:lang="en">Carroll, Lewis</meta>
+    <meta refines="#c1" property="file-as">Dodgson, Charles</meta>"##,
+            "",
+            "",
+        );
+        let data = parse_opf(&opf).unwrap();
+        let creator = &data.metadata.creators[0];
+        assert_eq!(creator.roles, vec!["aut", "ill"]);
+        assert_eq!(creator.file_as.as_deref(), Some("Carroll, Lewis"));
+        let attrs = |key: &str, value: &str| {
+            vec![
+                ("scheme".to_string(), MARC_RELATORS.to_string()),
+                (key.to_string(), value.to_string()),
+            ]
+        };
+        assert_eq!(
+            creator.refinement_attributes_for("role", "aut"),
+            Some(attrs("id", "r1").as_slice())
+        );
+        assert_eq!(creator.refinement_attributes_for("role", "ill"), None);
+        assert_eq!(
+            creator.refinement_attributes_for("file-as", "Carroll, Lewis"),
+            Some([("xml:lang".to_string(), "en".to_string())].as_slice())
+        );
+        let raw = &data.extras.metadata_elements;
+        assert_eq!(raw.len(), 2, "{raw:?}");
+        assert!(raw[0].contains("onix:codelist17"));
+        assert!(raw[1].contains("Dodgson, Charles"));
+    }
+
+    #[test]
+    fn parse_opf_title_refinements() {
+        let opf = minimal_opf(
+            "3.0",
+            r##"<dc:title id="t2">A Subtitle</dc:title>
+    <meta refines="#t2" property="title-type">subtitle</meta>
+    <meta refines="#t2" property="display-seq">2</meta>"##,
+            "",
+            "",
+        );
+        let data = parse_opf(&opf).unwrap();
+        assert_eq!(data.metadata.titles.len(), 2);
+        assert_eq!(
+            data.metadata.titles[1].title_type.as_deref(),
+            Some("subtitle")
+        );
+        assert_eq!(data.metadata.titles[1].display_seq, Some(2));
+        assert_eq!(data.metadata.title(), Some("Test Book"));
+    }
+
+    #[test]
+    fn parse_opf_epub2_creator_attributes() {
+        let opf = minimal_opf(
+            "2.0",
+            r#"<dc:creator opf:role="aut" opf:file-as="Doe, Jane">Jane Doe</dc:creator>"#,
+            "",
+            "",
+        );
+        let data = parse_opf(&opf).unwrap();
+        let creator = &data.metadata.creators[0];
+        assert_eq!(creator.roles, vec!["aut"]);
+        assert_eq!(creator.file_as.as_deref(), Some("Doe, Jane"));
     }
 
     #[test]
@@ -330,4 +876,112 @@ mod tests {
         assert!(data.manifest.is_empty());
         assert!(data.spine.is_empty());
     }
+
+    const EXTENDED_OPF: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="pub-id" prefix="ibooks: http://vocabulary.itunes.apple.com/rdf/ibooks/vocabulary-extensions-1.0/" xml:lang="en" dir="ltr">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="pub-id" opf:scheme="ISBN">9780000000000</dc:identifier>
+    <dc:title>Test Book</dc:title>
+    <dc:language>en</dc:language>
+    <dc:source>urn:isbn:9781111111111</dc:source>
+    <meta name="calibre:series" content="Tests"/>
+    <meta property="ibooks:specified-fonts">true</meta>
+    <link rel="record" href="meta/record.xml" media-type="application/marc"/>
+  </metadata>
+  <manifest>
+    <item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml" media-overlay="ch1-smil"/>
+    <item id="ch1-smil" href="ch1.smil" media-type="application/smil+xml"/>
+    <item id="widget" href="widget.js" media-type="application/javascript" fallback="ch1"/>
+  </manifest>
+  <spine page-progression-direction="rtl" id="spine">
+    <itemref idref="ch1" id="ref-ch1"/>
+  </spine>
+  <guide>
+    <reference type="cover" title="Cover" href="ch1.xhtml"/>
+  </guide>
+  <collection role="index"><link href="ch1.xhtml"/></collection>
+</package>"#;
+
+    #[test]
+    fn parse_opf_preserves_unmodelled_content() {
+        let data = parse_opf(EXTENDED_OPF).unwrap();
+        let extras = &data.extras;
+        assert_eq!(extras.unique_identifier_id.as_deref(), Some("pub-id"));
+        assert!(
+            extras
+                .package_attributes
+                .iter()
+                .any(|(k, v)| k == "dir" && v == "ltr")
+        );
+        assert_eq!(
+            extras.attributes_for("identifier", "9780000000000"),
+            &[
+                ("id".to_string(), "pub-id".to_string()),
+                ("opf:scheme".to_string(), "ISBN".to_string())
+            ]
+        );
+        assert_eq!(extras.metadata_elements.len(), 3);
+        assert!(extras.metadata_elements[0].starts_with("<dc:source>"));
+        assert_eq!(
+            data.metadata.custom.get("ibooks:specified-fonts"),
+            Some(&"true".to_string())
+        );
+        assert_eq!(extras.page_progression_direction.as_deref(), Some("rtl"));
+        assert_eq!(extras.trailing_elements.len(), 1);
+        assert!(extras.trailing_elements[0].starts_with("<collection"));
+        assert_eq!(data.guide[0].epub_type.as_deref(), Some("cover"));
+        assert_eq!(data.guide[0].label, "Cover");
+
+        assert_eq!(data.manifest[0].media_overlay.as_deref(), Some("ch1-smil"));
+        assert_eq!(data.manifest[2].fallback.as_deref(), Some("ch1"));
+        assert_eq!(data.spine[0].id.as_deref(), Some("ref-ch1"));
+    }
+
+    #[test]
+    fn parse_opf_places_comments() {
+        let xml = r#"<?xml version="1.0"?>
+<!-- generated -->
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <!-- titles --><dc:title>T<!-- inner --></dc:title>
+  </metadata>
+  <!-- files -->
+  <manifest>
+    <!-- first --><item id="a" href="a.xhtml" media-type="application/xhtml+xml"/>
+    <!-- last -->
+  </manifest>
+  <spine><itemref idref="a"/><!-- end --></spine>
+  <!-- index --><collection role="index"/>
+</package>"#;
+        let data = parse_opf(xml).unwrap();
+        let positions: Vec<(&CommentPosition, &str)> = data
+            .extras
+            .comments
+            .iter()
+            .map(|c| (&c.position, c.text.as_str()))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (&CommentPosition::Prolog, "<!-- generated -->"),
+                (&CommentPosition::Metadata, "<!-- titles -->"),
+                (
+                    &CommentPosition::Before("manifest".to_string()),
+                    "<!-- files -->"
+                ),
+                (&CommentPosition::Item("a".to_string()), "<!-- first -->"),
+                (
+                    &CommentPosition::End("manifest".to_string()),
+                    "<!-- last -->"
+                ),
+                (&CommentPosition::End("spine".to_string()), "<!-- end -->"),
+            ]
+        );
+        assert_eq!(
+            data.extras.trailing_elements,
+            vec!["<!-- index -->\n  <collection role=\"index\"/>"]
+        );
+        assert!(!data.extras.has_guide);
+        assert!(parse_opf(EXTENDED_OPF).unwrap().extras.has_guide);
+    }
 }
@@ -1,52 +1,196 @@
-use crate::epub::{EpubBook, container, navigation, opf, zip_utils};
-use crate::error::Result;
+use crate::epub::encryption::{self, FontObfuscation, Obfuscation};
+use crate::epub::meta_inf::MetaInf;
+use crate::epub::resources::Resources;
+use crate::epub::zip_utils::{EpubArchive, ReadSeek};
+use crate::epub::{
+    EpubBook, SourcePackage, container, meta_inf, navigation, opf, repair, zip_utils,
+};
+use crate::error::{EpxError, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read, SeekFrom};
 use std::path::Path;
+use zip::ZipArchive;
 
-/// Read and parse an EPUB file into an EpubBook model
-pub fn read_epub(path: &Path) -> Result<EpubBook> {
-    let mut archive = zip_utils::open_epub(path)?;
-    zip_utils::validate_mimetype(&mut archive)?;
+/// Options for [`read_epub_with`]
+#[derive(Debug, Default, Clone)]
+pub struct ReadOptions {
+    /// Rendition to read, as understood by [`container::select_rendition`];
+    /// the default rendition when `None`
+    pub rendition: Option<String>,
+    /// Work around a damaged ZIP, a bad `mimetype` or `container.xml` and
+    /// manifest hrefs that miss their entries, recording each problem in
+    /// [`EpubBook::repairs`]
+    pub lenient: bool,
+}
+
+/// Read and parse an EPUB file into an EpubBook model, with the given
+/// options
+///
+/// Only `META-INF/`, the OPF and the navigation document are read here;
+/// every other entry is loaded on demand through [`Resources`].
+pub fn read_epub_with(path: &Path, options: &ReadOptions) -> Result<EpubBook> {
+    read_epub_from(File::open(path)?, options)
+}
 
-    // Parse container.xml to find OPF path
-    let container_xml = zip_utils::read_entry_string(&mut archive, "META-INF/container.xml")?;
-    let opf_path = container::parse_container(&container_xml)?;
+/// Read an EPUB from a source that cannot seek, such as stdin, by buffering
+/// it in memory first
+pub fn read_epub_from_stream(mut source: impl Read, options: &ReadOptions) -> Result<EpubBook> {
+    let mut data = Vec::new();
+    source.read_to_end(&mut data)?;
+    read_epub_from(Cursor::new(data), options)
+}
 
-    // Determine the base directory of the OPF file for resolving relative paths
-    let opf_dir = if let Some(idx) = opf_path.rfind('/') {
-        &opf_path[..=idx]
+/// Read an EPUB from any seekable source, e.g. an in-memory buffer.
+///
+/// The book keeps the source open and reads resources from it on demand.
+pub fn read_epub_from(source: impl ReadSeek + 'static, options: &ReadOptions) -> Result<EpubBook> {
+    let mut repairs = Vec::new();
+    let mut archive = if options.lenient {
+        open_lenient(source, &mut repairs)?
     } else {
-        ""
+        zip_utils::open_archive(source)?
     };
 
+    // Parse container.xml to find the OPF of the selected rendition
+    let renditions = if options.lenient {
+        repairs.extend(zip_utils::mimetype_problems(&mut archive));
+        repair::locate_renditions(&mut archive, &mut repairs)?
+    } else {
+        zip_utils::validate_mimetype(&mut archive)?;
+        let container_xml = zip_utils::read_entry_string(&mut archive, meta_inf::CONTAINER_PATH)?;
+        container::parse_container(&container_xml)?
+    };
+    let opf_path = match options.rendition {
+        Some(ref selector) => container::select_rendition(&renditions, selector)?,
+        None => &renditions[0],
+    }
+    .full_path
+    .clone();
+
     // Parse OPF
     let opf_xml = zip_utils::read_entry_string(&mut archive, &opf_path)?;
     let opf_data = opf::parse_opf(&opf_xml)?;
 
-    // Load resources
-    let mut resources = std::collections::HashMap::new();
-    let entries = zip_utils::list_entries(&archive);
-    for entry_name in &entries {
-        if entry_name == "mimetype" || entry_name.starts_with("META-INF/") {
-            continue;
-        }
-        if let Ok(data) = zip_utils::read_entry(&mut archive, entry_name) {
-            resources.insert(entry_name.clone(), data);
-        }
+    // Other META-INF files are small and kept verbatim
+    let mut meta_inf = meta_inf::read_meta_inf(&mut archive)?;
+
+    // Catalog resources; entry data is read lazily
+    let mut resources = Resources::from_archive(archive);
+
+    let unique_identifier = opf_data
+        .unique_identifier
+        .as_ref()
+        .or(opf_data.metadata.identifiers.first())
+        .map_or("", String::as_str);
+    let obfuscated_fonts = read_encryption(
+        &mut meta_inf,
+        &mut resources,
+        unique_identifier,
+        &opf_data.metadata.identifiers,
+    )?;
+
+    // The source keeps the manifest as read, so repairs count as changes
+    let mut manifest = opf_data.manifest.clone();
+    if options.lenient {
+        repairs.extend(repair::repair_manifest(
+            &mut manifest,
+            &opf_path,
+            &resources,
+            renditions.len() == 1,
+        ));
     }
 
-    // Parse navigation
-    let nav = navigation::parse_navigation(&opf_data.manifest, &|href| {
-        let full_path = format!("{opf_dir}{href}");
-        resources
-            .get(&full_path)
-            .and_then(|bytes| String::from_utf8(bytes.clone()).ok())
-    })?;
+    // Parse navigation; without nav landmarks the EPUB 2 guide stands in
+    let mut nav =
+        navigation::parse_navigation(&manifest, &opf_path, &|path| resources.get_string(path))?;
+    if nav.landmarks.is_empty() {
+        nav.landmarks = opf_data.guide;
+    }
+
+    let source = SourcePackage {
+        opf_path: opf_path.clone(),
+        version: opf_data.version,
+        unique_identifier: opf_data.unique_identifier.clone(),
+        metadata: opf_data.metadata.clone(),
+        manifest: opf_data.manifest.clone(),
+        spine: opf_data.spine.clone(),
+        opf_extras: opf_data.extras.clone(),
+        navigation: nav.clone(),
+    };
 
     Ok(EpubBook {
         metadata: opf_data.metadata,
-        manifest: opf_data.manifest,
+        manifest,
         spine: opf_data.spine,
         navigation: nav,
+        opf_extras: opf_data.extras,
         resources,
+        meta_inf,
+        renditions,
+        obfuscated_fonts,
+        source: Some(source),
+        repairs,
     })
 }
+
+/// Open an archive, recovering its entries from the local file headers when
+/// the central directory cannot be read
+fn open_lenient(
+    mut source: impl ReadSeek + 'static,
+    repairs: &mut Vec<String>,
+) -> Result<EpubArchive> {
+    let start = source.stream_position()?;
+    let probe = ZipArchive::new(&mut source).map(drop);
+    source.seek(SeekFrom::Start(start))?;
+    match probe {
+        Ok(()) => zip_utils::open_archive(source),
+        Err(err) => {
+            let (archive, recovered) = zip_utils::salvage_epub(source)?;
+            repairs.push(format!(
+                "ZIP central directory is unreadable ({err}); recovered {recovered} entries"
+            ));
+            Ok(archive)
+        }
+    }
+}
+
+/// Take `encryption.xml` out of the preserved META-INF files and set up
+/// de-obfuscation of the fonts it lists.
+///
+/// Anything encrypted with an algorithm other than font obfuscation is DRM
+/// and cannot be read.
+fn read_encryption(
+    meta_inf: &mut MetaInf,
+    resources: &mut Resources,
+    unique_identifier: &str,
+    identifiers: &[String],
+) -> Result<HashMap<String, FontObfuscation>> {
+    let mut fonts = HashMap::new();
+    let Some(pos) = meta_inf
+        .files
+        .iter()
+        .position(|f| f.path == encryption::ENCRYPTION_PATH)
+    else {
+        return Ok(fonts);
+    };
+    let xml = String::from_utf8_lossy(&meta_inf.files[pos].data).into_owned();
+
+    for entry in encryption::parse_encryption(&xml)? {
+        let Some(method) = FontObfuscation::from_algorithm(&entry.algorithm) else {
+            return Err(EpxError::Encrypted(entry.path));
+        };
+        let obfuscation = Obfuscation::for_package(method, unique_identifier, identifiers)
+            .ok_or_else(|| {
+                EpxError::InvalidEpub(format!(
+                    "cannot derive font key for {} from the package identifiers",
+                    entry.path
+                ))
+            })?;
+        resources.set_source_obfuscation(&entry.path, obfuscation);
+        fonts.insert(entry.path, method);
+    }
+
+    meta_inf.files.remove(pos);
+    Ok(fonts)
+}
@@ -0,0 +1,657 @@
+use crate::epub::writer::xml_escape;
+use crate::epub::{EpubBook, url};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+static CSS_COMMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)/\*.*?\*/").expect("valid regex"));
+static CSS_IMPORT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)@import\s+(?:url\(\s*)?(?:"([^"]*)"|'([^']*)'|([^"'\s;)]+))"#)
+        .expect("valid regex")
+});
+static CSS_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\burl\(\s*(?:"([^"]*)"|'([^']*)'|([^"')\s]*))\s*\)"#).expect("valid regex")
+});
+
+/// How a document refers to a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReferenceKind {
+    /// `<a href>` or `<area href>`
+    Hyperlink,
+    /// An element that loads the resource, e.g. `<img src>`,
+    /// `<link href>` or SVG `<image xlink:href>`
+    Element,
+    /// One candidate of a `srcset`
+    Srcset,
+    /// CSS `url()` in a stylesheet, `<style>` element or `style` attribute
+    CssUrl,
+    /// CSS `@import`
+    Import,
+}
+
+/// One reference from a content document, SVG, media overlay or stylesheet
+/// to another resource
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AssetReference {
+    /// ZIP path of the referencing document
+    pub source: String,
+    /// ZIP path the reference resolves to, without fragment; `source` for
+    /// a fragment-only href and the URL itself for an external one
+    pub target: String,
+    /// The reference as written
+    pub href: String,
+    pub kind: ReferenceKind,
+    /// Local name of the referencing element; `None` in stylesheets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<String>,
+    /// Byte offset of the reference in the decoded source
+    #[serde(skip)]
+    pub offset: usize,
+    /// Where the href is written in the decoded source
+    #[serde(skip)]
+    span: Range<usize>,
+    #[serde(skip)]
+    strip: Strip,
+}
+
+impl AssetReference {
+    /// Whether the reference points outside the container
+    pub fn is_external(&self) -> bool {
+        url::is_external(&self.href)
+    }
+}
+
+/// The edit that takes a reference out of its source
+#[derive(Debug, Clone, PartialEq)]
+enum Strip {
+    Remove(Range<usize>),
+    /// Replace `outer` with `inner`, keeping the content of a link
+    Unwrap {
+        outer: Range<usize>,
+        inner: Range<usize>,
+    },
+}
+
+impl Strip {
+    fn outer(&self) -> &Range<usize> {
+        match self {
+            Strip::Remove(range) => range,
+            Strip::Unwrap { outer, .. } => outer,
+        }
+    }
+}
+
+/// `EPUB/ch1.xhtml: <img> ../images/a.png` or `EPUB/style.css: url(a.woff)`
+impl std::fmt::Display for AssetReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: ", self.source)?;
+        match (self.kind, &self.element) {
+            (ReferenceKind::Import, _) => write!(f, "@import {}", self.href),
+            (ReferenceKind::CssUrl, Some(element)) => {
+                write!(f, "<{element}> url({})", self.href)
+            }
+            (ReferenceKind::CssUrl, None) => write!(f, "url({})", self.href),
+            (ReferenceKind::Srcset, Some(element)) => {
+                write!(f, "<{element} srcset> {}", self.href)
+            }
+            (_, Some(element)) => write!(f, "<{element}> {}", self.href),
+            (_, None) => write!(f, "{}", self.href),
+        }
+    }
+}
+
+/// Every reference in the resources of a book, from XHTML, SVG and SMIL
+/// markup and from CSS, including `<style>` elements and `style`
+/// attributes. External URLs and same-document fragments are listed too,
+/// but never count as referring to a resource.
+#[derive(Debug, Default)]
+pub struct ReferenceGraph {
+    pub references: Vec<AssetReference>,
+}
+
+impl ReferenceGraph {
+    pub fn build(book: &EpubBook) -> Self {
+        let mut references = Vec::new();
+        for item in &book.manifest {
+            let markup = match item.media_type.as_str() {
+                "application/xhtml+xml" | "image/svg+xml" | "application/smil+xml" => true,
+                "text/css" => false,
+                _ => continue,
+            };
+            let path = book.resource_key(&item.href);
+            let Some(text) = book.resources.get_string(&path) else {
+                continue;
+            };
+            if markup {
+                scan_markup(&path, &text, &mut references);
+            } else {
+                scan_css(&path, &text, 0..text.len(), None, &mut references);
+            }
+        }
+        Self { references }
+    }
+
+    /// References to the resource at ZIP path `target` from other documents
+    pub fn referrers<'a>(&'a self, target: &'a str) -> impl Iterator<Item = &'a AssetReference> {
+        self.references.iter().filter(move |reference| {
+            reference.target == target && reference.source != target && !reference.is_external()
+        })
+    }
+
+    pub fn is_referenced(&self, target: &str) -> bool {
+        self.referrers(target).next().is_some()
+    }
+}
+
+/// Take references out of their documents: elements that load the resource
+/// are removed, links are unwrapped to their content, `srcset` candidates,
+/// CSS declarations and `@import` rules are deleted.
+///
+/// An edit inside another one, such as an image inside a removed link, is
+/// covered by the outer edit. Returns the number of edits made.
+pub fn strip_references(book: &mut EpubBook, references: &[&AssetReference]) -> usize {
+    let mut by_source: BTreeMap<&str, Vec<&Strip>> = BTreeMap::new();
+    for reference in references {
+        by_source
+            .entry(reference.source.as_str())
+            .or_default()
+            .push(&reference.strip);
+    }
+
+    let mut count = 0;
+    for (source, mut strips) in by_source {
+        let Some(text) = book.resources.get_string(source) else {
+            continue;
+        };
+        strips.sort_by_key(|strip| (strip.outer().start, std::cmp::Reverse(strip.outer().end)));
+        let mut stripped = String::with_capacity(text.len());
+        let mut position = 0;
+        for strip in strips {
+            let outer = strip.outer();
+            if outer.start < position {
+                continue;
+            }
+            stripped.push_str(&text[position..outer.start]);
+            if let Strip::Unwrap { inner, .. } = strip {
+                stripped.push_str(&text[inner.clone()]);
+            }
+            position = outer.end;
+            count += 1;
+        }
+        stripped.push_str(&text[position..]);
+        book.resources
+            .insert(source.to_string(), stripped.into_bytes());
+    }
+    count
+}
+
+/// Point references elsewhere: each href `f` maps is replaced, escaped in
+/// markup. The references must come from a graph of the book as it is.
+/// Returns the number of hrefs replaced.
+pub fn rewrite_references(
+    book: &mut EpubBook,
+    references: &[AssetReference],
+    f: impl Fn(&AssetReference) -> Option<String>,
+) -> usize {
+    let mut by_source: BTreeMap<&str, Vec<(&Range<usize>, String)>> = BTreeMap::new();
+    for reference in references {
+        if let Some(href) = f(reference) {
+            let href = match reference.element {
+                Some(_) => xml_escape(&href),
+                None => href,
+            };
+            by_source
+                .entry(reference.source.as_str())
+                .or_default()
+                .push((&reference.span, href));
+        }
+    }
+
+    let mut count = 0;
+    for (source, mut edits) in by_source {
+        let Some(text) = book.resources.get_string(source) else {
+            continue;
+        };
+        edits.sort_by_key(|(span, _)| span.start);
+        let mut rewritten = String::with_capacity(text.len());
+        let mut position = 0;
+        for (span, href) in edits {
+            if span.start < position {
+                continue;
+            }
+            rewritten.push_str(&text[position..span.start]);
+            rewritten.push_str(&href);
+            position = span.end;
+            count += 1;
+        }
+        rewritten.push_str(&text[position..]);
+        book.resources
+            .insert(source.to_string(), rewritten.into_bytes());
+    }
+    count
+}
+
+/// Record a non-empty reference written at `span` of the source; returns
+/// its index
+fn push(
+    references: &mut Vec<AssetReference>,
+    source: &str,
+    href: &str,
+    kind: ReferenceKind,
+    element: Option<&str>,
+    span: Range<usize>,
+    strip: Strip,
+) -> Option<usize> {
+    let href = href.trim();
+    if href.is_empty() {
+        return None;
+    }
+    let target = if url::is_external(href) {
+        href.to_string()
+    } else {
+        url::resolve(source, href)
+    };
+    references.push(AssetReference {
+        source: source.to_string(),
+        target,
+        href: href.to_string(),
+        kind,
+        element: element.map(str::to_string),
+        offset: span.start,
+        span,
+        strip,
+    });
+    Some(references.len() - 1)
+}
+
+fn scan_markup(path: &str, text: &str, references: &mut Vec<AssetReference>) {
+    let mut reader = Reader::from_str(text);
+    // Open elements: start tag and the references to edit once the end tag
+    // is known
+    let mut open: Vec<(Range<usize>, Vec<usize>)> = Vec::new();
+    let mut in_style = false;
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(_) => break,
+        };
+        let end = reader.buffer_position() as usize;
+        match event {
+            Event::Start(e) => {
+                in_style = e.local_name().as_ref() == b"style";
+                let pending = scan_element(path, text, &e, start..end, references);
+                open.push((start..end, pending));
+            }
+            Event::Empty(e) => {
+                scan_element(path, text, &e, start..end, references);
+            }
+            Event::End(_) => {
+                in_style = false;
+                let Some((tag, pending)) = open.pop() else {
+                    continue;
+                };
+                for index in pending {
+                    let reference = &mut references[index];
+                    reference.strip = if reference.kind == ReferenceKind::Hyperlink {
+                        Strip::Unwrap {
+                            outer: tag.start..end,
+                            inner: tag.end..start,
+                        }
+                    } else {
+                        Strip::Remove(tag.start..end)
+                    };
+                }
+            }
+            Event::Text(_) if in_style => {
+                scan_css(path, text, start..end, Some("style"), references);
+            }
+            Event::CData(_) if in_style && end >= start + 12 => {
+                scan_css(path, text, start + 9..end - 3, Some("style"), references);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+}
+
+/// Record the references in the attributes of one element. Returns the
+/// references whose edit spans the whole element, which for a start tag
+/// waits for its end tag.
+fn scan_element(
+    path: &str,
+    text: &str,
+    e: &BytesStart,
+    tag: Range<usize>,
+    references: &mut Vec<AssetReference>,
+) -> Vec<usize> {
+    let local = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+    let element = Some(local.as_str());
+    let mut whole_element = Vec::new();
+    for attr in e.attributes().flatten() {
+        let key = attr.key.as_ref();
+        let Some(value_start) = offset_in(text, &attr.value) else {
+            continue;
+        };
+        let value_range = value_start..value_start + attr.value.len();
+        // The href as written, without surrounding whitespace
+        let raw = &text[value_range.clone()];
+        let href_span =
+            value_start + (raw.len() - raw.trim_start().len())..value_start + raw.trim_end().len();
+        // From the whitespace before the name to the closing quote
+        let attr_range = offset_in(text, key).map_or(tag.clone(), |key_start| {
+            let start = text[..key_start].trim_end().len();
+            start..value_range.end + 1
+        });
+        let value = match attr.unescape_value() {
+            Ok(value) => value.into_owned(),
+            Err(_) => String::from_utf8_lossy(&attr.value).into_owned(),
+        };
+
+        let kind = match (local.as_str(), key) {
+            ("a" | "area", b"href" | b"xlink:href") => ReferenceKind::Hyperlink,
+            (_, b"style") => {
+                scan_css(path, text, value_range, element, references);
+                continue;
+            }
+            (_, b"srcset") => {
+                scan_srcset(path, text, value_range, attr_range, element, references);
+                continue;
+            }
+            ("video", b"poster") => {
+                push(
+                    references,
+                    path,
+                    &value,
+                    ReferenceKind::Element,
+                    element,
+                    href_span,
+                    Strip::Remove(attr_range),
+                );
+                continue;
+            }
+            ("link", b"href")
+            | ("object", b"data")
+            | ("image" | "use" | "feImage", b"href" | b"xlink:href")
+            | (
+                "img" | "audio" | "video" | "source" | "track" | "script" | "iframe" | "embed"
+                | "input" | "text",
+                b"src",
+            )
+            | ("seq" | "body", b"epub:textref") => ReferenceKind::Element,
+            _ => continue,
+        };
+        // Links are unwrapped and elements removed once the end tag is
+        // known; an empty element is removed as it is
+        let strip = Strip::Remove(tag.clone());
+        if let Some(index) = push(references, path, &value, kind, element, href_span, strip) {
+            whole_element.push(index);
+        }
+    }
+    whole_element
+}
+
+/// Record each candidate URL of a `srcset`; removing one deletes it and a
+/// comma, removing the only one deletes the attribute
+fn scan_srcset(
+    path: &str,
+    text: &str,
+    value_range: Range<usize>,
+    attr_range: Range<usize>,
+    element: Option<&str>,
+    references: &mut Vec<AssetReference>,
+) {
+    let value = &text[value_range.clone()];
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if c == ',' {
+            segments.push(start..i);
+            start = i + 1;
+        }
+    }
+    segments.push(start..value.len());
+    let count = segments.len();
+    for (i, segment) in segments.into_iter().enumerate() {
+        let candidate = &value[segment.clone()];
+        let Some(href) = candidate.split_whitespace().next() else {
+            continue;
+        };
+        let href_start = segment.start + (candidate.len() - candidate.trim_start().len());
+        let base = value_range.start;
+        let strip = if count == 1 {
+            Strip::Remove(attr_range.clone())
+        } else if i + 1 < count {
+            Strip::Remove(base + segment.start..base + segment.end + 1)
+        } else {
+            Strip::Remove(base + segment.start - 1..base + segment.end)
+        };
+        push(
+            references,
+            path,
+            href,
+            ReferenceKind::Srcset,
+            element,
+            base + href_start..base + href_start + href.len(),
+            strip,
+        );
+    }
+}
+
+/// Record the `url()` and `@import` references of the CSS at `range` of
+/// `text`. A `url()` is removed with its declaration, an import with its
+/// rule.
+fn scan_css(
+    path: &str,
+    text: &str,
+    range: Range<usize>,
+    element: Option<&str>,
+    references: &mut Vec<AssetReference>,
+) {
+    let base = range.start;
+    // Blank out comments, keeping offsets
+    let css = CSS_COMMENT.replace_all(&text[range], |caps: &regex::Captures| {
+        " ".repeat(caps[0].len())
+    });
+
+    let mut imports: Vec<Range<usize>> = Vec::new();
+    for caps in CSS_IMPORT.captures_iter(&css) {
+        let (Some(whole), Some(href)) = (caps.get(0), caps.get(1).or(caps.get(2)).or(caps.get(3)))
+        else {
+            continue;
+        };
+        let rule_end = css[href.end()..]
+            .find(';')
+            .map_or(css.len(), |i| href.end() + i + 1);
+        imports.push(whole.start()..rule_end);
+        push(
+            references,
+            path,
+            href.as_str(),
+            ReferenceKind::Import,
+            element,
+            base + href.start()..base + href.end(),
+            Strip::Remove(base + whole.start()..base + rule_end),
+        );
+    }
+
+    for caps in CSS_URL.captures_iter(&css) {
+        let (Some(whole), Some(href)) = (caps.get(0), caps.get(1).or(caps.get(2)).or(caps.get(3)))
+        else {
+            continue;
+        };
+        if imports.iter().any(|rule| rule.contains(&whole.start())) {
+            continue;
+        }
+        let declaration_start = css[..whole.start()].rfind([';', '{']).map_or(0, |i| i + 1);
+        let declaration_end = match css[whole.end()..].find([';', '}']) {
+            Some(i) if css[whole.end() + i..].starts_with(';') => whole.end() + i + 1,
+            Some(i) => whole.end() + i,
+            None => css.len(),
+        };
+        push(
+            references,
+            path,
+            href.as_str(),
+            ReferenceKind::CssUrl,
+            element,
+            base + href.start()..base + href.end(),
+            Strip::Remove(base + declaration_start..base + declaration_end),
+        );
+    }
+}
+
+/// Byte offset of `slice` in `text`, when it borrows from it
+pub(crate) fn offset_in(text: &str, slice: &[u8]) -> Option<usize> {
+    let start = (slice.as_ptr() as usize).checked_sub(text.as_ptr() as usize)?;
+    (start + slice.len() <= text.len()).then_some(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::ManifestItem;
+
+    fn item(id: &str, href: &str, media_type: &str) -> ManifestItem {
+        ManifestItem {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: media_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    const CHAPTER: &str = r##"<html><head><link rel="stylesheet" href="../css/style.css"/>
+<style>/* url(old.png) */ p { color: red; background: url('../images/bg.png'); }</style></head>
+<body><p style="border-image: url(../images/border.png)">Text</p>
+<img src="../images/a.png" srcset="../images/a.png 1x, ../images/a2.png 2x" alt=""/>
+<a href="../images/big.png#x">See <b>it</b></a><a href="#top">Top</a>
+<a href="https://example.com/">Web</a>
+<svg><image xlink:href="../images/vector.png"/></svg>
+</body></html>"##;
+
+    const CSS: &str = r#"@import "base.css";
+@import url(print.css) print;
+@font-face { font-family: Body; src: url("../fonts/body.woff2") format("woff2"); }
+h1 { color: blue }"#;
+
+    fn book() -> EpubBook {
+        let mut book = EpubBook {
+            manifest: vec![
+                item("ch1", "text/ch1.xhtml", "application/xhtml+xml"),
+                item("css", "css/style.css", "text/css"),
+            ],
+            ..Default::default()
+        };
+        book.resources
+            .insert("OEBPS/content.opf".to_string(), Vec::new());
+        book.resources.insert(
+            "OEBPS/text/ch1.xhtml".to_string(),
+            CHAPTER.as_bytes().to_vec(),
+        );
+        book.resources
+            .insert("OEBPS/css/style.css".to_string(), CSS.as_bytes().to_vec());
+        book
+    }
+
+    #[test]
+    fn graph_covers_markup_and_css() {
+        let graph = ReferenceGraph::build(&book());
+        let found: Vec<String> = graph.references.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            found,
+            vec![
+                "OEBPS/text/ch1.xhtml: <link> ../css/style.css",
+                "OEBPS/text/ch1.xhtml: <style> url(../images/bg.png)",
+                "OEBPS/text/ch1.xhtml: <p> url(../images/border.png)",
+                "OEBPS/text/ch1.xhtml: <img> ../images/a.png",
+                "OEBPS/text/ch1.xhtml: <img srcset> ../images/a.png",
+                "OEBPS/text/ch1.xhtml: <img srcset> ../images/a2.png",
+                "OEBPS/text/ch1.xhtml: <a> ../images/big.png#x",
+                "OEBPS/text/ch1.xhtml: <a> #top",
+                "OEBPS/text/ch1.xhtml: <a> https://example.com/",
+                "OEBPS/text/ch1.xhtml: <image> ../images/vector.png",
+                "OEBPS/css/style.css: @import base.css",
+                "OEBPS/css/style.css: @import print.css",
+                "OEBPS/css/style.css: url(../fonts/body.woff2)",
+            ]
+        );
+        assert_eq!(graph.referrers("OEBPS/images/a.png").count(), 2);
+        assert!(graph.is_referenced("OEBPS/images/big.png"));
+        assert!(graph.is_referenced("OEBPS/fonts/body.woff2"));
+        assert!(!graph.is_referenced("OEBPS/images/old.png"));
+        assert!(!graph.is_referenced("OEBPS/text/ch1.xhtml"));
+        assert!(!graph.is_referenced("https://example.com/"));
+    }
+
+    #[test]
+    fn rewrite_hrefs_in_place() {
+        let mut book = book();
+        let graph = ReferenceGraph::build(&book);
+        let count = rewrite_references(&mut book, &graph.references, |reference| {
+            (reference.target.ends_with(".png") || reference.target.ends_with("base.css")).then(
+                || {
+                    reference
+                        .href
+                        .replace(".png", ".webp")
+                        .replace("base", "a&b")
+                },
+            )
+        });
+        assert_eq!(count, 8);
+        let text = book.resources.get_string("OEBPS/text/ch1.xhtml").unwrap();
+        assert!(text.contains("url('../images/bg.webp')"));
+        assert!(text.contains(r#"srcset="../images/a.webp 1x, ../images/a2.webp 2x""#));
+        assert!(text.contains(r#"<a href="../images/big.webp#x">"#));
+        assert!(text.contains("/* url(old.png) */"));
+        let css = book.resources.get_string("OEBPS/css/style.css").unwrap();
+        assert!(css.starts_with(r#"@import "a&b.css";"#));
+    }
+
+    #[test]
+    fn strip_markup_references() {
+        let mut book = book();
+        let graph = ReferenceGraph::build(&book);
+        let referrers: Vec<_> = [
+            "OEBPS/images/a.png",
+            "OEBPS/images/big.png",
+            "OEBPS/images/bg.png",
+            "OEBPS/images/border.png",
+        ]
+        .into_iter()
+        .flat_map(|target| graph.referrers(target))
+        .collect();
+        // The image and its srcset candidate share one edit
+        assert_eq!(strip_references(&mut book, &referrers), 4);
+        let text = book.resources.get_string("OEBPS/text/ch1.xhtml").unwrap();
+        assert!(text.contains("p { color: red; }"));
+        assert!(text.contains(r#"<p style="">Text</p>"#));
+        assert!(!text.contains("<img"));
+        assert!(text.contains("\nSee <b>it</b><a href=\"#top\">"));
+        assert!(text.contains("vector.png"));
+    }
+
+    #[test]
+    fn strip_srcset_candidate_and_css_rules() {
+        let mut book = book();
+        let graph = ReferenceGraph::build(&book);
+        let referrers: Vec<_> = graph
+            .referrers("OEBPS/images/a2.png")
+            .chain(graph.referrers("OEBPS/css/base.css"))
+            .chain(graph.referrers("OEBPS/fonts/body.woff2"))
+            .collect();
+        assert_eq!(strip_references(&mut book, &referrers), 3);
+        let text = book.resources.get_string("OEBPS/text/ch1.xhtml").unwrap();
+        assert!(text.contains(r#"srcset="../images/a.png 1x""#));
+        let css = book.resources.get_string("OEBPS/css/style.css").unwrap();
+        assert!(!css.contains("base.css"));
+        assert!(css.starts_with("\n@import url(print.css) print;"));
+        assert!(css.contains(r#"@font-face { font-family: Body; }"#));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::assemble::asset_embed::infer_media_type;
+use crate::epub::container::{self, Rendition};
+use crate::epub::meta_inf::CONTAINER_PATH;
+use crate::epub::resources::Resources;
+use crate::epub::{ManifestItem, unique_id, url, zip_utils};
+use crate::error::{EpxError, Result};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Read the renditions from `container.xml`, falling back to the package
+/// document found in the archive when it is missing, malformed or points at
+/// an entry that does not exist.
+///
+/// The fallback only works when the archive holds exactly one `.opf`.
+pub fn locate_renditions<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    repairs: &mut Vec<String>,
+) -> Result<Vec<Rendition>> {
+    let problem = match zip_utils::read_entry_string(archive, CONTAINER_PATH) {
+        Err(_) => format!("{CONTAINER_PATH} is missing"),
+        Ok(xml) => match container::parse_container(&xml) {
+            Ok(renditions)
+                if renditions
+                    .iter()
+                    .all(|r| archive.index_for_name(&r.full_path).is_some()) =>
+            {
+                return Ok(renditions);
+            }
+            Ok(_) => format!("{CONTAINER_PATH} points at a missing package document"),
+            Err(err) => format!("{CONTAINER_PATH} is malformed ({err})"),
+        },
+    };
+
+    let packages: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".opf"))
+        .map(String::from)
+        .collect();
+    match packages.as_slice() {
+        [opf_path] => {
+            repairs.push(format!("{problem}; using {opf_path}"));
+            Ok(vec![Rendition::new(opf_path)])
+        }
+        [] => Err(EpxError::InvalidEpub(format!(
+            "{problem} and the archive has no package document"
+        ))),
+        _ => Err(EpxError::InvalidEpub(format!(
+            "{problem} and the archive has {} package documents: {}",
+            packages.len(),
+            packages.join(", ")
+        ))),
+    }
+}
+
+/// Point manifest items at the entries they were meant to reference.
+///
+/// Hrefs that miss their entry only by letter case or escaping are
+/// corrected, and hrefs with characters a URL cannot hold (spaces, mostly)
+/// are escaped. With `add_unlisted`, entries in the package directory that
+/// no item references get an item of their own; this is off for
+/// multi-rendition books, where they may belong to another rendition.
+///
+/// Returns a description of every change made.
+pub fn repair_manifest(
+    manifest: &mut Vec<ManifestItem>,
+    opf_path: &str,
+    resources: &Resources,
+    add_unlisted: bool,
+) -> Vec<String> {
+    let opf_dir = opf_dir(opf_path);
+    let mut repairs = Vec::new();
+
+    for item in manifest.iter_mut() {
+        let key = url::resolve(opf_path, &item.href);
+        if resources.contains_key(&key) {
+            if let Cow::Owned(href) = url::escape_href(&item.href) {
+                repairs.push(format!(
+                    "manifest href '{}' is not escaped; changed to '{href}'",
+                    item.href
+                ));
+                item.href = href;
+            }
+            continue;
+        }
+        let wanted = key.to_lowercase();
+        let Some(relative) = resources
+            .keys()
+            .find(|k| k.to_lowercase() == wanted)
+            .and_then(|k| k.strip_prefix(opf_dir))
+        else {
+            continue;
+        };
+        let href = url::escape_href(relative).into_owned();
+        repairs.push(format!(
+            "manifest href '{}' does not match the case of {opf_dir}{relative}; changed to '{href}'",
+            item.href
+        ));
+        item.href = href;
+    }
+
+    if !add_unlisted {
+        return repairs;
+    }
+    for key in unlisted_entries(manifest, opf_path, resources) {
+        let id = add_manifest_item(manifest, opf_path, &key);
+        repairs.push(format!("{key} is not in the manifest; added as '{id}'"));
+    }
+    repairs
+}
+
+/// Entries in the package directory that no manifest item references,
+/// leaving out the package document, directories and hidden files such as
+/// `.DS_Store`
+pub fn unlisted_entries(
+    manifest: &[ManifestItem],
+    opf_path: &str,
+    resources: &Resources,
+) -> Vec<String> {
+    let opf_dir = opf_dir(opf_path);
+    let listed: HashSet<String> = manifest
+        .iter()
+        .filter(|item| !url::is_external(&item.href))
+        .map(|item| url::resolve(opf_path, &item.href))
+        .collect();
+    resources
+        .keys()
+        .filter(|key| {
+            *key != opf_path
+                && key.starts_with(opf_dir)
+                && !key.ends_with('/')
+                && !key.rsplit('/').next().unwrap_or(key).starts_with('.')
+                && !listed.contains(*key)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Add a manifest item for the entry `key`, with an ID derived from its file
+/// name and a media type from its extension; returns the ID
+pub fn add_manifest_item(manifest: &mut Vec<ManifestItem>, opf_path: &str, key: &str) -> String {
+    let relative = key.strip_prefix(opf_dir(opf_path)).unwrap_or(key);
+    let id = unique_id(&id_base(relative), |id| {
+        manifest.iter().any(|item| item.id == id)
+    });
+    manifest.push(ManifestItem {
+        id: id.clone(),
+        href: url::escape_href(relative).into_owned(),
+        media_type: infer_media_type(Path::new(relative)).to_string(),
+        ..Default::default()
+    });
+    id
+}
+
+fn opf_dir(opf_path: &str) -> &str {
+    match opf_path.rfind('/') {
+        Some(idx) => &opf_path[..=idx],
+        None => "",
+    }
+}
+
+/// A manifest ID for a file: its name without extension, made a valid XML
+/// name
+fn id_base(relative: &str) -> String {
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let mut base: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    if !base.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        base.insert_str(0, "item-");
+    }
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, href: &str) -> ManifestItem {
+        ManifestItem {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn resources(keys: &[&str]) -> Resources {
+        let mut resources = Resources::default();
+        for key in keys {
+            resources.insert(key.to_string(), Vec::new());
+        }
+        resources
+    }
+
+    #[test]
+    fn repair_manifest_case_and_spaces() {
+        let resources = resources(&[
+            "OEBPS/content.opf",
+            "OEBPS/Text/chapter1.xhtml",
+            "OEBPS/Text/Chapter 2.xhtml",
+        ]);
+        let mut manifest = vec![
+            item("c1", "text/Chapter1.xhtml"),
+            item("c2", "Text/Chapter 2.xhtml"),
+        ];
+        let repairs = repair_manifest(&mut manifest, "OEBPS/content.opf", &resources, false);
+        assert_eq!(repairs.len(), 2);
+        assert_eq!(manifest[0].href, "Text/chapter1.xhtml");
+        assert_eq!(manifest[1].href, "Text/Chapter%202.xhtml");
+    }
+
+    #[test]
+    fn repair_manifest_adds_unlisted_entries() {
+        let resources = resources(&[
+            "OEBPS/content.opf",
+            "OEBPS/chapter1.xhtml",
+            "OEBPS/style.css",
+            "OEBPS/2.png",
+            "OEBPS/.DS_Store",
+            "iTunesMetadata.plist",
+        ]);
+        let mut manifest = vec![item("style", "chapter1.xhtml")];
+        let repairs = repair_manifest(&mut manifest, "OEBPS/content.opf", &resources, true);
+        assert_eq!(repairs.len(), 2);
+        assert_eq!(manifest[1].id, "style-2");
+        assert_eq!(manifest[1].media_type, "text/css");
+        assert_eq!(manifest[2].id, "item-2");
+        assert_eq!(manifest[2].href, "2.png");
+
+        let mut manifest = vec![item("c1", "chapter1.xhtml")];
+        assert!(repair_manifest(&mut manifest, "OEBPS/content.opf", &resources, false).is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::epub::encoding;
+use crate::epub::encryption::Obfuscation;
+use crate::epub::zip_utils::EpubArchive;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+use zip::ZipWriter;
+use zip::result::ZipResult;
+
+/// Lazily loaded EPUB resources, keyed by full ZIP path.
+///
+/// Entries backed by the source archive are only decompressed the first time
+/// they are requested, then cached. Inserted entries live in memory. This lets
+/// read-only commands touch just the handful of entries they need.
+#[derive(Default)]
+pub struct Resources {
+    archive: Option<RefCell<EpubArchive>>,
+    entries: HashMap<String, Entry>,
+    next_order: usize,
+}
+
+struct Entry {
+    /// Index of the entry in the source archive, `None` for in-memory data
+    source: Option<usize>,
+    /// Uncompressed size in bytes
+    size: u64,
+    /// Position used to keep iteration in archive order
+    order: usize,
+    /// Font obfuscation of the entry in the source archive
+    obfuscation: Option<Obfuscation>,
+    data: OnceCell<Vec<u8>>,
+}
+
+impl Resources {
+    /// Catalog the content entries of an archive without reading them.
+    ///
+    /// `mimetype` and everything under `META-INF/` are skipped, matching what
+    /// the book model treats as resources.
+    pub fn from_archive(mut archive: EpubArchive) -> Self {
+        let mut entries = HashMap::new();
+        for index in 0..archive.len() {
+            let Ok(file) = archive.by_index_raw(index) else {
+                continue;
+            };
+            let name = file.name().to_string();
+            if file.is_dir() || name == "mimetype" || name.starts_with("META-INF/") {
+                continue;
+            }
+            entries.insert(
+                name,
+                Entry {
+                    source: Some(index),
+                    size: file.size(),
+                    order: index,
+                    obfuscation: None,
+                    data: OnceCell::new(),
+                },
+            );
+        }
+        let next_order = archive.len();
+        Self {
+            archive: Some(RefCell::new(archive)),
+            entries,
+            next_order,
+        }
+    }
+
+    /// Get the bytes of a resource, reading it from the archive on first use.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        let entry = self.entries.get(key)?;
+        if let Some(data) = entry.data.get() {
+            return Some(data);
+        }
+        let mut data = self.read_source(entry.source?)?;
+        if let Some(obfuscation) = &entry.obfuscation {
+            obfuscation.apply(&mut data);
+        }
+        Some(entry.data.get_or_init(|| data))
+    }
+
+    /// Get a markup resource decoded as text.
+    ///
+    /// The encoding comes from the BOM or the document's declaration, see
+    /// [`encoding::detect`]. Legacy-encoded documents come back declaring
+    /// UTF-8, so the text can be modified and inserted back as is.
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.get(key)
+            .and_then(encoding::decode)
+            .map(|text| text.into_owned())
+    }
+
+    /// Insert or replace a resource with in-memory data.
+    pub fn insert(&mut self, key: String, data: Vec<u8>) {
+        let order = match self.entries.get(&key) {
+            Some(existing) => existing.order,
+            None => {
+                self.next_order += 1;
+                self.next_order
+            }
+        };
+        self.entries.insert(
+            key,
+            Entry {
+                source: None,
+                size: data.len() as u64,
+                order,
+                obfuscation: None,
+                data: OnceCell::from(data),
+            },
+        );
+    }
+
+    /// Record that an archive entry is an obfuscated font.
+    ///
+    /// Reads through [`Resources::get`] then return the plain font data.
+    pub fn set_source_obfuscation(&mut self, key: &str, obfuscation: Obfuscation) {
+        if let Some(entry) = self.entries.get_mut(key)
+            && entry.source.is_some()
+        {
+            entry.obfuscation = Some(obfuscation);
+            entry.data = OnceCell::new();
+        }
+    }
+
+    /// Obfuscation of an entry as stored in the source archive
+    pub fn source_obfuscation(&self, key: &str) -> Option<&Obfuscation> {
+        self.entries.get(key)?.obfuscation.as_ref()
+    }
+
+    /// Remove a resource, returning whether it existed.
+    pub fn remove(&mut self, key: &str) -> bool {
+        self.entries.remove(key).is_some()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Resource keys in archive order, followed by inserted keys.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        let mut keys: Vec<(&String, usize)> =
+            self.entries.iter().map(|(k, e)| (k, e.order)).collect();
+        keys.sort_by_key(|(_, order)| *order);
+        keys.into_iter().map(|(k, _)| k)
+    }
+
+    /// Uncompressed size of a resource, without reading its data.
+    pub fn size(&self, key: &str) -> Option<u64> {
+        self.entries.get(key).map(|e| e.size)
+    }
+
+    /// Total uncompressed size of all resources.
+    pub fn total_size(&self) -> u64 {
+        self.entries.values().map(|e| e.size).sum()
+    }
+
+    /// Stream a resource into `out` without caching it.
+    ///
+    /// Used by the writer so that rewriting a book does not pull every
+    /// untouched entry into memory.
+    pub fn write_to(&self, key: &str, out: &mut impl Write) -> std::io::Result<bool> {
+        let Some(entry) = self.entries.get(key) else {
+            return Ok(false);
+        };
+        if let Some(data) = entry.data.get() {
+            out.write_all(data)?;
+            return Ok(true);
+        }
+        if entry.obfuscation.is_some() {
+            // Fonts are small; decode them whole
+            let Some(data) = self.get(key) else {
+                return Ok(false);
+            };
+            out.write_all(data)?;
+            return Ok(true);
+        }
+        let (Some(index), Some(archive)) = (entry.source, self.archive.as_ref()) else {
+            return Ok(false);
+        };
+        let mut archive = archive.borrow_mut();
+        let mut file = archive.by_index(index).map_err(std::io::Error::other)?;
+        std::io::copy(&mut file, out)?;
+        Ok(true)
+    }
+
+    /// Copy an unmodified resource into `zip` as raw compressed data.
+    ///
+    /// Only entries that were never replaced through [`Resources::insert`] keep
+    /// their source index. For anything else this returns `false` without
+    /// writing, and the caller has to encode the data itself. Obfuscated fonts
+    /// are copied as stored, i.e. still obfuscated.
+    pub fn raw_copy_to<W: Write + Seek>(
+        &self,
+        key: &str,
+        name: &str,
+        zip: &mut ZipWriter<W>,
+    ) -> ZipResult<bool> {
+        let Some(entry) = self.entries.get(key) else {
+            return Ok(false);
+        };
+        let (Some(index), Some(archive)) = (entry.source, self.archive.as_ref()) else {
+            return Ok(false);
+        };
+        let mut archive = archive.borrow_mut();
+        let file = archive.by_index_raw(index)?;
+        zip.raw_copy_file_rename(file, name)?;
+        Ok(true)
+    }
+
+    fn read_source(&self, index: usize) -> Option<Vec<u8>> {
+        let mut archive = self.archive.as_ref()?.borrow_mut();
+        let mut file = archive.by_index(index).ok()?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf).ok()?;
+        Some(buf)
+    }
+}
+
+impl From<HashMap<String, Vec<u8>>> for Resources {
+    fn from(map: HashMap<String, Vec<u8>>) -> Self {
+        let mut resources = Resources::default();
+        let mut keys: Vec<String> = map.keys().cloned().collect();
+        keys.sort();
+        let mut map = map;
+        for key in keys {
+            let data = map.remove(&key).unwrap_or_default();
+            resources.insert(key, data);
+        }
+        resources
+    }
+}
+
+impl std::fmt::Debug for Resources {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resources")
+            .field("entries", &self.keys().collect::<Vec<_>>())
+            .field(
+                "loaded",
+                &self
+                    .entries
+                    .values()
+                    .filter(|e| e.data.get().is_some())
+                    .count(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::zip_utils;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("tests/fixtures");
+        p.push(name);
+        p
+    }
+
+    fn loaded_count(resources: &Resources) -> usize {
+        resources
+            .entries
+            .values()
+            .filter(|e| e.data.get().is_some())
+            .count()
+    }
+
+    #[test]
+    fn from_archive_reads_nothing_up_front() {
+        let archive = zip_utils::open_epub(&fixture("basic-v3plus2.epub")).unwrap();
+        let resources = Resources::from_archive(archive);
+        assert!(resources.contains_key("EPUB/images/cover.jpg"));
+        assert!(!resources.contains_key("META-INF/container.xml"));
+        assert!(!resources.contains_key("mimetype"));
+        assert_eq!(loaded_count(&resources), 0);
+        assert!(resources.total_size() > 0);
+    }
+
+    #[test]
+    fn get_loads_and_caches_single_entry() {
+        let archive = zip_utils::open_epub(&fixture("basic-v3plus2.epub")).unwrap();
+        let resources = Resources::from_archive(archive);
+        let css = resources.get_string("EPUB/css/epub.css").unwrap();
+        assert!(!css.is_empty());
+        assert_eq!(loaded_count(&resources), 1);
+        assert_eq!(resources.size("EPUB/css/epub.css"), Some(css.len() as u64));
+    }
+
+    #[test]
+    fn insert_overrides_archive_entry() {
+        let archive = zip_utils::open_epub(&fixture("minimal-v3.epub")).unwrap();
+        let mut resources = Resources::from_archive(archive);
+        resources.insert("EPUB/nav.xhtml".to_string(), b"new".to_vec());
+        assert_eq!(resources.get("EPUB/nav.xhtml"), Some(&b"new"[..]));
+        assert!(resources.remove("EPUB/nav.xhtml"));
+        assert!(resources.get("EPUB/nav.xhtml").is_none());
+    }
+
+    #[test]
+    fn keys_keep_archive_order() {
+        let mut resources = Resources::default();
+        resources.insert("b.xhtml".to_string(), vec![]);
+        resources.insert("a.xhtml".to_string(), vec![]);
+        let keys: Vec<_> = resources.keys().cloned().collect();
+        assert_eq!(keys, vec!["b.xhtml", "a.xhtml"]);
+    }
+
+    #[test]
+    fn raw_copy_only_untouched_entries() {
+        let archive = zip_utils::open_epub(&fixture("minimal-v3.epub")).unwrap();
+        let mut resources = Resources::from_archive(archive);
+        resources.insert("EPUB/nav.xhtml".to_string(), b"new".to_vec());
+
+        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        assert!(
+            !resources
+                .raw_copy_to("EPUB/nav.xhtml", "EPUB/nav.xhtml", &mut zip)
+                .unwrap()
+        );
+        assert!(
+            !resources
+                .raw_copy_to("missing", "missing", &mut zip)
+                .unwrap()
+        );
+        let key = resources
+            .keys()
+            .find(|k| k.ends_with(".xhtml") && *k != "EPUB/nav.xhtml")
+            .cloned()
+            .unwrap();
+        assert!(resources.raw_copy_to(&key, &key, &mut zip).unwrap());
+        assert_eq!(loaded_count(&resources), 1);
+
+        let mut written = zip::ZipArchive::new(zip.finish().unwrap()).unwrap();
+        assert_eq!(written.len(), 1);
+        let mut copied = String::new();
+        written
+            .by_name(&key)
+            .unwrap()
+            .read_to_string(&mut copied)
+            .unwrap();
+        assert_eq!(Some(copied), resources.get_string(&key));
+    }
+}
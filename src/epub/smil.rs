@@ -0,0 +1,800 @@
+use crate::epub::references::ReferenceGraph;
+use crate::epub::{EpubBook, ManifestItem, url};
+use crate::error::{EpxError, Result};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+pub const SMIL_MEDIA_TYPE: &str = "application/smil+xml";
+
+/// Durations closer than this are the same, clock values have millisecond
+/// precision
+const DURATION_TOLERANCE: f64 = 0.001;
+
+static ID_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\sid\s*=\s*["']([^"']+)["']"#).expect("valid regex"));
+static META_ATTR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\s(property|refines)\s*=\s*["']([^"']*)["']"#).expect("valid regex")
+});
+
+/// A SMIL media overlay document and the content documents it narrates
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct MediaOverlay {
+    /// Manifest ID of the SMIL document
+    pub id: String,
+    /// ZIP path of the SMIL document
+    pub path: String,
+    /// Manifest IDs of the content documents whose `media-overlay` points
+    /// here
+    pub documents: Vec<String>,
+    /// `<par>` elements in document order
+    pub pars: Vec<Par>,
+}
+
+/// A `<par>`: a fragment of a content document and the audio narrating it
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Par {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// `<text src>`, relative to the SMIL document
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioClip>,
+    /// Byte offset of the `<par>` start tag in the SMIL document
+    #[serde(skip)]
+    pub offset: usize,
+}
+
+/// An `<audio>` clip; clip times are kept as written, see [`parse_clock`]
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct AudioClip {
+    /// `<audio src>`, relative to the SMIL document
+    pub src: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_begin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_end: Option<String>,
+}
+
+impl AudioClip {
+    /// Start of the clip in seconds; a missing `clipBegin` is the start of
+    /// the audio file
+    pub fn begin(&self) -> Option<f64> {
+        self.clip_begin.as_deref().map_or(Some(0.0), parse_clock)
+    }
+
+    /// End of the clip in seconds, `None` if it runs to the end of the audio
+    /// file or the value is malformed
+    pub fn end(&self) -> Option<f64> {
+        self.clip_end.as_deref().and_then(parse_clock)
+    }
+}
+
+impl MediaOverlay {
+    /// Total length of the clips in seconds; `None` if a clip has no end
+    pub fn duration(&self) -> Option<f64> {
+        self.pars
+            .iter()
+            .filter_map(|par| par.audio.as_ref())
+            .map(|clip| Some(clip.end()? - clip.begin()?))
+            .sum()
+    }
+}
+
+impl ManifestItem {
+    /// Whether this item is a SMIL media overlay document
+    pub fn is_media_overlay(&self) -> bool {
+        self.media_type == SMIL_MEDIA_TYPE
+    }
+}
+
+/// Parse the `<par>` elements of a SMIL document, flattening `<seq>` nesting
+pub fn parse_smil(xml: &str) -> Result<Vec<Par>> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut pars = Vec::new();
+    let mut current: Option<Par> = None;
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => match e.local_name().as_ref() {
+                b"par" => {
+                    current = Some(Par {
+                        id: attribute(e, "id"),
+                        offset: start,
+                        ..Default::default()
+                    })
+                }
+                b"text" => {
+                    if let Some(par) = current.as_mut() {
+                        par.text = attribute(e, "src").unwrap_or_default();
+                    }
+                }
+                b"audio" => {
+                    if let Some(par) = current.as_mut() {
+                        par.audio = Some(AudioClip {
+                            src: attribute(e, "src").unwrap_or_default(),
+                            clip_begin: attribute(e, "clipBegin"),
+                            clip_end: attribute(e, "clipEnd"),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Ok(Event::End(ref e)) if e.local_name().as_ref() == b"par" => {
+                if let Some(par) = current.take() {
+                    pars.push(par);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(EpxError::Xml(e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(pars)
+}
+
+/// Unescaped value of an unprefixed attribute
+fn attribute(e: &BytesStart, name: &str) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name.as_bytes())
+        .map(|attr| {
+            attr.unescape_value()
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned())
+        })
+}
+
+/// Parse a SMIL clock value to seconds.
+///
+/// Accepts full clock values (`1:02:03.5`), partial clock values
+/// (`02:03.5`) and timecounts (`3.5s`, `1.5min`, `2h`, `350ms`, or a plain
+/// number of seconds).
+pub fn parse_clock(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = |s: &str| {
+        (!s.is_empty() && s.chars().all(|c| c.is_ascii_digit() || c == '.'))
+            .then(|| s.parse::<f64>().ok())
+            .flatten()
+    };
+    if value.contains(':') {
+        let parts: Vec<&str> = value.split(':').collect();
+        let (hours, minutes, seconds) = match parts.as_slice() {
+            [h, m, s] => (number(h)?, *m, *s),
+            [m, s] => (0.0, *m, *s),
+            _ => return None,
+        };
+        if minutes.len() != 2 || minutes.contains('.') || whole_digits(seconds) != 2 {
+            return None;
+        }
+        let (minutes, seconds) = (number(minutes)?, number(seconds)?);
+        if minutes >= 60.0 || seconds >= 60.0 || hours.fract() != 0.0 {
+            return None;
+        }
+        return Some(hours * 3600.0 + minutes * 60.0 + seconds);
+    }
+    let split = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(value.len());
+    let count = number(&value[..split])?;
+    match &value[split..] {
+        "h" => Some(count * 3600.0),
+        "min" => Some(count * 60.0),
+        "s" | "" => Some(count),
+        "ms" => Some(count / 1000.0),
+        _ => None,
+    }
+}
+
+/// Digits before the decimal point of a clock's seconds
+fn whole_digits(seconds: &str) -> usize {
+    seconds.split('.').next().unwrap_or("").len()
+}
+
+/// Format seconds as a full clock value, e.g. `0:01:02.500`
+pub fn format_clock(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Load one SMIL item of the manifest
+pub fn load_overlay(book: &EpubBook, item: &ManifestItem) -> Result<MediaOverlay> {
+    read_overlay(book, item)
+        .map_err(|(path, problem)| EpxError::InvalidEpub(format!("{path} {problem}")))
+}
+
+/// [`load_overlay`], failing with the SMIL path and what is wrong with it
+fn read_overlay(
+    book: &EpubBook,
+    item: &ManifestItem,
+) -> std::result::Result<MediaOverlay, (String, String)> {
+    let path = book.resource_key(&item.href);
+    let Some(xml) = book.resources.get_string(&path) else {
+        return Err((path, "is missing".to_string()));
+    };
+    let pars = match parse_smil(&xml) {
+        Ok(pars) => pars,
+        Err(err) => return Err((path, format!("is malformed: {err}"))),
+    };
+    Ok(MediaOverlay {
+        id: item.id.clone(),
+        path,
+        documents: book
+            .manifest
+            .iter()
+            .filter(|doc| doc.media_overlay.as_deref() == Some(item.id.as_str()))
+            .map(|doc| doc.id.clone())
+            .collect(),
+        pars,
+    })
+}
+
+/// Load every media overlay of the book, in manifest order
+pub fn load_overlays(book: &EpubBook) -> Result<Vec<MediaOverlay>> {
+    book.manifest
+        .iter()
+        .filter(|item| item.is_media_overlay())
+        .map(|item| load_overlay(book, item))
+        .collect()
+}
+
+/// Remove the overlay with manifest ID `id`, with the audio files no other
+/// overlay or content document refers to.
+///
+/// Call [`update_durations`] afterwards.
+pub fn remove_overlay(book: &mut EpubBook, id: &str) -> Result<()> {
+    let Some(item) = book.manifest.iter().find(|item| item.id == id).cloned() else {
+        return Ok(());
+    };
+    let audio: HashSet<String> = load_overlay(book, &item)
+        .map(|overlay| {
+            overlay
+                .pars
+                .iter()
+                .filter_map(|par| par.audio.as_ref())
+                .map(|clip| url::resolve(&overlay.path, &clip.src))
+                .collect()
+        })
+        .unwrap_or_default();
+    book.resources.remove(&book.resource_key(&item.href));
+    book.manifest.retain(|other| other.id != id);
+    for document in book.manifest.iter_mut() {
+        if document.media_overlay.as_deref() == Some(id) {
+            document.media_overlay = None;
+        }
+    }
+
+    // Audio other overlays or content documents still point at stays
+    let graph = ReferenceGraph::build(book);
+    for path in audio {
+        if graph.is_referenced(&path) {
+            continue;
+        }
+        book.resources.remove(&path);
+        let opf_dir = book.detect_opf_dir();
+        book.manifest
+            .retain(|item| url::resolve(&opf_dir, &item.href) != path);
+    }
+    Ok(())
+}
+
+/// Find an overlay by its manifest ID or the ID of a content document it
+/// narrates
+pub fn find_overlay<'a>(overlays: &'a [MediaOverlay], id: &str) -> Option<&'a MediaOverlay> {
+    overlays
+        .iter()
+        .find(|overlay| overlay.id == id)
+        .or_else(|| {
+            overlays
+                .iter()
+                .find(|overlay| overlay.documents.iter().any(|doc| doc == id))
+        })
+}
+
+/// The declared `media:duration` of the overlay with manifest ID `id`, or
+/// of the whole book for `None`
+pub fn declared_duration(book: &EpubBook, id: Option<&str>) -> Option<String> {
+    match id {
+        None => book.metadata.custom.get("media:duration").cloned(),
+        Some(id) => book
+            .opf_extras
+            .metadata_elements
+            .iter()
+            .find(|element| duration_refines(element) == Some(id))
+            .and_then(|element| element_text(element)),
+    }
+}
+
+/// Text content of a raw `<meta>` element
+fn element_text(element: &str) -> Option<String> {
+    let start = element.find('>')? + 1;
+    let end = element.rfind("</")?;
+    Some(element.get(start..end)?.trim().to_string())
+}
+
+/// The ID a raw `media:duration` refinement refines
+fn duration_refines(element: &str) -> Option<&str> {
+    if !element.trim_start().starts_with("<meta") {
+        return None;
+    }
+    let mut property = None;
+    let mut refines = None;
+    for caps in META_ATTR.captures_iter(element) {
+        let value = caps.get(2).map_or("", |m| m.as_str());
+        match &caps[1] {
+            "property" => property = Some(value),
+            _ => refines = Some(value.trim_start_matches('#')),
+        }
+    }
+    (property == Some("media:duration"))
+        .then_some(refines)
+        .flatten()
+}
+
+/// Recompute the `media:duration` of every overlay and of the book from the
+/// clip times.
+///
+/// Overlays whose clips run to the end of the audio file keep their
+/// declared duration, and then so does the book. Refinements of overlays
+/// no longer in the book are dropped. Returns a description of every
+/// change made.
+pub fn update_durations(book: &mut EpubBook) -> Result<Vec<String>> {
+    let overlays = load_overlays(book)?;
+    let mut changes = Vec::new();
+    let mut computed: HashMap<&str, f64> = HashMap::new();
+    let mut total = Some(0.0);
+    for overlay in &overlays {
+        match overlay.duration() {
+            Some(duration) => {
+                computed.insert(&overlay.id, duration);
+                total = total.map(|total| total + duration);
+            }
+            None => {
+                changes.push(format!(
+                    "{}: clips without clipEnd, media:duration left as it is",
+                    overlay.path
+                ));
+                total = None;
+            }
+        }
+    }
+
+    let ids: HashSet<&str> = overlays.iter().map(|overlay| overlay.id.as_str()).collect();
+    let mut previous: HashMap<String, String> = HashMap::new();
+    let mut kept = Vec::new();
+    for element in std::mem::take(&mut book.opf_extras.metadata_elements) {
+        match duration_refines(&element) {
+            Some(id) if !ids.contains(id) => {
+                changes.push(format!("media:duration of removed overlay '{id}' dropped"))
+            }
+            Some(id) if computed.contains_key(id) => {
+                if let Some(value) = element_text(&element) {
+                    previous.insert(id.to_string(), value);
+                }
+            }
+            _ => kept.push(element),
+        }
+    }
+    book.opf_extras.metadata_elements = kept;
+
+    for overlay in &overlays {
+        let Some(&duration) = computed.get(overlay.id.as_str()) else {
+            continue;
+        };
+        let clock = format_clock(duration);
+        let previous = previous.get(&overlay.id);
+        book.opf_extras.metadata_elements.push(format!(
+            "<meta property=\"media:duration\" refines=\"#{}\">{clock}</meta>",
+            overlay.id
+        ));
+        if !same_duration(previous.map(String::as_str), duration) {
+            changes.push(format!("media:duration of '{}' set to {clock}", overlay.id));
+        }
+    }
+
+    if overlays.is_empty() {
+        if book.metadata.custom.remove("media:duration").is_some() {
+            changes.push("media:duration of the book dropped, it has no overlays".to_string());
+        }
+    } else if let Some(total) = total {
+        let previous = book.metadata.custom.get("media:duration").cloned();
+        if !same_duration(previous.as_deref(), total) {
+            let clock = format_clock(total);
+            changes.push(format!("media:duration of the book set to {clock}"));
+            book.metadata
+                .custom
+                .insert("media:duration".to_string(), clock);
+        }
+    }
+    Ok(changes)
+}
+
+/// Whether a declared clock value matches `seconds`
+fn same_duration(declared: Option<&str>, seconds: f64) -> bool {
+    declared
+        .and_then(parse_clock)
+        .is_some_and(|declared| (declared - seconds).abs() < DURATION_TOLERANCE)
+}
+
+/// A problem found by [`validate_overlays`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OverlayIssue {
+    /// ZIP path of the SMIL document, `None` for the package document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Byte offset of the `<par>` at fault in the SMIL document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl OverlayIssue {
+    fn in_package(message: String) -> Self {
+        Self {
+            path: None,
+            offset: None,
+            message,
+        }
+    }
+
+    fn at(path: &str, offset: Option<usize>, message: String) -> Self {
+        Self {
+            path: Some(path.to_string()),
+            offset,
+            message,
+        }
+    }
+}
+
+/// `EPUB/smil/ch1.smil: par 'p1': audio 'a.mp3' is not in the book`
+impl std::fmt::Display for OverlayIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{path}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Check the media overlays of a book.
+///
+/// Every `<text src>` must point at an element of a content document that
+/// uses the overlay, every clip at an audio file in the book with a
+/// `clipEnd` after its `clipBegin`, and the declared `media:duration`
+/// values must match the clips.
+pub fn validate_overlays(book: &EpubBook) -> Vec<OverlayIssue> {
+    let mut issues = Vec::new();
+    let mut ids_by_document: HashMap<String, HashSet<String>> = HashMap::new();
+
+    for item in &book.manifest {
+        if let Some(overlay_id) = &item.media_overlay
+            && !book
+                .manifest
+                .iter()
+                .any(|other| other.id == *overlay_id && other.is_media_overlay())
+        {
+            issues.push(OverlayIssue::in_package(format!(
+                "{}: media-overlay '{overlay_id}' is not a SMIL document in the manifest",
+                item.href
+            )));
+        }
+    }
+
+    let mut total = Some(0.0);
+    let mut overlay_count = 0;
+    for item in book.manifest.iter().filter(|item| item.is_media_overlay()) {
+        overlay_count += 1;
+        let overlay = match read_overlay(book, item) {
+            Ok(overlay) => overlay,
+            Err((path, problem)) => {
+                issues.push(OverlayIssue::at(
+                    &path,
+                    None,
+                    format!("SMIL document {problem}"),
+                ));
+                total = None;
+                continue;
+            }
+        };
+        let path = &overlay.path;
+        if overlay.documents.is_empty() {
+            issues.push(OverlayIssue::at(
+                path,
+                None,
+                "no content document uses this overlay".to_string(),
+            ));
+        }
+        let documents: HashSet<String> = overlay
+            .documents
+            .iter()
+            .filter_map(|id| book.manifest.iter().find(|doc| doc.id == *id))
+            .map(|doc| book.resource_key(&doc.href))
+            .collect();
+
+        for (index, par) in overlay.pars.iter().enumerate() {
+            let name = par
+                .id
+                .as_ref()
+                .map_or_else(|| format!("par {}", index + 1), |id| format!("par '{id}'"));
+            let mut report = |problem: String| {
+                issues.push(OverlayIssue::at(
+                    path,
+                    Some(par.offset),
+                    format!("{name}: {problem}"),
+                ))
+            };
+            if let Some(problem) =
+                check_text(book, path, &par.text, &documents, &mut ids_by_document)
+            {
+                report(problem);
+            }
+            let Some(clip) = &par.audio else {
+                continue;
+            };
+            if !book.resources.contains_key(&url::resolve(path, &clip.src)) {
+                report(format!("audio '{}' is not in the book", clip.src));
+            }
+            for (attr, value) in [("clipBegin", &clip.clip_begin), ("clipEnd", &clip.clip_end)] {
+                if let Some(value) = value
+                    && parse_clock(value).is_none()
+                {
+                    report(format!("{attr} '{value}' is not a clock value"));
+                }
+            }
+            if let (Some(begin), Some(end)) = (clip.begin(), clip.end())
+                && end <= begin
+            {
+                report(format!(
+                    "clipEnd {} is not after clipBegin {}",
+                    format_clock(end),
+                    format_clock(begin)
+                ));
+            }
+        }
+
+        let duration = overlay.duration();
+        total = total
+            .zip(duration)
+            .map(|(total, duration)| total + duration);
+        match (declared_duration(book, Some(&overlay.id)), duration) {
+            (None, _) => issues.push(OverlayIssue::at(
+                path,
+                None,
+                format!("no media:duration refinement for '{}'", overlay.id),
+            )),
+            (Some(declared), Some(duration)) if !same_duration(Some(&declared), duration) => issues
+                .push(OverlayIssue::at(
+                    path,
+                    None,
+                    format!(
+                        "media:duration is {declared} but the clips add up to {}",
+                        format_clock(duration)
+                    ),
+                )),
+            _ => {}
+        }
+    }
+
+    if overlay_count > 0 {
+        match (declared_duration(book, None), total) {
+            (None, _) => issues.push(OverlayIssue::in_package(
+                "no media:duration for the book".to_string(),
+            )),
+            (Some(declared), Some(total)) if !same_duration(Some(&declared), total) => {
+                issues.push(OverlayIssue::in_package(format!(
+                    "media:duration of the book is {declared} but the overlays add up to {}",
+                    format_clock(total)
+                )))
+            }
+            _ => {}
+        }
+    }
+    issues
+}
+
+/// Check that a `<text src>` points at an element of one of `documents`
+fn check_text(
+    book: &EpubBook,
+    path: &str,
+    src: &str,
+    documents: &HashSet<String>,
+    ids_by_document: &mut HashMap<String, HashSet<String>>,
+) -> Option<String> {
+    if src.is_empty() {
+        return Some("<text> has no src".to_string());
+    }
+    let target = url::resolve(path, src);
+    let Some(xhtml) = book.resources.get_string(&target) else {
+        return Some(format!("text '{src}' points at a missing document"));
+    };
+    if !documents.contains(&target) {
+        return Some(format!(
+            "text '{src}' points at a document that does not use this overlay"
+        ));
+    }
+    let (_, fragment) = url::split_fragment(src);
+    let fragment = url::percent_decode(fragment.unwrap_or_default());
+    if fragment.is_empty() {
+        return None;
+    }
+    let ids = ids_by_document.entry(target).or_insert_with(|| {
+        ID_ATTR
+            .captures_iter(&xhtml)
+            .map(|caps| caps[1].to_string())
+            .collect()
+    });
+    (!ids.contains(&*fragment))
+        .then(|| format!("text '{src}' points at a missing element '{fragment}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMIL: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<smil xmlns="http://www.w3.org/ns/SMIL" xmlns:epub="http://www.idpf.org/2007/ops" version="3.0">
+  <body>
+    <seq id="s1" epub:textref="ch1.xhtml" epub:type="chapter">
+      <par id="p1">
+        <text src="ch1.xhtml#t1"/>
+        <audio src="audio/ch1.mp3" clipBegin="0:00:00.000" clipEnd="0:00:02.500"/>
+      </par>
+      <par id="p2">
+        <text src="ch1.xhtml#t2"/>
+        <audio src="audio/ch1.mp3" clipBegin="2.5s" clipEnd="5s"/>
+      </par>
+    </seq>
+  </body>
+</smil>"##;
+
+    fn overlay_book() -> EpubBook {
+        let mut book = EpubBook {
+            manifest: vec![
+                ManifestItem {
+                    id: "ch1".to_string(),
+                    href: "ch1.xhtml".to_string(),
+                    media_type: "application/xhtml+xml".to_string(),
+                    media_overlay: Some("ch1-smil".to_string()),
+                    ..Default::default()
+                },
+                ManifestItem {
+                    id: "ch1-smil".to_string(),
+                    href: "ch1.smil".to_string(),
+                    media_type: SMIL_MEDIA_TYPE.to_string(),
+                    ..Default::default()
+                },
+                ManifestItem {
+                    id: "ch1-audio".to_string(),
+                    href: "audio/ch1.mp3".to_string(),
+                    media_type: "audio/mpeg".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        for (key, data) in [
+            ("OEBPS/content.opf", ""),
+            (
+                "OEBPS/ch1.xhtml",
+                r#"<html><body><p id="t1">One</p><p id="t2">Two</p></body></html>"#,
+            ),
+            ("OEBPS/ch1.smil", SMIL),
+            ("OEBPS/audio/ch1.mp3", ""),
+        ] {
+            book.resources
+                .insert(key.to_string(), data.as_bytes().to_vec());
+        }
+        book
+    }
+
+    #[test]
+    fn parse_smil_pars() {
+        let pars = parse_smil(SMIL).unwrap();
+        assert_eq!(pars.len(), 2);
+        assert_eq!(pars[0].id.as_deref(), Some("p1"));
+        assert_eq!(pars[1].text, "ch1.xhtml#t2");
+        let clip = pars[1].audio.as_ref().unwrap();
+        assert_eq!(clip.begin(), Some(2.5));
+        assert_eq!(clip.end(), Some(5.0));
+    }
+
+    #[test]
+    fn clock_values() {
+        assert_eq!(parse_clock("1:02:03.5"), Some(3723.5));
+        assert_eq!(parse_clock("02:03"), Some(123.0));
+        assert_eq!(parse_clock("3.5s"), Some(3.5));
+        assert_eq!(parse_clock("1.5min"), Some(90.0));
+        assert_eq!(parse_clock("350ms"), Some(0.35));
+        assert_eq!(parse_clock("2h"), Some(7200.0));
+        assert_eq!(parse_clock("12"), Some(12.0));
+        assert_eq!(parse_clock("1:2:3"), None);
+        assert_eq!(parse_clock("00:75"), None);
+        assert_eq!(parse_clock("5 s"), None);
+        assert_eq!(format_clock(3723.5), "1:02:03.500");
+    }
+
+    #[test]
+    fn update_and_validate_durations() {
+        let mut book = overlay_book();
+        let issues = validate_overlays(&book);
+        assert_eq!(issues.len(), 2, "{issues:?}");
+        assert!(issues[0].message.contains("no media:duration refinement"));
+        assert_eq!(issues[0].path.as_deref(), Some("OEBPS/ch1.smil"));
+
+        let changes = update_durations(&mut book).unwrap();
+        assert_eq!(changes.len(), 2, "{changes:?}");
+        assert_eq!(
+            declared_duration(&book, Some("ch1-smil")).as_deref(),
+            Some("0:00:05.000")
+        );
+        assert_eq!(
+            book.metadata
+                .custom
+                .get("media:duration")
+                .map(String::as_str),
+            Some("0:00:05.000")
+        );
+        assert!(validate_overlays(&book).is_empty());
+        assert!(update_durations(&mut book).unwrap().is_empty());
+    }
+
+    #[test]
+    fn validate_missing_fragments_and_bad_clips() {
+        let mut book = overlay_book();
+        let smil = SMIL.replace("#t2", "#t9").replace(
+            "clipBegin=\"2.5s\" clipEnd=\"5s\"",
+            "clipBegin=\"5s\" clipEnd=\"2.5s\"",
+        );
+        book.resources
+            .insert("OEBPS/ch1.smil".to_string(), smil.into_bytes());
+        update_durations(&mut book).unwrap();
+
+        let issues = validate_overlays(&book);
+        let par = SMIL.find("<par id=\"p2\"").unwrap();
+        for problem in ["missing element 't9'", "is not after clipBegin"] {
+            let issue = issues
+                .iter()
+                .find(|issue| issue.message.contains(problem))
+                .unwrap();
+            assert_eq!(issue.path.as_deref(), Some("OEBPS/ch1.smil"));
+            assert_eq!(issue.offset, Some(par));
+            assert!(issue.message.starts_with("par 'p2': "));
+        }
+    }
+
+    #[test]
+    fn remove_overlay_keeps_only_referenced_audio() {
+        // Naming the file in text is not a reference
+        let mut book = overlay_book();
+        book.resources.insert(
+            "OEBPS/ch1.xhtml".to_string(),
+            br#"<html><body><p id="t1">Play ch1.mp3</p></body></html>"#.to_vec(),
+        );
+        remove_overlay(&mut book, "ch1-smil").unwrap();
+        assert!(!book.resources.contains_key("OEBPS/ch1.smil"));
+        assert!(!book.resources.contains_key("OEBPS/audio/ch1.mp3"));
+        assert_eq!(book.manifest.len(), 1);
+        assert_eq!(book.manifest[0].media_overlay, None);
+
+        let mut book = overlay_book();
+        book.resources.insert(
+            "OEBPS/ch1.xhtml".to_string(),
+            br#"<html><body><audio src="audio/ch1.mp3"/></body></html>"#.to_vec(),
+        );
+        remove_overlay(&mut book, "ch1-smil").unwrap();
+        assert!(book.resources.contains_key("OEBPS/audio/ch1.mp3"));
+        assert_eq!(book.manifest.len(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+use std::borrow::Cow;
+
+/// ASCII characters a URL cannot contain literally
+const UNSAFE_CHARS: &[u8] = b" \"<>\\^`{|}";
+
+/// Whether an href points outside the container, e.g. `https://...` or
+/// `mailto:...`
+pub fn is_external(href: &str) -> bool {
+    href.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && !scheme.contains(['/', '#', '?'])
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+    })
+}
+
+/// Split an href into its path and fragment, dropping any query
+pub fn split_fragment(href: &str) -> (&str, Option<&str>) {
+    let (rest, fragment) = match href.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (href, None),
+    };
+    let path = rest.split_once('?').map_or(rest, |(path, _)| path);
+    (path, fragment)
+}
+
+/// Resolve an href found in the document at `base` to the ZIP path it
+/// points at.
+///
+/// `base` is the ZIP path of the referencing document, or a directory with a
+/// trailing slash. The fragment and query are dropped, escapes decoded and
+/// `.` and `..` segments collapsed; `..` never climbs above the container
+/// root. A fragment-only href resolves to `base` itself.
+pub fn resolve(base: &str, href: &str) -> String {
+    let (path, _) = split_fragment(href);
+    if path.is_empty() {
+        return base.to_string();
+    }
+    let path = percent_decode(path);
+    let mut segments: Vec<&str> = Vec::new();
+    if !path.starts_with('/') {
+        segments.extend(base.split('/'));
+        // The document name
+        segments.pop();
+    }
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    // Keep a trailing slash of directory hrefs
+    let mut resolved = segments.join("/");
+    if path.ends_with('/') && !resolved.is_empty() {
+        resolved.push('/');
+    }
+    resolved
+}
+
+/// The href that points from the document at `base` to the ZIP path
+/// `target`
+pub fn relative(base: &str, target: &str) -> String {
+    let base_dirs: Vec<&str> = match base.rsplit_once('/') {
+        Some((dir, _)) => dir.split('/').collect(),
+        None => Vec::new(),
+    };
+    let target_segments: Vec<&str> = target.split('/').collect();
+    let common = base_dirs
+        .iter()
+        .zip(&target_segments[..target_segments.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut href = "../".repeat(base_dirs.len() - common);
+    href.push_str(&encode_path(&target_segments[common..].join("/")));
+    href
+}
+
+/// Rewrite an href found in the document at `from` so it points at the same
+/// place from the document at `to`.
+///
+/// External URLs are returned as they are; fragments are kept.
+pub fn rebase(href: &str, from: &str, to: &str) -> String {
+    if is_external(href) {
+        return href.to_string();
+    }
+    let target = resolve(from, href);
+    let mut rebased = relative(to, &target);
+    if let (_, Some(fragment)) = split_fragment(href) {
+        rebased.push('#');
+        rebased.push_str(fragment);
+    }
+    rebased
+}
+
+/// Decode `%XX` escapes in an href.
+///
+/// Malformed escapes are kept as they are. Returns the input unchanged if
+/// the decoded bytes are not UTF-8.
+pub fn percent_decode(href: &str) -> Cow<'_, str> {
+    if !href.contains('%') {
+        return Cow::Borrowed(href);
+    }
+    let bytes = href.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(_) => Cow::Borrowed(href),
+    }
+}
+
+/// Percent-encode the characters an href must not contain literally, such
+/// as spaces.
+///
+/// Existing escapes and non-ASCII characters (allowed in IRIs) are left
+/// alone.
+pub fn escape_href(href: &str) -> Cow<'_, str> {
+    let needs_escape =
+        |c: char| c.is_ascii_control() || (c.is_ascii() && UNSAFE_CHARS.contains(&(c as u8)));
+    if !href.contains(needs_escape) {
+        return Cow::Borrowed(href);
+    }
+    let mut escaped = String::with_capacity(href.len() + 8);
+    for c in href.chars() {
+        if needs_escape(c) {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Percent-encode a ZIP path for use as an href
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c.is_ascii_control() || (c.is_ascii() && b"%#?".contains(&(c as u8))) {
+            encoded.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            encoded.push(c);
+        }
+    }
+    escape_href(&encoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_relative_hrefs() {
+        assert_eq!(
+            resolve("OEBPS/content.opf", "Text/Chapter%201.xhtml"),
+            "OEBPS/Text/Chapter 1.xhtml"
+        );
+        assert_eq!(
+            resolve("OEBPS/Text/ch1.xhtml", "../Images/a.png"),
+            "OEBPS/Images/a.png"
+        );
+        assert_eq!(
+            resolve("OEBPS/Text/ch1.xhtml", "./ch2.xhtml?v=1#note-3"),
+            "OEBPS/Text/ch2.xhtml"
+        );
+        assert_eq!(resolve("OEBPS/", "ch1.xhtml"), "OEBPS/ch1.xhtml");
+        assert_eq!(resolve("content.opf", "../../ch1.xhtml"), "ch1.xhtml");
+        assert_eq!(
+            resolve("OEBPS/Text/ch1.xhtml", "/cover.xhtml"),
+            "cover.xhtml"
+        );
+        assert_eq!(
+            resolve("OEBPS/Text/ch1.xhtml", "#top"),
+            "OEBPS/Text/ch1.xhtml"
+        );
+    }
+
+    #[test]
+    fn relative_and_rebase() {
+        assert_eq!(
+            relative("OEBPS/Text/ch1.xhtml", "OEBPS/Images/a b.png"),
+            "../Images/a%20b.png"
+        );
+        assert_eq!(
+            relative("OEBPS/content.opf", "OEBPS/ch1.xhtml"),
+            "ch1.xhtml"
+        );
+        assert_eq!(
+            relative("content.opf", "OEBPS/ch1.xhtml"),
+            "OEBPS/ch1.xhtml"
+        );
+        assert_eq!(
+            rebase(
+                "../Text/ch1.xhtml#p1",
+                "OEBPS/nav/nav.xhtml",
+                "OEBPS/content.opf"
+            ),
+            "Text/ch1.xhtml#p1"
+        );
+        assert_eq!(
+            rebase("#toc", "OEBPS/nav.xhtml", "OEBPS/content.opf"),
+            "nav.xhtml#toc"
+        );
+        assert_eq!(
+            rebase("https://example.com/a", "OEBPS/nav.xhtml", "x.opf"),
+            "https://example.com/a"
+        );
+    }
+
+    #[test]
+    fn external_hrefs() {
+        assert!(is_external("https://example.com/"));
+        assert!(is_external("mailto:someone@example.com"));
+        assert!(!is_external("ch1.xhtml"));
+        assert!(!is_external("Text/a:b.xhtml"));
+        assert!(!is_external("#note"));
+    }
+
+    #[test]
+    fn percent_decode_escapes() {
+        assert_eq!(
+            percent_decode("Text/Chapter%201.xhtml"),
+            "Text/Chapter 1.xhtml"
+        );
+        assert_eq!(percent_decode("caf%C3%A9.xhtml"), "caf\u{e9}.xhtml");
+        assert_eq!(percent_decode("plain.xhtml"), "plain.xhtml");
+        assert_eq!(percent_decode("100%.xhtml"), "100%.xhtml");
+        assert_eq!(percent_decode("bad%FF.xhtml"), "bad%FF.xhtml");
+    }
+
+    #[test]
+    fn escape_href_unsafe_characters() {
+        assert_eq!(
+            escape_href("Text/Chapter 1.xhtml"),
+            "Text/Chapter%201.xhtml"
+        );
+        assert_eq!(escape_href("a{b}.css"), "a%7Bb%7D.css");
+        assert_eq!(escape_href("Chapter%201.xhtml"), "Chapter%201.xhtml");
+        assert_eq!(escape_href("caf\u{e9}.xhtml"), "caf\u{e9}.xhtml");
+    }
+}
@@ -1,105 +1,638 @@
-use crate::epub::{EpubBook, NavPoint};
+use crate::epub::container::Rendition;
+use crate::epub::encoding;
+use crate::epub::encryption::{self, ENCRYPTION_PATH, FontObfuscation, Obfuscation};
+use crate::epub::meta_inf::{CONTAINER_PATH, SIGNATURES_PATH};
+use crate::epub::opf::MARC_RELATORS;
+use crate::epub::{
+    AlternateScript, CommentPosition, Contributor, EpubBook, EpubMetadata, EpubVersion,
+    ManifestItem, NavPoint, Navigation, OpfExtras, SourcePackage, Title, accessibility, navigation,
+    url,
+};
 use crate::util::format_iso8601;
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Seek, Write};
 use std::path::Path;
 use zip::ZipWriter;
 use zip::write::SimpleFileOptions;
 
+/// Directory the normalized layout puts the package in
+const NORMALIZED_OPF_DIR: &str = "OEBPS/";
+
+/// Extensions of the documents `transcode_utf8` applies to
+const MARKUP_EXTENSIONS: &[&str] = &["xhtml", "html", "htm", "xml", "opf", "ncx", "svg", "smil"];
+
+/// How [`write_epub_with`] lays out the package
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Write a fresh package under `OEBPS/` with generated navigation
+    #[default]
+    Normalize,
+    /// Keep the OPF path, navigation hrefs and manifest IDs the book was read
+    /// with, and only regenerate the OPF and navigation when they changed.
+    ///
+    /// Books without a [`SourcePackage`] are written normalized.
+    Preserve,
+}
+
+/// Options for [`write_epub_with`]
+#[derive(Debug, Default, Clone)]
+pub struct WriteOptions {
+    pub layout: Layout,
+    /// Re-encode markup in legacy encodings or UTF-16 as UTF-8
+    pub transcode_utf8: bool,
+    /// Package version to write. `None` keeps the version the book was read
+    /// with; new books and normalized books are written as EPUB 3.
+    ///
+    /// EPUB 2 output has an OPF 2 package with a `<guide>` and the NCX as
+    /// its only navigation. Upgrading a book whose manifest lists a nav
+    /// document keeps the layout; any other version change rewrites the book
+    /// normalized.
+    pub version: Option<EpubVersion>,
+}
+
 /// Write an EpubBook to an EPUB file with atomic rename
+#[cfg(test)]
 pub fn write_epub(book: &EpubBook, path: &Path) -> anyhow::Result<()> {
+    write_epub_with(book, path, &WriteOptions::default())
+}
+
+/// Write an EpubBook to an EPUB file with the given options
+pub fn write_epub_with(book: &EpubBook, path: &Path, options: &WriteOptions) -> anyhow::Result<()> {
     let tmp_path = path.with_extension("epub.tmp");
     let file = std::fs::File::create(&tmp_path)?;
-    let mut zip = ZipWriter::new(file);
+    write_epub_to(book, file, options)?;
 
-    // 1. mimetype (stored, no compression, first entry)
+    // Atomic rename
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Write an EpubBook to a sink that cannot seek, such as stdout, by
+/// building the archive in memory first
+pub fn write_epub_to_stream(
+    book: &EpubBook,
+    mut sink: impl Write,
+    options: &WriteOptions,
+) -> anyhow::Result<()> {
+    let buffer = write_epub_to(book, Cursor::new(Vec::new()), options)?;
+    sink.write_all(buffer.get_ref())?;
+    sink.flush()?;
+    Ok(())
+}
+
+/// Write an EpubBook to any seekable sink, e.g. an in-memory buffer.
+///
+/// Returns the sink once the archive is finished.
+pub fn write_epub_to<W: Write + Seek>(
+    book: &EpubBook,
+    sink: W,
+    options: &WriteOptions,
+) -> anyhow::Result<W> {
+    let mut zip = ZipWriter::new(sink);
+
+    // mimetype (stored, no compression, first entry)
     let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
     zip.start_file("mimetype", stored)?;
     zip.write_all(b"application/epub+zip")?;
 
-    let deflate = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let new_version = match (options.version, &book.source) {
+        (Some(version), Some(source)) if version != source.version => Some(version),
+        _ => None,
+    };
+    // Normalizing one rendition would move files the others share
+    let layout = if book.renditions.len() > 1 {
+        if new_version.is_some() {
+            anyhow::bail!("cannot change the EPUB version of a book with several renditions");
+        }
+        Layout::Preserve
+    } else if new_version.is_some_and(|version| {
+        version == EpubVersion::V2 || !book.manifest.iter().any(|item| item.is_nav())
+    }) {
+        Layout::Normalize
+    } else {
+        options.layout
+    };
+    let rewritten = match (layout, &book.source) {
+        (Layout::Preserve, Some(source)) => write_preserved(book, source, options, &mut zip)?,
+        _ => {
+            write_normalized(book, options, &mut zip)?;
+            true
+        }
+    };
 
-    // 2. META-INF/container.xml
-    zip.start_file("META-INF/container.xml", deflate)?;
-    zip.write_all(generate_container_xml().as_bytes())?;
+    let sink = zip.finish()?;
 
-    // 3. Determine OPF directory
-    let opf_dir = "OEBPS";
+    if rewritten && book.meta_inf.has_signatures() {
+        eprintln!(
+            "warning: content changed, {SIGNATURES_PATH} was kept but its signatures are no longer valid"
+        );
+    }
 
-    // 4. Generate and write OPF
-    let opf = generate_opf(book);
-    zip.start_file(format!("{opf_dir}/content.opf"), deflate)?;
-    zip.write_all(opf.as_bytes())?;
+    Ok(sink)
+}
 
-    // 5. Generate and write navigation
-    let toc_xhtml = generate_toc_xhtml(&book.navigation.toc, &book.metadata.titles);
-    zip.start_file(format!("{opf_dir}/toc.xhtml"), deflate)?;
-    zip.write_all(toc_xhtml.as_bytes())?;
+fn deflate() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated)
+}
 
-    let toc_ncx = generate_toc_ncx(
-        &book.navigation.toc,
-        &book.metadata.titles,
-        &book.metadata.identifiers,
-    );
-    zip.start_file(format!("{opf_dir}/toc.ncx"), deflate)?;
+/// Write the package under `OEBPS/` with a generated OPF, nav and NCX; EPUB 2
+/// packages get no nav
+fn write_normalized<W: Write + Seek>(
+    book: &EpubBook,
+    options: &WriteOptions,
+    zip: &mut ZipWriter<W>,
+) -> anyhow::Result<()> {
+    let version = options.version.unwrap_or(EpubVersion::V3);
+    let opf_dir = NORMALIZED_OPF_DIR;
+    let opf_path = format!("{opf_dir}content.opf");
+    let nav_path = format!("{opf_dir}toc.xhtml");
+    let ncx_path = format!("{opf_dir}toc.ncx");
+
+    write_meta_inf(book, &opf_path, zip)?;
+
+    // Keys are rebased from the source OPF directory into OEBPS/. The source
+    // OPF and navigation documents are replaced by the generated ones.
+    let source_dir = book
+        .source
+        .as_ref()
+        .map_or(opf_dir, |source| source.opf_dir());
+    let mut replaced: Vec<String> = book
+        .manifest
+        .iter()
+        .filter(|item| item.is_nav() || item.is_ncx())
+        .map(|item| url::resolve(source_dir, &item.href))
+        .collect();
+
+    // Links into the source nav document now go to the generated one
+    let mut navigation = book.navigation.clone();
+    if let Some(source_nav) = book.manifest.iter().find(|item| item.is_nav()) {
+        let source_nav = url::resolve(source_dir, &source_nav.href);
+        navigation::map_hrefs(&mut navigation, &|href| {
+            if url::is_external(href) || url::resolve(source_dir, href) != source_nav {
+                return href.to_string();
+            }
+            match url::split_fragment(href) {
+                (_, Some(fragment)) => format!("toc.xhtml#{fragment}"),
+                (_, None) => "toc.xhtml".to_string(),
+            }
+        });
+    }
+
+    // Without a nav document, landmarks into it have nothing to point at
+    if version == EpubVersion::V2 {
+        navigation.landmarks.retain(|landmark| {
+            url::is_external(&landmark.href) || url::resolve(opf_dir, &landmark.href) != nav_path
+        });
+    }
+
+    zip.start_file(&opf_path, deflate())?;
+    zip.write_all(generate_opf(book, &navigation.landmarks, version).as_bytes())?;
+
+    if version == EpubVersion::V3 {
+        let toc_xhtml = generate_toc_xhtml(&navigation, book.metadata.title());
+        zip.start_file(&nav_path, deflate())?;
+        zip.write_all(toc_xhtml.as_bytes())?;
+    }
+
+    let toc_ncx = generate_toc_ncx(&navigation, book.metadata.title(), package_uid(book));
+    zip.start_file(&ncx_path, deflate())?;
     zip.write_all(toc_ncx.as_bytes())?;
 
-    // 6. Write content and assets from resources
-    for (path_key, data) in &book.resources {
-        // Skip OPF and navigation (already written)
-        if path_key.ends_with(".opf")
-            || path_key.ends_with("toc.xhtml")
-            || path_key.ends_with("toc.ncx")
-        {
+    if let Some(source) = &book.source {
+        replaced.push(source.opf_path.clone());
+    }
+
+    let mut fonts = FontEncryption::new(
+        book,
+        book.metadata.identifiers.get(unique_identifier_index(book)),
+    );
+    for path_key in book.resources.keys() {
+        if replaced.contains(path_key) {
             continue;
         }
-        // Rebase into OEBPS if not already
-        let zip_path = if path_key.starts_with("OEBPS/") || path_key.starts_with("META-INF/") {
+        let zip_path = if path_key.starts_with("META-INF/") {
             path_key.clone()
         } else {
-            format!("{opf_dir}/{path_key}")
+            let relative = path_key.strip_prefix(source_dir).unwrap_or(path_key);
+            format!("{opf_dir}{relative}")
         };
-        zip.start_file(&zip_path, deflate)?;
-        zip.write_all(data)?;
+        if zip_path == opf_path || zip_path == nav_path || zip_path == ncx_path {
+            continue;
+        }
+        let obfuscation = fonts.obfuscation_for(path_key, &zip_path);
+        write_resource(
+            book,
+            path_key,
+            &zip_path,
+            obfuscation.as_ref(),
+            options.transcode_utf8,
+            zip,
+        )?;
     }
+    fonts.finish(zip)?;
+
+    Ok(())
+}
 
-    zip.finish()?;
+/// Write the package back to the paths it was read from.
+///
+/// The OPF, nav document and NCX are regenerated only when the parts of the
+/// book they describe changed; everything else is copied through. Returns
+/// whether any entry had to be rewritten.
+fn write_preserved<W: Write + Seek>(
+    book: &EpubBook,
+    source: &SourcePackage,
+    options: &WriteOptions,
+    zip: &mut ZipWriter<W>,
+) -> anyhow::Result<bool> {
+    write_meta_inf(book, &source.opf_path, zip)?;
 
-    // Atomic rename
-    std::fs::rename(&tmp_path, path)?;
+    let metadata = &book.metadata;
+    let navigation = &book.navigation;
+    let source_navigation = &source.navigation;
+    let version = options.version.unwrap_or(source.version);
+
+    // The guide is generated from the landmarks
+    let opf_changed = version != source.version
+        || *metadata != source.metadata
+        || book.manifest != source.manifest
+        || book.spine != source.spine
+        || book.opf_extras != source.opf_extras
+        || navigation.landmarks != source_navigation.landmarks;
+    let nav_changed = navigation.toc != source_navigation.toc
+        || navigation.landmarks != source_navigation.landmarks
+        || navigation.page_list != source_navigation.page_list;
+    // The NCX repeats the unique identifier, so it has to follow it
+    let ncx_changed = navigation.toc != source_navigation.toc
+        || navigation.page_list != source_navigation.page_list
+        || metadata.identifiers != source.metadata.identifiers
+        || book.opf_extras.unique_identifier_id != source.opf_extras.unique_identifier_id;
 
+    let mut generated: HashMap<String, String> = HashMap::new();
+    if opf_changed {
+        generated.insert(
+            source.opf_path.clone(),
+            generate_preserved_opf(book, source, version),
+        );
+    }
+    // Navigation hrefs are relative to the OPF, the documents need them
+    // relative to themselves. A nav document added to the manifest is
+    // generated too.
+    if let Some(nav) = book.manifest.iter().find(|item| item.is_nav())
+        && let nav_path = url::resolve(&source.opf_path, &nav.href)
+        && (nav_changed || !book.resources.contains_key(&nav_path))
+    {
+        let mut navigation = navigation.clone();
+        navigation::rebase_navigation(&mut navigation, &source.opf_path, &nav_path);
+        generated.insert(nav_path, generate_toc_xhtml(&navigation, metadata.title()));
+    }
+    if ncx_changed && let Some(ncx) = book.manifest.iter().find(|item| item.is_ncx()) {
+        let ncx_path = url::resolve(&source.opf_path, &ncx.href);
+        let mut navigation = navigation.clone();
+        navigation::rebase_navigation(&mut navigation, &source.opf_path, &ncx_path);
+        generated.insert(
+            ncx_path,
+            generate_toc_ncx(&navigation, metadata.title(), package_uid(book)),
+        );
+    }
+
+    let mut rewritten = !generated.is_empty();
+
+    // Fonts are keyed by the unique identifier of the OPF being written
+    let unique_identifier = if opf_changed {
+        metadata.identifiers.get(unique_identifier_index(book))
+    } else {
+        source
+            .unique_identifier
+            .as_ref()
+            .or(metadata.identifiers.first())
+    };
+    let mut fonts = FontEncryption::new(book, unique_identifier);
+
+    // Regenerated documents keep their position in the archive
+    for path_key in book.resources.keys() {
+        if let Some(content) = generated.remove(path_key) {
+            zip.start_file(path_key, deflate())?;
+            zip.write_all(content.as_bytes())?;
+            continue;
+        }
+        let obfuscation = fonts.obfuscation_for(path_key, path_key);
+        if !write_resource(
+            book,
+            path_key,
+            path_key,
+            obfuscation.as_ref(),
+            options.transcode_utf8,
+            zip,
+        )? {
+            rewritten = true;
+        }
+    }
+    fonts.finish(zip)?;
+
+    // Documents the archive did not have yet
+    let mut remaining: Vec<_> = generated.into_iter().collect();
+    remaining.sort();
+    for (zip_path, content) in remaining {
+        zip.start_file(&zip_path, deflate())?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(rewritten)
+}
+
+/// Write `container.xml` pointing at `opf_path`, then the preserved
+/// `META-INF/` files
+fn write_meta_inf<W: Write + Seek>(
+    book: &EpubBook,
+    opf_path: &str,
+    zip: &mut ZipWriter<W>,
+) -> anyhow::Result<()> {
+    zip.start_file(CONTAINER_PATH, deflate())?;
+    zip.write_all(generate_container_xml(&container_renditions(book, opf_path)).as_bytes())?;
+    for file in &book.meta_inf.files {
+        zip.start_file(&file.path, deflate())?;
+        zip.write_all(&file.data)?;
+    }
     Ok(())
 }
 
-fn generate_container_xml() -> String {
-    r##"<?xml version="1.0" encoding="UTF-8"?>
-<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
-  <rootfiles>
-    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
-  </rootfiles>
-</container>"##
-        .to_string()
+/// Write one resource, copying it raw when it is unmodified.
+///
+/// `obfuscation` is the font obfuscation the entry needs in the output; a
+/// font is only copied raw if the source archive used the same key. With
+/// `transcode`, markup not in UTF-8 is re-encoded.
+/// Returns `true` if the entry was copied raw, `false` if it was re-encoded.
+fn write_resource<W: Write + Seek>(
+    book: &EpubBook,
+    path_key: &str,
+    zip_path: &str,
+    obfuscation: Option<&Obfuscation>,
+    transcode: bool,
+    zip: &mut ZipWriter<W>,
+) -> anyhow::Result<bool> {
+    if transcode
+        && is_markup(path_key)
+        && let Some(data) = book
+            .resources
+            .get(path_key)
+            .and_then(encoding::transcode_to_utf8)
+    {
+        zip.start_file(zip_path, deflate())?;
+        zip.write_all(&data)?;
+        return Ok(false);
+    }
+    if book.resources.source_obfuscation(path_key) == obfuscation
+        && book.resources.raw_copy_to(path_key, zip_path, zip)?
+    {
+        return Ok(true);
+    }
+    zip.start_file(zip_path, deflate())?;
+    match obfuscation {
+        Some(obfuscation) => {
+            let mut data = book.resources.get(path_key).unwrap_or_default().to_vec();
+            obfuscation.apply(&mut data);
+            zip.write_all(&data)?;
+        }
+        None => {
+            book.resources.write_to(path_key, zip)?;
+        }
+    }
+    Ok(false)
+}
+
+fn is_markup(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, extension)| {
+        MARKUP_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+    })
+}
+
+/// Obfuscates fonts for the package being written and collects the entries
+/// `encryption.xml` has to list
+struct FontEncryption<'a> {
+    book: &'a EpubBook,
+    unique_identifier: Option<&'a String>,
+    entries: Vec<(String, FontObfuscation)>,
+}
+
+impl<'a> FontEncryption<'a> {
+    fn new(book: &'a EpubBook, unique_identifier: Option<&'a String>) -> Self {
+        Self {
+            book,
+            unique_identifier,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Obfuscation for a resource written to `zip_path`, if it is a font
+    /// marked for obfuscation. Without a usable identifier the font is
+    /// written in the clear.
+    fn obfuscation_for(&mut self, path_key: &str, zip_path: &str) -> Option<Obfuscation> {
+        let method = *self.book.obfuscated_fonts.get(path_key)?;
+        let obfuscation = Obfuscation::for_package(
+            method,
+            self.unique_identifier?,
+            &self.book.metadata.identifiers,
+        )?;
+        self.entries.push((zip_path.to_string(), method));
+        Some(obfuscation)
+    }
+
+    fn finish<W: Write + Seek>(mut self, zip: &mut ZipWriter<W>) -> anyhow::Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        zip.start_file(ENCRYPTION_PATH, deflate())?;
+        zip.write_all(encryption::generate_encryption_xml(&self.entries).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The renditions `container.xml` lists, with the book's own one at
+/// `opf_path`
+fn container_renditions(book: &EpubBook, opf_path: &str) -> Vec<Rendition> {
+    let current = book.source.as_ref().map(|source| source.opf_path.as_str());
+    let mut renditions = book.renditions.clone();
+    match renditions
+        .iter_mut()
+        .find(|rendition| Some(rendition.full_path.as_str()) == current)
+    {
+        Some(rendition) => rendition.full_path = opf_path.to_string(),
+        None => renditions.insert(0, Rendition::new(opf_path)),
+    }
+    renditions
+}
+
+/// Generate `container.xml` listing every rendition
+fn generate_container_xml(renditions: &[Rendition]) -> String {
+    let uses_rendition_attrs = renditions
+        .iter()
+        .any(|r| !r.selection_attributes().is_empty());
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\"",
+    );
+    if uses_rendition_attrs {
+        xml.push_str(" xmlns:rendition=\"http://www.idpf.org/2013/rendition\"");
+    }
+    xml.push_str(">\n  <rootfiles>\n");
+    for rendition in renditions {
+        xml.push_str(&format!(
+            "    <rootfile full-path=\"{}\" media-type=\"{}\"",
+            xml_escape(&rendition.full_path),
+            xml_escape(&rendition.media_type)
+        ));
+        for (name, value) in [
+            ("rendition:layout", &rendition.layout),
+            ("rendition:language", &rendition.language),
+            ("rendition:media", &rendition.media),
+            ("rendition:accessMode", &rendition.access_mode),
+            ("rendition:label", &rendition.label),
+        ] {
+            if let Some(value) = value {
+                xml.push_str(&format!(" {name}=\"{}\"", xml_escape(value)));
+            }
+        }
+        xml.push_str("/>\n");
+    }
+    xml.push_str("  </rootfiles>\n</container>");
+    xml
 }
 
-fn generate_opf(book: &EpubBook) -> String {
+/// Generate the OPF for the normalized layout, with its own nav and NCX items
+fn generate_opf(book: &EpubBook, landmarks: &[NavPoint], version: EpubVersion) -> String {
+    let mut manifest = Vec::new();
+    if version == EpubVersion::V3 {
+        manifest.push(ManifestItem {
+            id: "toc".to_string(),
+            href: "toc.xhtml".to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            properties: Some("nav".to_string()),
+            ..Default::default()
+        });
+    }
+    manifest.push(ManifestItem {
+        id: "ncx".to_string(),
+        href: "toc.ncx".to_string(),
+        media_type: "application/x-dtbncx+xml".to_string(),
+        properties: None,
+        ..Default::default()
+    });
+    manifest.extend(
+        book.manifest
+            .iter()
+            .filter(|item| !item.is_nav() && !item.is_ncx())
+            .cloned(),
+    );
+    render_opf(
+        book,
+        &version.to_string(),
+        &manifest,
+        landmarks,
+        Some("ncx"),
+    )
+}
+
+/// Generate the OPF for a preserved layout, keeping the book's manifest as is
+fn generate_preserved_opf(book: &EpubBook, source: &SourcePackage, version: EpubVersion) -> String {
+    let ncx_id = book
+        .manifest
+        .iter()
+        .find(|item| item.is_ncx())
+        .map(|item| item.id.as_str());
+    // A package without a guide only gets one when its landmarks or version
+    // change
+    let navigation = &book.navigation;
+    let landmarks: &[NavPoint] = if book.opf_extras.has_guide
+        || navigation.landmarks != source.navigation.landmarks
+        || version != source.version
+    {
+        &navigation.landmarks
+    } else {
+        &[]
+    };
+    render_opf(
+        book,
+        &version.to_string(),
+        &book.manifest,
+        landmarks,
+        ncx_id,
+    )
+}
+
+fn render_opf(
+    book: &EpubBook,
+    version: &str,
+    manifest: &[ManifestItem],
+    landmarks: &[NavPoint],
+    ncx_id: Option<&str>,
+) -> String {
+    let extras = &book.opf_extras;
+    let uid_index = unique_identifier_index(book);
+    let uid = book
+        .metadata
+        .identifiers
+        .get(uid_index)
+        .and_then(|value| attribute(extras.attributes_for("identifier", value), "id"))
+        .unwrap_or("uid");
+
+    // OPF 2 has neither prefixes nor a package language or direction
+    let epub2 = version.starts_with('2');
+    let package_attrs: Vec<(String, String)> = extras
+        .package_attributes
+        .iter()
+        .filter(|(key, _)| !epub2 || !matches!(key.as_str(), "prefix" | "xml:lang" | "dir"))
+        .cloned()
+        .collect();
+
+    let comments = |opf: &mut String, position: CommentPosition, indent: &str| {
+        for comment in extras.comments.iter().filter(|c| c.position == position) {
+            opf.push_str(&format!("{indent}{}\n", comment.text));
+        }
+    };
+
     let mut opf = String::new();
     opf.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
-    opf.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"uid\">\n");
-
-    // Metadata
-    opf.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    comments(&mut opf, CommentPosition::Prolog, "");
+    opf.push_str(&format!(
+        "<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"{version}\" unique-identifier=\"{}\"{}>\n",
+        xml_escape(uid),
+        render_attrs(&package_attrs)
+    ));
 
-    for (i, id) in book.metadata.identifiers.iter().enumerate() {
-        if i == 0 {
-            opf.push_str(&format!(
-                "    <dc:identifier id=\"uid\">{}</dc:identifier>\n",
-                xml_escape(id)
-            ));
-        } else {
-            opf.push_str(&format!(
-                "    <dc:identifier>{}</dc:identifier>\n",
-                xml_escape(id)
-            ));
+    // Metadata; EPUB 2 expresses refinements as opf: attributes
+    let mut metadata_attrs: Vec<(String, String)> = extras
+        .metadata_attributes
+        .iter()
+        .filter(|(key, _)| key != "xmlns:dc")
+        .cloned()
+        .collect();
+    let declares_opf = |attrs: &[(String, String)]| attrs.iter().any(|(key, _)| key == "xmlns:opf");
+    if epub2 && !declares_opf(&metadata_attrs) && !declares_opf(&extras.package_attributes) {
+        metadata_attrs.push((
+            "xmlns:opf".to_string(),
+            "http://www.idpf.org/2007/opf".to_string(),
+        ));
+    }
+    comments(&mut opf, before("metadata"), "  ");
+    opf.push_str(&format!(
+        "  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\"{}>\n",
+        render_attrs(&metadata_attrs)
+    ));
+    comments(&mut opf, CommentPosition::Metadata, "    ");
+    let mut ids = RefinementIds::new(&book.metadata);
+    for (i, value) in book.metadata.identifiers.iter().enumerate() {
+        let mut attrs = extras.attributes_for("identifier", value).to_vec();
+        if i == uid_index && attribute(&attrs, "id").is_none() {
+            attrs.insert(0, ("id".to_string(), uid.to_string()));
         }
+        opf.push_str(&format!(
+            "    <dc:identifier{}>{}</dc:identifier>\n",
+            render_attrs(&attrs),
+            xml_escape(value)
+        ));
     }
     if book.metadata.identifiers.is_empty() {
         let uuid = uuid::Uuid::new_v4();
@@ -109,117 +642,463 @@ fn generate_opf(book: &EpubBook) -> String {
     }
 
     for title in &book.metadata.titles {
-        opf.push_str(&format!("    <dc:title>{}</dc:title>\n", xml_escape(title)));
+        render_title(&mut opf, title, epub2, extras, &mut ids);
     }
 
     for lang in &book.metadata.languages {
-        opf.push_str(&format!("    <dc:language>{lang}</dc:language>\n"));
+        push_dc(&mut opf, extras, "language", lang);
     }
     if book.metadata.languages.is_empty() {
         opf.push_str("    <dc:language>en</dc:language>\n");
     }
 
     for creator in &book.metadata.creators {
-        opf.push_str(&format!(
-            "    <dc:creator>{}</dc:creator>\n",
-            xml_escape(creator)
-        ));
+        render_contributor(&mut opf, "creator", creator, epub2, extras, &mut ids);
+    }
+    for contributor in &book.metadata.contributors {
+        render_contributor(
+            &mut opf,
+            "contributor",
+            contributor,
+            epub2,
+            extras,
+            &mut ids,
+        );
     }
 
     for publisher in &book.metadata.publishers {
-        opf.push_str(&format!(
-            "    <dc:publisher>{}</dc:publisher>\n",
-            xml_escape(publisher)
-        ));
+        push_dc(&mut opf, extras, "publisher", publisher);
     }
 
     if let Some(ref desc) = book.metadata.description {
-        opf.push_str(&format!(
-            "    <dc:description>{}</dc:description>\n",
-            xml_escape(desc)
-        ));
+        push_dc(&mut opf, extras, "description", desc);
     }
 
     for subject in &book.metadata.subjects {
-        opf.push_str(&format!(
-            "    <dc:subject>{}</dc:subject>\n",
-            xml_escape(subject)
-        ));
+        push_dc(&mut opf, extras, "subject", subject);
     }
 
     if let Some(ref rights) = book.metadata.rights {
-        opf.push_str(&format!(
-            "    <dc:rights>{}</dc:rights>\n",
-            xml_escape(rights)
-        ));
+        push_dc(&mut opf, extras, "rights", rights);
     }
 
     for date in &book.metadata.dates {
-        opf.push_str(&format!("    <dc:date>{}</dc:date>\n", xml_escape(date)));
+        push_dc(&mut opf, extras, "date", date);
     }
 
-    // Modified timestamp (required for EPUB 3)
-    opf.push_str("    <meta property=\"dcterms:modified\">");
-    if let Some(ref modified) = book.metadata.modified {
-        opf.push_str(modified);
-    } else {
-        opf.push_str(&format_iso8601());
+    // Modified timestamp (required for EPUB 3, which introduced it)
+    if !epub2 {
+        opf.push_str("    <meta property=\"dcterms:modified\">");
+        if let Some(ref modified) = book.metadata.modified {
+            opf.push_str(modified);
+        } else {
+            opf.push_str(&format_iso8601());
+        }
+        opf.push_str("</meta>\n");
     }
-    opf.push_str("</meta>\n");
 
-    // Custom metadata properties
+    // Custom metadata properties; OPF 2 meta elements are name/content pairs
+    let push_meta = |opf: &mut String, key: &str, value: &str| {
+        if epub2 {
+            opf.push_str(&format!(
+                "    <meta name=\"{}\" content=\"{}\"/>\n",
+                xml_escape(key),
+                xml_escape(value)
+            ));
+        } else {
+            opf.push_str(&format!(
+                "    <meta property=\"{}\">{}</meta>\n",
+                xml_escape(key),
+                xml_escape(value)
+            ));
+        }
+    };
     let mut custom_keys: Vec<&String> = book.metadata.custom.keys().collect();
     custom_keys.sort();
     for key in custom_keys {
-        let value = &book.metadata.custom[key];
+        push_meta(&mut opf, key, &book.metadata.custom[key]);
+    }
+
+    // Accessibility metadata; a conformance claim given as a URL is a link
+    for (property, value) in book.metadata.accessibility.properties() {
+        if !epub2 && property == accessibility::CONFORMS_TO && value.starts_with("http") {
+            opf.push_str(&format!(
+                "    <link rel=\"{property}\" href=\"{}\"/>\n",
+                xml_escape(value)
+            ));
+        } else {
+            push_meta(&mut opf, property, value);
+        }
+    }
+
+    if let Some(ref cover) = book.metadata.cover_id {
         opf.push_str(&format!(
-            "    <meta property=\"{}\">{}</meta>\n",
-            xml_escape(key),
-            xml_escape(value)
+            "    <meta name=\"cover\" content=\"{}\"/>\n",
+            xml_escape(cover)
         ));
     }
 
+    for element in &extras.metadata_elements {
+        if epub2 && is_epub3_metadata(element) {
+            continue;
+        }
+        opf.push_str(&format!("    {element}\n"));
+    }
+
+    comments(&mut opf, end("metadata"), "    ");
     opf.push_str("  </metadata>\n");
 
     // Manifest
+    comments(&mut opf, before("manifest"), "  ");
     opf.push_str("  <manifest>\n");
-    opf.push_str("    <item id=\"toc\" href=\"toc.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n");
-    opf.push_str(
-        "    <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n",
-    );
-
-    for item in &book.manifest {
-        let props = if let Some(ref p) = item.properties {
-            format!(" properties=\"{p}\"")
-        } else {
-            String::new()
-        };
+    for item in manifest {
+        comments(&mut opf, CommentPosition::Item(item.id.clone()), "    ");
+        let mut attrs = Vec::new();
+        if let Some(ref fallback) = item.fallback {
+            attrs.push(("fallback".to_string(), fallback.clone()));
+        }
+        if !epub2 {
+            if let Some(ref p) = item.properties {
+                attrs.push(("properties".to_string(), p.clone()));
+            }
+            if let Some(ref overlay) = item.media_overlay {
+                attrs.push(("media-overlay".to_string(), overlay.clone()));
+            }
+        }
+        attrs.extend(item.attributes.iter().cloned());
         opf.push_str(&format!(
-            "    <item id=\"{}\" href=\"{}\" media-type=\"{}\"{props}/>\n",
+            "    <item id=\"{}\" href=\"{}\" media-type=\"{}\"{}/>\n",
             xml_escape(&item.id),
             xml_escape(&item.href),
-            xml_escape(&item.media_type)
+            xml_escape(&item.media_type),
+            render_attrs(&attrs)
         ));
     }
+    comments(&mut opf, end("manifest"), "    ");
     opf.push_str("  </manifest>\n");
 
     // Spine
-    opf.push_str("  <spine toc=\"ncx\">\n");
+    let mut spine_attrs = extras.spine_attributes.clone();
+    if let Some(direction) = extras
+        .page_progression_direction
+        .as_ref()
+        .filter(|_| !epub2)
+    {
+        spine_attrs.insert(
+            0,
+            ("page-progression-direction".to_string(), direction.clone()),
+        );
+    }
+    if let Some(id) = ncx_id {
+        spine_attrs.insert(0, ("toc".to_string(), id.to_string()));
+    }
+    comments(&mut opf, before("spine"), "  ");
+    opf.push_str(&format!("  <spine{}>\n", render_attrs(&spine_attrs)));
     for item in &book.spine {
+        comments(
+            &mut opf,
+            CommentPosition::ItemRef(item.idref.clone()),
+            "    ",
+        );
+        let id = id_attr(item.id.as_deref());
         let linear = if item.linear { "" } else { " linear=\"no\"" };
+        let props = match item.properties {
+            Some(ref p) if !epub2 => format!(" properties=\"{}\"", xml_escape(p)),
+            _ => String::new(),
+        };
         opf.push_str(&format!(
-            "    <itemref idref=\"{}\"{linear}/>\n",
-            item.idref
+            "    <itemref{id} idref=\"{}\"{linear}{props}{}/>\n",
+            xml_escape(&item.idref),
+            render_attrs(&item.attributes)
         ));
     }
+    comments(&mut opf, end("spine"), "    ");
     opf.push_str("  </spine>\n");
 
+    // The guide serves the same EPUB 2 reading systems as the NCX
+    if (epub2 || ncx_id.is_some()) && !landmarks.is_empty() {
+        let nav_href = manifest
+            .iter()
+            .find(|item| item.is_nav())
+            .map_or("", |item| item.href.as_str());
+        comments(&mut opf, before("guide"), "  ");
+        opf.push_str("  <guide>\n");
+        for landmark in landmarks {
+            let guide_type = navigation::epub_type_to_guide_type(
+                landmark.epub_type.as_deref().unwrap_or("text"),
+            );
+            // Fragment-only landmarks point into the nav document itself
+            let href = if landmark.href.starts_with('#') {
+                format!("{nav_href}{}", landmark.href)
+            } else {
+                landmark.href.clone()
+            };
+            opf.push_str(&format!(
+                "    <reference type=\"{}\" title=\"{}\" href=\"{}\"/>\n",
+                xml_escape(&guide_type),
+                xml_escape(&landmark_label(landmark)),
+                xml_escape(&href)
+            ));
+        }
+        comments(&mut opf, end("guide"), "    ");
+        opf.push_str("  </guide>\n");
+    }
+
+    for element in &extras.trailing_elements {
+        // <bindings> and <collection> are EPUB 3 only; the comments before
+        // an element are kept with it
+        let mut tag = element.as_str();
+        while let Some((_, rest)) = tag
+            .strip_prefix("<!--")
+            .and_then(|rest| rest.split_once("-->"))
+        {
+            tag = rest.trim_start();
+        }
+        if epub2 && !tag.starts_with("<tours") {
+            continue;
+        }
+        opf.push_str(&format!("  {element}\n"));
+    }
+
+    comments(&mut opf, end("package"), "  ");
     opf.push_str("</package>\n");
+    comments(&mut opf, CommentPosition::Epilogue, "");
     opf
 }
 
-fn generate_toc_xhtml(toc: &[NavPoint], titles: &[String]) -> String {
-    let title = titles.first().map_or("Table of Contents", |s| s.as_str());
+fn before(element: &str) -> CommentPosition {
+    CommentPosition::Before(element.to_string())
+}
+
+fn end(element: &str) -> CommentPosition {
+    CommentPosition::End(element.to_string())
+}
+
+/// Whether a raw metadata element only exists in EPUB 3: `<link>`, and
+/// `<meta>` with a `property` or `refines`
+fn is_epub3_metadata(element: &str) -> bool {
+    let tag_end = element.find('>').unwrap_or(element.len());
+    let tag = &element[..tag_end];
+    let name = tag
+        .trim_start_matches('<')
+        .split(|c: char| c.is_whitespace() || c == '/')
+        .next()
+        .unwrap_or("");
+    let name = name.rsplit(':').next().unwrap_or(name);
+    name == "link" || (name == "meta" && (tag.contains(" property=") || tag.contains(" refines=")))
+}
+
+/// Allocates ids for titles and contributors that need `refines` targets
+struct RefinementIds<'a> {
+    used: HashSet<&'a str>,
+    counter: usize,
+}
+
+impl<'a> RefinementIds<'a> {
+    fn new(metadata: &'a EpubMetadata) -> Self {
+        let used = metadata
+            .titles
+            .iter()
+            .filter_map(|t| t.id.as_deref())
+            .chain(
+                metadata
+                    .creators
+                    .iter()
+                    .chain(&metadata.contributors)
+                    .filter_map(|c| c.id.as_deref()),
+            )
+            .collect();
+        Self { used, counter: 0 }
+    }
+
+    /// The element's own id, or a fresh one when it has refinements to emit
+    fn get(&mut self, id: Option<&str>, prefix: &str, refined: bool) -> Option<String> {
+        if let Some(id) = id {
+            return Some(id.to_string());
+        }
+        if !refined {
+            return None;
+        }
+        loop {
+            self.counter += 1;
+            let candidate = format!("{prefix}{}", self.counter);
+            if !self.used.contains(candidate.as_str()) {
+                return Some(candidate);
+            }
+        }
+    }
+}
+
+fn render_title(
+    opf: &mut String,
+    title: &Title,
+    epub2: bool,
+    extras: &OpfExtras,
+    ids: &mut RefinementIds,
+) {
+    let refined = !epub2
+        && (title.title_type.is_some()
+            || title.file_as.is_some()
+            || !title.alternate_script.is_empty()
+            || title.display_seq.is_some());
+    let id = ids.get(title.id.as_deref(), "title", refined);
+    opf.push_str(&format!(
+        "    <dc:title{}{}>{}</dc:title>\n",
+        id_attr(id.as_deref()),
+        render_attrs(extras.attributes_for("title", &title.value)),
+        xml_escape(&title.value)
+    ));
+    if let Some(id) = id.filter(|_| !epub2) {
+        if let Some(ref title_type) = title.title_type {
+            push_refinement(opf, &id, "title-type", &[], title_type);
+        }
+        if let Some(ref file_as) = title.file_as {
+            push_refinement(opf, &id, "file-as", &[], file_as);
+        }
+        for alternate in &title.alternate_script {
+            push_alternate_script(opf, &id, alternate);
+        }
+        if let Some(seq) = title.display_seq {
+            push_refinement(opf, &id, "display-seq", &[], &seq.to_string());
+        }
+    }
+}
+
+fn render_contributor(
+    opf: &mut String,
+    element: &str,
+    contributor: &Contributor,
+    epub2: bool,
+    extras: &OpfExtras,
+    ids: &mut RefinementIds,
+) {
+    let extra_attrs = render_attrs(extras.attributes_for(element, &contributor.name));
+    if epub2 {
+        let mut attrs = id_attr(contributor.id.as_deref());
+        if let Some(role) = contributor.roles.first() {
+            attrs.push_str(&format!(" opf:role=\"{}\"", xml_escape(role)));
+        }
+        if let Some(ref file_as) = contributor.file_as {
+            attrs.push_str(&format!(" opf:file-as=\"{}\"", xml_escape(file_as)));
+        }
+        attrs.push_str(&extra_attrs);
+        opf.push_str(&format!(
+            "    <dc:{element}{attrs}>{}</dc:{element}>\n",
+            xml_escape(&contributor.name)
+        ));
+        return;
+    }
+
+    let refined = !contributor.roles.is_empty()
+        || contributor.file_as.is_some()
+        || !contributor.alternate_script.is_empty()
+        || contributor.display_seq.is_some();
+    let id = ids.get(contributor.id.as_deref(), element, refined);
+    opf.push_str(&format!(
+        "    <dc:{element}{}{extra_attrs}>{}</dc:{element}>\n",
+        id_attr(id.as_deref()),
+        xml_escape(&contributor.name)
+    ));
+    if let Some(id) = id {
+        let relators = [("scheme".to_string(), MARC_RELATORS.to_string())];
+        for role in &contributor.roles {
+            let attrs = contributor
+                .refinement_attributes_for("role", role)
+                .unwrap_or(&relators);
+            push_refinement(opf, &id, "role", attrs, role);
+        }
+        if let Some(ref file_as) = contributor.file_as {
+            let attrs = contributor
+                .refinement_attributes_for("file-as", file_as)
+                .unwrap_or_default();
+            push_refinement(opf, &id, "file-as", attrs, file_as);
+        }
+        for alternate in &contributor.alternate_script {
+            push_alternate_script(opf, &id, alternate);
+        }
+        if let Some(seq) = contributor.display_seq {
+            push_refinement(opf, &id, "display-seq", &[], &seq.to_string());
+        }
+    }
+}
+
+/// A Dublin Core element with the attributes preserved for it
+fn push_dc(opf: &mut String, extras: &OpfExtras, element: &str, value: &str) {
+    opf.push_str(&format!(
+        "    <dc:{element}{}>{}</dc:{element}>\n",
+        render_attrs(extras.attributes_for(element, value)),
+        xml_escape(value)
+    ));
+}
+
+/// Index of the identifier written as the package's unique identifier:
+/// the one the source package pointed at, else the first
+/// The identifier `package@unique-identifier` is written to point at
+fn package_uid(book: &EpubBook) -> Option<&str> {
+    book.metadata
+        .identifiers
+        .get(unique_identifier_index(book))
+        .map(String::as_str)
+}
+
+fn unique_identifier_index(book: &EpubBook) -> usize {
+    let extras = &book.opf_extras;
+    extras
+        .unique_identifier_id
+        .as_deref()
+        .and_then(|uid| {
+            book.metadata.identifiers.iter().position(|value| {
+                attribute(extras.attributes_for("identifier", value), "id") == Some(uid)
+            })
+        })
+        .unwrap_or(0)
+}
+
+fn attribute<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn render_attrs(attrs: &[(String, String)]) -> String {
+    attrs
+        .iter()
+        .map(|(key, value)| format!(" {key}=\"{}\"", xml_escape(value)))
+        .collect()
+}
+
+fn id_attr(id: Option<&str>) -> String {
+    id.map(|id| format!(" id=\"{}\"", xml_escape(id)))
+        .unwrap_or_default()
+}
+
+fn push_refinement(
+    opf: &mut String,
+    id: &str,
+    property: &str,
+    attrs: &[(String, String)],
+    value: &str,
+) {
+    opf.push_str(&format!(
+        "    <meta refines=\"#{}\" property=\"{property}\"{}>{}</meta>\n",
+        xml_escape(id),
+        render_attrs(attrs),
+        xml_escape(value)
+    ));
+}
+
+fn push_alternate_script(opf: &mut String, id: &str, alternate: &AlternateScript) {
+    opf.push_str(&format!(
+        "    <meta refines=\"#{}\" property=\"alternate-script\" xml:lang=\"{}\">{}</meta>\n",
+        xml_escape(id),
+        xml_escape(&alternate.lang),
+        xml_escape(&alternate.value)
+    ));
+}
+
+fn generate_toc_xhtml(navigation: &Navigation, title: Option<&str>) -> String {
+    let title = title.unwrap_or("Table of Contents");
     let mut html = String::new();
     html.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
     html.push_str("<!DOCTYPE html>\n");
@@ -228,14 +1107,49 @@ fn generate_toc_xhtml(toc: &[NavPoint], titles: &[String]) -> String {
     html.push_str(&xml_escape(title));
     html.push_str("</title></head>\n");
     html.push_str("<body>\n");
-    html.push_str("<nav epub:type=\"toc\">\n");
+    html.push_str("<nav epub:type=\"toc\" id=\"toc\">\n");
     html.push_str("<h1>Table of Contents</h1>\n");
-    write_nav_ol(&mut html, toc);
+    write_nav_ol(&mut html, &navigation.toc);
     html.push_str("</nav>\n");
+    if !navigation.landmarks.is_empty() {
+        html.push_str("<nav epub:type=\"landmarks\" hidden=\"hidden\">\n");
+        html.push_str("<h2>Landmarks</h2>\n");
+        html.push_str("<ol>\n");
+        for landmark in &navigation.landmarks {
+            let epub_type = landmark
+                .epub_type
+                .as_ref()
+                .map(|t| format!(" epub:type=\"{}\"", xml_escape(t)))
+                .unwrap_or_default();
+            html.push_str(&format!(
+                "<li><a{epub_type} href=\"{}\">{}</a></li>\n",
+                xml_escape(&landmark.href),
+                xml_escape(&landmark_label(landmark))
+            ));
+        }
+        html.push_str("</ol>\n");
+        html.push_str("</nav>\n");
+    }
+    if !navigation.page_list.is_empty() {
+        html.push_str("<nav epub:type=\"page-list\" hidden=\"hidden\">\n");
+        html.push_str("<h2>Pages</h2>\n");
+        write_nav_ol(&mut html, &navigation.page_list);
+        html.push_str("</nav>\n");
+    }
     html.push_str("</body>\n</html>\n");
     html
 }
 
+/// Label of a landmark, falling back to its type for untitled guide
+/// references
+fn landmark_label(landmark: &NavPoint) -> String {
+    if landmark.label.is_empty() {
+        landmark.epub_type.clone().unwrap_or_default()
+    } else {
+        landmark.label.clone()
+    }
+}
+
 fn write_nav_ol(html: &mut String, points: &[NavPoint]) {
     if points.is_empty() {
         return;
@@ -256,9 +1170,11 @@ fn write_nav_ol(html: &mut String, points: &[NavPoint]) {
     html.push_str("</ol>\n");
 }
 
-fn generate_toc_ncx(toc: &[NavPoint], titles: &[String], identifiers: &[String]) -> String {
-    let title = titles.first().map_or("", |s| s.as_str());
-    let uid = identifiers.first().map_or("", |s| s.as_str());
+/// Generate the NCX; `uid` is the package's unique identifier, which
+/// `dtb:uid` has to match
+fn generate_toc_ncx(navigation: &Navigation, title: Option<&str>, uid: Option<&str>) -> String {
+    let title = title.unwrap_or_default();
+    let uid = uid.unwrap_or_default();
 
     let mut ncx = String::new();
     ncx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
@@ -268,18 +1184,72 @@ fn generate_toc_ncx(toc: &[NavPoint], titles: &[String], identifiers: &[String])
         "  <meta name=\"dtb:uid\" content=\"{}\"/>\n",
         xml_escape(uid)
     ));
+    ncx.push_str(&format!(
+        "  <meta name=\"dtb:depth\" content=\"{}\"/>\n",
+        nav_depth(&navigation.toc).max(1)
+    ));
+    let max_page = navigation
+        .page_list
+        .iter()
+        .filter_map(|page| page.label.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0);
+    ncx.push_str(&format!(
+        "  <meta name=\"dtb:totalPageCount\" content=\"{}\"/>\n",
+        navigation.page_list.len()
+    ));
+    ncx.push_str(&format!(
+        "  <meta name=\"dtb:maxPageNumber\" content=\"{max_page}\"/>\n"
+    ));
     ncx.push_str("</head>\n");
     ncx.push_str(&format!(
         "<docTitle><text>{}</text></docTitle>\n",
         xml_escape(title)
     ));
     ncx.push_str("<navMap>\n");
-    write_ncx_points(&mut ncx, toc, &mut 1);
+    let mut play_order = 1;
+    write_ncx_points(&mut ncx, &navigation.toc, &mut play_order);
     ncx.push_str("</navMap>\n");
+    if !navigation.page_list.is_empty() {
+        ncx.push_str("<pageList>\n");
+        ncx.push_str("<navLabel><text>Pages</text></navLabel>\n");
+        for (i, page) in navigation.page_list.iter().enumerate() {
+            // Only numbered pages have a value; anything else is special
+            let kind = if !page.label.is_empty() && page.label.chars().all(|c| c.is_ascii_digit()) {
+                format!("type=\"normal\" value=\"{}\"", page.label)
+            } else {
+                "type=\"special\"".to_string()
+            };
+            ncx.push_str(&format!(
+                "<pageTarget id=\"page-{}\" {kind} playOrder=\"{play_order}\">\n",
+                i + 1
+            ));
+            play_order += 1;
+            ncx.push_str(&format!(
+                "  <navLabel><text>{}</text></navLabel>\n",
+                xml_escape(&page.label)
+            ));
+            ncx.push_str(&format!(
+                "  <content src=\"{}\"/>\n",
+                xml_escape(&page.href)
+            ));
+            ncx.push_str("</pageTarget>\n");
+        }
+        ncx.push_str("</pageList>\n");
+    }
     ncx.push_str("</ncx>\n");
     ncx
 }
 
+/// Nesting depth of a navigation tree
+fn nav_depth(points: &[NavPoint]) -> usize {
+    points
+        .iter()
+        .map(|point| 1 + nav_depth(&point.children))
+        .max()
+        .unwrap_or(0)
+}
+
 fn write_ncx_points(ncx: &mut String, points: &[NavPoint], counter: &mut usize) {
     for point in points {
         let id = *counter;
@@ -322,8 +1292,8 @@ mod tests {
 
         EpubBook {
             metadata: EpubMetadata {
-                titles: vec!["Test Title".to_string()],
-                creators: vec!["Test Author".to_string()],
+                titles: vec![Title::new("Test Title")],
+                creators: vec![Contributor::new("Test Author")],
                 identifiers: vec!["urn:uuid:12345".to_string()],
                 languages: vec!["en".to_string()],
                 publishers: vec!["Test Publisher".to_string()],
@@ -340,34 +1310,38 @@ mod tests {
                 href: "ch1.xhtml".to_string(),
                 media_type: "application/xhtml+xml".to_string(),
                 properties: None,
+                ..Default::default()
             }],
             spine: vec![SpineItem {
                 idref: "ch1".to_string(),
                 linear: true,
                 properties: None,
+                ..Default::default()
             }],
             navigation: Navigation {
                 toc: vec![NavPoint {
                     label: "Chapter 1".to_string(),
                     href: "ch1.xhtml".to_string(),
                     children: Vec::new(),
+                    ..Default::default()
                 }],
                 ..Default::default()
             },
-            resources,
+            resources: resources.into(),
+            ..Default::default()
         }
     }
 
     #[test]
     fn test_generate_container_xml() {
-        let xml = generate_container_xml();
+        let xml = generate_container_xml(&[Rendition::new("OEBPS/content.opf")]);
         insta::assert_snapshot!("container_xml", xml);
     }
 
     #[test]
     fn test_generate_opf_full() {
         let book = test_book();
-        let opf = generate_opf(&book);
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V3);
         insta::assert_snapshot!("opf_full", opf);
     }
 
@@ -380,7 +1354,7 @@ mod tests {
             },
             ..Default::default()
         };
-        let opf = generate_opf(&book);
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V3);
         // Should have auto-generated UUID and default language
         assert!(opf.contains("dc:language>en</dc:language"));
         assert!(opf.contains("urn:uuid:"));
@@ -389,7 +1363,7 @@ mod tests {
     #[test]
     fn test_generate_opf_dates_and_custom() {
         let book = test_book();
-        let opf = generate_opf(&book);
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V3);
         assert!(
             opf.contains("<dc:date>2024-01-01</dc:date>"),
             "missing dc:date"
@@ -407,15 +1381,20 @@ mod tests {
                 label: "Chapter 1".to_string(),
                 href: "ch1.xhtml".to_string(),
                 children: Vec::new(),
+                ..Default::default()
             },
             NavPoint {
                 label: "Chapter 2".to_string(),
                 href: "ch2.xhtml".to_string(),
                 children: Vec::new(),
+                ..Default::default()
             },
         ];
-        let titles = vec!["My Book".to_string()];
-        let html = generate_toc_xhtml(&toc, &titles);
+        let navigation = Navigation {
+            toc,
+            ..Default::default()
+        };
+        let html = generate_toc_xhtml(&navigation, Some("My Book"));
         insta::assert_snapshot!("toc_xhtml", html);
     }
 
@@ -426,16 +1405,20 @@ mod tests {
                 label: "Chapter 1".to_string(),
                 href: "ch1.xhtml".to_string(),
                 children: Vec::new(),
+                ..Default::default()
             },
             NavPoint {
                 label: "Chapter 2".to_string(),
                 href: "ch2.xhtml".to_string(),
                 children: Vec::new(),
+                ..Default::default()
             },
         ];
-        let titles = vec!["My Book".to_string()];
-        let ids = vec!["urn:uuid:12345".to_string()];
-        let ncx = generate_toc_ncx(&toc, &titles, &ids);
+        let navigation = Navigation {
+            toc,
+            ..Default::default()
+        };
+        let ncx = generate_toc_ncx(&navigation, Some("My Book"), Some("urn:uuid:12345"));
         insta::assert_snapshot!("toc_ncx", ncx);
     }
 
@@ -462,9 +1445,451 @@ mod tests {
         assert!(epub_path.exists());
 
         // Read back and verify
-        let book2 = crate::epub::reader::read_epub(&epub_path).unwrap();
-        assert_eq!(book2.metadata.titles, vec!["Test Title"]);
-        assert_eq!(book2.metadata.creators, vec!["Test Author"]);
+        let book2 = crate::epub::reader::read_epub_with(&epub_path, &Default::default()).unwrap();
+        assert_eq!(book2.metadata.title(), Some("Test Title"));
+        assert_eq!(book2.metadata.creator_names(), vec!["Test Author"]);
         assert_eq!(book2.spine.len(), 1);
     }
+
+    #[test]
+    fn test_write_epub_in_memory_roundtrip() {
+        let book = test_book();
+        let buffer = write_epub_to(&book, std::io::Cursor::new(Vec::new()), &Default::default())
+            .unwrap()
+            .into_inner();
+
+        let mut piped = Vec::new();
+        write_epub_to_stream(&book, &mut piped, &Default::default()).unwrap();
+        assert_eq!(piped.len(), buffer.len());
+
+        let options = crate::epub::reader::ReadOptions::default();
+        let book2 = crate::epub::reader::read_epub_from_stream(&buffer[..], &options).unwrap();
+        assert_eq!(book2.metadata.title(), Some("Test Title"));
+        let book3 =
+            crate::epub::reader::read_epub_from(std::io::Cursor::new(buffer), &options).unwrap();
+        assert_eq!(book3.spine.len(), 1);
+    }
+
+    #[test]
+    fn test_write_landmarks_and_page_list() {
+        let mut book = test_book();
+        book.navigation.landmarks = vec![
+            NavPoint {
+                label: "Contents".to_string(),
+                href: "toc.xhtml#toc".to_string(),
+                epub_type: Some("toc".to_string()),
+                ..Default::default()
+            },
+            NavPoint {
+                label: "Start".to_string(),
+                href: "ch1.xhtml".to_string(),
+                epub_type: Some("bodymatter".to_string()),
+                ..Default::default()
+            },
+        ];
+        book.navigation.page_list = vec![NavPoint {
+            label: "1".to_string(),
+            href: "ch1.xhtml#p1".to_string(),
+            ..Default::default()
+        }];
+
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V3);
+        assert!(
+            opf.contains("<reference type=\"toc\" title=\"Contents\" href=\"toc.xhtml#toc\"/>")
+        );
+        assert!(opf.contains("<reference type=\"text\" title=\"Start\" href=\"ch1.xhtml\"/>"));
+        let ncx = generate_toc_ncx(&book.navigation, None, package_uid(&book));
+        assert!(
+            ncx.contains("<pageTarget id=\"page-1\" type=\"normal\" value=\"1\" playOrder=\"2\">")
+        );
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let epub_path = tmp.path().join("test.epub");
+        write_epub(&book, &epub_path).unwrap();
+        let book2 = crate::epub::reader::read_epub_with(&epub_path, &Default::default()).unwrap();
+        assert_eq!(book2.navigation.landmarks, book.navigation.landmarks);
+        assert_eq!(book2.navigation.page_list, book.navigation.page_list);
+    }
+
+    #[test]
+    fn test_epub2_guide_becomes_landmarks() {
+        let mut book = test_book();
+        book.navigation.landmarks = vec![NavPoint {
+            label: "Cover".to_string(),
+            href: "ch1.xhtml".to_string(),
+            epub_type: Some("cover".to_string()),
+            ..Default::default()
+        }];
+        let opf = render_opf(
+            &book,
+            "2.0",
+            &book.manifest,
+            &book.navigation.landmarks,
+            None,
+        );
+        let parsed = crate::epub::opf::parse_opf(&opf).unwrap();
+        assert_eq!(parsed.guide, book.navigation.landmarks);
+        assert!(parsed.extras.trailing_elements.is_empty());
+    }
+
+    fn refined_book() -> EpubBook {
+        let mut book = test_book();
+        book.metadata.titles.push(Title {
+            title_type: Some("subtitle".to_string()),
+            ..Title::new("A Subtitle")
+        });
+        book.metadata.creators = vec![Contributor {
+            roles: vec!["aut".to_string(), "ill".to_string()],
+            file_as: Some("Author, Test".to_string()),
+            alternate_script: vec![AlternateScript {
+                lang: "ja".to_string(),
+                value: "テスト".to_string(),
+            }],
+            display_seq: Some(1),
+            ..Contributor::new("Test Author")
+        }];
+        book.metadata.contributors = vec![Contributor {
+            id: Some("creator1".to_string()),
+            roles: vec!["edt".to_string()],
+            ..Contributor::new("An Editor")
+        }];
+        book
+    }
+
+    #[test]
+    fn test_opf_refinements_roundtrip() {
+        let book = refined_book();
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V3);
+        assert!(opf.contains(
+            "<meta refines=\"#creator2\" property=\"role\" scheme=\"marc:relators\">aut</meta>"
+        ));
+
+        let parsed = crate::epub::opf::parse_opf(&opf).unwrap().metadata;
+        assert_eq!(
+            parsed.titles[1].title_type,
+            book.metadata.titles[1].title_type
+        );
+        let creator = Contributor {
+            id: Some("creator2".to_string()),
+            ..book.metadata.creators[0].clone()
+        };
+        assert_eq!(parsed.creators, vec![creator]);
+        assert_eq!(parsed.contributors, book.metadata.contributors);
+        assert_eq!(parsed.custom, book.metadata.custom);
+    }
+
+    #[test]
+    fn test_opf_refinement_attributes_roundtrip() {
+        let opf = r##"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:test</dc:identifier>
+    <dc:title>T</dc:title>
+    <dc:creator id="c1">Lewis Carroll</dc:creator>
+    <meta refines="#c1" property="role" id="r1" scheme="marc:relators">aut</meta>
+    <meta refines="#c1" property="role">ill</meta>
+    <meta refines="#c1" property="role" scheme="onix:codelist17">A01</meta>
+    <meta refines="#c1" property="file-as" xml:lang="en">Carroll, Lewis</meta>
+  </metadata>
+  <manifest/>
+  <spine/>
+</package>"##;
+        let data = crate::epub::opf::parse_opf(opf).unwrap();
+        let book = EpubBook {
+            metadata: data.metadata,
+            opf_extras: data.extras,
+            ..Default::default()
+        };
+        let out = render_opf(&book, "3.0", &[], &[], None);
+        for line in [
+            r##"<meta refines="#c1" property="role" id="r1" scheme="marc:relators">aut</meta>"##,
+            r##"<meta refines="#c1" property="role">ill</meta>"##,
+            r##"<meta refines="#c1" property="role" scheme="onix:codelist17">A01</meta>"##,
+            r##"<meta refines="#c1" property="file-as" xml:lang="en">Carroll, Lewis</meta>"##,
+        ] {
+            assert_eq!(out.matches(line).count(), 1, "{line} in {out}");
+        }
+        let reparsed = crate::epub::opf::parse_opf(&out).unwrap();
+        assert_eq!(reparsed.metadata.creators, book.metadata.creators);
+    }
+
+    #[test]
+    fn test_opf_epub2_contributor_attributes() {
+        let book = refined_book();
+        let opf = render_opf(
+            &book,
+            "2.0",
+            &book.manifest,
+            &book.navigation.landmarks,
+            None,
+        );
+        assert!(opf.contains(
+            "<dc:creator opf:role=\"aut\" opf:file-as=\"Author, Test\">Test Author</dc:creator>"
+        ));
+        assert!(!opf.contains("refines="));
+
+        let parsed = crate::epub::opf::parse_opf(&opf).unwrap().metadata;
+        assert_eq!(parsed.creators[0].roles, vec!["aut"]);
+        assert_eq!(parsed.creators[0].file_as.as_deref(), Some("Author, Test"));
+    }
+
+    #[test]
+    fn test_opf_extras_roundtrip() {
+        let opf = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="pub-id" xml:lang="en" dir="ltr">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="pub-id" opf:scheme="ISBN">9780000000000</dc:identifier>
+    <dc:title>Test Book</dc:title>
+    <dc:language>en</dc:language>
+    <dc:source>urn:isbn:9781111111111</dc:source>
+    <meta name="calibre:series" content="Tests"/>
+  </metadata>
+  <manifest>
+    <item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml" media-overlay="ch1-smil"/>
+    <item id="ch1-smil" href="ch1.smil" media-type="application/smil+xml"/>
+    <item id="widget" href="widget.js" media-type="application/javascript" fallback="ch1" data-vendor="a&amp;b"/>
+  </manifest>
+  <spine page-progression-direction="rtl" id="spine">
+    <itemref idref="ch1" id="ref-ch1" data-page="1"/>
+  </spine>
+  <collection role="index"><link href="ch1.xhtml"/></collection>
+</package>"#;
+        let data = crate::epub::opf::parse_opf(opf).unwrap();
+        let book = EpubBook {
+            metadata: data.metadata,
+            manifest: data.manifest,
+            spine: data.spine,
+            opf_extras: data.extras,
+            ..Default::default()
+        };
+
+        let out = render_opf(
+            &book,
+            "3.0",
+            &book.manifest,
+            &book.navigation.landmarks,
+            None,
+        );
+        assert!(out.contains("unique-identifier=\"pub-id\""));
+        assert!(out.contains(
+            "<dc:identifier id=\"pub-id\" opf:scheme=\"ISBN\">9780000000000</dc:identifier>"
+        ));
+        assert!(out.contains("<dc:source>urn:isbn:9781111111111</dc:source>"));
+        assert!(out.contains("<collection role=\"index\"><link href=\"ch1.xhtml\"/></collection>"));
+        assert!(out.contains("fallback=\"ch1\" data-vendor=\"a&amp;b\"/>"));
+        assert!(out.contains("<itemref id=\"ref-ch1\" idref=\"ch1\" data-page=\"1\"/>"));
+
+        let reparsed = crate::epub::opf::parse_opf(&out).unwrap();
+        assert_eq!(reparsed.extras, book.opf_extras);
+        assert_eq!(reparsed.manifest, book.manifest);
+        assert_eq!(reparsed.spine, book.spine);
+        assert_eq!(reparsed.unique_identifier.as_deref(), Some("9780000000000"));
+    }
+
+    #[test]
+    fn test_ncx_uid_follows_unique_identifier() {
+        let opf = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="isbn">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier>urn:uuid:other</dc:identifier>
+    <dc:identifier id="isbn">9780000000000</dc:identifier>
+    <dc:title>T</dc:title>
+  </metadata>
+  <manifest/>
+  <spine/>
+</package>"#;
+        let data = crate::epub::opf::parse_opf(opf).unwrap();
+        let book = EpubBook {
+            metadata: data.metadata,
+            opf_extras: data.extras,
+            ..Default::default()
+        };
+        let ncx = generate_toc_ncx(&book.navigation, None, package_uid(&book));
+        assert!(ncx.contains("<meta name=\"dtb:uid\" content=\"9780000000000\"/>"));
+    }
+
+    #[test]
+    fn test_opf_escapes_itemref_attributes() {
+        let mut book = test_book();
+        book.spine = vec![SpineItem {
+            idref: "a&b".to_string(),
+            properties: Some("page-spread-left\"".to_string()),
+            ..Default::default()
+        }];
+        let out = render_opf(&book, "3.0", &book.manifest, &[], None);
+        assert!(out.contains("<itemref idref=\"a&amp;b\" properties=\"page-spread-left&quot;\"/>"));
+    }
+
+    fn fixture_copy(name: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(name);
+        let tmp = tempfile::TempDir::new().unwrap();
+        let dest = tmp.path().join(name);
+        std::fs::copy(src, &dest).unwrap();
+        (tmp, dest)
+    }
+
+    fn entry_string(path: &Path, name: &str) -> String {
+        let mut archive = crate::epub::zip_utils::open_epub(path).unwrap();
+        crate::epub::zip_utils::read_entry_string(&mut archive, name).unwrap()
+    }
+
+    fn preserve() -> WriteOptions {
+        WriteOptions {
+            layout: Layout::Preserve,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_preserve_layout_keeps_paths() {
+        let (_tmp, path) = fixture_copy("basic-v3plus2.epub");
+        let nav_before = entry_string(&path, "EPUB/nav.xhtml");
+
+        let mut book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        book.metadata.titles = vec![Title::new("Changed")];
+        write_epub_with(&book, &path, &preserve()).unwrap();
+        drop(book);
+
+        let container = entry_string(&path, "META-INF/container.xml");
+        assert!(container.contains("full-path=\"EPUB/package.opf\""));
+        let opf = entry_string(&path, "EPUB/package.opf");
+        assert!(opf.contains("<dc:title>Changed</dc:title>"));
+        assert_eq!(opf.matches("properties=\"nav\"").count(), 1);
+        assert!(opf.contains("<item id=\"nav\" href=\"nav.xhtml\""));
+        assert!(opf.contains("<spine toc=\"ncx\">"));
+        // Navigation was not touched, so it is copied through unchanged
+        assert_eq!(entry_string(&path, "EPUB/nav.xhtml"), nav_before);
+
+        let archive = crate::epub::zip_utils::open_epub(&path).unwrap();
+        assert!(!archive.file_names().any(|n| n.starts_with("OEBPS/")));
+    }
+
+    #[test]
+    fn test_preserve_layout_regenerates_changed_nav() {
+        let (_tmp, path) = fixture_copy("basic-v3plus2.epub");
+        let opf_before = entry_string(&path, "EPUB/package.opf");
+
+        let mut book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        book.navigation.toc.truncate(1);
+        book.navigation.toc[0].label = "Only Entry".to_string();
+        write_epub_with(&book, &path, &preserve()).unwrap();
+        drop(book);
+
+        assert_eq!(entry_string(&path, "EPUB/package.opf"), opf_before);
+        assert!(entry_string(&path, "EPUB/nav.xhtml").contains("Only Entry"));
+        assert!(entry_string(&path, "EPUB/toc.ncx").contains("Only Entry"));
+
+        let book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        assert_eq!(book.navigation.toc.len(), 1);
+    }
+
+    #[test]
+    fn test_preserve_layout_keeps_comments_and_missing_guide() {
+        let (_tmp, path) = fixture_copy("alice-in-wonderland.epub");
+        let opf_before = entry_string(&path, "OEBPS/content.opf");
+        let mut book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        book.metadata.titles = vec![Title::new("Changed")];
+        write_epub_with(&book, &path, &preserve()).unwrap();
+        drop(book);
+
+        let opf = entry_string(&path, "OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>Changed</dc:title>"));
+        assert_eq!(
+            opf.matches("<!--").count(),
+            opf_before.matches("<!--").count()
+        );
+        assert!(opf.contains(
+            "    <!--Chunk: size=3958 Split on div.chapter-->\n    <item id=\"pg-header\""
+        ));
+
+        // No guide is added to a package without one, unless the landmarks
+        // change
+        let (_tmp, path) = fixture_copy("childrens-literature.epub");
+        let mut book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        assert!(!book.navigation.landmarks.is_empty());
+        book.metadata.titles = vec![Title::new("Changed")];
+        write_epub_with(&book, &path, &preserve()).unwrap();
+        assert!(!entry_string(&path, "EPUB/package.opf").contains("<guide>"));
+        book.navigation.landmarks.truncate(1);
+        write_epub_with(&book, &path, &preserve()).unwrap();
+        assert!(entry_string(&path, "EPUB/package.opf").contains("<guide>"));
+    }
+
+    #[test]
+    fn test_normalize_replaces_source_navigation() {
+        let (_tmp, path) = fixture_copy("basic-v3plus2.epub");
+        let mut book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        book.resources
+            .insert("EPUB/xhtml/mytoc.xhtml".to_string(), b"<html/>".to_vec());
+        let out = path.with_file_name("normalized.epub");
+        write_epub(&book, &out).unwrap();
+
+        let archive = crate::epub::zip_utils::open_epub(&out).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert!(names.contains(&"OEBPS/xhtml/mytoc.xhtml"));
+        assert!(names.contains(&"OEBPS/images/cover.jpg"));
+        assert!(!names.iter().any(|n| n.starts_with("EPUB/")));
+        assert!(!names.contains(&"OEBPS/nav.xhtml"));
+
+        let opf = entry_string(&out, "OEBPS/content.opf");
+        assert_eq!(opf.matches("properties=\"nav\"").count(), 1);
+        assert_eq!(opf.matches("application/x-dtbncx+xml").count(), 1);
+    }
+
+    #[test]
+    fn test_write_epub2_package() {
+        let (_tmp, path) = fixture_copy("basic-v3plus2.epub");
+        let book = crate::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+        let out = path.with_file_name("epub2.epub");
+        let options = WriteOptions {
+            version: Some(EpubVersion::V2),
+            ..preserve()
+        };
+        write_epub_with(&book, &out, &options).unwrap();
+
+        let archive = crate::epub::zip_utils::open_epub(&out).unwrap();
+        let names: Vec<&str> = archive.file_names().collect();
+        assert!(names.contains(&"OEBPS/toc.ncx"));
+        assert!(!names.contains(&"OEBPS/toc.xhtml"));
+
+        let opf = entry_string(&out, "OEBPS/content.opf");
+        assert!(opf.contains("version=\"2.0\""));
+        assert!(opf.contains("<spine toc=\"ncx\""));
+        assert!(!opf.contains("properties="));
+        assert!(!opf.contains("dcterms:modified"));
+        assert!(!opf.contains("prefix="));
+
+        let book2 = crate::epub::reader::read_epub_with(&out, &Default::default()).unwrap();
+        assert_eq!(book2.navigation.epub_version, EpubVersion::V2);
+        assert_eq!(book2.navigation.toc.len(), book.navigation.toc.len());
+        assert_eq!(book2.metadata.title(), book.metadata.title());
+    }
+
+    #[test]
+    fn test_epub2_opf_metadata_syntax() {
+        let mut book = refined_book();
+        book.navigation.landmarks = vec![NavPoint {
+            label: "Start".to_string(),
+            href: "ch1.xhtml".to_string(),
+            epub_type: Some("bodymatter".to_string()),
+            ..Default::default()
+        }];
+        book.manifest[0].properties = Some("scripted".to_string());
+        book.opf_extras.metadata_elements = vec![
+            "<meta refines=\"#t1\" property=\"title-type\">main</meta>".to_string(),
+            "<link rel=\"record\" href=\"record.xml\"/>".to_string(),
+            "<meta name=\"calibre:series\" content=\"Tests\"/>".to_string(),
+        ];
+        let opf = generate_opf(&book, &book.navigation.landmarks, EpubVersion::V2);
+        assert!(opf.contains("<meta name=\"rendition:layout\" content=\"reflowable\"/>"));
+        assert!(opf.contains("opf:file-as=\"Author, Test\""));
+        assert!(opf.contains("opf:role=\"edt\""));
+        assert!(opf.contains("<meta name=\"calibre:series\" content=\"Tests\"/>"));
+        assert!(!opf.contains("refines="));
+        assert!(!opf.contains("<link"));
+        assert!(!opf.contains("properties="));
+        assert!(opf.contains("<reference type=\"text\" title=\"Start\" href=\"ch1.xhtml\"/>"));
+    }
 }
@@ -1,16 +1,40 @@
+use crate::epub::encoding;
 use crate::error::{EpxError, Result};
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::Read;
-use std::path::Path;
-use zip::ZipArchive;
-
-pub fn open_epub(path: &Path) -> Result<ZipArchive<File>> {
-    let file = File::open(path)?;
-    let archive = ZipArchive::new(file)?;
-    Ok(archive)
+use std::io::{BufReader, Read, Seek, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// The only content the `mimetype` entry may have
+const MIMETYPE: &str = "application/epub+zip";
+
+/// A seekable source an EPUB can be read from: a file, an in-memory buffer,
+/// or anything else implementing [`Read`] and [`Seek`]
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// An EPUB archive over any seekable source.
+///
+/// The source is boxed so that a book and its lazily loaded resources do
+/// not carry the source type around.
+pub type EpubArchive = ZipArchive<Box<dyn ReadSeek>>;
+
+/// Open an EPUB file; outside tests, books are read through
+/// [`open_archive`]
+#[cfg(test)]
+pub fn open_epub(path: &std::path::Path) -> Result<EpubArchive> {
+    open_archive(File::open(path)?)
+}
+
+/// Open an archive over a seekable source
+pub fn open_archive(source: impl ReadSeek + 'static) -> Result<EpubArchive> {
+    let source: Box<dyn ReadSeek> = Box::new(source);
+    Ok(ZipArchive::new(source)?)
 }
 
-pub fn validate_mimetype(archive: &mut ZipArchive<File>) -> Result<()> {
+pub fn validate_mimetype<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<()> {
     let mut mimetype = archive
         .by_index(0)
         .map_err(|_| EpxError::InvalidEpub("missing mimetype entry".into()))?;
@@ -24,7 +48,7 @@ pub fn validate_mimetype(archive: &mut ZipArchive<File>) -> Result<()> {
     let mut content = String::new();
     mimetype.read_to_string(&mut content)?;
 
-    if content.trim() != "application/epub+zip" {
+    if content.trim() != MIMETYPE {
         return Err(EpxError::InvalidEpub(format!(
             "invalid mimetype: {content}"
         )));
@@ -33,7 +57,82 @@ pub fn validate_mimetype(archive: &mut ZipArchive<File>) -> Result<()> {
     Ok(())
 }
 
-pub fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+/// Everything wrong with the `mimetype` entry, for lenient reading.
+///
+/// Unlike [`validate_mimetype`] this also reports a compressed entry and
+/// trailing bytes after the media type. The writer always produces a
+/// correct `mimetype`, so none of these survive a rewrite.
+pub fn mimetype_problems<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Vec<String> {
+    let Some(index) = archive.index_for_name("mimetype") else {
+        return vec!["mimetype entry is missing".to_string()];
+    };
+    let mut problems = Vec::new();
+    if index != 0 {
+        problems.push("mimetype is not the first entry".to_string());
+    }
+    let Ok(mut entry) = archive.by_index(index) else {
+        return problems;
+    };
+    if entry.compression() != CompressionMethod::Stored {
+        problems.push("mimetype is compressed".to_string());
+    }
+    let mut content = Vec::new();
+    if entry.read_to_end(&mut content).is_err() || content != MIMETYPE.as_bytes() {
+        let content = String::from_utf8_lossy(&content);
+        problems.push(format!("mimetype has unexpected content {content:?}"));
+    }
+    problems
+}
+
+/// Recover the entries of an archive whose central directory is damaged or
+/// truncated, by scanning its local file headers.
+///
+/// `source` is read from its current position. Scanning stops at the first
+/// entry that cannot be read. The recovered entries are re-packed into an
+/// unlinked temporary file, which keeps large books out of memory. Returns
+/// the archive and the number of entries recovered.
+pub fn salvage_epub(source: impl Read) -> Result<(EpubArchive, usize)> {
+    let mut source = BufReader::new(source);
+    let tmp_path = std::env::temp_dir().join(format!("epx-salvage-{}.zip", uuid::Uuid::new_v4()));
+    let tmp = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    // The open handle keeps the data readable on Unix
+    let _ = std::fs::remove_file(&tmp_path);
+
+    let mut zip = ZipWriter::new(tmp);
+    let mut recovered = 0;
+    while let Ok(Some(mut entry)) = zip::read::read_zipfile_from_stream(&mut source) {
+        let name = entry.name().to_string();
+        if entry.is_dir() {
+            continue;
+        }
+        let mut data = Vec::new();
+        if entry.read_to_end(&mut data).is_err() {
+            break;
+        }
+        let method = if name == "mimetype" {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        zip.start_file(
+            name,
+            SimpleFileOptions::default().compression_method(method),
+        )?;
+        zip.write_all(&data)?;
+        recovered += 1;
+    }
+    if recovered == 0 {
+        return Err(EpxError::InvalidEpub("no readable ZIP entries".into()));
+    }
+    let archive = open_archive(zip.finish()?)?;
+    Ok((archive, recovered))
+}
+
+pub fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>> {
     let mut entry = archive
         .by_name(name)
         .map_err(|_| EpxError::InvalidEpub(format!("missing entry: {name}")))?;
@@ -42,16 +141,18 @@ pub fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>>
     Ok(buf)
 }
 
-pub fn read_entry_string(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+/// Read an XML entry as text, honouring its BOM or encoding declaration
+pub fn read_entry_string<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<String> {
     let bytes = read_entry(archive, name)?;
-    String::from_utf8(bytes)
-        .map_err(|e| EpxError::InvalidEpub(format!("invalid UTF-8 in {name}: {e}")))
-}
-
-pub fn list_entries(archive: &ZipArchive<File>) -> Vec<String> {
-    (0..archive.len())
-        .filter_map(|i| archive.name_for_index(i).map(|s| s.to_string()))
-        .collect()
+    encoding::decode(&bytes)
+        .map(Cow::into_owned)
+        .ok_or_else(|| {
+            let (encoding, _) = encoding::detect(&bytes);
+            EpxError::InvalidEpub(format!("invalid {} in {name}", encoding.name()))
+        })
 }
 
 #[cfg(test)]
@@ -86,6 +187,30 @@ mod tests {
         assert!(container.contains("rootfile"));
     }
 
+    #[test]
+    fn mimetype_problems_none_for_valid_epub() {
+        let mut archive = open_epub(&fixture("minimal-v3.epub")).unwrap();
+        assert!(mimetype_problems(&mut archive).is_empty());
+    }
+
+    #[test]
+    fn salvage_truncated_central_directory() {
+        let bytes = std::fs::read(fixture("minimal-v3.epub")).unwrap();
+        let central_directory = bytes.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        let truncated = bytes[..central_directory + 10].to_vec();
+        assert!(open_archive(std::io::Cursor::new(truncated.clone())).is_err());
+
+        let expected = open_epub(&fixture("minimal-v3.epub"))
+            .unwrap()
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .count();
+        let (mut archive, recovered) = salvage_epub(truncated.as_slice()).unwrap();
+        assert_eq!(recovered, expected);
+        assert!(mimetype_problems(&mut archive).is_empty());
+        assert!(read_entry_string(&mut archive, "META-INF/container.xml").is_ok());
+    }
+
     #[test]
     fn read_entry_missing() {
         let path = fixture("minimal-v3.epub");
@@ -20,6 +20,12 @@ pub enum EpxError {
     #[error("YAML error: {0}")]
     Yaml(#[from] serde_yaml_ng::Error),
 
+    #[error("{0} is encrypted; DRM-protected EPUBs are not supported")]
+    Encrypted(String),
+
+    #[error("no rendition matches '{0}'")]
+    RenditionNotFound(String),
+
     #[error("regex error: {0}")]
     Regex(#[from] regex::Error),
 }
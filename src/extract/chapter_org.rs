@@ -1,4 +1,4 @@
-use crate::epub::{EpubBook, NavPoint};
+use crate::epub::{EpubBook, NavPoint, url};
 use slug::slugify;
 
 /// Generate chapter filename from TOC, heading, or original filename
@@ -21,6 +21,19 @@ pub fn chapter_filename(index: usize, book: &EpubBook, href: &str) -> String {
     format!("{index:02}-{name}.md")
 }
 
+fn find_toc_label(toc: &[NavPoint], href: &str) -> Option<String> {
+    for point in toc {
+        // Both hrefs are relative to the OPF; compare the paths they resolve to
+        if url::resolve("", &point.href) == url::resolve("", href) {
+            return Some(point.label.clone());
+        }
+        if let Some(label) = find_toc_label(&point.children, href) {
+            return Some(label);
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,6 +55,7 @@ mod tests {
             label: "Introduction".to_string(),
             href: "ch1.xhtml".to_string(),
             children: vec![],
+            ..Default::default()
         }]);
         let name = chapter_filename(0, &book, "ch1.xhtml");
         assert_eq!(name, "00-introduction.md");
@@ -60,6 +74,7 @@ mod tests {
             label: "".to_string(),
             href: "_.xhtml".to_string(),
             children: vec![],
+            ..Default::default()
         }]);
         // href "_.xhtml" with empty toc label and stem "_" slugs to empty
         let name = chapter_filename(2, &book, "_.xhtml");
@@ -74,18 +89,3 @@ mod tests {
         assert!(name.starts_with("05-"), "got: {name}");
     }
 }
-
-fn find_toc_label(toc: &[NavPoint], href: &str) -> Option<String> {
-    for point in toc {
-        // Match by href (ignoring fragment)
-        let point_href = point.href.split('#').next().unwrap_or(&point.href);
-        let target_href = href.split('#').next().unwrap_or(href);
-        if point_href == target_href || target_href.ends_with(point_href) {
-            return Some(point.label.clone());
-        }
-        if let Some(label) = find_toc_label(&point.children, href) {
-            return Some(label);
-        }
-    }
-    None
-}
@@ -0,0 +1,296 @@
+use quick_xml::Reader;
+use quick_xml::escape::{escape, unescape};
+use quick_xml::events::{BytesStart, Event};
+use std::borrow::Cow;
+
+/// HTML elements that never have content; tolerated without an end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// A node of a lenient XHTML tree for rewriting content documents.
+///
+/// Text and attribute values are kept escaped as in the source, so entities
+/// the XML parser does not know, such as `&nbsp;`, survive a round trip.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Element(Element),
+    /// Character data, escaped
+    Text(String),
+    /// Markup written back as is: the XML declaration, doctype, comments
+    /// and processing instructions
+    Raw(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Element {
+    /// Qualified name as in the source, e.g. `img` or `epub:switch`
+    pub name: String,
+    /// Names and escaped values, in document order
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+    /// Whether the source wrote the element as `<name/>`; written back so
+    /// when it has no children
+    pub empty: bool,
+}
+
+impl Element {
+    /// An empty element, e.g. `<img/>`
+    pub fn empty(name: &str, attributes: &[(&str, &str)]) -> Self {
+        Self {
+            name: name.to_string(),
+            attributes: attributes
+                .iter()
+                .map(|(name, value)| (name.to_string(), escape(*value).into_owned()))
+                .collect(),
+            children: Vec::new(),
+            empty: true,
+        }
+    }
+
+    /// The name without its namespace prefix
+    pub fn local_name(&self) -> &str {
+        self.name.rsplit(':').next().unwrap_or(&self.name)
+    }
+
+    /// The unescaped value of an attribute
+    pub fn attr(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| unescape(value).unwrap_or(Cow::Borrowed(value)))
+    }
+
+    pub fn set_attr(&mut self, name: &str, value: &str) {
+        let value = escape(value).into_owned();
+        match self.attributes.iter_mut().find(|(n, _)| n == name) {
+            Some(attribute) => attribute.1 = value,
+            None => self.attributes.push((name.to_string(), value)),
+        }
+    }
+
+    /// Remove an attribute, returning its unescaped value
+    pub fn remove_attr(&mut self, name: &str) -> Option<String> {
+        let index = self.attributes.iter().position(|(n, _)| n == name)?;
+        let (_, value) = self.attributes.remove(index);
+        Some(unescape(&value).map_or(value.clone(), Cow::into_owned))
+    }
+
+    /// Whether a whitespace-separated attribute such as `epub:type` has
+    /// `token`
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.attr(name)
+            .is_some_and(|value| value.split_whitespace().any(|t| t == token))
+    }
+
+    /// Descendant elements, depth first
+    pub fn descendants(&self) -> Vec<&Element> {
+        let mut found = Vec::new();
+        collect_elements(&self.children, &mut found);
+        found
+    }
+
+    /// The children written back as markup
+    pub fn inner_html(&self) -> String {
+        serialize(&self.children)
+    }
+}
+
+fn collect_elements<'a>(nodes: &'a [Node], found: &mut Vec<&'a Element>) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            found.push(element);
+            collect_elements(&element.children, found);
+        }
+    }
+}
+
+/// Parse a document, tolerating HTML habits: end tags that do not match
+/// (the elements in between are closed), void elements without an end tag
+/// and attributes without quotes or values. Input the parser gives up on is
+/// kept as raw markup from that point.
+pub fn parse(text: &str) -> Vec<Node> {
+    let mut reader = Reader::from_str(text);
+    let config = reader.config_mut();
+    config.check_end_names = false;
+    config.allow_unmatched_ends = true;
+
+    // Open elements; the document's top-level nodes are at the bottom
+    let mut stack: Vec<Element> = vec![Element::default()];
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(_) => {
+                let rest = text[start..].to_string();
+                push(&mut stack, Node::Raw(rest));
+                break;
+            }
+        };
+        let end = reader.buffer_position() as usize;
+        match event {
+            Event::Start(e) => {
+                let element = element(&e, false);
+                if VOID_ELEMENTS.contains(&element.local_name()) {
+                    push(
+                        &mut stack,
+                        Node::Element(Element {
+                            empty: true,
+                            ..element
+                        }),
+                    );
+                } else {
+                    stack.push(element);
+                }
+            }
+            Event::Empty(e) => push(&mut stack, Node::Element(element(&e, true))),
+            Event::End(e) => {
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                // Close up to the matching element; ignore stray end tags
+                if let Some(depth) = stack.iter().skip(1).rposition(|open| open.name == name) {
+                    while stack.len() > depth + 1 {
+                        close(&mut stack);
+                    }
+                }
+            }
+            Event::Text(_) => push(&mut stack, Node::Text(text[start..end].to_string())),
+            Event::CData(e) => {
+                let data = String::from_utf8_lossy(&e);
+                push(&mut stack, Node::Text(escape(&*data).into_owned()));
+            }
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => {
+                push(&mut stack, Node::Raw(text[start..end].to_string()));
+            }
+            Event::Eof => break,
+        }
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack.pop().map(|root| root.children).unwrap_or_default()
+}
+
+fn element(e: &BytesStart, empty: bool) -> Element {
+    let attributes = e
+        .html_attributes()
+        .with_checks(false)
+        .flatten()
+        .map(|attr| {
+            let name = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            // Values are written back double-quoted
+            let value = String::from_utf8_lossy(&attr.value).replace('"', "&quot;");
+            (name, value)
+        })
+        .collect();
+    Element {
+        name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+        attributes,
+        children: Vec::new(),
+        empty,
+    }
+}
+
+fn push(stack: &mut [Element], node: Node) {
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(node);
+    }
+}
+
+fn close(stack: &mut Vec<Element>) {
+    if let Some(element) = stack.pop() {
+        push(stack, Node::Element(element));
+    }
+}
+
+/// Write nodes back as markup
+pub fn serialize(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    write_nodes(nodes, &mut out);
+    out
+}
+
+fn write_nodes(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) | Node::Raw(text) => out.push_str(text),
+            Node::Element(element) => {
+                out.push('<');
+                out.push_str(&element.name);
+                for (name, value) in &element.attributes {
+                    out.push_str(&format!(" {name}=\"{value}\""));
+                }
+                if element.children.is_empty() && element.empty {
+                    out.push_str("/>");
+                    continue;
+                }
+                out.push('>');
+                write_nodes(&element.children, out);
+                out.push_str(&format!("</{}>", element.name));
+            }
+        }
+    }
+}
+
+/// Replace every node with what `f` returns for it, children first, so `f`
+/// sees elements whose content is already rewritten
+pub fn rewrite(nodes: Vec<Node>, f: &mut impl FnMut(Node) -> Vec<Node>) -> Vec<Node> {
+    let mut out = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let node = match node {
+            Node::Element(mut element) => {
+                element.children = rewrite(std::mem::take(&mut element.children), f);
+                Node::Element(element)
+            }
+            node => node,
+        };
+        out.extend(f(node));
+    }
+    out
+}
+
+/// Call `f` on every element, parents before children
+pub fn for_each_element(nodes: &mut [Node], f: &mut impl FnMut(&mut Element)) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            f(element);
+            for_each_element(&mut element.children, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_keeps_markup() {
+        let xhtml = r#"<?xml version="1.0"?><!DOCTYPE html><html><!-- note --><body><p class='a "b"'>x &nbsp; &amp; y<br/></p><div></div></body></html>"#;
+        assert_eq!(
+            serialize(&parse(xhtml)),
+            r#"<?xml version="1.0"?><!DOCTYPE html><html><!-- note --><body><p class="a &quot;b&quot;">x &nbsp; &amp; y<br/></p><div></div></body></html>"#
+        );
+    }
+
+    #[test]
+    fn parse_tolerates_html() {
+        let nodes = parse("<p>One<br>two <b>bold</p><p><![CDATA[a < b]]></i></p>");
+        assert_eq!(
+            serialize(&nodes),
+            "<p>One<br/>two <b>bold</b></p><p>a &lt; b</p>"
+        );
+    }
+
+    #[test]
+    fn attributes_unescape_and_escape() {
+        let nodes = parse(r#"<a href="a.xhtml?x=1&amp;y=2" epub:type="noteref footnote">1</a>"#);
+        let Node::Element(mut a) = nodes[0].clone() else {
+            panic!("expected an element");
+        };
+        assert_eq!(a.attr("href").as_deref(), Some("a.xhtml?x=1&y=2"));
+        assert!(a.has_token("epub:type", "footnote"));
+        a.set_attr("href", "b & c.md");
+        assert_eq!(a.remove_attr("href").as_deref(), Some("b & c.md"));
+        assert_eq!(a.local_name(), "a");
+    }
+}
@@ -0,0 +1,333 @@
+use crate::epub::{EpubBook, url};
+use crate::extract::dom::{self, Element, Node};
+use crate::extract::html_to_md;
+use quick_xml::escape::escape;
+use std::collections::{HashMap, HashSet};
+
+/// `epub:type` or `role` tokens of a single note
+const NOTE_TYPES: &[&str] = &[
+    "footnote",
+    "endnote",
+    "rearnote",
+    "doc-footnote",
+    "doc-endnote",
+];
+/// Tokens of a section whose list items are notes
+const NOTE_SECTION_TYPES: &[&str] = &["footnotes", "endnotes", "rearnotes", "doc-endnotes"];
+
+/// The footnotes and endnotes of a book, converted to Markdown, and where
+/// they are referenced from
+#[derive(Debug, Default)]
+pub struct NoteIndex {
+    /// Markdown of each note, by ZIP path and ID
+    notes: HashMap<(String, String), String>,
+    /// Notes some note reference points at
+    referenced: HashSet<(String, String)>,
+}
+
+impl NoteIndex {
+    /// Index the notes of the book's spine documents, with links in them
+    /// rewritten through `path_map`
+    pub fn build(
+        book: &EpubBook,
+        path_map: &HashMap<String, String>,
+        referenced_ids: &HashSet<String>,
+    ) -> Self {
+        let opf_dir = book.detect_opf_dir();
+        let documents: Vec<(String, String)> = book
+            .spine
+            .iter()
+            .filter_map(|spine_item| book.manifest.iter().find(|m| m.id == spine_item.idref))
+            .filter(|item| item.media_type.contains("html"))
+            .filter_map(|item| {
+                let path = url::resolve(&opf_dir, &item.href);
+                let xhtml = book.resources.get_string(&path)?;
+                Some((path, xhtml))
+            })
+            .collect();
+        Self::from_documents(&documents, path_map, referenced_ids)
+    }
+
+    /// Index the notes of documents given as ZIP path and XHTML
+    pub fn from_documents(
+        documents: &[(String, String)],
+        path_map: &HashMap<String, String>,
+        referenced_ids: &HashSet<String>,
+    ) -> Self {
+        let trees: Vec<(&str, Vec<Node>)> = documents
+            .iter()
+            .map(|(path, xhtml)| (path.as_str(), dom::parse(xhtml)))
+            .collect();
+        let trees: Vec<(&str, &[Node])> = trees
+            .iter()
+            .map(|(path, nodes)| (*path, nodes.as_slice()))
+            .collect();
+        Self::from_trees(&trees, path_map, referenced_ids)
+    }
+
+    /// Index the notes of documents given as ZIP path and parsed tree
+    pub fn from_trees(
+        documents: &[(&str, &[Node])],
+        path_map: &HashMap<String, String>,
+        referenced_ids: &HashSet<String>,
+    ) -> Self {
+        let mut found: Vec<(String, Element)> = Vec::new();
+        let mut referenced = HashSet::new();
+        // Note references that are themselves link targets, which is what
+        // back links point at
+        let mut noteref_ids = HashSet::new();
+        for (path, nodes) in documents {
+            collect(nodes, false, &mut |element, in_section| {
+                if is_note(element, in_section) {
+                    found.push((path.to_string(), element.clone()));
+                } else if is_noteref(element) {
+                    if let Some(target) = element.attr("href").and_then(|h| resolve(path, &h)) {
+                        referenced.insert(target);
+                    }
+                    if let Some(id) = element.attr("id") {
+                        noteref_ids.insert((path.to_string(), id.into_owned()));
+                    }
+                }
+            });
+        }
+
+        let mut notes = HashMap::new();
+        for (path, mut note) in found {
+            let Some(id) = note.attr("id").map(|id| id.into_owned()) else {
+                continue;
+            };
+            note.children = drop_back_links(note.children, &path, &noteref_ids);
+            let markdown =
+                html_to_md::xhtml_to_markdown(&note.inner_html(), &path, path_map, referenced_ids);
+            notes.insert((path, id), markdown.trim().to_string());
+        }
+        Self { notes, referenced }
+    }
+
+    fn get(&self, path: &str, id: &str) -> Option<&String> {
+        self.notes.get(&(path.to_string(), id.to_string()))
+    }
+
+    fn is_referenced(&self, path: &str, id: &str) -> bool {
+        self.referenced
+            .contains(&(path.to_string(), id.to_string()))
+    }
+}
+
+/// Footnote definitions collected while converting one document
+#[derive(Debug, Default)]
+pub struct Footnotes {
+    /// Label and Markdown, in order of first reference
+    definitions: Vec<(String, String)>,
+    /// Label of each note, by ZIP path and ID
+    labels: HashMap<(String, String), String>,
+}
+
+impl Footnotes {
+    /// The label for a note, numbering apart notes of different documents
+    /// that share an ID
+    fn label(&mut self, path: &str, id: &str, markdown: &str) -> String {
+        let key = (path.to_string(), id.to_string());
+        if let Some(label) = self.labels.get(&key) {
+            return label.clone();
+        }
+        let mut label = id.to_string();
+        let mut n = 1;
+        while self.definitions.iter().any(|(taken, _)| *taken == label) {
+            n += 1;
+            label = format!("{id}-{n}");
+        }
+        self.labels.insert(key, label.clone());
+        self.definitions.push((label.clone(), markdown.to_string()));
+        label
+    }
+
+    /// The definitions as Markdown, later paragraphs indented under the
+    /// first; empty without any
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        for (label, markdown) in &self.definitions {
+            out.push('\n');
+            for (i, line) in markdown.lines().enumerate() {
+                if i == 0 {
+                    out.push_str(&format!("[^{label}]: {line}"));
+                } else if !line.is_empty() {
+                    out.push_str(&format!("    {line}"));
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// Replace note references with `[^label]`, in a `<span>` with the
+/// reference's ID if it has one, recording the notes they point
+/// at in `footnotes`, and take out the notes that are referenced, which are
+/// defined where they are referenced instead. Notes nothing references stay
+/// in place.
+pub fn replace_notes(
+    nodes: Vec<Node>,
+    doc_path: &str,
+    index: &NoteIndex,
+    footnotes: &mut Footnotes,
+) -> Vec<Node> {
+    replace(nodes, false, doc_path, index, footnotes)
+}
+
+fn replace(
+    nodes: Vec<Node>,
+    in_section: bool,
+    doc_path: &str,
+    index: &NoteIndex,
+    footnotes: &mut Footnotes,
+) -> Vec<Node> {
+    let mut out = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let Node::Element(mut element) = node else {
+            out.push(node);
+            continue;
+        };
+        if is_note(&element, in_section)
+            && element
+                .attr("id")
+                .is_some_and(|id| index.is_referenced(doc_path, &id))
+        {
+            continue;
+        }
+        if is_noteref(&element)
+            && let Some((path, id)) = element.attr("href").and_then(|h| resolve(doc_path, &h))
+            && let Some(markdown) = index.get(&path, &id)
+        {
+            let label = footnotes.label(&path, &id, markdown);
+            let reference = Node::Text(format!("[^{}]", escape(&label)));
+            // The reference keeps its ID, which links may point at
+            out.push(match element.attr("id") {
+                Some(id) => Node::Element(Element {
+                    name: "span".to_string(),
+                    attributes: vec![("id".to_string(), escape(&*id).into_owned())],
+                    children: vec![reference],
+                    empty: false,
+                }),
+                None => reference,
+            });
+            continue;
+        }
+        let in_section = in_section || is_note_section(&element);
+        element.children = replace(element.children, in_section, doc_path, index, footnotes);
+        out.push(Node::Element(element));
+    }
+    out
+}
+
+/// Call `f` on every element with whether it is inside a notes section
+fn collect(nodes: &[Node], in_section: bool, f: &mut impl FnMut(&Element, bool)) {
+    for node in nodes {
+        if let Node::Element(element) = node {
+            f(element, in_section);
+            collect(&element.children, in_section || is_note_section(element), f);
+        }
+    }
+}
+
+fn has_type(element: &Element, types: &[&str]) -> bool {
+    types
+        .iter()
+        .any(|t| element.has_token("epub:type", t) || element.has_token("role", t))
+}
+
+/// A footnote or endnote: typed as one, or a list item of a notes section
+fn is_note(element: &Element, in_section: bool) -> bool {
+    element.attr("id").is_some()
+        && (has_type(element, NOTE_TYPES) || (in_section && element.local_name() == "li"))
+}
+
+fn is_note_section(element: &Element) -> bool {
+    has_type(element, NOTE_SECTION_TYPES)
+}
+
+fn is_noteref(element: &Element) -> bool {
+    element.local_name() == "a" && has_type(element, &["noteref", "doc-noteref"])
+}
+
+/// The ZIP path and ID a note reference's `href` points at
+fn resolve(doc_path: &str, href: &str) -> Option<(String, String)> {
+    if url::is_external(href) {
+        return None;
+    }
+    let (_, fragment) = url::split_fragment(href);
+    let id = url::percent_decode(fragment.filter(|f| !f.is_empty())?);
+    Some((url::resolve(doc_path, href), id.into_owned()))
+}
+
+/// Remove the links from a note back to its reference: typed as back links
+/// or pointing at a note reference
+fn drop_back_links(
+    nodes: Vec<Node>,
+    doc_path: &str,
+    noteref_ids: &HashSet<(String, String)>,
+) -> Vec<Node> {
+    dom::rewrite(nodes, &mut |node| match &node {
+        Node::Element(element)
+            if element.local_name() == "a"
+                && (has_type(element, &["backlink", "doc-backlink"])
+                    || element
+                        .attr("href")
+                        .and_then(|href| resolve(doc_path, &href))
+                        .is_some_and(|target| noteref_ids.contains(&target))) =>
+        {
+            vec![]
+        }
+        _ => vec![node],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn documents(docs: &[(&str, &str)]) -> Vec<(String, String)> {
+        docs.iter()
+            .map(|(path, xhtml)| (path.to_string(), xhtml.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn index_endnote_section_in_another_file() {
+        let docs = documents(&[
+            (
+                "OEBPS/ch1.xhtml",
+                r##"<html><body><p>Text<a id="r1" role="doc-noteref" href="notes.xhtml#n1">1</a></p></body></html>"##,
+            ),
+            (
+                "OEBPS/notes.xhtml",
+                r##"<html><body><section epub:type="endnotes"><ol>
+<li id="n1"><p><a href="ch1.xhtml#r1">1.</a> First <em>paragraph</em>.</p><p>Second, see <a href="ch1.xhtml">one</a>.</p></li>
+<li id="n2"><p>Unreferenced</p></li>
+</ol></section></body></html>"##,
+            ),
+        ]);
+        let mut path_map = HashMap::new();
+        path_map.insert("OEBPS/ch1.xhtml".to_string(), "001-one.md".to_string());
+        let index = NoteIndex::from_documents(&docs, &path_map, &HashSet::new());
+
+        assert_eq!(
+            index.get("OEBPS/notes.xhtml", "n1").map(String::as_str),
+            Some("First *paragraph*.\n\nSecond, see [one](001-one.md).")
+        );
+        assert!(index.is_referenced("OEBPS/notes.xhtml", "n1"));
+        assert!(!index.is_referenced("OEBPS/notes.xhtml", "n2"));
+    }
+
+    #[test]
+    fn definitions_indent_later_paragraphs() {
+        let mut footnotes = Footnotes::default();
+        assert_eq!(footnotes.label("a.xhtml", "n1", "One.\n\nTwo."), "n1");
+        assert_eq!(footnotes.label("b.xhtml", "n1", "Other."), "n1-2");
+        assert_eq!(footnotes.label("a.xhtml", "n1", "One.\n\nTwo."), "n1");
+        assert_eq!(
+            footnotes.to_markdown(),
+            "\n[^n1]: One.\n\n    Two.\n\n[^n1-2]: Other.\n"
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::epub::EpubMetadata;
+use crate::epub::{Accessibility, Contributor, EpubMetadata, Title};
 use crate::extract::profile::BookProfile;
 use crate::util::format_iso8601_date;
 use serde::{Deserialize, Serialize};
@@ -7,10 +7,17 @@ use std::collections::HashMap;
 /// Metadata YAML for the extracted book
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BookMetadataYaml {
+    /// The main title
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
+    /// Every title with its refinements; only written when `title` alone
+    /// would lose information
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
-    pub creators: Vec<String>,
+    pub titles: Vec<Title>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub creators: Vec<Contributor>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub contributors: Vec<Contributor>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub identifiers: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
@@ -27,6 +34,8 @@ pub struct BookMetadataYaml {
     pub rights: Option<String>,
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
     pub custom: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Accessibility::is_empty", default)]
+    pub accessibility: Accessibility,
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
     pub epx: HashMap<String, String>,
 }
@@ -56,9 +65,16 @@ impl BookMetadataYaml {
             epx.insert("has_svg_cover".to_string(), p.has_svg_cover.to_string());
         }
 
+        let titles = match meta.titles.as_slice() {
+            [title] if title.is_plain() => Vec::new(),
+            titles => titles.to_vec(),
+        };
+
         Self {
-            title: meta.titles.first().cloned(),
+            title: meta.title().map(str::to_string),
+            titles,
             creators: meta.creators.clone(),
+            contributors: meta.contributors.clone(),
             identifiers: meta.identifiers.clone(),
             languages: meta.languages.clone(),
             publishers: meta.publishers.clone(),
@@ -67,10 +83,20 @@ impl BookMetadataYaml {
             subjects: meta.subjects.clone(),
             rights: meta.rights.clone(),
             custom: meta.custom.clone(),
+            accessibility: meta.accessibility.clone(),
             epx,
         }
     }
 
+    /// The book's titles, from `titles` when present, else from `title`
+    pub fn titles(&self) -> Vec<Title> {
+        if self.titles.is_empty() {
+            self.title.iter().map(Title::new).collect()
+        } else {
+            self.titles.clone()
+        }
+    }
+
     pub fn to_yaml(&self) -> anyhow::Result<String> {
         Ok(serde_yaml_ng::to_string(self)?)
     }
@@ -100,8 +126,8 @@ mod tests {
     #[test]
     fn test_from_epub_metadata_full() {
         let meta = EpubMetadata {
-            titles: vec!["My Book".to_string()],
-            creators: vec!["Author".to_string()],
+            titles: vec![Title::new("My Book")],
+            creators: vec![Contributor::new("Author")],
             identifiers: vec!["urn:uuid:test".to_string()],
             languages: vec!["en".to_string()],
             publishers: vec!["Publisher".to_string()],
@@ -112,15 +138,16 @@ mod tests {
         };
         let yaml = BookMetadataYaml::from_epub_metadata(&meta, "3.0", None);
         assert_eq!(yaml.title, Some("My Book".to_string()));
-        assert_eq!(yaml.creators, vec!["Author"]);
+        assert_eq!(yaml.creators, vec![Contributor::new("Author")]);
+        assert!(yaml.titles.is_empty());
         assert!(yaml.epx.contains_key("epub_version"));
     }
 
     #[test]
     fn test_to_yaml_output() {
         let meta = EpubMetadata {
-            titles: vec!["My Book".to_string()],
-            creators: vec!["Author".to_string()],
+            titles: vec![Title::new("My Book")],
+            creators: vec![Contributor::new("Author")],
             ..Default::default()
         };
         let yaml_obj = BookMetadataYaml::from_epub_metadata(&meta, "3.0", None);
@@ -129,6 +156,38 @@ mod tests {
         assert!(yaml.contains("creators:"), "yaml: {yaml}");
     }
 
+    #[test]
+    fn test_refined_metadata_yaml_roundtrip() {
+        let meta = EpubMetadata {
+            titles: vec![
+                Title::new("My Book"),
+                Title {
+                    title_type: Some("subtitle".to_string()),
+                    ..Title::new("A Tale")
+                },
+            ],
+            creators: vec![
+                Contributor {
+                    roles: vec!["aut".to_string()],
+                    file_as: Some("Author, An".to_string()),
+                    ..Contributor::new("An Author")
+                },
+                Contributor::new("Plain Name"),
+            ],
+            ..Default::default()
+        };
+        let yaml = BookMetadataYaml::from_epub_metadata(&meta, "3.0", None)
+            .to_yaml()
+            .unwrap();
+        assert!(yaml.contains("- Plain Name"), "yaml: {yaml}");
+        assert!(yaml.contains("file_as: Author, An"), "yaml: {yaml}");
+
+        let parsed: BookMetadataYaml = serde_yaml_ng::from_str(&yaml).unwrap();
+        assert_eq!(parsed.title.as_deref(), Some("My Book"));
+        assert_eq!(parsed.titles(), meta.titles);
+        assert_eq!(parsed.creators, meta.creators);
+    }
+
     #[test]
     fn test_chapter_frontmatter_to_yaml_header() {
         let fm = ChapterFrontmatter {
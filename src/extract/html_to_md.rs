@@ -1,202 +1,265 @@
-use crate::util::strip_html_tags;
+use crate::epub::url;
+use crate::extract::dom::{self, Element, Node};
+use crate::extract::footnotes::{self, Footnotes, NoteIndex};
+use quick_xml::escape::escape;
 use regex::Regex;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
 /// Convert EPUB XHTML content to Markdown
 ///
+/// `doc_path` is the ZIP path of the document; links and image sources are
+/// resolved against it and looked up in `path_map`, which is keyed by ZIP
+/// path.
+///
 /// `referenced_ids` controls which anchor IDs are preserved:
 /// - Empty set: no anchors preserved (single-chapter extraction without full-book context)
 /// - Non-empty set: only IDs in the set are preserved (full-book extraction)
 pub fn xhtml_to_markdown(
     xhtml: &str,
+    doc_path: &str,
     path_map: &HashMap<String, String>,
     referenced_ids: &HashSet<String>,
 ) -> String {
-    let preprocessed = preprocess_xhtml(xhtml, path_map, referenced_ids);
-    let md = html_to_markdown_rs::convert(&preprocessed, None).unwrap_or_default();
-    postprocess_markdown(&md)
+    let nodes = dom::parse(xhtml);
+    let notes = NoteIndex::from_trees(&[(doc_path, &nodes)], path_map, referenced_ids);
+    convert(nodes, doc_path, path_map, referenced_ids, &notes)
 }
 
-/// Pre-process EPUB XHTML before Markdown conversion
-fn preprocess_xhtml(
+/// Convert EPUB XHTML content to Markdown, with the notes it references
+/// looked up in `notes`, which may hold those of other documents.
+///
+/// Referenced notes become Markdown footnotes at the end of the chapter.
+pub fn xhtml_to_markdown_with_notes(
     xhtml: &str,
+    doc_path: &str,
     path_map: &HashMap<String, String>,
     referenced_ids: &HashSet<String>,
+    notes: &NoteIndex,
 ) -> String {
-    let mut html = xhtml.to_string();
-
-    // Strip XML declaration
-    if let Some(end) = html.find("?>")
-        && html.starts_with("<?xml")
-    {
-        html = html[end + 2..].to_string();
-    }
-
-    // Strip <head> section to prevent the converter from emitting frontmatter
-    // from <title> and <meta> tags (epx generates its own frontmatter from metadata)
-    if let Ok(head_re) = Regex::new("(?is)<head[^>]*>.*?</head>") {
-        html = head_re.replace_all(&html, "").to_string();
-    }
-
-    // Unwrap SVG cover images: replace <svg>...<image xlink:href="X"/>...</svg>
-    // with <img src="X" alt="Cover image"/> when SVG contains only a single <image>
-    // and no drawing elements (to avoid replacing actual SVG diagrams).
-    if let Ok(svg_re) = Regex::new(r"(?is)<svg\b[^>]*>(.*?)</svg>") {
-        let drawing_re =
-            Regex::new(r"(?i)<(?:rect|circle|path|text|line|polygon|polyline|ellipse)\b")
-                .expect("valid regex");
-        let image_href_re = Regex::new(r#"(?i)<image\b[^>]*(?:xlink:)?href="([^"]+)"[^>]*/?\s*>"#)
-            .expect("valid regex");
-        html = svg_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let inner = &caps[1];
-                // Only unwrap if there's exactly one <image> and no drawing elements
-                if drawing_re.is_match(inner) {
-                    return caps[0].to_string();
-                }
-                let image_caps: Vec<_> = image_href_re.captures_iter(inner).collect();
-                if image_caps.len() == 1 {
-                    let href = &image_caps[0][1];
-                    format!(r#"<img src="{href}" alt="Cover image"/>"#)
-                } else {
-                    caps[0].to_string()
-                }
-            })
-            .to_string();
-    }
-
-    // Fill in empty or missing alt attributes on images with derived text
-    if let Ok(empty_alt_re) = Regex::new(r#"(<img\b[^>]*)\balt\s*=\s*""([^>]*>)"#) {
-        html = empty_alt_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let before = &caps[1];
-                let after = &caps[2];
-                let alt = derive_alt_from_tag(before);
-                format!(r#"{before}alt="{alt}"{after}"#)
-            })
-            .to_string();
-    }
-    // Inject alt for <img> tags missing it entirely
-    if let Ok(img_tag_re) = Regex::new(r"<img\b[^>]*>") {
-        let alt_attr_re = Regex::new(r#"\balt\s*="#).expect("valid regex");
-        html = img_tag_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let tag = &caps[0];
-                if alt_attr_re.is_match(tag) {
-                    return tag.to_string(); // already has alt
-                }
-                let alt = derive_alt_from_tag(tag);
-                // Insert alt after <img
-                format!(r#"<img alt="{alt}"{}"#, &tag[4..])
-            })
-            .to_string();
-    }
-
-    // Preserve fragment-target IDs as placeholders before the markdown converter strips them.
-    // EPUBs use id attributes as fragment targets for cross-references (#id links).
-    // The markdown converter drops all id attributes, so we extract them as text tokens
-    // that survive conversion and are restored to HTML anchors in postprocessing.
-    //
-    // Only IDs present in `referenced_ids` are preserved. If the set is empty,
-    // no anchors are preserved (single-chapter mode or no references in the EPUB).
-
-    // Step 1a: Empty <a id="..."></a> anchors — preserve if referenced, drop if not
-    if let Ok(anchor_re) = Regex::new(r#"<a\s[^>]*id="([^"]+)"[^>]*>\s*</a>"#) {
-        html = anchor_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let id = &caps[1];
-                if referenced_ids.contains(id) {
-                    format!("EPXANCHOR__{id}__ENDEPX")
-                } else {
-                    String::new()
-                }
-            })
-            .to_string();
-    }
-
-    // Step 1b: Non-empty <a id="...">content</a> — preserve id if referenced, strip if not
-    if let Ok(anchor_id_re) = Regex::new(r#"(<a\b)([^>]*?)\sid="([^"]+)"([^>]*>)"#) {
-        html = anchor_id_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let tag_start = &caps[1];
-                let before = &caps[2];
-                let id = &caps[3];
-                let after = &caps[4];
-                if referenced_ids.contains(id) {
-                    format!("EPXANCHOR__{id}__ENDEPX{tag_start}{before}{after}")
-                } else {
-                    format!("{tag_start}{before}{after}")
-                }
-            })
-            .to_string();
-    }
-
-    // Step 2: Any element IDs (except <a>, handled above) — preserve if referenced
-    if let Ok(elem_id_re) = Regex::new(r#"(<(\w+)\b)([^>]*?)\sid="([^"]+)"([^>]*>)"#) {
-        html = elem_id_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let tag_start = &caps[1];
-                let tag_name = &caps[2];
-                let before = &caps[3];
-                let id = &caps[4];
-                let after = &caps[5];
-                // Skip <a> tags — already handled in Steps 1a/1b
-                if tag_name.eq_ignore_ascii_case("a") {
-                    return caps[0].to_string();
-                }
-                if referenced_ids.contains(id) {
-                    format!("{tag_start}{before}{after}EPXANCHOR__{id}__ENDEPX")
-                } else {
-                    format!("{tag_start}{before}{after}")
-                }
-            })
-            .to_string();
-    }
-
-    // Strip epub namespace prefixes from tags
-    html = html.replace("epub:", "data-epub-");
-
-    // Rewrite image/asset paths using placeholders to prevent double-replacement
-    // (e.g. replacing "cover.jpeg" inside an already-rewritten "../assets/images/cover.jpeg")
-    let mut path_entries: Vec<_> = path_map.iter().collect();
-    path_entries.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
-    let mut placeholders: Vec<(String, String)> = Vec::new();
-    for (i, (old_path, new_path)) in path_entries.iter().enumerate() {
-        let placeholder = format!("\x00EPX_PATH_{i}\x00");
-        html = html.replace(old_path.as_str(), &placeholder);
-        placeholders.push((placeholder, new_path.to_string()));
-    }
-    for (placeholder, new_path) in &placeholders {
-        html = html.replace(placeholder, new_path);
-    }
-
-    // Convert epub:type footnotes to markdown-style footnote markers
-    if let Ok(footnote_re) =
-        Regex::new("<aside[^>]*data-epub-type=\"footnote\"[^>]*id=\"([^\"]*)\"[^>]*>(.*?)</aside>")
-    {
-        html = footnote_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let id = &caps[1];
-                let content = &caps[2];
-                let text = strip_html_tags(content);
-                format!("[^{id}]: {text}")
-            })
-            .to_string();
-    }
-
-    // Convert footnote references
-    if let Ok(fn_ref_re) =
-        Regex::new("<a[^>]*data-epub-type=\"noteref\"[^>]*href=\"#([^\"]*)\"[^>]*>[^<]*</a>")
-    {
-        html = fn_ref_re
-            .replace_all(&html, |caps: &regex::Captures| {
-                let id = &caps[1];
-                format!("[^{id}]")
-            })
-            .to_string();
-    }
-
-    html
+    convert(dom::parse(xhtml), doc_path, path_map, referenced_ids, notes)
+}
+
+fn convert(
+    nodes: Vec<Node>,
+    doc_path: &str,
+    path_map: &HashMap<String, String>,
+    referenced_ids: &HashSet<String>,
+    notes: &NoteIndex,
+) -> String {
+    let context = Context {
+        doc_path,
+        path_map,
+        referenced_ids,
+        notes,
+        footnotes: RefCell::default(),
+    };
+    let preprocessed = preprocess_xhtml(nodes, &context);
+    let md = html_to_markdown_rs::convert(&preprocessed, None).unwrap_or_default();
+    let mut md = postprocess_markdown(&md);
+    md.push_str(&context.footnotes.borrow().to_markdown());
+    md
+}
+
+/// What the preprocessing transforms need to know about the document
+struct Context<'a> {
+    doc_path: &'a str,
+    path_map: &'a HashMap<String, String>,
+    referenced_ids: &'a HashSet<String>,
+    notes: &'a NoteIndex,
+    /// Notes referenced so far, defined at the end of the chapter
+    footnotes: RefCell<Footnotes>,
+}
+
+/// A rewrite of the parsed document
+type Transform = fn(Vec<Node>, &Context) -> Vec<Node>;
+
+/// Preprocessing, in order. Footnotes come before anchors, which would
+/// otherwise take the IDs footnotes are matched by.
+const TRANSFORMS: &[Transform] = &[
+    strip_head,
+    unwrap_svg_images,
+    fill_alt_text,
+    convert_footnotes,
+    preserve_anchors,
+    rename_epub_attributes,
+    rewrite_paths,
+];
+
+/// Pre-process parsed EPUB XHTML before Markdown conversion
+fn preprocess_xhtml(nodes: Vec<Node>, context: &Context) -> String {
+    let nodes = TRANSFORMS
+        .iter()
+        .fold(nodes, |nodes, transform| transform(nodes, context));
+    dom::serialize(&nodes)
+}
+
+/// Drop the XML declaration and `<head>`, so the converter does not emit
+/// frontmatter from `<title>` and `<meta>` (epx generates its own from the
+/// metadata)
+fn strip_head(nodes: Vec<Node>, _: &Context) -> Vec<Node> {
+    dom::rewrite(nodes, &mut |node| match &node {
+        Node::Raw(raw) if raw.starts_with("<?xml") => vec![],
+        Node::Element(element) if element.local_name() == "head" => vec![],
+        _ => vec![node],
+    })
+}
+
+/// Replace an SVG wrapping a single `<image>`, as covers often are, with an
+/// `<img>`; SVGs that draw anything are kept
+fn unwrap_svg_images(nodes: Vec<Node>, _: &Context) -> Vec<Node> {
+    const DRAWING: &[&str] = &[
+        "rect", "circle", "path", "text", "line", "polygon", "polyline", "ellipse",
+    ];
+    dom::rewrite(nodes, &mut |node| {
+        let Node::Element(svg) = &node else {
+            return vec![node];
+        };
+        if svg.local_name() != "svg" {
+            return vec![node];
+        }
+        let descendants = svg.descendants();
+        if descendants
+            .iter()
+            .any(|element| DRAWING.contains(&element.local_name()))
+        {
+            return vec![node];
+        }
+        let hrefs: Vec<_> = descendants
+            .iter()
+            .filter(|element| element.local_name() == "image")
+            .filter_map(|image| image.attr("xlink:href").or_else(|| image.attr("href")))
+            .collect();
+        match hrefs.as_slice() {
+            [href] => vec![Node::Element(Element::empty(
+                "img",
+                &[("src", href), ("alt", "Cover image")],
+            ))],
+            _ => vec![node],
+        }
+    })
+}
+
+/// Give images without alt text one derived from the file name
+fn fill_alt_text(mut nodes: Vec<Node>, _: &Context) -> Vec<Node> {
+    dom::for_each_element(&mut nodes, &mut |element| {
+        if element.local_name() != "img" || element.attr("alt").is_some_and(|alt| !alt.is_empty()) {
+            return;
+        }
+        let alt = derive_alt(&element.attr("src").unwrap_or_default());
+        element.set_attr("alt", &alt);
+    });
+    nodes
+}
+
+/// Turn note references into `[^label]`, collecting the footnotes and
+/// endnotes they point at, in this document or another, for the end of the
+/// chapter
+fn convert_footnotes(nodes: Vec<Node>, context: &Context) -> Vec<Node> {
+    footnotes::replace_notes(
+        nodes,
+        context.doc_path,
+        context.notes,
+        &mut context.footnotes.borrow_mut(),
+    )
+}
+
+/// Keep the IDs links point at as placeholders the converter leaves alone,
+/// which `postprocess_markdown` turns into pandoc anchors; drop the others.
+///
+/// Only IDs in `referenced_ids` are kept, so none are in single-chapter
+/// mode.
+fn preserve_anchors(nodes: Vec<Node>, context: &Context) -> Vec<Node> {
+    dom::rewrite(nodes, &mut |node| {
+        let Node::Element(mut element) = node else {
+            return vec![node];
+        };
+        let Some(id) = element.remove_attr("id") else {
+            return vec![Node::Element(element)];
+        };
+        let referenced = context.referenced_ids.contains(&id);
+        if element.local_name() == "a" {
+            let is_empty = element
+                .children
+                .iter()
+                .all(|child| matches!(child, Node::Text(text) if text.trim().is_empty()));
+            // An empty anchor is only there for its ID
+            return match (is_empty, referenced) {
+                (true, true) => vec![anchor_placeholder(&id)],
+                (true, false) => vec![],
+                (false, true) => vec![anchor_placeholder(&id), Node::Element(element)],
+                (false, false) => vec![Node::Element(element)],
+            };
+        }
+        if !referenced {
+            vec![Node::Element(element)]
+        } else if element.empty && element.children.is_empty() {
+            vec![Node::Element(element), anchor_placeholder(&id)]
+        } else {
+            element.children.insert(0, anchor_placeholder(&id));
+            vec![Node::Element(element)]
+        }
+    })
+}
+
+/// `EPXANCHOR__id__ENDEPX`, which survives conversion as text
+fn anchor_placeholder(id: &str) -> Node {
+    Node::Text(format!("EPXANCHOR__{}__ENDEPX", escape(id)))
+}
+
+/// Rename `epub:` elements and attributes to `data-epub-`, which the
+/// converter treats as plain HTML
+fn rename_epub_attributes(mut nodes: Vec<Node>, _: &Context) -> Vec<Node> {
+    let rename = |name: &mut String| {
+        if let Some(rest) = name.strip_prefix("epub:") {
+            *name = format!("data-epub-{rest}");
+        }
+    };
+    dom::for_each_element(&mut nodes, &mut |element| {
+        rename(&mut element.name);
+        for (name, _) in &mut element.attributes {
+            rename(name);
+        }
+    });
+    nodes
+}
+
+/// Point `href`, `src` and `xlink:href` attributes at the extracted files in
+/// `path_map`, keeping fragments
+fn rewrite_paths(mut nodes: Vec<Node>, context: &Context) -> Vec<Node> {
+    dom::for_each_element(&mut nodes, &mut |element| {
+        for name in ["href", "src", "xlink:href"] {
+            let Some(value) = element.attr(name) else {
+                continue;
+            };
+            let (path, fragment) = url::split_fragment(&value);
+            if path.is_empty() || url::is_external(&value) {
+                continue;
+            }
+            let Some(new_path) = context.path_map.get(&url::resolve(context.doc_path, path)) else {
+                continue;
+            };
+            let rewritten = match fragment {
+                Some(fragment) => format!("{new_path}#{fragment}"),
+                None => new_path.clone(),
+            };
+            element.set_attr(name, &rewritten);
+        }
+    });
+    nodes
+}
+
+/// Rewrite `href` and `src` attributes that point at files in `path_map`
+/// to their extracted paths, keeping fragments
+pub fn rewrite_links(html: &str, doc_path: &str, path_map: &HashMap<String, String>) -> String {
+    let context = Context {
+        doc_path,
+        path_map,
+        referenced_ids: &HashSet::new(),
+        notes: &NoteIndex::default(),
+        footnotes: RefCell::default(),
+    };
+    dom::serialize(&rewrite_paths(dom::parse(html), &context))
 }
 
 /// Post-process converted Markdown
@@ -323,25 +386,19 @@ fn postprocess_markdown(md: &str) -> String {
     result
 }
 
-/// Derive alt text from an `<img>` tag's `src` attribute.
+/// Derive alt text from an image's `src`.
 ///
 /// Extracts the filename, strips the extension, and humanizes it.
 /// Purely numeric filenames (like `338838561`) become `"Image"`.
-fn derive_alt_from_tag(tag: &str) -> String {
-    let src_re = Regex::new(r#"src="([^"]+)""#).expect("valid regex");
-    let src = src_re
-        .captures(tag)
-        .map(|c| c[1].to_string())
-        .unwrap_or_default();
-
+fn derive_alt(src: &str) -> String {
     // Extract filename without extension
     let filename = src
         .rsplit('/')
         .next()
-        .unwrap_or(&src)
+        .unwrap_or(src)
         .rsplit('\\')
         .next()
-        .unwrap_or(&src);
+        .unwrap_or(src);
     let name = match filename.rfind('.') {
         Some(pos) => &filename[..pos],
         None => filename,
@@ -363,6 +420,7 @@ fn derive_alt_from_tag(tag: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::strip_html_tags;
 
     fn empty_refs() -> HashSet<String> {
         HashSet::new()
@@ -375,7 +433,7 @@ mod tests {
     #[test]
     fn test_basic_xhtml_to_markdown() {
         let xhtml = r#"<html><body><h1>Title</h1><p>Text paragraph.</p></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             md.contains("# Title") || md.contains("Title\n="),
             "expected heading in: {md}"
@@ -391,18 +449,38 @@ mod tests {
             "images/foo.png".to_string(),
             "../assets/images/foo.png".to_string(),
         );
-        let md = xhtml_to_markdown(xhtml, &path_map, &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &path_map, &empty_refs());
         assert!(
             md.contains("../assets/images/foo.png"),
             "path not rewritten: {md}"
         );
     }
 
+    #[test]
+    fn test_path_rewriting_resolves_relative_links() {
+        let xhtml = r#"<html><body><p><img src="../Images/a%20b.png" alt="A"/> <a href="ch2.xhtml#s1">next</a> <a href="ab.png">other</a></p></body></html>"#;
+        let mut path_map = HashMap::new();
+        path_map.insert(
+            "OEBPS/Images/a b.png".to_string(),
+            "../assets/images/a b.png".to_string(),
+        );
+        path_map.insert("OEBPS/Text/ch2.xhtml".to_string(), "002-two.md".to_string());
+        path_map.insert(
+            "OEBPS/b.png".to_string(),
+            "../assets/images/b.png".to_string(),
+        );
+        let md = xhtml_to_markdown(xhtml, "OEBPS/Text/ch1.xhtml", &path_map, &empty_refs());
+        assert!(md.contains("../assets/images/a b.png"), "image: {md}");
+        assert!(md.contains("002-two.md#s1"), "link: {md}");
+        assert!(md.contains("ab.png"), "unrelated link: {md}");
+        assert!(!md.contains("assets/images/b.png"), "suffix match: {md}");
+    }
+
     #[test]
     fn test_xml_declaration_stripping() {
         let xhtml =
             r#"<?xml version="1.0" encoding="UTF-8"?><html><body><p>Hello</p></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(!md.contains("<?xml"));
         assert!(md.contains("Hello"));
     }
@@ -410,7 +488,7 @@ mod tests {
     #[test]
     fn test_footnote_conversion() {
         let xhtml = r##"<html><body><p>Text<a epub:type="noteref" href="#fn1">1</a></p><aside epub:type="footnote" id="fn1"><p>A footnote</p></aside></body></html>"##;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(md.contains("[^fn1]"), "footnote ref not found: {md}");
     }
 
@@ -426,7 +504,7 @@ mod tests {
 
     #[test]
     fn test_empty_input() {
-        let md = xhtml_to_markdown("", &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown("", "ch.xhtml", &HashMap::new(), &empty_refs());
         assert_eq!(md, "\n");
     }
 
@@ -440,7 +518,7 @@ mod tests {
         let xhtml =
             r#"<html><body><a id="41401"></a><h2>Section Title</h2><p>Content</p></body></html>"#;
         let refs = refs_containing(&["41401"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         // Anchor before heading should merge as {#id} attribute
         assert!(
             md.contains("{#41401}"),
@@ -457,7 +535,7 @@ mod tests {
     fn test_multiple_anchor_ids_pandoc() {
         let xhtml = r#"<html><body><a id="100"></a><h2>First</h2><a id="200"></a><h2>Second</h2></body></html>"#;
         let refs = refs_containing(&["100", "200"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(md.contains("{#100}"), "first anchor missing: {md}");
         assert!(md.contains("{#200}"), "second anchor missing: {md}");
         assert!(
@@ -470,7 +548,7 @@ mod tests {
     fn test_element_id_preservation_pandoc() {
         let xhtml = r#"<html><body><p id="abc123" class="toc">Chapter 1</p></body></html>"#;
         let refs = refs_containing(&["abc123"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(
             md.contains("{#abc123}"),
             "element ID not preserved as pandoc syntax: {md}"
@@ -481,7 +559,7 @@ mod tests {
     fn test_adjacent_anchor_ids_pandoc() {
         let xhtml = r#"<html><body><a id="111"></a><a id="222"></a><h2>Title</h2></body></html>"#;
         let refs = refs_containing(&["111", "222"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(md.contains("{#111}"), "first adjacent anchor missing: {md}");
         assert!(
             md.contains("{#222}"),
@@ -499,7 +577,7 @@ mod tests {
         let xhtml =
             r#"<html><body><a id="orphan1"></a><a id="keep"></a><h2>Title</h2></body></html>"#;
         let refs = refs_containing(&["keep"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(md.contains("{#keep}"), "referenced anchor missing: {md}");
         assert!(
             !md.contains("orphan1"),
@@ -511,7 +589,7 @@ mod tests {
     fn test_unreferenced_element_ids_stripped() {
         // Element IDs not in referenced set should be stripped (id attr only)
         let xhtml = r#"<html><body><p id="calibre_pb_1">Content</p></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             !md.contains("calibre_pb_1"),
             "unreferenced element ID should be stripped: {md}"
@@ -523,7 +601,7 @@ mod tests {
     fn test_empty_refs_preserves_nothing() {
         // With empty referenced_ids, no anchors should be preserved
         let xhtml = r#"<html><body><a id="100"></a><p id="200">Text</p></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             !md.contains("{#100}") && !md.contains("{#200}"),
             "no anchors should be preserved with empty refs: {md}"
@@ -536,7 +614,7 @@ mod tests {
     #[test]
     fn test_svg_single_image_unwrapped() {
         let xhtml = r#"<html><body><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 100 100"><image xlink:href="cover.jpeg"/></svg></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             md.contains("Cover image"),
             "SVG should be unwrapped to img: {md}"
@@ -547,7 +625,7 @@ mod tests {
     #[test]
     fn test_svg_with_drawing_elements_preserved() {
         let xhtml = r#"<html><body><svg xmlns="http://www.w3.org/2000/svg"><rect x="0" y="0"/><image xlink:href="diagram.png"/></svg></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         // SVG with drawing elements should NOT be unwrapped
         assert!(
             !md.contains("Cover image"),
@@ -561,7 +639,7 @@ mod tests {
     fn test_div_id_preserved() {
         let xhtml = r#"<html><body><div id="myref">Content</div></body></html>"#;
         let refs = refs_containing(&["myref"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(
             md.contains("{#myref}"),
             "div ID not preserved as pandoc syntax: {md}"
@@ -572,7 +650,7 @@ mod tests {
     fn test_span_id_preserved() {
         let xhtml = r#"<html><body><p><span id="target1">text</span></p></body></html>"#;
         let refs = refs_containing(&["target1"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(
             md.contains("{#target1}"),
             "span ID not preserved as pandoc syntax: {md}"
@@ -583,7 +661,7 @@ mod tests {
     fn test_blockquote_id_preserved() {
         let xhtml = r#"<html><body><blockquote id="bq1">Quote text</blockquote></body></html>"#;
         let refs = refs_containing(&["bq1"]);
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &refs);
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
         assert!(
             md.contains("{#bq1}"),
             "blockquote ID not preserved as pandoc syntax: {md}"
@@ -595,7 +673,7 @@ mod tests {
     #[test]
     fn test_empty_alt_gets_derived() {
         let xhtml = r#"<html><body><img src="images/fig_3-2.png" alt=""/></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(!md.contains("![]"), "empty alt should be replaced: {md}");
         assert!(
             md.contains("fig 3-2"),
@@ -606,7 +684,7 @@ mod tests {
     #[test]
     fn test_missing_alt_gets_injected() {
         let xhtml = r#"<html><body><img src="images/diagram.png"/></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(!md.contains("![]"), "missing alt should be injected: {md}");
         assert!(
             md.contains("diagram"),
@@ -617,7 +695,7 @@ mod tests {
     #[test]
     fn test_numeric_filename_becomes_image() {
         let xhtml = r#"<html><body><img src="images/338838561.jpg" alt=""/></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             md.contains("Image"),
             "numeric filename should become 'Image': {md}"
@@ -627,7 +705,7 @@ mod tests {
     #[test]
     fn test_existing_alt_preserved() {
         let xhtml = r#"<html><body><img src="foo.png" alt="My photo"/></body></html>"#;
-        let md = xhtml_to_markdown(xhtml, &HashMap::new(), &empty_refs());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
         assert!(
             md.contains("My photo"),
             "existing alt should be preserved: {md}"
@@ -635,13 +713,73 @@ mod tests {
     }
 
     #[test]
-    fn test_derive_alt_from_tag_helper() {
-        assert_eq!(
-            derive_alt_from_tag(r#"<img src="images/fig_3-2.png""#),
-            "fig 3-2"
+    fn test_derive_alt_helper() {
+        assert_eq!(derive_alt("images/fig_3-2.png"), "fig 3-2");
+        assert_eq!(derive_alt("338838561.jpg"), "Image");
+        assert_eq!(derive_alt("cover.jpeg"), "cover");
+        assert_eq!(derive_alt(""), "Image");
+    }
+
+    // ─── Parsed-tree preprocessing tests ─────────────────────
+
+    #[test]
+    fn test_single_quoted_attributes() {
+        let xhtml = r#"<html><body><p id='p1'>Text <img src='images/a_b.png'/> <a href='ch2.xhtml'>next</a></p></body></html>"#;
+        let mut path_map = HashMap::new();
+        path_map.insert("ch2.xhtml".to_string(), "002-two.md".to_string());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &path_map, &refs_containing(&["p1"]));
+        assert!(md.contains("{#p1}"), "anchor: {md}");
+        assert!(md.contains("![a b](images/a_b.png)"), "alt: {md}");
+        assert!(md.contains("(002-two.md)"), "link: {md}");
+    }
+
+    #[test]
+    fn test_text_is_not_rewritten() {
+        let xhtml = r#"<html><body><p>Set epub:type on nav, see ch2.xhtml and href="ch2.xhtml"</p><pre><![CDATA[<a href="ch2.xhtml">]]></pre></body></html>"#;
+        let mut path_map = HashMap::new();
+        path_map.insert("ch2.xhtml".to_string(), "002-two.md".to_string());
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &path_map, &empty_refs());
+        assert!(md.contains("epub:type on nav"), "text renamed: {md}");
+        assert!(!md.contains("002-two.md"), "text path rewritten: {md}");
+        assert!(md.contains(r#"<a href="ch2.xhtml">"#), "CDATA: {md}");
+    }
+
+    #[test]
+    fn test_footnote_attributes_in_any_order() {
+        let xhtml = r##"<html><body><p>Text<a href="#fn1" epub:type="noteref">1</a></p><aside id="fn1" epub:type="footnote">
+<p>A footnote</p></aside></body></html>"##;
+        let md = xhtml_to_markdown(
+            xhtml,
+            "ch.xhtml",
+            &HashMap::new(),
+            &refs_containing(&["fn1"]),
+        );
+        assert!(md.contains("Text[^fn1]"), "reference: {md}");
+        assert!(md.contains("[^fn1]: A footnote"), "footnote: {md}");
+    }
+
+    #[test]
+    fn test_multi_paragraph_footnote_without_back_link() {
+        let xhtml = r##"<html><body><p>Text<a id="ref1" role="doc-noteref" href="#fn1">1</a> more.</p>
+<aside epub:type="footnote" id="fn1"><p><a epub:type="backlink" href="#ref1">↩</a> First <em>point</em>.</p>
+<p>Second point.</p></aside></body></html>"##;
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
+        assert!(md.contains("Text[^fn1] more."), "reference: {md}");
+        assert!(
+            md.ends_with("[^fn1]: First *point*.\n\n    Second point.\n"),
+            "definition: {md}"
         );
-        assert_eq!(derive_alt_from_tag(r#"<img src="338838561.jpg""#), "Image");
-        assert_eq!(derive_alt_from_tag(r#"<img src="cover.jpeg""#), "cover");
-        assert_eq!(derive_alt_from_tag(r#"<img"#), "Image");
+        assert!(!md.contains('↩'), "back link: {md}");
+    }
+
+    #[test]
+    fn test_footnote_reference_keeps_referenced_anchor() {
+        let xhtml = r##"<html><body><p>Text<a id="ref1" epub:type="noteref" href="#fn1">1</a>.</p>
+<aside epub:type="footnote" id="fn1"><p>Note.</p></aside></body></html>"##;
+        let refs: HashSet<String> = ["ref1".to_string()].into();
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &refs);
+        assert!(md.contains("Text[]{#ref1}[^fn1]."), "anchor: {md}");
+        let md = xhtml_to_markdown(xhtml, "ch.xhtml", &HashMap::new(), &empty_refs());
+        assert!(md.contains("Text[^fn1]."), "no anchor: {md}");
     }
 }
@@ -1,11 +1,14 @@
 pub mod asset_extract;
 pub mod chapter_org;
+pub mod dom;
+pub mod footnotes;
 pub mod frontmatter;
 pub mod html_to_md;
 pub mod profile;
 pub mod summary;
 
-use crate::epub::{self, EpubBook};
+use crate::epub::fixed_layout::RenditionLayout;
+use crate::epub::{self, EpubBook, url};
 use crate::extract::frontmatter::ChapterFrontmatter;
 use anyhow::Context;
 use regex::Regex;
@@ -29,17 +32,9 @@ fn collect_referenced_ids(book: &EpubBook, opf_dir: &str) -> HashSet<String> {
             continue;
         }
 
-        let full_path = if opf_dir.is_empty() {
-            manifest_item.href.clone()
-        } else {
-            format!("{opf_dir}{}", manifest_item.href)
-        };
+        let full_path = url::resolve(opf_dir, &manifest_item.href);
 
-        let xhtml = book
-            .resources
-            .get(&full_path)
-            .and_then(|bytes| String::from_utf8(bytes.clone()).ok())
-            .unwrap_or_default();
+        let xhtml = book.resources.get_string(&full_path).unwrap_or_default();
 
         for cap in href_re.captures_iter(&xhtml) {
             ids.insert(cap[1].to_string());
@@ -201,8 +196,41 @@ fn validate_extraction_links(output_dir: &Path) -> LinkValidationReport {
     }
 }
 
+/// What extraction does with pre-paginated (fixed-layout) pages, whose
+/// positioned content does not survive conversion to Markdown
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FixedLayoutMode {
+    /// Copy the XHTML to `pages/`, with links rewritten to the extracted
+    /// files
+    #[default]
+    Passthrough,
+    /// Leave the pages out
+    Skip,
+}
+
+impl std::str::FromStr for FixedLayoutMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "passthrough" => Ok(FixedLayoutMode::Passthrough),
+            "skip" => Ok(FixedLayoutMode::Skip),
+            _ => Err(format!(
+                "unknown fixed-layout mode '{s}' (expected passthrough or skip)"
+            )),
+        }
+    }
+}
+
 /// Extract a full EPUB to the opinionated directory structure
-pub fn extract_book(book: &EpubBook, output_dir: &Path) -> anyhow::Result<()> {
+///
+/// Reflowable chapters are converted to Markdown in `chapters/`;
+/// fixed-layout pages are handled according to `fixed_layout`.
+pub fn extract_book(
+    book: &EpubBook,
+    output_dir: &Path,
+    fixed_layout: FixedLayoutMode,
+) -> anyhow::Result<()> {
     let opf_dir = book.detect_opf_dir();
 
     // Analyze book structure before extraction
@@ -212,8 +240,10 @@ pub fn extract_book(book: &EpubBook, output_dir: &Path) -> anyhow::Result<()> {
     let chapters_dir = output_dir.join("chapters");
     std::fs::create_dir_all(&chapters_dir)?;
 
-    // Pass 1: pre-compute chapter href → markdown filename mapping
+    // Pass 1: pre-compute chapter href → markdown filename mapping, and
+    // fixed-layout page href → pages/ filename
     let mut chapter_files: Vec<(String, String)> = Vec::new();
+    let mut page_files: Vec<(String, String)> = Vec::new();
     for (index, spine_item) in book.spine.iter().enumerate() {
         let Some(manifest_item) = book.manifest.iter().find(|m| m.id == spine_item.idref) else {
             continue;
@@ -222,14 +252,31 @@ pub fn extract_book(book: &EpubBook, output_dir: &Path) -> anyhow::Result<()> {
             continue;
         }
         let chapter_filename = chapter_org::chapter_filename(index, book, &manifest_item.href);
+        if book.spine_layout(spine_item) == RenditionLayout::PrePaginated {
+            if fixed_layout == FixedLayoutMode::Passthrough {
+                let stem = chapter_filename.trim_end_matches(".md");
+                page_files.push((manifest_item.href.clone(), format!("{stem}.xhtml")));
+            }
+            continue;
+        }
         chapter_files.push((manifest_item.href.clone(), chapter_filename));
     }
 
     // Collect referenced fragment IDs (between Pass 1 and path map)
     let referenced_ids = collect_referenced_ids(book, &opf_dir);
 
-    // Build path map for asset + chapter cross-reference rewriting
-    let path_map = asset_extract::build_path_map(book, &opf_dir, &chapter_files);
+    // Build path map for asset + chapter cross-reference rewriting; pages
+    // are reached from chapters/ and pages/ alike through the parent
+    let mut path_map = asset_extract::build_path_map(book, &opf_dir, &chapter_files);
+    for (href, page_filename) in &page_files {
+        path_map.insert(
+            url::resolve(&opf_dir, href),
+            format!("../pages/{page_filename}"),
+        );
+    }
+
+    // Footnotes and endnotes, which move to the chapters referencing them
+    let notes = footnotes::NoteIndex::build(book, &path_map, &referenced_ids);
 
     // Pass 2: extract chapters using the complete path map
     let mut written_chapters: Vec<(String, String)> = Vec::new();
@@ -242,26 +289,36 @@ pub fn extract_book(book: &EpubBook, output_dir: &Path) -> anyhow::Result<()> {
             continue;
         }
 
-        let full_path = if opf_dir.is_empty() {
-            manifest_item.href.clone()
-        } else {
-            format!("{opf_dir}{}", manifest_item.href)
-        };
+        let full_path = url::resolve(&opf_dir, &manifest_item.href);
 
-        let xhtml = book
-            .resources
-            .get(&full_path)
-            .and_then(|bytes| String::from_utf8(bytes.clone()).ok())
-            .unwrap_or_default();
+        let xhtml = book.resources.get_string(&full_path).unwrap_or_default();
 
         if xhtml.is_empty() {
             continue;
         }
 
+        if book.spine_layout(spine_item) == RenditionLayout::PrePaginated {
+            if let Some((_, page_filename)) = page_files
+                .iter()
+                .find(|(href, _)| *href == manifest_item.href)
+            {
+                // Pages stay out of SUMMARY.md; `book assemble --fixed-layout`
+                // takes them from pages/ in file name order
+                write_page(output_dir, page_filename, &xhtml, &full_path, &path_map)?;
+            }
+            continue;
+        }
+
         let chapter_filename = chapter_org::chapter_filename(index, book, &manifest_item.href);
 
         // Convert XHTML to Markdown
-        let md = html_to_md::xhtml_to_markdown(&xhtml, &path_map, &referenced_ids);
+        let md = html_to_md::xhtml_to_markdown_with_notes(
+            &xhtml,
+            &full_path,
+            &path_map,
+            &referenced_ids,
+            &notes,
+        );
 
         // Generate frontmatter
         let fm = ChapterFrontmatter {
@@ -303,6 +360,36 @@ pub fn extract_book(book: &EpubBook, output_dir: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Write a fixed-layout page to `pages/` as is, but for its links
+fn write_page(
+    output_dir: &Path,
+    page_filename: &str,
+    xhtml: &str,
+    doc_path: &str,
+    path_map: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    // Chapters are mapped as siblings within chapters/
+    let page_map: HashMap<String, String> = path_map
+        .iter()
+        .map(|(path, extracted)| {
+            let extracted = if extracted.starts_with("../") {
+                extracted.clone()
+            } else {
+                format!("../chapters/{extracted}")
+            };
+            (path.clone(), extracted)
+        })
+        .collect();
+    let pages_dir = output_dir.join("pages");
+    std::fs::create_dir_all(&pages_dir)?;
+    let page_path = pages_dir.join(page_filename);
+    std::fs::write(
+        &page_path,
+        html_to_md::rewrite_links(xhtml, doc_path, &page_map),
+    )
+    .with_context(|| format!("writing {}", page_path.display()))
+}
+
 /// Extract a single chapter by ID or index
 pub fn extract_single_chapter(book: &EpubBook, id_or_index: &str) -> anyhow::Result<String> {
     let opf_dir = book.detect_opf_dir();
@@ -310,22 +397,21 @@ pub fn extract_single_chapter(book: &EpubBook, id_or_index: &str) -> anyhow::Res
 
     let (manifest_item, _index) = find_chapter(book, id_or_index)?;
 
-    let full_path = if opf_dir.is_empty() {
-        manifest_item.href.clone()
-    } else {
-        format!("{opf_dir}{}", manifest_item.href)
-    };
+    let full_path = url::resolve(&opf_dir, &manifest_item.href);
 
     let xhtml = book
         .resources
-        .get(&full_path)
-        .and_then(|bytes| String::from_utf8(bytes.clone()).ok())
+        .get_string(&full_path)
         .ok_or_else(|| anyhow::anyhow!("chapter content not found: {}", manifest_item.href))?;
 
-    Ok(html_to_md::xhtml_to_markdown(
+    let referenced_ids = HashSet::new();
+    let notes = footnotes::NoteIndex::build(book, &path_map, &referenced_ids);
+    Ok(html_to_md::xhtml_to_markdown_with_notes(
         &xhtml,
+        &full_path,
         &path_map,
-        &HashSet::new(),
+        &referenced_ids,
+        &notes,
     ))
 }
 
@@ -1,4 +1,4 @@
-use crate::epub::EpubBook;
+use crate::epub::{EpubBook, url};
 use regex::Regex;
 use std::fmt;
 
@@ -68,17 +68,9 @@ pub fn analyze_book(book: &EpubBook) -> BookProfile {
             continue;
         }
 
-        let full_path = if opf_dir.is_empty() {
-            manifest_item.href.clone()
-        } else {
-            format!("{opf_dir}{}", manifest_item.href)
-        };
+        let full_path = url::resolve(&opf_dir, &manifest_item.href);
 
-        let xhtml = book
-            .resources
-            .get(&full_path)
-            .and_then(|bytes| String::from_utf8(bytes.clone()).ok())
-            .unwrap_or_default();
+        let xhtml = book.resources.get_string(&full_path).unwrap_or_default();
 
         let chapter_images = img_re.find_iter(&xhtml).count();
         image_count += chapter_images;
@@ -144,11 +136,13 @@ mod tests {
                 href,
                 media_type: "application/xhtml+xml".to_string(),
                 properties: None,
+                ..Default::default()
             });
             book.spine.push(SpineItem {
                 idref: format!("ch{i}"),
                 linear: true,
                 properties: None,
+                ..Default::default()
             });
             book.resources
                 .insert(full_path, xhtml_content.as_bytes().to_vec());
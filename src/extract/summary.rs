@@ -1,4 +1,4 @@
-use crate::epub::NavPoint;
+use crate::epub::{NavPoint, url};
 
 /// Generate SUMMARY.md content from navigation tree
 pub fn generate_summary(toc: &[NavPoint], chapter_files: &[(String, String)]) -> String {
@@ -7,6 +7,32 @@ pub fn generate_summary(toc: &[NavPoint], chapter_files: &[(String, String)]) ->
     output
 }
 
+fn write_nav_entries(
+    output: &mut String,
+    points: &[NavPoint],
+    chapter_files: &[(String, String)],
+    indent: usize,
+) {
+    for point in points {
+        let prefix = "  ".repeat(indent);
+        let path = url::resolve("", &point.href);
+
+        // Find matching chapter file
+        let link = chapter_files
+            .iter()
+            .find(|(orig, _)| url::resolve("", orig) == path)
+            .map(|(_, md_file)| format!("chapters/{md_file}"));
+
+        if let Some(path) = link {
+            output.push_str(&format!("{prefix}- [{}]({path})\n", point.label));
+        } else {
+            output.push_str(&format!("{prefix}- {}\n", point.label));
+        }
+
+        write_nav_entries(output, &point.children, chapter_files, indent + 1);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -18,11 +44,13 @@ mod tests {
                 label: "Chapter 1".to_string(),
                 href: "ch1.xhtml".to_string(),
                 children: vec![],
+                ..Default::default()
             },
             NavPoint {
                 label: "Chapter 2".to_string(),
                 href: "ch2.xhtml".to_string(),
                 children: vec![],
+                ..Default::default()
             },
         ];
         let files = vec![
@@ -44,7 +72,9 @@ mod tests {
                 label: "Ch 1".to_string(),
                 href: "ch1.xhtml".to_string(),
                 children: vec![],
+                ..Default::default()
             }],
+            ..Default::default()
         }];
         let files = vec![
             ("p1.xhtml".to_string(), "00-part-1.md".to_string()),
@@ -63,6 +93,7 @@ mod tests {
             label: "Missing Chapter".to_string(),
             href: "missing.xhtml".to_string(),
             children: vec![],
+            ..Default::default()
         }];
         let files = vec![];
         let summary = generate_summary(&toc, &files);
@@ -70,29 +101,3 @@ mod tests {
         assert!(!summary.contains("]("));
     }
 }
-
-fn write_nav_entries(
-    output: &mut String,
-    points: &[NavPoint],
-    chapter_files: &[(String, String)],
-    indent: usize,
-) {
-    for point in points {
-        let prefix = "  ".repeat(indent);
-        let href = point.href.split('#').next().unwrap_or(&point.href);
-
-        // Find matching chapter file
-        let link = chapter_files
-            .iter()
-            .find(|(orig, _)| href == orig || orig.ends_with(href))
-            .map(|(_, md_file)| format!("chapters/{md_file}"));
-
-        if let Some(path) = link {
-            output.push_str(&format!("{prefix}- [{}]({path})\n", point.label));
-        } else {
-            output.push_str(&format!("{prefix}- {}\n", point.label));
-        }
-
-        write_nav_entries(output, &point.children, chapter_files, indent + 1);
-    }
-}
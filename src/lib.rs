@@ -5,3 +5,4 @@ pub mod error;
 pub mod extract;
 pub mod manipulate;
 pub mod util;
+pub mod validate;
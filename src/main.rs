@@ -5,6 +5,7 @@ mod error;
 mod extract;
 mod manipulate;
 mod util;
+mod validate;
 
 use anyhow::{Context, Result};
 use clap::Parser;
@@ -23,44 +24,116 @@ fn format_size(bytes: usize) -> String {
     }
 }
 
+/// A title with its type, e.g. "Alice (main)"
+fn describe_title(title: &epub::Title) -> String {
+    match title.title_type {
+        Some(ref title_type) => format!("{} ({title_type})", title.value),
+        None => title.value.clone(),
+    }
+}
+
+/// A contributor with their roles, e.g. "Lewis Carroll (aut)"
+fn describe_contributor(contributor: &epub::Contributor) -> String {
+    if contributor.roles.is_empty() {
+        contributor.name.clone()
+    } else {
+        format!("{} ({})", contributor.name, contributor.roles.join(", "))
+    }
+}
+
+/// Read a book for a command, warning about what a lenient read worked
+/// around
+fn read_book(
+    file: &std::path::Path,
+    edit_options: &manipulate::meta_edit::EditOptions,
+) -> Result<epub::EpubBook> {
+    let book = util::read_epub_input(file, &edit_options.read)
+        .with_context(|| format!("failed to read {}", file.display()))?;
+    for repair in &book.repairs {
+        eprintln!("warning: {repair}");
+    }
+    Ok(book)
+}
+
+/// Modify a book in place; a book read from stdin is written to stdout
+fn modify_book(
+    file: &std::path::Path,
+    output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
+    modify: impl FnOnce(&mut epub::EpubBook) -> Result<()>,
+) -> Result<()> {
+    if util::is_stdio(file) {
+        output.reserve_stdout();
+    }
+    manipulate::meta_edit::modify_epub(file, edit_options, modify)
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
     let output =
         cli::output::OutputConfig::from_global(cli.json, cli.verbose, cli.quiet, cli.no_color);
+    let edit_options = manipulate::meta_edit::EditOptions {
+        read: epub::reader::ReadOptions {
+            rendition: cli.rendition,
+            lenient: cli.lenient,
+        },
+        transcode_utf8: cli.transcode_utf8,
+    };
 
     match cli.command {
-        Resource::Book { command } => handle_book(command, &output)?,
-        Resource::Chapter { command } => handle_chapter(command, &output)?,
-        Resource::Metadata { command } => handle_metadata(command, &output)?,
-        Resource::Toc { command } => handle_toc(command, &output)?,
-        Resource::Spine { command } => handle_spine(command, &output)?,
-        Resource::Asset { command } => handle_asset(command, &output)?,
-        Resource::Content { command } => handle_content(command, &output)?,
+        Resource::Book { command } => handle_book(command, &output, &edit_options)?,
+        Resource::Chapter { command } => handle_chapter(command, &output, &edit_options)?,
+        Resource::Metadata { command } => handle_metadata(command, &output, &edit_options)?,
+        Resource::Toc { command } => handle_toc(command, &output, &edit_options)?,
+        Resource::Spine { command } => handle_spine(command, &output, &edit_options)?,
+        Resource::Asset { command } => handle_asset(command, &output, &edit_options)?,
+        Resource::Content { command } => handle_content(command, &output, &edit_options)?,
+        Resource::MediaOverlay { command } => {
+            handle_media_overlay(command, &output, &edit_options)?
+        }
     }
 
     Ok(())
 }
 
-fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConfig) -> Result<()> {
+fn handle_book(
+    command: cli::book::BookCommand,
+    output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
+) -> Result<()> {
     use cli::book::BookCommand;
 
     match command {
         BookCommand::Info { file } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
-            let total_size: usize = book.resources.values().map(|v| v.len()).sum();
+            let total_size = book.resources.total_size() as usize;
             let opf_dir = book.detect_opf_dir();
+            let opf_path = book.source.as_ref().map(|source| source.opf_path.as_str());
 
             if output.json {
                 let mut info = serde_json::json!({
-                    "title": book.metadata.titles.first().unwrap_or(&"(untitled)".to_string()),
-                    "creators": book.metadata.creators,
+                    "title": book.metadata.title().unwrap_or("(untitled)"),
+                    "creators": book.metadata.creator_names(),
                     "languages": book.metadata.languages,
                     "epub_version": book.navigation.epub_version.to_string(),
                     "chapters": book.spine.len(),
                     "assets": book.manifest.len(),
+                    "layout": book.layout(),
                 });
+                if book.renditions.len() > 1 {
+                    let renditions: Vec<serde_json::Value> = book
+                        .renditions
+                        .iter()
+                        .map(|rendition| {
+                            let mut value = serde_json::to_value(rendition)?;
+                            value["selected"] =
+                                serde_json::json!(Some(rendition.full_path.as_str()) == opf_path);
+                            Ok(value)
+                        })
+                        .collect::<Result<_>>()?;
+                    info["renditions"] = serde_json::json!(renditions);
+                }
                 if output.verbose {
                     info["opf_dir"] = serde_json::json!(if opf_dir.is_empty() {
                         "(root)"
@@ -72,13 +145,23 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                     if let Some(ref cover) = book.metadata.cover_id {
                         info["cover_id"] = serde_json::json!(cover);
                     }
+                    let meta_inf: Vec<&str> = book
+                        .meta_inf
+                        .files
+                        .iter()
+                        .map(|f| f.path.as_str())
+                        .collect();
+                    info["meta_inf"] = serde_json::json!(meta_inf);
+                    if let Some(display_options) = book.meta_inf.display_options() {
+                        info["display_options"] = serde_json::to_value(display_options?)?;
+                    }
                 }
                 output.print_json(&info)?;
             } else {
-                let title = book.metadata.titles.first().map_or("(untitled)", |s| s);
+                let title = book.metadata.title().unwrap_or("(untitled)");
                 println!("Title:    {title}");
                 if !book.metadata.creators.is_empty() {
-                    println!("Author:   {}", book.metadata.creators.join(", "));
+                    println!("Author:   {}", book.metadata.creator_names().join(", "));
                 }
                 if !book.metadata.languages.is_empty() {
                     println!("Language: {}", book.metadata.languages.join(", "));
@@ -86,6 +169,42 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                 println!("Version:  EPUB {}", book.navigation.epub_version);
                 println!("Chapters: {}", book.spine.len());
                 println!("Assets:   {}", book.manifest.len());
+                if book.has_fixed_layout() {
+                    let pages = book.fixed_layout_pages();
+                    let mut viewports: Vec<String> = pages
+                        .iter()
+                        .filter_map(|page| page.viewport.map(|v| v.to_string()))
+                        .collect();
+                    viewports.sort();
+                    viewports.dedup();
+                    let viewports = if viewports.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", {}", viewports.join(", "))
+                    };
+                    println!(
+                        "Layout:   {} ({} fixed-layout pages{viewports})",
+                        book.layout(),
+                        pages.len()
+                    );
+                }
+                if book.renditions.len() > 1 {
+                    println!("Renditions:");
+                    for (i, rendition) in book.renditions.iter().enumerate() {
+                        let marker = if Some(rendition.full_path.as_str()) == opf_path {
+                            "*"
+                        } else {
+                            " "
+                        };
+                        let attributes = rendition.selection_attributes();
+                        let attributes = if attributes.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" ({})", attributes.join(", "))
+                        };
+                        println!("  {marker} {i}: {}{attributes}", rendition.full_path);
+                    }
+                }
                 output.detail(&format!(
                     "OPF dir:  {}",
                     if opf_dir.is_empty() {
@@ -106,25 +225,49 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                 {
                     output.detail(&format!("Cover:    {cover}"));
                 }
+                if output.verbose && !book.meta_inf.files.is_empty() {
+                    let paths: Vec<&str> = book
+                        .meta_inf
+                        .files
+                        .iter()
+                        .map(|f| f.path.as_str())
+                        .collect();
+                    output.detail(&format!("META-INF: {}", paths.join(", ")));
+                }
+                if output.verbose
+                    && let Some(display_options) = book.meta_inf.display_options()
+                {
+                    for platform in display_options?.platforms {
+                        let options: Vec<String> = platform
+                            .options
+                            .iter()
+                            .map(|o| format!("{}={}", o.name, o.value))
+                            .collect();
+                        output.detail(&format!(
+                            "Display:  [{}] {}",
+                            platform.name,
+                            options.join(", ")
+                        ));
+                    }
+                }
             }
         }
         BookCommand::Extract {
             file,
             output: out_dir,
+            fixed_layout,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let title = book
                 .metadata
-                .titles
-                .first()
+                .title()
                 .map(slug::slugify)
                 .unwrap_or_else(|| "epub-extract".to_string());
             let output_dir = out_dir.unwrap_or_else(|| std::path::PathBuf::from(&title));
 
             std::fs::create_dir_all(&output_dir)?;
-            extract::extract_book(&book, &output_dir)
+            extract::extract_book(&book, &output_dir, fixed_layout)
                 .with_context(|| format!("extracting to {}", output_dir.display()))?;
 
             output.status(&format!("Extracted to {}", output_dir.display()));
@@ -133,10 +276,25 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                 book.spine.len(),
                 book.manifest.len()
             ));
+            let pages = book.fixed_layout_pages().len();
+            if pages > 0 {
+                output.detail(&match fixed_layout {
+                    extract::FixedLayoutMode::Passthrough => {
+                        format!("  {pages} fixed-layout pages copied to pages/")
+                    }
+                    extract::FixedLayoutMode::Skip => {
+                        format!("  {pages} fixed-layout pages skipped")
+                    }
+                });
+            }
         }
         BookCommand::Assemble {
             dir,
             output: out_file,
+            obfuscate_fonts,
+            epub_version,
+            fixed_layout,
+            viewport,
         } => {
             let title = dir
                 .file_name()
@@ -144,8 +302,17 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                 .unwrap_or_else(|| "output".to_string());
             let epub_path =
                 out_file.unwrap_or_else(|| std::path::PathBuf::from(format!("{title}.epub")));
+            if util::is_stdio(&epub_path) {
+                output.reserve_stdout();
+            }
 
-            assemble::package::package_epub(&dir, &epub_path).with_context(|| {
+            let options = assemble::package::AssembleOptions {
+                obfuscate_fonts,
+                version: epub_version,
+                fixed_layout,
+                viewport,
+            };
+            assemble::package::package_epub(&dir, &epub_path, &options).with_context(|| {
                 format!("assembling {} to {}", dir.display(), epub_path.display())
             })?;
 
@@ -156,56 +323,274 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
                 output.detail(&format!("  Size: {}", format_size(meta.len() as usize)));
             }
         }
-        BookCommand::Validate { file } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+        BookCommand::Validate {
+            file,
+            format,
+            fail_on,
+            fix,
+            aggressive,
+        } => {
+            use validate::report::{self, ReportFormat};
 
-            let mut issues: Vec<String> = Vec::new();
+            let name = file.display().to_string();
+            let mut fixes = None;
+            let mut checked = None;
+            if fix {
+                if util::is_stdio(&file) {
+                    output.reserve_stdout();
+                }
+                // A book nothing was fixed in is left untouched
+                manipulate::meta_edit::modify_epub_if_changed(&file, edit_options, |book| {
+                    let report = validate::fix::fix_book(book, aggressive);
+                    let changed = !report.applied.is_empty();
+                    fixes = Some(report);
+                    checked = Some((validate::validate(book), book.manifest.len()));
+                    Ok(changed)
+                })?;
+            }
+            let (issues, manifest_items) = match checked {
+                Some(checked) => checked,
+                None => {
+                    let book = read_book(&file, edit_options)?;
+                    (validate::validate(&book), book.manifest.len())
+                }
+            };
+            let errors = issues.iter().filter(|issue| issue.is_error()).count();
+            let warnings = issues.len() - errors;
 
-            // Check required metadata
-            if book.metadata.titles.is_empty() {
-                issues.push("missing dc:title".to_string());
+            let format = format.unwrap_or(if output.json {
+                ReportFormat::Json
+            } else {
+                ReportFormat::Text
+            });
+            if let Some(fixes) = &fixes {
+                if format == ReportFormat::Json {
+                    let mut json = serde_json::json!({
+                        "fixed": fixes.applied,
+                        "refused": fixes.refused,
+                    });
+                    json["report"] = report::to_json(&issues);
+                    output.print_json(&json)?;
+                } else {
+                    output.println(&format!("{name}: {} fix(es) applied", fixes.applied.len()));
+                    for applied in &fixes.applied {
+                        output.println(&format!("  - {applied}"));
+                    }
+                    if !fixes.refused.is_empty() {
+                        output.println(&format!(
+                            "{name}: {} risky fix(es) skipped (use --aggressive)",
+                            fixes.refused.len()
+                        ));
+                        for refused in &fixes.refused {
+                            output.println(&format!("  - {refused}"));
+                        }
+                    }
+                }
             }
-            if book.metadata.languages.is_empty() {
-                issues.push("missing dc:language".to_string());
+
+            match format {
+                ReportFormat::Json if fixes.is_some() => {}
+                ReportFormat::Json => output.print_json(&report::to_json(&issues))?,
+                ReportFormat::Sarif => output.print_json(&report::to_sarif(&issues, &name))?,
+                ReportFormat::Junit => output.println(report::to_junit(&issues, &name).trim_end()),
+                ReportFormat::Text if issues.is_empty() => {
+                    output.println(&format!("{name}: valid"))
+                }
+                ReportFormat::Text => {
+                    let verdict = if errors == 0 { "valid" } else { "invalid" };
+                    output.println(&format!(
+                        "{name}: {verdict}, {errors} error(s), {warnings} warning(s)"
+                    ));
+                    for issue in &issues {
+                        output.println(&format!("  - {issue}"));
+                        output.detail(&format!("      fix: {}", issue.suggestion));
+                    }
+                }
             }
-            if book.metadata.identifiers.is_empty() {
-                issues.push("missing dc:identifier".to_string());
+            if format == ReportFormat::Text {
+                output.detail(&format!(
+                    "  Checked: {} rules, {manifest_items} manifest items",
+                    validate::rules::ALL.len()
+                ));
             }
 
-            // Check spine references exist in manifest
-            for spine_item in &book.spine {
-                if !book.manifest.iter().any(|m| m.id == spine_item.idref) {
-                    issues.push(format!(
-                        "spine references missing manifest item: {}",
-                        spine_item.idref
-                    ));
+            if let Some(fail_on) = fail_on {
+                let failing = fail_on.count(&issues);
+                if failing > 0 {
+                    anyhow::bail!(
+                        "{name}: {failing} issue(s) at or above {} severity",
+                        fail_on.0
+                    );
                 }
             }
+        }
+        BookCommand::Repair {
+            file,
+            output: out_file,
+        } => {
+            let read = epub::reader::ReadOptions {
+                lenient: true,
+                ..edit_options.read.clone()
+            };
+            let book = util::read_epub_input(&file, &read)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let out_path = out_file.as_ref().unwrap_or(&file);
 
-            // Check empty spine
-            if book.spine.is_empty() {
-                issues.push("spine is empty".to_string());
+            // A book without problems is only copied when asked to, except
+            // in a pipeline, which always gets the book back
+            let write = !book.repairs.is_empty() || out_file.is_some() || util::is_stdio(&file);
+            if write {
+                if util::is_stdio(out_path) {
+                    output.reserve_stdout();
+                }
+                let options = epub::writer::WriteOptions {
+                    layout: epub::writer::Layout::Preserve,
+                    transcode_utf8: edit_options.transcode_utf8,
+                    ..Default::default()
+                };
+                util::write_epub_output(&book, out_path, &options)
+                    .with_context(|| format!("writing {}", out_path.display()))?;
             }
 
             if output.json {
                 let json = serde_json::json!({
-                    "valid": issues.is_empty(),
-                    "issues": issues,
+                    "file": out_path.display().to_string(),
+                    "written": write,
+                    "repairs": book.repairs,
                 });
                 output.print_json(&json)?;
-            } else if issues.is_empty() {
-                println!("{}: valid", file.display());
+            } else if book.repairs.is_empty() {
+                output.println(&format!("{}: nothing to repair", file.display()));
             } else {
-                println!("{}: {} issue(s)", file.display(), issues.len());
-                for issue in &issues {
-                    println!("  - {issue}");
+                output.println(&format!(
+                    "{}: {} repair(s), written to {}",
+                    file.display(),
+                    book.repairs.len(),
+                    out_path.display()
+                ));
+                for repair in &book.repairs {
+                    output.println(&format!("  - {repair}"));
+                }
+            }
+        }
+        BookCommand::Upgrade {
+            file,
+            output: out_file,
+        } => {
+            let mut book = read_book(&file, edit_options)?;
+            let out_path = out_file.as_ref().unwrap_or(&file);
+
+            let changes = if book.version() == epub::EpubVersion::V2 {
+                manipulate::upgrade::upgrade_to_epub3(&mut book)
+            } else {
+                Vec::new()
+            };
+            // Like repair, an EPUB 3 book is only copied when asked to
+            let write = !changes.is_empty() || out_file.is_some() || util::is_stdio(&file);
+            if write {
+                if util::is_stdio(out_path) {
+                    output.reserve_stdout();
+                }
+                let options = epub::writer::WriteOptions {
+                    layout: epub::writer::Layout::Preserve,
+                    transcode_utf8: edit_options.transcode_utf8,
+                    version: Some(epub::EpubVersion::V3),
+                };
+                util::write_epub_output(&book, out_path, &options)
+                    .with_context(|| format!("writing {}", out_path.display()))?;
+            }
+
+            if output.json {
+                let json = serde_json::json!({
+                    "file": out_path.display().to_string(),
+                    "written": write,
+                    "changes": changes,
+                });
+                output.print_json(&json)?;
+            } else if changes.is_empty() {
+                output.println(&format!("{}: already EPUB 3", file.display()));
+            } else {
+                output.println(&format!(
+                    "{}: upgraded to EPUB 3 with {} change(s), written to {}",
+                    file.display(),
+                    changes.len(),
+                    out_path.display()
+                ));
+                for change in &changes {
+                    output.println(&format!("  - {change}"));
+                }
+            }
+        }
+        BookCommand::A11y {
+            file,
+            write,
+            summary,
+            conforms_to,
+        } => {
+            use epub::accessibility;
+
+            let audit = |book: &epub::EpubBook| {
+                let mut report = accessibility::audit(book);
+                if let Some(summary) = &summary {
+                    report.suggested.summary = Some(summary.clone());
+                }
+                if let Some(claim) = &conforms_to {
+                    report.suggested.set(accessibility::CONFORMS_TO, claim);
+                }
+                report
+            };
+            let report = if write {
+                let mut report = None;
+                modify_book(&file, output, edit_options, |book| {
+                    let audited = audit(book);
+                    book.metadata.accessibility = audited.suggested.clone();
+                    report = Some(audited);
+                    Ok(())
+                })
+                .with_context(|| format!("writing {}", file.display()))?;
+                report.expect("modify_book runs the edit")
+            } else {
+                audit(&read_book(&file, edit_options)?)
+            };
+
+            if output.json {
+                let json = serde_json::json!({
+                    "file": file.display().to_string(),
+                    "metadata": report.declared,
+                    "issues": report.issues,
+                    "suggested": report.suggested,
+                    "written": write,
+                });
+                output.print_json(&json)?;
+            } else {
+                let declared = report.declared.properties();
+                if declared.is_empty() {
+                    output.println(&format!("{}: no accessibility metadata", file.display()));
+                } else {
+                    output.println(&format!("{}: accessibility metadata", file.display()));
+                    for (property, value) in declared {
+                        output.println(&format!("  {property}: {value}"));
+                    }
+                }
+                if report.issues.is_empty() {
+                    output.println("No issues found");
+                } else {
+                    output.println(&format!("{} issue(s):", report.issues.len()));
+                    for issue in &report.issues {
+                        output.println(&format!("  - {issue}"));
+                    }
+                }
+                if report.suggested != report.declared {
+                    output.println(if write {
+                        "Written metadata:"
+                    } else {
+                        "Suggested metadata (write with --write):"
+                    });
+                    for (property, value) in report.suggested.properties() {
+                        output.println(&format!("  {property}: {value}"));
+                    }
                 }
             }
-            output.detail(&format!(
-                "  Checked: metadata, spine references, {} manifest items",
-                book.manifest.len()
-            ));
         }
     }
 
@@ -215,14 +600,13 @@ fn handle_book(command: cli::book::BookCommand, output: &cli::output::OutputConf
 fn handle_chapter(
     command: cli::chapter::ChapterCommand,
     output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
 ) -> Result<()> {
     use cli::chapter::ChapterCommand;
 
     match command {
         ChapterCommand::List { file } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
-            let opf_dir = book.detect_opf_dir();
+            let book = read_book(&file, edit_options)?;
 
             if output.verbose {
                 let rows: Vec<Vec<String>> = book
@@ -232,12 +616,8 @@ fn handle_chapter(
                     .map(|(i, item)| {
                         let manifest_item = book.manifest.iter().find(|m| m.id == item.idref);
                         let href = manifest_item.map_or("-".to_string(), |m| m.href.clone());
-                        let full_path = if opf_dir.is_empty() {
-                            href.clone()
-                        } else {
-                            format!("{opf_dir}{href}")
-                        };
-                        let size = book.resources.get(&full_path).map_or(0, |v| v.len());
+                        let full_path = book.resource_key(&href);
+                        let size = book.resources.size(&full_path).unwrap_or(0) as usize;
                         vec![i.to_string(), item.idref.clone(), href, format_size(size)]
                     })
                     .collect();
@@ -279,16 +659,16 @@ fn handle_chapter(
             id,
             output: out_file,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let md = extract::extract_single_chapter(&book, &id)?;
 
-            if let Some(path) = out_file {
-                std::fs::write(&path, &md)?;
-                output.status(&format!("Extracted to {}", path.display()));
-            } else {
-                print!("{md}");
+            match out_file {
+                Some(path) if !util::is_stdio(&path) => {
+                    std::fs::write(&path, &md)?;
+                    output.status(&format!("Extracted to {}", path.display()));
+                }
+                _ => print!("{md}"),
             }
         }
         ChapterCommand::Add {
@@ -298,7 +678,7 @@ fn handle_chapter(
             title,
         } => {
             let out = output;
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 let id = manipulate::chapter_manage::add_chapter(
                     book,
                     &markdown,
@@ -312,15 +692,24 @@ fn handle_chapter(
         }
         ChapterCommand::Remove { file, id } => {
             let out = output;
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 let removed = manipulate::chapter_manage::remove_chapter(book, &id)?;
                 out.status(&format!("Removed chapter: {removed}"));
                 Ok(())
             })
             .with_context(|| format!("removing chapter from {}", file.display()))?;
         }
+        ChapterCommand::Rename { file, id, new_href } => {
+            let out = output;
+            modify_book(&file, output, edit_options, |book| {
+                let path = manipulate::chapter_manage::rename_chapter(book, &id, &new_href)?;
+                out.status(&format!("Moved chapter {id} to {path}"));
+                Ok(())
+            })
+            .with_context(|| format!("renaming chapter in {}", file.display()))?;
+        }
         ChapterCommand::Reorder { file, from, to } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::chapter_manage::reorder_chapter(book, from, to)
             })
             .with_context(|| format!("reordering chapters in {}", file.display()))?;
@@ -334,23 +723,31 @@ fn handle_chapter(
 fn handle_metadata(
     command: cli::metadata::MetadataCommand,
     output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
 ) -> Result<()> {
     use cli::metadata::MetadataCommand;
 
     match command {
         MetadataCommand::Show { file } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             if output.json {
                 output.print_json(&book.metadata)?;
             } else {
                 let m = &book.metadata;
                 if !m.titles.is_empty() {
-                    println!("Title:       {}", m.titles.join("; "));
+                    let titles: Vec<String> = m.titles.iter().map(describe_title).collect();
+                    println!("Title:       {}", titles.join("; "));
                 }
                 if !m.creators.is_empty() {
-                    println!("Creator:     {}", m.creators.join("; "));
+                    let creators: Vec<String> =
+                        m.creators.iter().map(describe_contributor).collect();
+                    println!("Creator:     {}", creators.join("; "));
+                }
+                if !m.contributors.is_empty() {
+                    let contributors: Vec<String> =
+                        m.contributors.iter().map(describe_contributor).collect();
+                    println!("Contributor: {}", contributors.join("; "));
                 }
                 if !m.identifiers.is_empty() {
                     println!("Identifier:  {}", m.identifiers.join("; "));
@@ -373,24 +770,27 @@ fn handle_metadata(
                 if let Some(ref rights) = m.rights {
                     println!("Rights:      {rights}");
                 }
+                for (property, value) in m.accessibility.properties() {
+                    println!("{property}: {value}");
+                }
             }
         }
         MetadataCommand::Set { file, field, value } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::meta_edit::set_field(book, &field, &value)
             })
             .with_context(|| format!("modifying {}", file.display()))?;
             output.status(&format!("Set {field} = {value}"));
         }
         MetadataCommand::Remove { file, field } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::meta_edit::remove_field(book, &field)
             })
             .with_context(|| format!("modifying {}", file.display()))?;
             output.status(&format!("Removed {field}"));
         }
         MetadataCommand::Import { file, metadata } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::meta_edit::import_metadata(book, &metadata)
             })
             .with_context(|| format!("importing metadata to {}", file.display()))?;
@@ -400,24 +800,28 @@ fn handle_metadata(
             file,
             output: out_file,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
             let yaml_path = out_file.unwrap_or_else(|| std::path::PathBuf::from("metadata.yml"));
             manipulate::meta_edit::export_metadata(&book, &yaml_path)?;
-            output.status(&format!("Exported metadata to {}", yaml_path.display()));
+            if !util::is_stdio(&yaml_path) {
+                output.status(&format!("Exported metadata to {}", yaml_path.display()));
+            }
         }
     }
 
     Ok(())
 }
 
-fn handle_toc(command: cli::toc::TocCommand, output: &cli::output::OutputConfig) -> Result<()> {
+fn handle_toc(
+    command: cli::toc::TocCommand,
+    output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
+) -> Result<()> {
     use cli::toc::TocCommand;
 
     match command {
         TocCommand::Show { file, depth } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             if output.json {
                 fn nav_to_json(points: &[epub::NavPoint]) -> Vec<serde_json::Value> {
@@ -451,15 +855,15 @@ fn handle_toc(command: cli::toc::TocCommand, output: &cli::output::OutputConfig)
             }
         }
         TocCommand::Set { file, toc } => {
-            let toc_content = std::fs::read_to_string(&toc)?;
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            let toc_content = util::read_input_string(&toc)?;
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::toc_edit::set_toc_from_markdown(book, &toc_content)
             })
             .with_context(|| format!("setting TOC on {}", file.display()))?;
             output.status(&format!("TOC updated from {}", toc.display()));
         }
         TocCommand::Generate { file, depth } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::toc_edit::generate_toc(book, depth)
             })
             .with_context(|| format!("generating TOC for {}", file.display()))?;
@@ -473,13 +877,13 @@ fn handle_toc(command: cli::toc::TocCommand, output: &cli::output::OutputConfig)
 fn handle_spine(
     command: cli::spine::SpineCommand,
     output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
 ) -> Result<()> {
     use cli::spine::SpineCommand;
 
     match command {
         SpineCommand::List { file } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let rows: Vec<Vec<String>> = book
                 .spine
@@ -490,31 +894,47 @@ fn handle_spine(
                         i.to_string(),
                         item.idref.clone(),
                         if item.linear { "yes" } else { "no" }.to_string(),
+                        item.properties.clone().unwrap_or_default(),
                     ]
                 })
                 .collect();
 
             if output.json {
+                let pages = book.fixed_layout_pages();
                 let items: Vec<_> = rows
                     .iter()
-                    .map(|r| serde_json::json!({"index": r[0], "idref": r[1], "linear": r[2]}))
-                    .collect();
+                    .enumerate()
+                    .map(|(i, r)| {
+                        let mut item =
+                            serde_json::json!({"index": r[0], "idref": r[1], "linear": r[2]});
+                        if !r[3].is_empty() {
+                            item["properties"] = serde_json::json!(r[3]);
+                        }
+                        if let Some(page) = pages.iter().find(|page| page.index == i) {
+                            item["layout"] = serde_json::json!("pre-paginated");
+                            if let Some(viewport) = page.viewport {
+                                item["viewport"] = serde_json::to_value(viewport)?;
+                            }
+                        }
+                        Ok(item)
+                    })
+                    .collect::<Result<_>>()?;
                 output.print_json(&items)?;
             } else {
-                output.print_table(&["#", "IDREF", "LINEAR"], &rows);
+                output.print_table(&["#", "IDREF", "LINEAR", "PROPERTIES"], &rows);
             }
         }
         SpineCommand::Reorder { file, from, to } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::toc_edit::reorder_spine(book, from, to)
             })
             .with_context(|| format!("reordering spine in {}", file.display()))?;
             output.status(&format!("Moved spine item {from} to {to}"));
         }
         SpineCommand::Set { file, spine } => {
-            let content = std::fs::read_to_string(&spine)?;
+            let content = util::read_input_string(&spine)?;
             let idrefs: Vec<String> = serde_yaml_ng::from_str(&content)?;
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 manipulate::toc_edit::set_spine_order(book, &idrefs)
             })
             .with_context(|| format!("setting spine on {}", file.display()))?;
@@ -528,27 +948,31 @@ fn handle_spine(
 fn handle_asset(
     command: cli::asset::AssetCommand,
     output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
 ) -> Result<()> {
     use cli::asset::AssetCommand;
 
     match command {
-        AssetCommand::List { file, r#type } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
-            let opf_dir = book.detect_opf_dir();
+        AssetCommand::List {
+            file,
+            r#type,
+            unused,
+        } => {
+            let book = read_book(&file, edit_options)?;
 
-            let items: Vec<_> = book
-                .manifest
-                .iter()
+            let candidates = if unused {
+                manipulate::asset_manage::unused_assets(&book)
+            } else {
+                book.manifest.iter().collect()
+            };
+            let items: Vec<_> = candidates
+                .into_iter()
                 .filter(|item| {
                     if let Some(ref filter) = r#type {
                         match filter.as_str() {
                             "image" => item.media_type.starts_with("image/"),
                             "css" => item.media_type == "text/css",
-                            "font" => {
-                                item.media_type.contains("font")
-                                    || item.media_type == "application/vnd.ms-opentype"
-                            }
+                            "font" => item.is_font(),
                             "audio" => item.media_type.starts_with("audio/"),
                             _ => true,
                         }
@@ -562,12 +986,8 @@ fn handle_asset(
                 let rows: Vec<Vec<String>> = items
                     .iter()
                     .map(|item| {
-                        let full_path = if opf_dir.is_empty() {
-                            item.href.clone()
-                        } else {
-                            format!("{opf_dir}{}", item.href)
-                        };
-                        let size = book.resources.get(&full_path).map_or(0, |v| v.len());
+                        let full_path = book.resource_key(&item.href);
+                        let size = book.resources.size(&full_path).unwrap_or(0) as usize;
                         vec![
                             item.id.clone(),
                             item.href.clone(),
@@ -581,12 +1001,8 @@ fn handle_asset(
                     let json: Vec<_> = items
                         .iter()
                         .map(|item| {
-                            let full_path = if opf_dir.is_empty() {
-                                item.href.clone()
-                            } else {
-                                format!("{opf_dir}{}", item.href)
-                            };
-                            let size = book.resources.get(&full_path).map_or(0, |v| v.len());
+                            let full_path = book.resource_key(&item.href);
+                            let size = book.resources.size(&full_path).unwrap_or(0);
                             serde_json::json!({
                                 "id": item.id,
                                 "href": item.href,
@@ -627,31 +1043,28 @@ fn handle_asset(
             asset_path,
             output: out_file,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let data = book
                 .resources
-                .iter()
-                .find(|(k, _)| k.ends_with(&asset_path) || **k == asset_path)
-                .map(|(_, v)| v)
+                .get(&asset_path)
+                .or_else(|| book.resources.get(&book.resource_key(&asset_path)))
                 .ok_or_else(|| anyhow::anyhow!("asset not found: {asset_path}"))?;
 
-            if let Some(path) = out_file {
-                std::fs::write(&path, data)?;
-                output.status(&format!("Extracted to {}", path.display()));
-                output.detail(&format!("  Size: {}", format_size(data.len())));
-            } else {
-                use std::io::Write;
-                std::io::stdout().write_all(data)?;
+            match out_file {
+                Some(path) if !util::is_stdio(&path) => {
+                    std::fs::write(&path, data)?;
+                    output.status(&format!("Extracted to {}", path.display()));
+                    output.detail(&format!("  Size: {}", format_size(data.len())));
+                }
+                _ => util::write_output(std::path::Path::new("-"), data)?,
             }
         }
         AssetCommand::ExtractAll {
             file,
             output: out_dir,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let output_dir = out_dir.unwrap_or_else(|| std::path::PathBuf::from("assets"));
             let opf_dir = book.detect_opf_dir();
@@ -662,21 +1075,61 @@ fn handle_asset(
             file,
             asset,
             media_type,
+            obfuscate,
         } => {
             let out = output;
-            manipulate::meta_edit::modify_epub(&file, |book| {
+            modify_book(&file, output, edit_options, |book| {
                 let id = manipulate::asset_manage::add_asset(book, &asset, media_type.as_deref())?;
+                if obfuscate {
+                    manipulate::asset_manage::obfuscate_font(book, &id)?;
+                }
                 out.status(&format!("Added asset: {id}"));
                 Ok(())
             })
             .with_context(|| format!("adding asset to {}", file.display()))?;
         }
-        AssetCommand::Remove { file, asset_path } => {
-            manipulate::meta_edit::modify_epub(&file, |book| {
-                manipulate::asset_manage::remove_asset(book, &asset_path)
+        AssetCommand::Remove {
+            file,
+            asset_path,
+            force,
+            cascade,
+        } => {
+            use manipulate::asset_manage::RemoveMode;
+            let mode = if cascade {
+                RemoveMode::Cascade
+            } else if force {
+                RemoveMode::Force
+            } else {
+                RemoveMode::Refuse
+            };
+            let mut removed = None;
+            modify_book(&file, output, edit_options, |book| {
+                removed = Some(manipulate::asset_manage::remove_asset(
+                    book,
+                    &asset_path,
+                    mode,
+                )?);
+                Ok(())
             })
             .with_context(|| format!("removing asset from {}", file.display()))?;
             output.status(&format!("Removed asset: {asset_path}"));
+            if let Some(removed) = removed {
+                if removed.stripped > 0 {
+                    output.status(&format!(
+                        "Removed {} reference(s) to {}",
+                        removed.stripped, removed.href
+                    ));
+                } else if !removed.referrers.is_empty() {
+                    output.status(&format!(
+                        "Left {} reference(s) to {}",
+                        removed.referrers.len(),
+                        removed.href
+                    ));
+                }
+                for referrer in &removed.referrers {
+                    output.detail(&format!("  {referrer}"));
+                }
+            }
         }
     }
 
@@ -686,6 +1139,7 @@ fn handle_asset(
 fn handle_content(
     command: cli::content::ContentCommand,
     output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
 ) -> Result<()> {
     use cli::content::ContentCommand;
     match command {
@@ -695,8 +1149,7 @@ fn handle_content(
             chapter,
             regex: use_regex,
         } => {
-            let book = epub::reader::read_epub(&file)
-                .with_context(|| format!("failed to read {}", file.display()))?;
+            let book = read_book(&file, edit_options)?;
 
             let matches =
                 manipulate::content_edit::search(&book, &pattern, chapter.as_deref(), use_regex)?;
@@ -730,8 +1183,7 @@ fn handle_content(
             dry_run,
         } => {
             if dry_run {
-                let book = epub::reader::read_epub(&file)
-                    .with_context(|| format!("failed to read {}", file.display()))?;
+                let book = read_book(&file, edit_options)?;
                 let matches = manipulate::content_edit::search(
                     &book,
                     &pattern,
@@ -744,7 +1196,7 @@ fn handle_content(
                 }
             } else {
                 let mut count = 0;
-                manipulate::meta_edit::modify_epub(&file, |book| {
+                modify_book(&file, output, edit_options, |book| {
                     count = manipulate::content_edit::replace(
                         book,
                         &pattern,
@@ -758,18 +1210,48 @@ fn handle_content(
                 output.status(&format!("Replaced {count} occurrence(s)"));
             }
         }
+        ContentCommand::Links { file, all } => {
+            use validate::links::LinkStatus;
+
+            let book = read_book(&file, edit_options)?;
+            let links = validate::links::check_links(&book);
+            let count = |status: LinkStatus| links.iter().filter(|l| l.status == status).count();
+            let broken = links.iter().filter(|link| link.status.is_broken()).count();
+
+            if output.json {
+                let json = serde_json::json!({
+                    "total": links.len(),
+                    "ok": count(LinkStatus::Ok),
+                    "broken": broken,
+                    "not_in_spine": count(LinkStatus::NotInSpine),
+                    "external": count(LinkStatus::External),
+                    "links": links,
+                });
+                output.print_json(&json)?;
+            } else {
+                for link in links.iter().filter(|l| all || l.status != LinkStatus::Ok) {
+                    println!("{link}");
+                }
+                output.status(&format!(
+                    "\n{} link(s): {} ok, {broken} broken, {} not in spine, {} external",
+                    links.len(),
+                    count(LinkStatus::Ok),
+                    count(LinkStatus::NotInSpine),
+                    count(LinkStatus::External)
+                ));
+            }
+        }
         ContentCommand::Headings { file, restructure } => {
             if let Some(mapping) = restructure {
                 let mut count = 0;
-                manipulate::meta_edit::modify_epub(&file, |book| {
+                modify_book(&file, output, edit_options, |book| {
                     count = manipulate::content_edit::restructure_headings(book, &mapping)?;
                     Ok(())
                 })
                 .with_context(|| format!("restructuring headings in {}", file.display()))?;
                 output.status(&format!("Restructured {count} heading(s)"));
             } else {
-                let book = epub::reader::read_epub(&file)
-                    .with_context(|| format!("failed to read {}", file.display()))?;
+                let book = read_book(&file, edit_options)?;
                 let headings = manipulate::content_edit::list_headings(&book)?;
                 if output.json {
                     let json: Vec<_> = headings
@@ -795,3 +1277,160 @@ fn handle_content(
 
     Ok(())
 }
+
+fn handle_media_overlay(
+    command: cli::media_overlay::MediaOverlayCommand,
+    output: &cli::output::OutputConfig,
+    edit_options: &manipulate::meta_edit::EditOptions,
+) -> Result<()> {
+    use cli::media_overlay::MediaOverlayCommand;
+    use epub::smil;
+
+    match command {
+        MediaOverlayCommand::List { file } => {
+            let book = read_book(&file, edit_options)?;
+            let overlays = smil::load_overlays(&book)?;
+            let duration = |overlay: &smil::MediaOverlay| {
+                overlay
+                    .duration()
+                    .map_or_else(|| "unknown".to_string(), smil::format_clock)
+            };
+
+            if output.json {
+                let json: Vec<_> = overlays
+                    .iter()
+                    .map(|overlay| {
+                        serde_json::json!({
+                            "id": overlay.id,
+                            "path": overlay.path,
+                            "documents": overlay.documents,
+                            "pars": overlay.pars.len(),
+                            "duration": overlay.duration(),
+                            "declared_duration": smil::declared_duration(&book, Some(&overlay.id)),
+                        })
+                    })
+                    .collect();
+                output.print_json(&json)?;
+            } else if overlays.is_empty() {
+                output.status(&format!("{}: no media overlays", file.display()));
+            } else {
+                let rows: Vec<Vec<String>> = overlays
+                    .iter()
+                    .map(|overlay| {
+                        vec![
+                            overlay.id.clone(),
+                            overlay.path.clone(),
+                            overlay.documents.join(", "),
+                            overlay.pars.len().to_string(),
+                            duration(overlay),
+                        ]
+                    })
+                    .collect();
+                output.print_table(&["ID", "PATH", "DOCUMENTS", "PARS", "DURATION"], &rows);
+                if let Some(total) = smil::declared_duration(&book, None) {
+                    output.detail(&format!("  media:duration: {total}"));
+                }
+            }
+        }
+        MediaOverlayCommand::Extract {
+            file,
+            id,
+            output: out_file,
+        } => {
+            let book = read_book(&file, edit_options)?;
+            let overlays = smil::load_overlays(&book)?;
+            let overlay = smil::find_overlay(&overlays, &id)
+                .ok_or_else(|| anyhow::anyhow!("media overlay not found: {id}"))?;
+
+            let points: Vec<_> = overlay
+                .pars
+                .iter()
+                .map(|par| {
+                    let text = epub::url::rebase(&par.text, &overlay.path, "");
+                    let clip = par.audio.as_ref();
+                    let audio = clip.map(|clip| epub::url::resolve(&overlay.path, &clip.src));
+                    (
+                        text,
+                        audio,
+                        clip.and_then(|c| c.begin()),
+                        clip.and_then(|c| c.end()),
+                    )
+                })
+                .collect();
+            let content = if output.json {
+                let json: Vec<_> = points
+                    .iter()
+                    .map(|(text, audio, begin, end)| {
+                        serde_json::json!({
+                            "text": text,
+                            "audio": audio,
+                            "clip_begin": begin,
+                            "clip_end": end,
+                        })
+                    })
+                    .collect();
+                format!("{}\n", serde_json::to_string_pretty(&json)?)
+            } else {
+                points
+                    .iter()
+                    .map(|(text, audio, begin, end)| {
+                        let clock = |t: &Option<f64>| t.map(smil::format_clock).unwrap_or_default();
+                        format!(
+                            "{text}\t{}\t{}\t{}\n",
+                            audio.as_deref().unwrap_or_default(),
+                            clock(begin),
+                            clock(end)
+                        )
+                    })
+                    .collect()
+            };
+
+            match out_file {
+                Some(path) if !util::is_stdio(&path) => {
+                    std::fs::write(&path, content)?;
+                    output.status(&format!(
+                        "Extracted {} sync points to {}",
+                        points.len(),
+                        path.display()
+                    ));
+                }
+                _ => util::write_output(std::path::Path::new("-"), content.as_bytes())?,
+            }
+        }
+        MediaOverlayCommand::Validate { file } => {
+            let book = read_book(&file, edit_options)?;
+            let issues = smil::validate_overlays(&book);
+
+            if output.json {
+                let json = serde_json::json!({
+                    "valid": issues.is_empty(),
+                    "issues": issues,
+                });
+                output.print_json(&json)?;
+            } else if issues.is_empty() {
+                println!("{}: media overlays valid", file.display());
+            } else {
+                println!("{}: {} issue(s)", file.display(), issues.len());
+                for issue in &issues {
+                    println!("  - {issue}");
+                }
+            }
+        }
+        MediaOverlayCommand::UpdateDurations { file } => {
+            let out = output;
+            modify_book(&file, output, edit_options, |book| {
+                let changes = smil::update_durations(book)?;
+                if changes.is_empty() {
+                    out.status("media:duration is up to date");
+                }
+                for change in &changes {
+                    out.status(change);
+                }
+                Ok(())
+            })
+            .with_context(|| format!("updating durations in {}", file.display()))?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use crate::assemble::asset_embed;
-use crate::epub::{EpubBook, ManifestItem};
+use crate::epub::encryption::FontObfuscation;
+use crate::epub::references::{AssetReference, ReferenceGraph, strip_references};
+use crate::epub::{EpubBook, ManifestItem, url};
+use std::collections::HashSet;
 use std::path::Path;
 
 /// Add an asset to an EPUB
@@ -21,12 +24,10 @@ pub fn add_asset(
     let data = std::fs::read(asset_path)?;
 
     let id = format!("asset-{}", slug::slugify(&filename));
-    let href = filename.clone();
+    let href = url::escape_href(&filename).into_owned();
 
     // Add to resources (under OPF dir)
-    let opf_dir = book.detect_opf_dir();
-    let resource_key = format!("{opf_dir}{href}");
-    book.resources.insert(resource_key, data);
+    book.resources.insert(book.resource_key(&href), data);
 
     // Add to manifest
     book.manifest.push(ManifestItem {
@@ -34,13 +35,60 @@ pub fn add_asset(
         href,
         media_type,
         properties: None,
+        ..Default::default()
     });
 
     Ok(id)
 }
 
-/// Remove an asset from an EPUB
-pub fn remove_asset(book: &mut EpubBook, asset_path: &str) -> anyhow::Result<()> {
+/// Mark a font asset for IDPF obfuscation when the book is written
+pub fn obfuscate_font(book: &mut EpubBook, asset: &str) -> anyhow::Result<()> {
+    let item = book
+        .manifest
+        .iter()
+        .find(|m| m.href == asset || m.id == asset)
+        .ok_or_else(|| anyhow::anyhow!("asset not found: {asset}"))?;
+    if !item.is_font() {
+        anyhow::bail!(
+            "only fonts can be obfuscated: {} is {}",
+            item.href,
+            item.media_type
+        );
+    }
+    let key = book.resource_key(&item.href);
+    book.obfuscated_fonts.insert(key, FontObfuscation::Idpf);
+    Ok(())
+}
+
+/// What `remove_asset` does with an asset other documents still reference
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RemoveMode {
+    /// Refuse, listing the referrers
+    #[default]
+    Refuse,
+    /// Remove the asset and leave the references dangling
+    Force,
+    /// Remove the asset and the markup and CSS referencing it
+    Cascade,
+}
+
+/// An asset `remove_asset` took out of the book
+#[derive(Debug)]
+pub struct RemovedAsset {
+    pub href: String,
+    /// References to the asset when it was removed
+    pub referrers: Vec<AssetReference>,
+    /// References taken out of their documents with `RemoveMode::Cascade`
+    pub stripped: usize,
+}
+
+/// Remove an asset from an EPUB, checking the XHTML, SVG and CSS that
+/// reference it first
+pub fn remove_asset(
+    book: &mut EpubBook,
+    asset_path: &str,
+    mode: RemoveMode,
+) -> anyhow::Result<RemovedAsset> {
     // Find in manifest
     let item = book
         .manifest
@@ -48,38 +96,61 @@ pub fn remove_asset(book: &mut EpubBook, asset_path: &str) -> anyhow::Result<()>
         .find(|m| m.href == asset_path || m.id == asset_path)
         .cloned()
         .ok_or_else(|| anyhow::anyhow!("asset not found: {asset_path}"))?;
+    let key = book.resource_key(&item.href);
 
-    // Check if still referenced in any XHTML
-    let mut referenced = false;
-    for (key, data) in &book.resources {
-        if !key.ends_with(".xhtml") && !key.ends_with(".html") {
-            continue;
-        }
-        if let Ok(content) = String::from_utf8(data.clone())
-            && content.contains(&item.href)
-        {
-            referenced = true;
-            break;
-        }
-    }
-
-    if referenced {
-        eprintln!(
-            "warning: asset {} is still referenced in content",
-            item.href
+    let graph = ReferenceGraph::build(book);
+    let referrers: Vec<&AssetReference> = graph.referrers(&key).collect();
+    if !referrers.is_empty() && mode == RemoveMode::Refuse {
+        let list: Vec<String> = referrers.iter().map(|r| format!("  {r}")).collect();
+        anyhow::bail!(
+            "asset {} is still referenced by:\n{}\nuse --force to remove it anyway or --cascade to also remove the references",
+            item.href,
+            list.join("\n")
         );
     }
+    let stripped = if mode == RemoveMode::Cascade {
+        strip_references(book, &referrers)
+    } else {
+        0
+    };
 
     // Remove from manifest
     book.manifest.retain(|m| m.id != item.id);
 
     // Remove resource
-    let opf_dir = book.detect_opf_dir();
-    let resource_key = format!("{opf_dir}{}", item.href);
-    book.resources.remove(&resource_key);
-    book.resources.remove(&item.href);
+    book.resources.remove(&key);
 
-    Ok(())
+    Ok(RemovedAsset {
+        href: item.href,
+        referrers: referrers.into_iter().cloned().collect(),
+        stripped,
+    })
+}
+
+/// Manifest items nothing uses: not in the spine, not the nav, NCX or
+/// cover image, not a fallback or media overlay, and not referenced from
+/// any XHTML, SVG, SMIL or CSS
+pub fn unused_assets(book: &EpubBook) -> Vec<&ManifestItem> {
+    let graph = ReferenceGraph::build(book);
+    let mut used: HashSet<&str> = book.spine.iter().map(|s| s.idref.as_str()).collect();
+    for item in &book.manifest {
+        used.extend(item.fallback.as_deref());
+        used.extend(item.media_overlay.as_deref());
+    }
+    used.extend(book.metadata.cover_id.as_deref());
+    book.manifest
+        .iter()
+        .filter(|item| {
+            !used.contains(item.id.as_str())
+                && !item.is_nav()
+                && !item.is_ncx()
+                && !item
+                    .properties
+                    .as_deref()
+                    .is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image"))
+                && !graph.is_referenced(&book.resource_key(&item.href))
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -99,13 +170,15 @@ mod tests {
                 href: "ch1.xhtml".to_string(),
                 media_type: "application/xhtml+xml".to_string(),
                 properties: None,
+                ..Default::default()
             }],
             spine: vec![SpineItem {
                 idref: "ch1".to_string(),
                 linear: true,
                 properties: None,
+                ..Default::default()
             }],
-            resources,
+            resources: resources.into(),
             ..Default::default()
         }
     }
@@ -147,14 +220,14 @@ mod tests {
         // Remove by href
         let item = book.manifest.iter().find(|m| m.id == id).unwrap();
         let href = item.href.clone();
-        remove_asset(&mut book, &href).unwrap();
+        remove_asset(&mut book, &href, RemoveMode::Refuse).unwrap();
         assert_eq!(book.manifest.len(), manifest_len - 1);
     }
 
     #[test]
     fn test_remove_asset_not_found() {
         let mut book = test_book();
-        assert!(remove_asset(&mut book, "nonexistent.png").is_err());
+        assert!(remove_asset(&mut book, "nonexistent.png", RemoveMode::Force).is_err());
     }
 
     #[test]
@@ -166,8 +239,45 @@ mod tests {
         std::fs::write(&asset_path, b"png data").unwrap();
 
         add_asset(&mut book, &asset_path, None).unwrap();
-        // Should warn but not error
-        let result = remove_asset(&mut book, "test.png");
-        assert!(result.is_ok());
+        let err = remove_asset(&mut book, "test.png", RemoveMode::Refuse).unwrap_err();
+        assert!(err.to_string().contains("OEBPS/ch1.xhtml: <img> test.png"));
+        assert_eq!(book.manifest.len(), 2);
+
+        let removed = remove_asset(&mut book, "test.png", RemoveMode::Force).unwrap();
+        assert_eq!(removed.referrers.len(), 1);
+        assert_eq!(removed.stripped, 0);
+        let content = book.resources.get_string("OEBPS/ch1.xhtml").unwrap();
+        assert!(content.contains("<img"));
+    }
+
+    #[test]
+    fn test_remove_asset_cascade() {
+        let mut book = test_book();
+        let tmp = tempfile::TempDir::new().unwrap();
+        let asset_path = tmp.path().join("test.png");
+        std::fs::write(&asset_path, b"png data").unwrap();
+
+        add_asset(&mut book, &asset_path, None).unwrap();
+        assert_eq!(unused_assets(&book).len(), 0);
+        let removed = remove_asset(&mut book, "test.png", RemoveMode::Cascade).unwrap();
+        assert_eq!(removed.stripped, 1);
+        let content = book.resources.get_string("OEBPS/ch1.xhtml").unwrap();
+        assert!(content.contains("<p>Content with  image</p>"));
+        assert!(!book.resources.contains_key("OEBPS/test.png"));
+    }
+
+    #[test]
+    fn test_unused_assets() {
+        let mut book = test_book();
+        let tmp = tempfile::TempDir::new().unwrap();
+        let asset_path = tmp.path().join("orphan.css");
+        std::fs::write(&asset_path, "body {}").unwrap();
+
+        add_asset(&mut book, &asset_path, None).unwrap();
+        let unused: Vec<&str> = unused_assets(&book)
+            .iter()
+            .map(|item| item.href.as_str())
+            .collect();
+        assert_eq!(unused, vec!["orphan.css"]);
     }
 }
@@ -1,5 +1,6 @@
 use crate::assemble::md_to_xhtml;
-use crate::epub::{EpubBook, ManifestItem, NavPoint, SpineItem};
+use crate::epub::references::{ReferenceGraph, rewrite_references};
+use crate::epub::{EpubBook, ManifestItem, NavPoint, SpineItem, navigation, smil, url};
 use std::path::Path;
 
 /// Add a chapter to an EPUB from a Markdown file
@@ -9,7 +10,7 @@ pub fn add_chapter(
     after: Option<&str>,
     title: Option<&str>,
 ) -> anyhow::Result<String> {
-    let md_content = std::fs::read_to_string(md_path)?;
+    let md_content = crate::util::read_input_string(md_path)?;
 
     let chapter_title = title.map(|s| s.to_string()).unwrap_or_else(|| {
         // Try to get title from first heading
@@ -25,7 +26,12 @@ pub fn add_chapter(
             .unwrap_or_else(|| "New Chapter".to_string())
     });
 
-    let xhtml = md_to_xhtml::markdown_to_xhtml(&md_content, &chapter_title, None);
+    let xhtml = md_to_xhtml::markdown_to_xhtml(
+        &md_content,
+        &chapter_title,
+        None,
+        book.navigation.epub_version,
+    );
 
     // Generate unique ID
     let id = format!("chapter-added-{}", slug::slugify(&chapter_title));
@@ -39,9 +45,8 @@ pub fn add_chapter(
     };
 
     // Add to resources
-    let opf_dir = book.detect_opf_dir();
-    let resource_key = format!("{opf_dir}{href}");
-    book.resources.insert(resource_key, xhtml.into_bytes());
+    book.resources
+        .insert(book.resource_key(&href), xhtml.into_bytes());
 
     // Add to manifest
     book.manifest.push(ManifestItem {
@@ -49,6 +54,7 @@ pub fn add_chapter(
         href: href.clone(),
         media_type: "application/xhtml+xml".to_string(),
         properties: None,
+        ..Default::default()
     });
 
     // Add to spine
@@ -56,6 +62,7 @@ pub fn add_chapter(
         idref: id.clone(),
         linear: true,
         properties: None,
+        ..Default::default()
     };
 
     if let Some(pos) = insert_pos {
@@ -69,6 +76,7 @@ pub fn add_chapter(
         label: chapter_title,
         href,
         children: Vec::new(),
+        ..Default::default()
     };
 
     if let Some(pos) = insert_pos {
@@ -99,18 +107,118 @@ pub fn remove_chapter(book: &mut EpubBook, id_or_index: &str) -> anyhow::Result<
 
     // Remove resource
     if let Some(item) = &manifest_item {
-        let opf_dir = book.detect_opf_dir();
-        let resource_key = format!("{opf_dir}{}", item.href);
+        let resource_key = book.resource_key(&item.href);
         book.resources.remove(&resource_key);
-        book.resources.remove(&item.href);
 
         // Remove from navigation
-        remove_from_nav(&mut book.navigation.toc, &item.href);
+        let opf_dir = book.detect_opf_dir();
+        remove_from_nav(&mut book.navigation.toc, &opf_dir, &resource_key);
+
+        // An overlay narrating only this chapter goes with it
+        if let Some(overlay_id) = &item.media_overlay {
+            if !book
+                .manifest
+                .iter()
+                .any(|m| m.media_overlay.as_ref() == Some(overlay_id))
+            {
+                smil::remove_overlay(book, overlay_id)?;
+            }
+            smil::update_durations(book)?;
+        }
     }
 
     Ok(idref)
 }
 
+/// Move a chapter to `new_href`, relative to the OPF, and point every
+/// reference to it at the new location: navigation, links in other
+/// documents and media overlay `<text>` elements.
+///
+/// Returns the new ZIP path.
+pub fn rename_chapter(
+    book: &mut EpubBook,
+    id_or_index: &str,
+    new_href: &str,
+) -> anyhow::Result<String> {
+    let (_, idref) = resolve_chapter(book, id_or_index)?;
+    let index = book
+        .manifest
+        .iter()
+        .position(|m| m.id == idref)
+        .ok_or_else(|| anyhow::anyhow!("chapter {idref} is not in the manifest"))?;
+    let old_key = book.resource_key(&book.manifest[index].href);
+    let new_key = book.resource_key(new_href);
+    if url::is_external(new_href) || new_key.is_empty() || new_key.ends_with('/') {
+        anyhow::bail!("not a document path: {new_href}");
+    }
+    if new_key == old_key {
+        anyhow::bail!("chapter {idref} already is {new_key}");
+    }
+    if book.resources.contains_key(&new_key) {
+        anyhow::bail!("{new_key} already exists");
+    }
+    if !book.resources.contains_key(&old_key) {
+        anyhow::bail!("{old_key} is missing");
+    }
+
+    // Relative references in the chapter keep their targets, references
+    // to it from other documents follow it
+    let graph = ReferenceGraph::build(book);
+    rewrite_references(book, &graph.references, |reference| {
+        if reference.is_external() || reference.href.starts_with('#') {
+            return None;
+        }
+        if reference.source == old_key {
+            let target = if reference.target == old_key {
+                &new_key
+            } else {
+                &reference.target
+            };
+            with_fragment(url::relative(&new_key, target), &reference.href)
+        } else if reference.target == old_key {
+            with_fragment(url::relative(&reference.source, &new_key), &reference.href)
+        } else {
+            None
+        }
+    });
+    let data = book
+        .resources
+        .get(&old_key)
+        .map(<[u8]>::to_vec)
+        .unwrap_or_default();
+    book.resources.remove(&old_key);
+    book.resources.insert(new_key.clone(), data);
+
+    // Manifest and navigation hrefs are relative to the package document
+    let opf_base = book
+        .source
+        .as_ref()
+        .map_or_else(|| book.detect_opf_dir(), |source| source.opf_path.clone());
+    book.manifest[index].href = url::relative(&opf_base, &new_key);
+    navigation::map_hrefs(&mut book.navigation, &|href| {
+        retarget(href, &opf_base, &old_key, &new_key).unwrap_or_else(|| href.to_string())
+    });
+    Ok(new_key)
+}
+
+/// The href pointing from `base` at `to` instead of `from`, or `None` if it
+/// does not point at `from`
+fn retarget(href: &str, base: &str, from: &str, to: &str) -> Option<String> {
+    if url::is_external(href) || url::resolve(base, href) != from {
+        return None;
+    }
+    with_fragment(url::relative(base, to), href)
+}
+
+/// `new` with the fragment of `old`, or `None` if that is `old` itself
+fn with_fragment(mut new: String, old: &str) -> Option<String> {
+    if let (_, Some(fragment)) = url::split_fragment(old) {
+        new.push('#');
+        new.push_str(fragment);
+    }
+    (new != old).then_some(new)
+}
+
 /// Reorder a chapter in the spine
 pub fn reorder_chapter(book: &mut EpubBook, from: usize, to: usize) -> anyhow::Result<()> {
     if from >= book.spine.len() {
@@ -152,10 +260,11 @@ fn resolve_chapter(book: &EpubBook, id_or_index: &str) -> anyhow::Result<(usize,
     anyhow::bail!("chapter not found: {id_or_index}")
 }
 
-fn remove_from_nav(toc: &mut Vec<NavPoint>, href: &str) {
-    toc.retain(|point| point.href != href);
+/// Remove the entries pointing into the document at `resource_key`
+fn remove_from_nav(toc: &mut Vec<NavPoint>, opf_dir: &str, resource_key: &str) {
+    toc.retain(|point| url::resolve(opf_dir, &point.href) != resource_key);
     for point in toc.iter_mut() {
-        remove_from_nav(&mut point.children, href);
+        remove_from_nav(&mut point.children, opf_dir, resource_key);
     }
 }
 
@@ -175,7 +284,7 @@ mod tests {
 
         EpubBook {
             metadata: EpubMetadata {
-                titles: vec!["Test".to_string()],
+                titles: vec![Title::new("Test")],
                 identifiers: vec!["urn:uuid:test".to_string()],
                 languages: vec!["en".to_string()],
                 ..Default::default()
@@ -186,12 +295,14 @@ mod tests {
                     href: "ch1.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
                 ManifestItem {
                     id: "ch2".to_string(),
                     href: "ch2.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
             ],
             spine: vec![
@@ -199,11 +310,13 @@ mod tests {
                     idref: "ch1".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
                 SpineItem {
                     idref: "ch2".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
             ],
             navigation: Navigation {
@@ -212,16 +325,19 @@ mod tests {
                         label: "Chapter 1".to_string(),
                         href: "ch1.xhtml".to_string(),
                         children: vec![],
+                        ..Default::default()
                     },
                     NavPoint {
                         label: "Chapter 2".to_string(),
                         href: "ch2.xhtml".to_string(),
                         children: vec![],
+                        ..Default::default()
                     },
                 ],
                 ..Default::default()
             },
-            resources,
+            resources: resources.into(),
+            ..Default::default()
         }
     }
 
@@ -275,4 +391,39 @@ mod tests {
         let mut book = test_book();
         assert!(reorder_chapter(&mut book, 99, 0).is_err());
     }
+
+    #[test]
+    fn test_rename_chapter_updates_links() {
+        let mut book = test_book();
+        book.resources.insert(
+            "OEBPS/ch2.xhtml".to_string(),
+            br#"<html><body><a href="ch1.xhtml#top">Back</a></body></html>"#.to_vec(),
+        );
+        let path = rename_chapter(&mut book, "ch1", "text/one.xhtml").unwrap();
+        assert_eq!(path, "OEBPS/text/one.xhtml");
+        assert_eq!(book.manifest[0].href, "text/one.xhtml");
+        assert_eq!(book.navigation.toc[0].href, "text/one.xhtml");
+        assert!(book.resources.contains_key("OEBPS/text/one.xhtml"));
+        let ch2 = book.resources.get_string("OEBPS/ch2.xhtml").unwrap();
+        assert!(ch2.contains(r#"href="text/one.xhtml#top""#));
+    }
+
+    #[test]
+    fn test_rename_chapter_relinks_srcset_and_styles() {
+        let mut book = test_book();
+        book.resources.insert(
+            "OEBPS/ch1.xhtml".to_string(),
+            br##"<html><head><style>p { background: url(img/bg.png) }</style></head>
+<body><img src="img/a.png" srcset="img/a.png 1x, img/a2.png 2x"/>
+<p style="background: url('img/bg.png')"><a href="#top">Top</a> <a href="ch2.xhtml">Next</a></p></body></html>"##
+                .to_vec(),
+        );
+        rename_chapter(&mut book, "ch1", "text/one.xhtml").unwrap();
+        let ch1 = book.resources.get_string("OEBPS/text/one.xhtml").unwrap();
+        assert!(ch1.contains("url(../img/bg.png)"));
+        assert!(ch1.contains(r#"srcset="../img/a.png 1x, ../img/a2.png 2x""#));
+        assert!(ch1.contains("url('../img/bg.png')"));
+        assert!(ch1.contains(r##"href="#top""##));
+        assert!(ch1.contains(r#"href="../ch2.xhtml""#));
+    }
 }
@@ -1,5 +1,5 @@
 use crate::epub::EpubBook;
-use crate::util::{find_resource_key, strip_html_tags};
+use crate::util::strip_html_tags;
 
 /// Search result with chapter context
 pub struct SearchMatch {
@@ -46,12 +46,10 @@ pub fn search(
             continue;
         }
 
-        let full_path = find_resource_key(&book.resources, &manifest_item.href);
-        let Some(full_path) = full_path else { continue };
+        let full_path = book.resource_key(&manifest_item.href);
 
-        let xhtml = match String::from_utf8(book.resources[&full_path].clone()) {
-            Ok(s) => s,
-            Err(_) => continue,
+        let Some(xhtml) = book.resources.get_string(&full_path) else {
+            continue;
         };
 
         // Extract text from XHTML for searching
@@ -112,12 +110,10 @@ pub fn replace(
         }
 
         let href = manifest_item.href.clone();
-        let full_path = find_resource_key(&book.resources, &href);
-        let Some(full_path) = full_path else { continue };
+        let full_path = book.resource_key(&href);
 
-        let xhtml = match String::from_utf8(book.resources[&full_path].clone()) {
-            Ok(s) => s,
-            Err(_) => continue,
+        let Some(xhtml) = book.resources.get_string(&full_path) else {
+            continue;
         };
 
         // Replace in text nodes only (between > and <)
@@ -146,12 +142,10 @@ pub fn list_headings(book: &EpubBook) -> anyhow::Result<Vec<(String, usize, Stri
             continue;
         }
 
-        let full_path = find_resource_key(&book.resources, &manifest_item.href);
-        let Some(full_path) = full_path else { continue };
+        let full_path = book.resource_key(&manifest_item.href);
 
-        let xhtml = match String::from_utf8(book.resources[&full_path].clone()) {
-            Ok(s) => s,
-            Err(_) => continue,
+        let Some(xhtml) = book.resources.get_string(&full_path) else {
+            continue;
         };
 
         for cap in heading_re.captures_iter(&xhtml) {
@@ -181,12 +175,16 @@ pub fn restructure_headings(book: &mut EpubBook, mapping: &str) -> anyhow::Resul
     }
 
     let mut total = 0;
-    let keys: Vec<String> = book.resources.keys().cloned().collect();
+    let keys: Vec<String> = book
+        .resources
+        .keys()
+        .filter(|k| k.ends_with(".xhtml") || k.ends_with(".html") || k.ends_with(".htm"))
+        .cloned()
+        .collect();
 
     for key in keys {
-        let xhtml = match String::from_utf8(book.resources[&key].clone()) {
-            Ok(s) => s,
-            Err(_) => continue,
+        let Some(xhtml) = book.resources.get_string(&key) else {
+            continue;
         };
 
         let mut modified = xhtml.clone();
@@ -268,12 +266,14 @@ mod tests {
                     href: "ch1.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
                 ManifestItem {
                     id: "ch2".to_string(),
                     href: "ch2.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
             ],
             spine: vec![
@@ -281,14 +281,16 @@ mod tests {
                     idref: "ch1".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
                 SpineItem {
                     idref: "ch2".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
             ],
-            resources,
+            resources: resources.into(),
             ..Default::default()
         }
     }
@@ -370,7 +372,7 @@ mod tests {
             .find(|k| k.contains("ch1"))
             .unwrap()
             .clone();
-        let content = String::from_utf8(book.resources[&key].clone()).unwrap();
+        let content = book.resources.get_string(&key).unwrap();
         assert!(content.contains("<h2>"), "no h2 found: {content}");
         assert!(!content.contains("<h1>"), "h1 still present: {content}");
     }
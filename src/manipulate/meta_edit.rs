@@ -1,19 +1,20 @@
 use crate::epub::writer;
-use crate::epub::{EpubBook, EpubMetadata, reader};
+use crate::epub::{Accessibility, Contributor, EpubBook, EpubMetadata, Title, reader};
+use crate::util;
 use std::path::Path;
 
 /// Set a metadata field on an EPUB
 pub fn set_field(book: &mut EpubBook, field: &str, value: &str) -> anyhow::Result<()> {
     match field {
-        "title" => {
-            if book.metadata.titles.is_empty() {
-                book.metadata.titles.push(value.to_string());
-            } else {
-                book.metadata.titles[0] = value.to_string();
-            }
-        }
+        "title" => match book.metadata.main_title_index() {
+            Some(index) => book.metadata.titles[index].value = value.to_string(),
+            None => book.metadata.titles.push(Title::new(value)),
+        },
         "creator" | "author" => {
-            book.metadata.creators = vec![value.to_string()];
+            book.metadata.creators = vec![Contributor::new(value)];
+        }
+        "contributor" => {
+            book.metadata.contributors = vec![Contributor::new(value)];
         }
         "language" => {
             book.metadata.languages = vec![value.to_string()];
@@ -40,11 +41,16 @@ pub fn set_field(book: &mut EpubBook, field: &str, value: &str) -> anyhow::Resul
         "subject" => {
             book.metadata.subjects.push(value.to_string());
         }
-        other => {
-            book.metadata
-                .custom
-                .insert(other.to_string(), value.to_string());
-        }
+        other => match Accessibility::property_name(other) {
+            Some(property) => {
+                book.metadata.accessibility.set(property, value);
+            }
+            None => {
+                book.metadata
+                    .custom
+                    .insert(other.to_string(), value.to_string());
+            }
+        },
     }
     Ok(())
 }
@@ -54,6 +60,7 @@ pub fn remove_field(book: &mut EpubBook, field: &str) -> anyhow::Result<()> {
     match field {
         "title" => book.metadata.titles.clear(),
         "creator" | "author" => book.metadata.creators.clear(),
+        "contributor" => book.metadata.contributors.clear(),
         "language" => book.metadata.languages.clear(),
         "publisher" => book.metadata.publishers.clear(),
         "description" => book.metadata.description = None,
@@ -61,21 +68,27 @@ pub fn remove_field(book: &mut EpubBook, field: &str) -> anyhow::Result<()> {
         "identifier" => book.metadata.identifiers.clear(),
         "date" => book.metadata.dates.clear(),
         "subject" => book.metadata.subjects.clear(),
-        other => {
-            book.metadata.custom.remove(other);
-        }
+        other => match Accessibility::property_name(other) {
+            Some(property) => {
+                book.metadata.accessibility.remove(property);
+            }
+            None => {
+                book.metadata.custom.remove(other);
+            }
+        },
     }
     Ok(())
 }
 
 /// Import metadata from a YAML file
 pub fn import_metadata(book: &mut EpubBook, yaml_path: &Path) -> anyhow::Result<()> {
-    let content = std::fs::read_to_string(yaml_path)?;
+    let content = util::read_input_string(yaml_path)?;
     let yaml: crate::extract::frontmatter::BookMetadataYaml = serde_yaml_ng::from_str(&content)?;
 
     book.metadata = EpubMetadata {
-        titles: yaml.title.into_iter().collect(),
+        titles: yaml.titles(),
         creators: yaml.creators,
+        contributors: yaml.contributors,
         identifiers: yaml.identifiers,
         languages: yaml.languages,
         publishers: yaml.publishers,
@@ -86,6 +99,7 @@ pub fn import_metadata(book: &mut EpubBook, yaml_path: &Path) -> anyhow::Result<
         modified: None,
         cover_id: None,
         custom: Default::default(),
+        accessibility: yaml.accessibility,
     };
 
     Ok(())
@@ -98,10 +112,57 @@ pub fn export_metadata(book: &EpubBook, yaml_path: &Path) -> anyhow::Result<()>
         &book.navigation.epub_version.to_string(),
         None,
     );
-    std::fs::write(yaml_path, yaml.to_yaml()?)?;
+    util::write_output(yaml_path, yaml.to_yaml()?.as_bytes())?;
+    Ok(())
+}
+
+/// How commands read and write back a book
+#[derive(Debug, Default, Clone)]
+pub struct EditOptions {
+    pub read: reader::ReadOptions,
+    /// Re-encode markup in legacy encodings as UTF-8 on write
+    pub transcode_utf8: bool,
+}
+
+/// Read EPUB, modify, write back atomically
+///
+/// The original package layout is kept, so only the parts touched by
+/// `modify` end up rewritten. A path of `-` reads the book from stdin and
+/// writes the result to stdout.
+pub fn modify_epub(
+    path: &Path,
+    edit_options: &EditOptions,
+    modify: impl FnOnce(&mut EpubBook) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    modify_epub_if_changed(path, edit_options, |book| modify(book).map(|()| true))?;
     Ok(())
 }
 
+/// Like [`modify_epub`], but the book is only written back when `modify`
+/// returns `true` or reading repaired it, except in a pipeline, which always
+/// gets the book back. Returns whether it was written.
+pub fn modify_epub_if_changed(
+    path: &Path,
+    edit_options: &EditOptions,
+    modify: impl FnOnce(&mut EpubBook) -> anyhow::Result<bool>,
+) -> anyhow::Result<bool> {
+    let mut book = util::read_epub_input(path, &edit_options.read)?;
+    for repair in &book.repairs {
+        eprintln!("warning: repaired: {repair}");
+    }
+    let changed = modify(&mut book)?;
+    let write = changed || !book.repairs.is_empty() || util::is_stdio(path);
+    if write {
+        let options = writer::WriteOptions {
+            layout: writer::Layout::Preserve,
+            transcode_utf8: edit_options.transcode_utf8,
+            ..Default::default()
+        };
+        util::write_epub_output(&book, path, &options)?;
+    }
+    Ok(write)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,8 +170,8 @@ mod tests {
     fn test_book() -> EpubBook {
         EpubBook {
             metadata: EpubMetadata {
-                titles: vec!["Original".to_string()],
-                creators: vec!["Author".to_string()],
+                titles: vec![Title::new("Original")],
+                creators: vec![Contributor::new("Author")],
                 identifiers: vec!["urn:uuid:test".to_string()],
                 languages: vec!["en".to_string()],
                 publishers: vec!["Publisher".to_string()],
@@ -125,14 +186,14 @@ mod tests {
     fn test_set_field_title() {
         let mut book = test_book();
         set_field(&mut book, "title", "New Title").unwrap();
-        assert_eq!(book.metadata.titles[0], "New Title");
+        assert_eq!(book.metadata.title(), Some("New Title"));
     }
 
     #[test]
     fn test_set_field_creator() {
         let mut book = test_book();
         set_field(&mut book, "creator", "New Author").unwrap();
-        assert_eq!(book.metadata.creators, vec!["New Author"]);
+        assert_eq!(book.metadata.creator_names(), vec!["New Author"]);
     }
 
     #[test]
@@ -159,6 +220,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_field_accessibility() {
+        let mut book = test_book();
+        set_field(&mut book, "accessibilityFeature", "alternativeText").unwrap();
+        set_field(&mut book, "schema:accessibilityFeature", "alternativeText").unwrap();
+        set_field(&mut book, "schema:accessibilitySummary", "Summary").unwrap();
+        assert_eq!(
+            book.metadata.accessibility.features,
+            vec!["alternativeText"]
+        );
+        assert_eq!(
+            book.metadata.accessibility.summary,
+            Some("Summary".to_string())
+        );
+        assert!(book.metadata.custom.is_empty());
+
+        remove_field(&mut book, "accessibilityFeature").unwrap();
+        assert!(book.metadata.accessibility.features.is_empty());
+    }
+
     #[test]
     fn test_remove_field_title() {
         let mut book = test_book();
@@ -184,18 +265,7 @@ mod tests {
 
         let mut book2 = EpubBook::default();
         import_metadata(&mut book2, &yaml_path).unwrap();
-        assert_eq!(book2.metadata.titles, vec!["Original"]);
-        assert_eq!(book2.metadata.creators, vec!["Author"]);
+        assert_eq!(book2.metadata.title(), Some("Original"));
+        assert_eq!(book2.metadata.creator_names(), vec!["Author"]);
     }
 }
-
-/// Read EPUB, modify, write back atomically
-pub fn modify_epub(
-    path: &Path,
-    modify: impl FnOnce(&mut EpubBook) -> anyhow::Result<()>,
-) -> anyhow::Result<()> {
-    let mut book = reader::read_epub(path)?;
-    modify(&mut book)?;
-    writer::write_epub(&book, path)?;
-    Ok(())
-}
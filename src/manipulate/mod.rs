@@ -3,3 +3,4 @@ pub mod chapter_manage;
 pub mod content_edit;
 pub mod meta_edit;
 pub mod toc_edit;
+pub mod upgrade;
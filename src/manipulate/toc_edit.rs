@@ -1,5 +1,5 @@
 use crate::epub::{EpubBook, NavPoint};
-use crate::util::{build_nav_tree, find_resource_key, strip_html_tags};
+use crate::util::{build_nav_tree, strip_html_tags};
 
 /// Reorder a spine item from one position to another
 pub fn reorder_spine(book: &mut EpubBook, from: usize, to: usize) -> anyhow::Result<()> {
@@ -58,10 +58,8 @@ pub fn set_toc_from_markdown(book: &mut EpubBook, toc_content: &str) -> anyhow::
                     list_depth.saturating_sub(1),
                 ));
             }
-            Event::Text(text) => {
-                if in_link {
-                    current_label.push_str(&text);
-                }
+            Event::Text(text) if in_link => {
+                current_label.push_str(&text);
             }
             _ => {}
         }
@@ -89,12 +87,10 @@ pub fn generate_toc(book: &mut EpubBook, max_depth: Option<usize>) -> anyhow::Re
         }
 
         let href = &manifest_item.href;
-        let full_path = find_resource_key(&book.resources, href);
-        let Some(full_path) = full_path else { continue };
+        let full_path = book.resource_key(href);
 
-        let xhtml = match String::from_utf8(book.resources[&full_path].clone()) {
-            Ok(s) => s,
-            Err(_) => continue,
+        let Some(xhtml) = book.resources.get_string(&full_path) else {
+            continue;
         };
 
         for cap in heading_re.captures_iter(&xhtml) {
@@ -108,6 +104,7 @@ pub fn generate_toc(book: &mut EpubBook, max_depth: Option<usize>) -> anyhow::Re
                     label: text,
                     href: href.clone(),
                     children: Vec::new(),
+                    ..Default::default()
                 });
             }
         }
@@ -133,7 +130,7 @@ mod tests {
 
         EpubBook {
             metadata: EpubMetadata {
-                titles: vec!["Test".to_string()],
+                titles: vec![Title::new("Test")],
                 identifiers: vec!["urn:uuid:test".to_string()],
                 languages: vec!["en".to_string()],
                 ..Default::default()
@@ -144,12 +141,14 @@ mod tests {
                     href: "ch1.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
                 ManifestItem {
                     id: "ch2".to_string(),
                     href: "ch2.xhtml".to_string(),
                     media_type: "application/xhtml+xml".to_string(),
                     properties: None,
+                    ..Default::default()
                 },
             ],
             spine: vec![
@@ -157,11 +156,13 @@ mod tests {
                     idref: "ch1".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
                 SpineItem {
                     idref: "ch2".to_string(),
                     linear: true,
                     properties: None,
+                    ..Default::default()
                 },
             ],
             navigation: Navigation {
@@ -170,16 +171,19 @@ mod tests {
                         label: "Chapter 1".to_string(),
                         href: "ch1.xhtml".to_string(),
                         children: vec![],
+                        ..Default::default()
                     },
                     NavPoint {
                         label: "Chapter 2".to_string(),
                         href: "ch2.xhtml".to_string(),
                         children: vec![],
+                        ..Default::default()
                     },
                 ],
                 ..Default::default()
             },
-            resources,
+            resources: resources.into(),
+            ..Default::default()
         }
     }
 
@@ -0,0 +1,387 @@
+use crate::epub::{ElementAttributes, EpubBook, EpubVersion, encoding, unique_id, url};
+use crate::util::format_iso8601;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+static DOCTYPE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?is)<!DOCTYPE\s+html\b(?:[^>\[]|\[[^\]]*\])*>").expect("valid regex")
+});
+static CONTENT_TYPE_META: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<meta\s[^>]*http-equiv\s*=\s*["']Content-Type["'][^>]*>"#)
+        .expect("valid regex")
+});
+static SVG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:[A-Za-z_][\w.-]*:)?svg[\s/>]").expect("valid regex"));
+static SCRIPT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<(?:[A-Za-z_][\w.-]*:)?script[\s/>]").expect("valid regex"));
+
+/// Convert an EPUB 2 book to EPUB 3 in memory.
+///
+/// Adds a nav document built from the NCX and the `<guide>`, marks the cover
+/// image, turns OPF 2 attributes into refinements, moves content documents to
+/// the HTML5 doctype and UTF-8, and sets the `svg` and `scripted` manifest
+/// properties. The NCX is kept for older reading systems. Write the book with
+/// [`WriteOptions::version`](crate::epub::writer::WriteOptions) set to EPUB 3
+/// afterwards.
+///
+/// Returns a description of every change made.
+pub fn upgrade_to_epub3(book: &mut EpubBook) -> Vec<String> {
+    let mut changes = vec![format!(
+        "package version changed from {} to {}",
+        book.version(),
+        EpubVersion::V3
+    )];
+    add_nav_document(book, &mut changes);
+    mark_cover_image(book, &mut changes);
+    convert_opf2_attributes(book, &mut changes);
+    upgrade_content_documents(book, &mut changes);
+
+    let modified = format_iso8601();
+    changes.push(format!("dcterms:modified set to {modified}"));
+    book.metadata.modified = Some(modified);
+    changes
+}
+
+/// Add a nav document, recording what the writer will generate into it
+fn add_nav_document(book: &mut EpubBook, changes: &mut Vec<String>) {
+    let Some(href) = book.add_nav_item() else {
+        return;
+    };
+
+    let navigation = &book.navigation;
+    changes.push(format!(
+        "added nav document {href} with {} table of contents entries from the NCX",
+        count_points(&navigation.toc)
+    ));
+    if !navigation.landmarks.is_empty() {
+        changes.push(format!(
+            "added {} landmarks from <guide>",
+            navigation.landmarks.len()
+        ));
+    }
+    if !navigation.page_list.is_empty() {
+        changes.push(format!(
+            "added a page list of {} pages from the NCX",
+            navigation.page_list.len()
+        ));
+    }
+}
+
+/// Give the image `<meta name="cover">` points at the `cover-image` property
+fn mark_cover_image(book: &mut EpubBook, changes: &mut Vec<String>) {
+    let Some(cover_id) = book.metadata.cover_id.as_deref() else {
+        return;
+    };
+    let Some(item) = book
+        .manifest
+        .iter_mut()
+        .find(|item| item.id == cover_id && item.media_type.starts_with("image/"))
+    else {
+        return;
+    };
+    if item.add_property("cover-image") {
+        changes.push(format!(
+            "{}: cover-image property added from <meta name=\"cover\">",
+            item.href
+        ));
+    }
+}
+
+/// Rewrite metadata attributes EPUB 3 no longer allows.
+///
+/// `opf:role` and `opf:file-as` are written as refinements from the model
+/// already; `opf:scheme` becomes an `identifier-type` refinement and EPUB 3
+/// keeps a single `dc:date`, the publication date.
+fn convert_opf2_attributes(book: &mut EpubBook, changes: &mut Vec<String>) {
+    let metadata = &mut book.metadata;
+    for contributor in metadata.creators.iter().chain(&metadata.contributors) {
+        let mut converted = Vec::new();
+        if !contributor.roles.is_empty() {
+            converted.push("opf:role");
+        }
+        if contributor.file_as.is_some() {
+            converted.push("opf:file-as");
+        }
+        if !converted.is_empty() {
+            changes.push(format!(
+                "{} of '{}' converted to refinements",
+                converted.join(" and "),
+                contributor.name
+            ));
+        }
+    }
+
+    let extras = &mut book.opf_extras;
+    if metadata.dates.len() > 1 {
+        let event_of = |date: &str| {
+            extras
+                .attributes_for("date", date)
+                .iter()
+                .find(|(key, _)| key == "opf:event")
+                .map(|(_, value)| value.clone())
+        };
+        let keep = metadata
+            .dates
+            .iter()
+            .position(|date| event_of(date).is_some_and(|event| event.contains("publication")))
+            .unwrap_or(0);
+        let kept = metadata.dates.remove(keep);
+        for date in std::mem::replace(&mut metadata.dates, vec![kept]) {
+            let event = event_of(&date).map_or(String::new(), |e| format!(" (opf:event=\"{e}\")"));
+            changes.push(format!(
+                "dc:date {date}{event} removed; EPUB 3 allows a single date"
+            ));
+        }
+        extras
+            .element_attributes
+            .retain(|e| e.element != "date" || metadata.dates.contains(&e.value));
+    }
+
+    let mut ids: HashSet<String> = extras
+        .element_attributes
+        .iter()
+        .flat_map(|e| &e.attributes)
+        .filter(|(key, _)| key == "id")
+        .map(|(_, value)| value.clone())
+        .collect();
+    let mut refinements = Vec::new();
+    for ElementAttributes {
+        element,
+        value,
+        attributes,
+    } in &mut extras.element_attributes
+    {
+        let (opf2, kept): (Vec<_>, Vec<_>) = std::mem::take(attributes)
+            .into_iter()
+            .partition(|(key, _)| key.starts_with("opf:"));
+        *attributes = kept;
+        for (key, attr_value) in opf2 {
+            if element == "identifier" && key == "opf:scheme" {
+                let id = match attributes.iter().find(|(key, _)| key == "id") {
+                    Some((_, id)) => id.clone(),
+                    None => {
+                        let id = unique_id("identifier", |id| ids.contains(id));
+                        ids.insert(id.clone());
+                        attributes.push(("id".to_string(), id.clone()));
+                        id
+                    }
+                };
+                refinements.push(format!(
+                    "<meta refines=\"#{id}\" property=\"identifier-type\">{}</meta>",
+                    crate::epub::writer::xml_escape(&attr_value)
+                ));
+                changes.push(format!(
+                    "{key}=\"{attr_value}\" of identifier {value} converted to an identifier-type refinement"
+                ));
+            } else {
+                changes.push(format!(
+                    "{key}=\"{attr_value}\" removed from dc:{element} {value}"
+                ));
+            }
+        }
+    }
+    extras
+        .element_attributes
+        .retain(|e| !e.attributes.is_empty());
+    extras.metadata_elements.extend(refinements);
+}
+
+/// Move XHTML content documents to the HTML5 doctype and UTF-8 and set the
+/// manifest properties their content calls for
+fn upgrade_content_documents(book: &mut EpubBook, changes: &mut Vec<String>) {
+    let opf_dir = book.detect_opf_dir();
+    for item in book.manifest.iter_mut() {
+        if item.media_type != "application/xhtml+xml" || item.is_nav() {
+            continue;
+        }
+        let key = url::resolve(&opf_dir, &item.href);
+        let Some(bytes) = book.resources.get(&key) else {
+            continue;
+        };
+        let Some(text) = encoding::decode(bytes) else {
+            changes.push(format!("{key}: could not be decoded, left unchanged"));
+            continue;
+        };
+        let mut rewritten = None;
+        if matches!(text, Cow::Owned(_)) {
+            changes.push(format!(
+                "{key}: transcoded from {} to UTF-8",
+                encoding::detect(bytes).0.name()
+            ));
+            rewritten = Some(text.to_string());
+        }
+
+        let current = rewritten.as_deref().unwrap_or(&text);
+        if let Some(doctype) = DOCTYPE.find(current)
+            && doctype.as_str() != "<!DOCTYPE html>"
+        {
+            changes.push(format!("{key}: doctype replaced with <!DOCTYPE html>"));
+            rewritten = Some(DOCTYPE.replace(current, "<!DOCTYPE html>").into_owned());
+        }
+        let current = rewritten.as_deref().unwrap_or(&text);
+        if CONTENT_TYPE_META.is_match(current) {
+            changes.push(format!(
+                "{key}: http-equiv Content-Type replaced with <meta charset=\"UTF-8\"/>"
+            ));
+            rewritten = Some(
+                CONTENT_TYPE_META
+                    .replace(current, "<meta charset=\"UTF-8\"/>")
+                    .into_owned(),
+            );
+        }
+
+        let current = rewritten.as_deref().unwrap_or(&text);
+        for (property, pattern) in [("svg", &SVG), ("scripted", &SCRIPT)] {
+            if pattern.is_match(current) && item.add_property(property) {
+                changes.push(format!("{}: {property} property added", item.href));
+            }
+        }
+
+        if let Some(content) = rewritten {
+            book.resources.insert(key, content.into_bytes());
+        }
+    }
+}
+
+fn count_points(points: &[crate::epub::NavPoint]) -> usize {
+    points
+        .iter()
+        .map(|point| 1 + count_points(&point.children))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::{Contributor, ManifestItem, NavPoint, Navigation};
+
+    fn epub2_book() -> EpubBook {
+        let mut book = EpubBook {
+            manifest: vec![
+                ManifestItem {
+                    id: "ch1".to_string(),
+                    href: "ch1.xhtml".to_string(),
+                    media_type: "application/xhtml+xml".to_string(),
+                    ..Default::default()
+                },
+                ManifestItem {
+                    id: "cover".to_string(),
+                    href: "cover.jpg".to_string(),
+                    media_type: "image/jpeg".to_string(),
+                    ..Default::default()
+                },
+            ],
+            navigation: Navigation {
+                toc: vec![NavPoint {
+                    label: "One".to_string(),
+                    href: "ch1.xhtml".to_string(),
+                    ..Default::default()
+                }],
+                landmarks: vec![NavPoint {
+                    label: "Start".to_string(),
+                    href: "ch1.xhtml".to_string(),
+                    epub_type: Some("bodymatter".to_string()),
+                    ..Default::default()
+                }],
+                epub_version: EpubVersion::V2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        book.metadata.cover_id = Some("cover".to_string());
+        book.metadata.identifiers = vec!["9780000000000".to_string()];
+        book.metadata.dates = vec!["2001".to_string(), "2010-05-01".to_string()];
+        book.metadata.creators = vec![Contributor {
+            roles: vec!["aut".to_string()],
+            file_as: Some("Author, An".to_string()),
+            ..Contributor::new("An Author")
+        }];
+        book.opf_extras.element_attributes = vec![
+            ElementAttributes {
+                element: "identifier".to_string(),
+                value: "9780000000000".to_string(),
+                attributes: vec![("opf:scheme".to_string(), "ISBN".to_string())],
+            },
+            ElementAttributes {
+                element: "date".to_string(),
+                value: "2010-05-01".to_string(),
+                attributes: vec![("opf:event".to_string(), "publication".to_string())],
+            },
+        ];
+        book.resources
+            .insert("OEBPS/content.opf".to_string(), Vec::new());
+        book.resources.insert(
+            "OEBPS/ch1.xhtml".to_string(),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"iso-8859-1\"?>\n",
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.1//EN\" \"http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd\">\n",
+                "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head>",
+                "<meta http-equiv=\"Content-Type\" content=\"application/xhtml+xml; charset=iso-8859-1\"/>",
+                "<title>One</title></head><body><p>caf\u{e9}</p><svg:svg xmlns:svg=\"http://www.w3.org/2000/svg\"/></body></html>",
+            )
+            .chars()
+            .map(|c| c as u8)
+            .collect(),
+        );
+        book
+    }
+
+    #[test]
+    fn upgrade_adds_nav_and_properties() {
+        let mut book = epub2_book();
+        let changes = upgrade_to_epub3(&mut book);
+
+        let nav = book.manifest.iter().find(|item| item.is_nav()).unwrap();
+        assert_eq!(nav.href, "nav.xhtml");
+        assert_eq!(book.manifest[1].properties.as_deref(), Some("cover-image"));
+        assert_eq!(book.manifest[0].properties.as_deref(), Some("svg"));
+        assert!(book.metadata.modified.is_some());
+        assert!(changes.iter().any(|c| c.contains("landmarks from <guide>")));
+        assert!(
+            changes
+                .iter()
+                .any(|c| c.contains("opf:role and opf:file-as"))
+        );
+    }
+
+    #[test]
+    fn upgrade_rewrites_content_documents() {
+        let mut book = epub2_book();
+        let changes = upgrade_to_epub3(&mut book);
+
+        let xhtml = book.resources.get_string("OEBPS/ch1.xhtml").unwrap();
+        assert!(xhtml.contains("<!DOCTYPE html>\n"));
+        assert!(xhtml.contains("<meta charset=\"UTF-8\"/>"));
+        assert!(xhtml.contains("encoding=\"UTF-8\""));
+        assert!(xhtml.contains("caf\u{e9}"));
+        assert!(!xhtml.contains("XHTML 1.1"));
+        assert!(
+            changes
+                .iter()
+                .any(|c| c.contains("transcoded from windows-1252"))
+        );
+    }
+
+    #[test]
+    fn upgrade_converts_opf2_attributes() {
+        let mut book = epub2_book();
+        upgrade_to_epub3(&mut book);
+
+        assert_eq!(book.metadata.dates, vec!["2010-05-01"]);
+        let identifier = book
+            .opf_extras
+            .attributes_for("identifier", "9780000000000");
+        assert_eq!(identifier, [("id".to_string(), "identifier".to_string())]);
+        assert!(
+            book.opf_extras
+                .attributes_for("date", "2010-05-01")
+                .is_empty()
+        );
+        assert_eq!(
+            book.opf_extras.metadata_elements,
+            vec!["<meta refines=\"#identifier\" property=\"identifier-type\">ISBN</meta>"]
+        );
+    }
+}
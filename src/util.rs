@@ -1,5 +1,6 @@
-use crate::epub::NavPoint;
-use std::collections::HashMap;
+use crate::epub::{EpubBook, NavPoint, reader, writer};
+use std::io::{Read, Write};
+use std::path::Path;
 
 /// Strip HTML tags from a string, keeping only text content.
 ///
@@ -10,17 +11,60 @@ pub fn strip_html_tags(html: &str) -> String {
     tag_re.replace_all(html, "").trim().to_string()
 }
 
-/// Find the full resource key in the resources map for a given href.
-///
-/// EPUB resources are stored with their full ZIP path (e.g. "OEBPS/ch1.xhtml"),
-/// but manifest hrefs are relative to the OPF directory (e.g. "ch1.xhtml").
-/// This function first checks for an exact match, then falls back to a
-/// suffix match.
-pub fn find_resource_key(resources: &HashMap<String, Vec<u8>>, href: &str) -> Option<String> {
-    if resources.contains_key(href) {
-        return Some(href.to_string());
+/// Whether a command-line path is `-`, which stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Read a file, or stdin when the path is `-`
+pub fn read_input(path: &Path) -> std::io::Result<Vec<u8>> {
+    if !is_stdio(path) {
+        return std::fs::read(path);
+    }
+    let mut data = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Read a text file, or stdin when the path is `-`
+pub fn read_input_string(path: &Path) -> std::io::Result<String> {
+    String::from_utf8(read_input(path)?)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+/// Write a file, or stdout when the path is `-`
+pub fn write_output(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if !is_stdio(path) {
+        return std::fs::write(path, data);
+    }
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(data)?;
+    stdout.flush()
+}
+
+/// Read an EPUB file, or an EPUB piped to stdin when the path is `-`
+pub fn read_epub_input(
+    path: &Path,
+    options: &reader::ReadOptions,
+) -> crate::error::Result<EpubBook> {
+    if is_stdio(path) {
+        reader::read_epub_from_stream(std::io::stdin().lock(), options)
+    } else {
+        reader::read_epub_with(path, options)
+    }
+}
+
+/// Write an EPUB file, or write the EPUB to stdout when the path is `-`
+pub fn write_epub_output(
+    book: &EpubBook,
+    path: &Path,
+    options: &writer::WriteOptions,
+) -> anyhow::Result<()> {
+    if is_stdio(path) {
+        writer::write_epub_to_stream(book, std::io::stdout().lock(), options)
+    } else {
+        writer::write_epub_with(book, path, options)
     }
-    resources.keys().find(|k| k.ends_with(href)).cloned()
 }
 
 /// Build a hierarchical navigation tree from a flat list of links with depth info.
@@ -37,6 +81,7 @@ pub fn build_nav_tree(links: &[(String, String, usize)]) -> Vec<NavPoint> {
             label: label.clone(),
             href: href.clone(),
             children: Vec::new(),
+            ..Default::default()
         };
 
         // Pop stack until we find parent depth
@@ -163,32 +208,6 @@ mod tests {
         );
     }
 
-    #[test]
-    fn test_find_resource_key_exact_match() {
-        let mut resources = HashMap::new();
-        resources.insert("ch1.xhtml".to_string(), vec![]);
-        assert_eq!(
-            find_resource_key(&resources, "ch1.xhtml"),
-            Some("ch1.xhtml".to_string())
-        );
-    }
-
-    #[test]
-    fn test_find_resource_key_suffix_match() {
-        let mut resources = HashMap::new();
-        resources.insert("OEBPS/ch1.xhtml".to_string(), vec![]);
-        assert_eq!(
-            find_resource_key(&resources, "ch1.xhtml"),
-            Some("OEBPS/ch1.xhtml".to_string())
-        );
-    }
-
-    #[test]
-    fn test_find_resource_key_not_found() {
-        let resources: HashMap<String, Vec<u8>> = HashMap::new();
-        assert_eq!(find_resource_key(&resources, "missing.xhtml"), None);
-    }
-
     #[test]
     fn test_build_nav_tree_flat() {
         let links = vec![
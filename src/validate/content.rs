@@ -0,0 +1,438 @@
+use crate::epub::references::{AssetReference, ReferenceGraph, ReferenceKind};
+use crate::epub::{EpubBook, EpubVersion, ManifestItem, NavPoint, smil, url};
+use crate::validate::media_type::is_core_media_type;
+use crate::validate::package::{fallback_chain, in_opf};
+use crate::validate::rules::Rule;
+use crate::validate::{Issue, line_column, rules};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use std::collections::{HashMap, HashSet};
+
+/// What a scan of one XML content document found
+#[derive(Debug, Default)]
+pub struct ScannedDocument {
+    pub ids: HashSet<String>,
+    /// Whether the document has a `<nav epub:type="toc">`
+    pub has_toc_nav: bool,
+    /// Whether that nav has an `<ol>` child
+    pub toc_nav_has_list: bool,
+}
+
+/// Well-formedness, IDs, references, the navigation document, fixed-layout
+/// viewports and media overlays
+pub fn check_content(book: &EpubBook, issues: &mut Vec<Issue>) {
+    let mut documents: HashMap<String, ScannedDocument> = HashMap::new();
+    for item in &book.manifest {
+        if !matches!(
+            item.media_type.as_str(),
+            "application/xhtml+xml" | "image/svg+xml"
+        ) {
+            continue;
+        }
+        let path = book.resource_key(&item.href);
+        let Some(text) = book.resources.get_string(&path) else {
+            continue;
+        };
+        let document = scan_document(&path, &text, issues);
+        if item.is_nav() {
+            if !document.has_toc_nav {
+                issues.push(
+                    Issue::new(&rules::MISSING_TOC_NAV, "no nav element of epub:type toc")
+                        .at(path.clone()),
+                );
+            } else if !document.toc_nav_has_list {
+                issues.push(
+                    Issue::new(&rules::TOC_NAV_STRUCTURE, "the toc nav has no ol child")
+                        .at(path.clone()),
+                );
+            }
+        }
+        documents.insert(path, document);
+    }
+
+    let manifest_paths: HashMap<String, &ManifestItem> = book
+        .manifest
+        .iter()
+        .map(|item| (book.resource_key(&item.href), item))
+        .collect();
+
+    check_references(book, &documents, &manifest_paths, issues);
+    check_ncx_links(book, &documents, &manifest_paths, issues);
+
+    for page in book.fixed_layout_pages() {
+        if page.viewport.is_none() && page.media_type == "application/xhtml+xml" {
+            issues.push(
+                Issue::new(
+                    &rules::MISSING_VIEWPORT,
+                    "fixed-layout page has no viewport meta",
+                )
+                .at(page.path),
+            );
+        }
+    }
+
+    for problem in smil::validate_overlays(book) {
+        let issue = Issue::new(&rules::MEDIA_OVERLAY, problem.message);
+        let Some(path) = problem.path else {
+            issues.push(in_opf(book, issue));
+            continue;
+        };
+        let position = problem
+            .offset
+            .zip(book.resources.get_string(&path))
+            .map(|(offset, text)| line_column(&text, offset));
+        issues.push(match position {
+            Some(position) => issue.at(path).at_position(position),
+            None => issue.at(path),
+        });
+    }
+}
+
+/// Parse an XML content document, recording well-formedness errors and
+/// duplicate IDs and collecting its IDs.
+///
+/// Entities besides the XML ones are only an error in documents without a
+/// DTD to declare them.
+pub fn scan_document(path: &str, text: &str, issues: &mut Vec<Issue>) -> ScannedDocument {
+    let mut document = ScannedDocument::default();
+    let strict_entities = !declares_dtd(text);
+    let mut reader = Reader::from_str(text);
+    let mut stack: Vec<String> = Vec::new();
+    let mut toc_nav_depth: Option<usize> = None;
+
+    let issue_at = |rule: &Rule, message: String, offset: usize| {
+        Issue::new(rule, message)
+            .at(path)
+            .at_position(line_column(text, offset))
+    };
+
+    loop {
+        let start = reader.buffer_position() as usize;
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                let local =
+                    scan_element(&e, strict_entities, &mut document, &mut |rule, message| {
+                        issues.push(issue_at(rule, message, start))
+                    });
+                if local == "nav" && toc_nav_depth.is_none() && is_toc_nav(&e) {
+                    document.has_toc_nav = true;
+                    toc_nav_depth = Some(stack.len());
+                } else if local == "ol" && toc_nav_depth.is_some_and(|d| stack.len() == d + 1) {
+                    document.toc_nav_has_list = true;
+                }
+                stack.push(local);
+            }
+            Ok(Event::Empty(e)) => {
+                scan_element(&e, strict_entities, &mut document, &mut |rule, message| {
+                    issues.push(issue_at(rule, message, start))
+                });
+            }
+            Ok(Event::End(_)) => {
+                stack.pop();
+                if toc_nav_depth == Some(stack.len()) {
+                    toc_nav_depth = None;
+                }
+            }
+            Ok(Event::Text(t)) if strict_entities => {
+                if let Err(err) = t.unescape() {
+                    issues.push(issue_at(&rules::NOT_WELL_FORMED, err.to_string(), start));
+                }
+            }
+            Ok(Event::Eof) => {
+                if let Some(open) = stack.last() {
+                    issues.push(issue_at(
+                        &rules::NOT_WELL_FORMED,
+                        format!("<{open}> is not closed"),
+                        text.len(),
+                    ));
+                }
+                break;
+            }
+            Err(err) => {
+                issues.push(issue_at(
+                    &rules::NOT_WELL_FORMED,
+                    err.to_string(),
+                    reader.error_position() as usize,
+                ));
+                break;
+            }
+            _ => {}
+        }
+    }
+    document
+}
+
+/// Record the IDs of one element, returning its local name
+fn scan_element(
+    e: &BytesStart,
+    strict_entities: bool,
+    document: &mut ScannedDocument,
+    report: &mut dyn FnMut(&Rule, String),
+) -> String {
+    let local = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+    for attr in e.attributes() {
+        let attr = match attr {
+            Ok(attr) => attr,
+            Err(err) => {
+                report(&rules::NOT_WELL_FORMED, err.to_string());
+                break;
+            }
+        };
+        let value = match attr.unescape_value() {
+            Ok(value) => value.into_owned(),
+            Err(err) => {
+                if strict_entities {
+                    report(&rules::NOT_WELL_FORMED, err.to_string());
+                }
+                String::from_utf8_lossy(&attr.value).into_owned()
+            }
+        };
+        if matches!(attr.key.as_ref(), b"id" | b"xml:id") && !document.ids.insert(value.clone()) {
+            report(&rules::DUPLICATE_ID, format!("duplicate id '{value}'"));
+        }
+    }
+    local
+}
+
+fn is_toc_nav(e: &BytesStart) -> bool {
+    e.attributes().flatten().any(|attr| {
+        attr.key.as_ref() == b"epub:type"
+            && String::from_utf8_lossy(&attr.value)
+                .split_whitespace()
+                .any(|t| t == "toc")
+    })
+}
+
+/// Whether the document has a DOCTYPE that references or embeds a DTD
+fn declares_dtd(text: &str) -> bool {
+    let Some(start) = text.find("<!DOCTYPE") else {
+        return false;
+    };
+    let doctype = &text[start..];
+    let doctype = &doctype[..doctype.find('>').unwrap_or(doctype.len())];
+    doctype.contains("PUBLIC") || doctype.contains("SYSTEM") || doctype.contains('[')
+}
+
+/// Check that a reference of a content document resolves to a manifest
+/// item and, for hyperlinks, that its fragment names an ID there
+fn check_reference(
+    book: &EpubBook,
+    reference: &AssetReference,
+    documents: &HashMap<String, ScannedDocument>,
+    manifest_paths: &HashMap<String, &ManifestItem>,
+) -> Option<Issue> {
+    let href = &reference.href;
+    let target = &reference.target;
+    let element = reference.element.as_deref().unwrap_or_default();
+
+    let Some(item) = manifest_paths.get(target) else {
+        let message = if book.resources.contains_key(target) {
+            format!("<{element}> references {target}, which is not listed in the manifest")
+        } else {
+            format!("<{element}> references missing resource {href}")
+        };
+        return Some(Issue::new(&rules::REFERENCE_NOT_FOUND, message));
+    };
+
+    if reference.kind != ReferenceKind::Hyperlink {
+        let needs_fallback = matches!(element, "img" | "image")
+            && !is_core_media_type(&item.media_type)
+            && !fallback_chain(book, item).is_some_and(|chain| {
+                chain
+                    .iter()
+                    .any(|item| is_core_media_type(&item.media_type))
+            });
+        return needs_fallback.then(|| {
+            Issue::new(
+                &rules::FOREIGN_WITHOUT_FALLBACK,
+                format!(
+                    "<{element}> embeds {target} ({}) without a core media type fallback",
+                    item.media_type
+                ),
+            )
+        });
+    }
+
+    check_fragment(target, href, documents).map(|fragment| {
+        Issue::new(
+            &rules::FRAGMENT_NOT_FOUND,
+            format!("<{element}> links to #{fragment}, which is not an ID in {target}"),
+        )
+    })
+}
+
+/// The fragment of `href` when it names no ID in the scanned target
+pub(crate) fn check_fragment<'a>(
+    target: &str,
+    href: &'a str,
+    documents: &HashMap<String, ScannedDocument>,
+) -> Option<std::borrow::Cow<'a, str>> {
+    let (_, fragment) = url::split_fragment(href);
+    let fragment = url::percent_decode(fragment.filter(|f| !f.is_empty())?);
+    // Media fragments and SVG views are not IDs
+    if fragment.contains(['(', '=']) {
+        return None;
+    }
+    let document = documents.get(target)?;
+    (!document.ids.contains(&*fragment)).then_some(fragment)
+}
+
+/// Check the NCX table of contents and page list of an EPUB 2 book; an
+/// EPUB 3 navigation document is checked as a content document
+fn check_ncx_links(
+    book: &EpubBook,
+    documents: &HashMap<String, ScannedDocument>,
+    manifest_paths: &HashMap<String, &ManifestItem>,
+    issues: &mut Vec<Issue>,
+) {
+    if book.navigation.epub_version != EpubVersion::V2 {
+        return;
+    }
+    let Some(ncx) = book.manifest.iter().find(|item| item.is_ncx()) else {
+        return;
+    };
+    let ncx_path = book.resource_key(&ncx.href);
+
+    fn flatten<'a>(points: &'a [NavPoint], out: &mut Vec<&'a NavPoint>) {
+        for point in points {
+            out.push(point);
+            flatten(&point.children, out);
+        }
+    }
+    let mut points = Vec::new();
+    flatten(&book.navigation.toc, &mut points);
+    flatten(&book.navigation.page_list, &mut points);
+
+    for point in points {
+        if point.href.is_empty() || url::is_external(&point.href) {
+            continue;
+        }
+        let target = book.resource_key(&point.href);
+        let issue = if !manifest_paths.contains_key(&target) {
+            Some(Issue::new(
+                &rules::REFERENCE_NOT_FOUND,
+                format!(
+                    "navPoint '{}' references missing resource {target}",
+                    point.label
+                ),
+            ))
+        } else {
+            check_fragment(&target, &point.href, documents).map(|fragment| {
+                Issue::new(
+                    &rules::FRAGMENT_NOT_FOUND,
+                    format!(
+                        "navPoint '{}' links to #{fragment}, which is not an ID in {target}",
+                        point.label
+                    ),
+                )
+            })
+        };
+        if let Some(issue) = issue {
+            issues.push(issue.at(ncx_path.clone()));
+        }
+    }
+}
+
+/// Check the references of the book's content documents and stylesheets:
+/// links and elements, `srcset` candidates, CSS `url()` and `@import`
+fn check_references(
+    book: &EpubBook,
+    documents: &HashMap<String, ScannedDocument>,
+    manifest_paths: &HashMap<String, &ManifestItem>,
+    issues: &mut Vec<Issue>,
+) {
+    let graph = ReferenceGraph::build(book);
+    let mut texts: HashMap<&str, Option<String>> = HashMap::new();
+    for reference in &graph.references {
+        if reference.is_external() {
+            continue;
+        }
+        let issue = match reference.kind {
+            // Media overlays are checked on their own
+            ReferenceKind::Hyperlink | ReferenceKind::Element => {
+                if !documents.contains_key(&reference.source) {
+                    continue;
+                }
+                check_reference(book, reference, documents, manifest_paths)
+            }
+            _ => check_style_reference(book, reference, manifest_paths),
+        };
+        let Some(issue) = issue else {
+            continue;
+        };
+        let mut issue = issue.at(reference.source.clone());
+        let text = texts
+            .entry(&reference.source)
+            .or_insert_with(|| book.resources.get_string(&reference.source));
+        if let Some(text) = text {
+            issue = issue.at_position(line_column(text, reference.offset));
+        }
+        issues.push(issue);
+    }
+}
+
+/// Check that a `srcset` candidate, CSS `url()` or `@import` resolves to a
+/// manifest item
+fn check_style_reference(
+    book: &EpubBook,
+    reference: &AssetReference,
+    manifest_paths: &HashMap<String, &ManifestItem>,
+) -> Option<Issue> {
+    if manifest_paths.contains_key(&reference.target) {
+        return None;
+    }
+    let what = match (reference.kind, &reference.element) {
+        (ReferenceKind::Import, _) => "@import".to_string(),
+        (ReferenceKind::Srcset, Some(element)) => format!("<{element}> srcset"),
+        _ => "url()".to_string(),
+    };
+    let message = if book.resources.contains_key(&reference.target) {
+        format!(
+            "{what} references {}, which is not listed in the manifest",
+            reference.target
+        )
+    } else {
+        format!("{what} references missing resource {}", reference.href)
+    };
+    Some(Issue::new(&rules::REFERENCE_NOT_FOUND, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_collects_ids_and_references() {
+        let xhtml = r##"<html><body>
+<p id="a"><a href="#b">B</a><img src="i.png" alt=""/></p>
+<p xml:id="b"/>
+</body></html>"##;
+        let mut issues = Vec::new();
+        let document = scan_document("ch1.xhtml", xhtml, &mut issues);
+        assert!(issues.is_empty());
+        assert_eq!(document.ids, HashSet::from(["a".into(), "b".into()]));
+    }
+
+    #[test]
+    fn dtd_declares_entities() {
+        let body = "<html><body><p>&nbsp;</p></body></html>";
+        let xhtml11 = format!(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD XHTML 1.1//EN" "http://www.w3.org/TR/xhtml11/DTD/xhtml11.dtd">{body}"#
+        );
+        let mut issues = Vec::new();
+        scan_document("ch1.xhtml", &xhtml11, &mut issues);
+        assert!(issues.is_empty());
+        scan_document("ch1.xhtml", &format!("<!DOCTYPE html>{body}"), &mut issues);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].rule, "HTM-001");
+    }
+
+    #[test]
+    fn toc_nav_needs_a_list() {
+        let nav = r#"<html><body><nav epub:type="toc"><h1>Contents</h1><div><ol/></div></nav></body></html>"#;
+        let mut issues = Vec::new();
+        let document = scan_document("nav.xhtml", nav, &mut issues);
+        assert!(document.has_toc_nav);
+        assert!(!document.toc_nav_has_list);
+    }
+}
@@ -0,0 +1,512 @@
+use crate::assemble::asset_embed::infer_media_type;
+use crate::epub::references::offset_in;
+use crate::epub::writer::xml_escape;
+use crate::epub::{EpubBook, EpubVersion, repair, unique_id};
+use crate::util::format_iso8601;
+use crate::validate::{content, package, rules};
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::LazyLock;
+
+static HTML_LANG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<html\b[^>]*?\s(?:xml:)?lang\s*=\s*["']([^"']+)["']"#).expect("valid regex")
+});
+
+/// One repair, applied or refused
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fix {
+    /// ID of the rule whose issue the repair fixes
+    pub rule: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub description: String,
+}
+
+/// `OPF-012 EPUB/package.opf: removed spine itemref 'gone'`
+impl std::fmt::Display for Fix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.rule)?;
+        if let Some(path) = &self.path {
+            write!(f, " {path}")?;
+        }
+        write!(f, ": {}", self.description)
+    }
+}
+
+/// What [`fix_book`] did
+#[derive(Debug, Default, Serialize)]
+pub struct FixReport {
+    pub applied: Vec<Fix>,
+    /// Risky repairs left undone without `aggressive`
+    pub refused: Vec<Fix>,
+}
+
+impl FixReport {
+    fn record(
+        &mut self,
+        applied: bool,
+        rule: &rules::Rule,
+        path: Option<&str>,
+        description: String,
+    ) {
+        let fix = Fix {
+            rule: rule.id,
+            path: path.map(str::to_string),
+            description,
+        };
+        if applied {
+            self.applied.push(fix);
+        } else {
+            self.refused.push(fix);
+        }
+    }
+}
+
+/// Repair the mechanical defects [`validate`](super::validate) reports:
+/// broken and repeated spine itemrefs, missing language, identifier and
+/// modified date, files missing from the manifest, the nav property, and
+/// duplicate IDs.
+///
+/// Safe repairs only add what is missing or drop references to nothing.
+/// Repairs that delete files or change content (renaming duplicate IDs can
+/// break stylesheets and scripts) are only made with `aggressive`, and are
+/// otherwise listed in [`FixReport::refused`].
+pub fn fix_book(book: &mut EpubBook, aggressive: bool) -> FixReport {
+    let mut report = FixReport::default();
+    fix_spine(book, &mut report);
+    fix_metadata(book, &mut report);
+    fix_unlisted_files(book, aggressive, &mut report);
+    fix_navigation(book, aggressive, &mut report);
+    fix_duplicate_ids(book, aggressive, &mut report);
+    report
+}
+
+fn opf_path(book: &EpubBook) -> Option<String> {
+    book.source.as_ref().map(|source| source.opf_path.clone())
+}
+
+/// Drop itemrefs to missing manifest items and repeated itemrefs
+fn fix_spine(book: &mut EpubBook, report: &mut FixReport) {
+    let opf = opf_path(book);
+    let ids: HashSet<String> = book.manifest.iter().map(|item| item.id.clone()).collect();
+    let mut seen = HashSet::new();
+    book.spine.retain(|spine_item| {
+        if !ids.contains(&spine_item.idref) {
+            report.record(
+                true,
+                &rules::SPINE_ITEM_NOT_FOUND,
+                opf.as_deref(),
+                format!(
+                    "removed spine itemref '{}', which has no manifest item",
+                    spine_item.idref
+                ),
+            );
+            return false;
+        }
+        if !seen.insert(spine_item.idref.clone()) {
+            report.record(
+                true,
+                &rules::DUPLICATE_SPINE_ITEM,
+                opf.as_deref(),
+                format!("removed repeated spine itemref '{}'", spine_item.idref),
+            );
+            return false;
+        }
+        true
+    });
+}
+
+/// Language, identifier and modified date
+fn fix_metadata(book: &mut EpubBook, report: &mut FixReport) {
+    let opf = opf_path(book);
+
+    if book.metadata.languages.is_empty() {
+        // The writer falls back to English for any package it generates
+        let (language, source) = match content_language(book) {
+            Some(language) => (language, "the language of the content"),
+            None => ("en".to_string(), "the default; the content declares none"),
+        };
+        report.record(
+            true,
+            &rules::MISSING_LANGUAGE,
+            opf.as_deref(),
+            format!("set dc:language to '{language}', {source}"),
+        );
+        book.metadata.languages.push(language);
+    }
+
+    if book.metadata.identifiers.is_empty() {
+        let identifier = format!("urn:uuid:{}", uuid::Uuid::new_v4());
+        report.record(
+            true,
+            &rules::MISSING_IDENTIFIER,
+            opf.as_deref(),
+            format!("added dc:identifier {identifier}"),
+        );
+        book.metadata.identifiers.push(identifier);
+    }
+
+    if book.version() == EpubVersion::V3 {
+        let mut issues = Vec::new();
+        package::check_metadata(book, &mut issues);
+        let rule = issues.iter().find_map(|issue| match issue.rule {
+            id if id == rules::MISSING_MODIFIED.id => Some(&rules::MISSING_MODIFIED),
+            id if id == rules::INVALID_MODIFIED.id => Some(&rules::INVALID_MODIFIED),
+            _ => None,
+        });
+        if let Some(rule) = rule {
+            let modified = format_iso8601();
+            report.record(
+                true,
+                rule,
+                opf.as_deref(),
+                format!("set dcterms:modified to {modified}"),
+            );
+            book.metadata.modified = Some(modified);
+        }
+    }
+}
+
+/// The `lang` of the first spine document that declares one
+fn content_language(book: &EpubBook) -> Option<String> {
+    book.spine.iter().find_map(|spine_item| {
+        let item = book.manifest.iter().find(|m| m.id == spine_item.idref)?;
+        let text = book.resources.get_string(&book.resource_key(&item.href))?;
+        let language = HTML_LANG.captures(&text)?[1].trim().to_string();
+        (!language.is_empty()).then_some(language)
+    })
+}
+
+/// List container files the manifest misses, picked and named as `book
+/// repair` does. Files of unknown type are deleted instead, with
+/// `aggressive`.
+fn fix_unlisted_files(book: &mut EpubBook, aggressive: bool, report: &mut FixReport) {
+    // Other renditions list their own files
+    if book.renditions.len() > 1 {
+        return;
+    }
+    // Hrefs resolve the same against the package's directory as against
+    // its path
+    let opf = opf_path(book).unwrap_or_else(|| book.detect_opf_dir());
+    for key in repair::unlisted_entries(&book.manifest, &opf, &book.resources) {
+        if infer_media_type(Path::new(&key)) == "application/octet-stream" {
+            report.record(
+                aggressive,
+                &rules::RESOURCE_NOT_IN_MANIFEST,
+                Some(&key),
+                "deleted the file, whose media type is unknown".to_string(),
+            );
+            if aggressive {
+                book.resources.remove(&key);
+            }
+            continue;
+        }
+        let id = repair::add_manifest_item(&mut book.manifest, &opf, &key);
+        let media_type = &book.manifest.last().expect("item just added").media_type;
+        report.record(
+            true,
+            &rules::RESOURCE_NOT_IN_MANIFEST,
+            Some(&key),
+            format!("added to the manifest as '{id}' ({media_type})"),
+        );
+    }
+}
+
+/// Give an EPUB 3 book exactly one nav document
+fn fix_navigation(book: &mut EpubBook, aggressive: bool, report: &mut FixReport) {
+    if book.version() != EpubVersion::V3 {
+        return;
+    }
+    let opf = opf_path(book);
+    let toc_navs: Vec<usize> = book
+        .manifest
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.media_type == "application/xhtml+xml")
+        .filter(|(_, item)| {
+            book.resources
+                .get_string(&book.resource_key(&item.href))
+                .is_some_and(|text| {
+                    content::scan_document(&item.href, &text, &mut Vec::new()).has_toc_nav
+                })
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let navs: Vec<usize> = (0..book.manifest.len())
+        .filter(|index| book.manifest[*index].is_nav())
+        .collect();
+
+    match navs.len() {
+        0 => {
+            if let Some(&index) = toc_navs.first() {
+                let item = &mut book.manifest[index];
+                item.add_property("nav");
+                report.record(
+                    true,
+                    &rules::MISSING_NAV,
+                    opf.as_deref(),
+                    format!(
+                        "added the nav property to {}, which has a toc nav",
+                        item.href
+                    ),
+                );
+            } else if !book.navigation.toc.is_empty() {
+                if let Some(href) = book.add_nav_item() {
+                    report.record(
+                        true,
+                        &rules::MISSING_NAV,
+                        opf.as_deref(),
+                        format!("added nav document {href} with the table of contents"),
+                    );
+                }
+            } else {
+                report.record(
+                    aggressive,
+                    &rules::MISSING_NAV,
+                    opf.as_deref(),
+                    "added a nav document with an empty table of contents".to_string(),
+                );
+                if aggressive {
+                    book.add_nav_item();
+                }
+            }
+        }
+        1 => {}
+        _ => {
+            // Keep the first nav with a toc
+            let keep = navs
+                .iter()
+                .copied()
+                .find(|index| toc_navs.contains(index))
+                .unwrap_or(navs[0]);
+            for index in navs.into_iter().filter(|index| *index != keep) {
+                let item = &mut book.manifest[index];
+                item.remove_property("nav");
+                report.record(
+                    true,
+                    &rules::MULTIPLE_NAV,
+                    opf.as_deref(),
+                    format!("removed the nav property from {}", item.href),
+                );
+            }
+        }
+    }
+}
+
+/// Rename the second and later elements sharing an ID. Links to the ID keep
+/// going to the first one, as reading systems resolve them.
+fn fix_duplicate_ids(book: &mut EpubBook, aggressive: bool, report: &mut FixReport) {
+    let paths: Vec<String> = book
+        .manifest
+        .iter()
+        .filter(|item| {
+            matches!(
+                item.media_type.as_str(),
+                "application/xhtml+xml" | "image/svg+xml"
+            )
+        })
+        .map(|item| book.resource_key(&item.href))
+        .collect();
+    for path in paths {
+        let Some(text) = book
+            .resources
+            .get(&path)
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+        else {
+            continue;
+        };
+        let Some(renamed) = rename_duplicate_ids(text) else {
+            continue;
+        };
+        for (old, new) in &renamed.renames {
+            report.record(
+                aggressive,
+                &rules::DUPLICATE_ID,
+                Some(&path),
+                format!("renamed duplicate id '{old}' to '{new}'"),
+            );
+        }
+        if aggressive {
+            book.resources.insert(path, renamed.text.into_bytes());
+        }
+    }
+}
+
+struct RenamedIds {
+    text: String,
+    renames: Vec<(String, String)>,
+}
+
+/// Rename the `id` attributes repeating an earlier one. Only attribute
+/// values change, never text, comments or CDATA.
+fn rename_duplicate_ids(text: &str) -> Option<RenamedIds> {
+    let mut ids = Vec::new();
+    let mut reader = Reader::from_str(text);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e) | Event::Empty(e)) => {
+                for attr in e.attributes().flatten() {
+                    if !matches!(attr.key.as_ref(), b"id" | b"xml:id") {
+                        continue;
+                    }
+                    let Some(start) = offset_in(text, &attr.value) else {
+                        continue;
+                    };
+                    let value = match attr.unescape_value() {
+                        Ok(value) => value.into_owned(),
+                        Err(_) => String::from_utf8_lossy(&attr.value).into_owned(),
+                    };
+                    ids.push((start..start + attr.value.len(), value));
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    let mut taken: HashSet<String> = ids.iter().map(|(_, id)| id.clone()).collect();
+    let mut seen = HashSet::new();
+    let mut renames = Vec::new();
+    let mut rewritten = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (range, id) in ids {
+        if seen.insert(id.clone()) {
+            continue;
+        }
+        let new = unique_id(&id, |candidate| taken.contains(candidate));
+        taken.insert(new.clone());
+        rewritten.push_str(&text[copied..range.start]);
+        rewritten.push_str(&xml_escape(&new));
+        copied = range.end;
+        renames.push((id, new));
+    }
+    if renames.is_empty() {
+        return None;
+    }
+    rewritten.push_str(&text[copied..]);
+    Some(RenamedIds {
+        text: rewritten,
+        renames,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::{ManifestItem, SpineItem};
+
+    fn xhtml(id: &str, href: &str) -> ManifestItem {
+        ManifestItem {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn book() -> EpubBook {
+        let mut book = EpubBook {
+            manifest: vec![xhtml("ch1", "ch1.xhtml")],
+            ..Default::default()
+        };
+        book.navigation.epub_version = EpubVersion::V3;
+        for idref in ["ch1", "gone", "ch1"] {
+            book.spine.push(SpineItem {
+                idref: idref.to_string(),
+                ..Default::default()
+            });
+        }
+        book.resources.insert(
+            "ch1.xhtml".to_string(),
+            br#"<html xmlns="http://www.w3.org/1999/xhtml" lang="fr"><body><p id="a"/><p id='a'/></body></html>"#
+                .to_vec(),
+        );
+        book.resources
+            .insert("notes.bin".to_string(), b"data".to_vec());
+        book.resources
+            .insert("images/photo.png".to_string(), b"\x89PNG".to_vec());
+        book.resources
+            .insert("images/.DS_Store".to_string(), b"\0".to_vec());
+        book
+    }
+
+    fn rules_of(fixes: &[Fix]) -> Vec<&str> {
+        fixes.iter().map(|fix| fix.rule).collect()
+    }
+
+    #[test]
+    fn safe_fixes() {
+        let mut book = book();
+        let report = fix_book(&mut book, false);
+        assert_eq!(
+            rules_of(&report.applied),
+            vec![
+                "OPF-012", "OPF-014", "OPF-002", "OPF-003", "OPF-004", "RSC-002"
+            ]
+        );
+        assert_eq!(
+            rules_of(&report.refused),
+            vec!["RSC-002", "NAV-001", "HTM-002"]
+        );
+        assert_eq!(book.spine.len(), 1);
+        assert_eq!(book.metadata.languages, vec!["fr"]);
+        assert!(book.metadata.identifiers[0].starts_with("urn:uuid:"));
+        assert!(book.metadata.modified.is_some());
+        let photo = book.manifest.last().unwrap();
+        assert_eq!(photo.id, "photo");
+        assert_eq!(photo.href, "images/photo.png");
+        assert_eq!(photo.media_type, "image/png");
+        assert!(book.resources.contains_key("notes.bin"));
+        assert!(!book.manifest.iter().any(|item| item.is_nav()));
+    }
+
+    #[test]
+    fn aggressive_fixes() {
+        let mut book = book();
+        let report = fix_book(&mut book, true);
+        assert!(report.refused.is_empty());
+        assert!(!book.resources.contains_key("notes.bin"));
+        assert!(book.resources.contains_key("images/.DS_Store"));
+        assert!(book.manifest.iter().any(|item| item.is_nav()));
+        let text = book.resources.get_string("ch1.xhtml").unwrap();
+        assert!(text.contains(r#"<p id="a"/><p id='a-2'/>"#));
+    }
+
+    #[test]
+    fn nav_property_from_content() {
+        let mut book = EpubBook {
+            manifest: vec![xhtml("toc", "toc.xhtml")],
+            ..Default::default()
+        };
+        book.navigation.epub_version = EpubVersion::V3;
+        book.resources.insert(
+            "toc.xhtml".to_string(),
+            br#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops"><body><nav epub:type="toc"><ol/></nav></body></html>"#
+                .to_vec(),
+        );
+        let mut report = FixReport::default();
+        fix_navigation(&mut book, false, &mut report);
+        assert_eq!(rules_of(&report.applied), vec!["NAV-001"]);
+        assert!(book.manifest[0].is_nav());
+    }
+
+    #[test]
+    fn duplicate_ids_keep_unique_names() {
+        let renamed = rename_duplicate_ids(r#"<a id="x"/><b id="x-2"/><c id="x"/>"#).unwrap();
+        assert_eq!(renamed.text, r#"<a id="x"/><b id="x-2"/><c id="x-3"/>"#);
+        assert_eq!(renamed.renames, vec![("x".to_string(), "x-3".to_string())]);
+        assert!(rename_duplicate_ids(r#"<a id="x"/><b data-id="x"/>"#).is_none());
+    }
+
+    #[test]
+    fn duplicate_ids_only_rename_attributes() {
+        let text = r#"<p id="x">see id="x"</p><!-- <b id="x"/> --><script><![CDATA[ el.id = "x"; ]]></script><p xml:id='x'/>"#;
+        let renamed = rename_duplicate_ids(text).unwrap();
+        assert_eq!(renamed.text, text.replace("xml:id='x'", "xml:id='x-2'"));
+        assert_eq!(renamed.renames, vec![("x".to_string(), "x-2".to_string())]);
+    }
+}
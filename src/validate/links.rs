@@ -0,0 +1,261 @@
+use crate::epub::references::{ReferenceGraph, ReferenceKind};
+use crate::epub::{EpubBook, ManifestItem, NavPoint, navigation, url};
+use crate::validate::content::{ScannedDocument, check_fragment, scan_document};
+use crate::validate::line_column;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Where a link goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkStatus {
+    Ok,
+    /// Outside the container, e.g. `https://...` or `mailto:...`
+    External,
+    /// A manifest item reading systems do not show, as it is not in the
+    /// spine
+    NotInSpine,
+    /// A file in the container the manifest does not list
+    NotInManifest,
+    MissingResource,
+    /// The target exists but has no element with the fragment's ID
+    MissingFragment,
+}
+
+impl LinkStatus {
+    /// Whether following the link leads nowhere
+    pub fn is_broken(self) -> bool {
+        matches!(
+            self,
+            LinkStatus::NotInManifest | LinkStatus::MissingResource | LinkStatus::MissingFragment
+        )
+    }
+}
+
+impl std::fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LinkStatus::Ok => "ok",
+            LinkStatus::External => "external",
+            LinkStatus::NotInSpine => "not in spine",
+            LinkStatus::NotInManifest => "not in manifest",
+            LinkStatus::MissingResource => "missing resource",
+            LinkStatus::MissingFragment => "missing fragment",
+        })
+    }
+}
+
+/// A hyperlink in a content document, the nav document or the NCX
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Link {
+    /// ZIP path of the document the link is in
+    pub source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    /// `a`, `area` or `navPoint`
+    pub element: String,
+    pub href: String,
+    /// ZIP path the link resolves to; `None` for external links
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub status: LinkStatus,
+}
+
+/// `EPUB/ch1.xhtml:3:5: <a> ch2.xhtml#end: missing fragment`
+impl std::fmt::Display for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, ":{line}:{column}")?;
+        }
+        write!(f, ": <{}> {}: {}", self.element, self.href, self.status)
+    }
+}
+
+/// Every hyperlink in the book's XHTML and SVG documents and its NCX, in
+/// document order, with where it resolves to.
+///
+/// Documents that fail to parse contribute the links before the error;
+/// `book validate` reports the error itself.
+pub fn check_links(book: &EpubBook) -> Vec<Link> {
+    let mut documents: HashMap<String, ScannedDocument> = HashMap::new();
+    for item in &book.manifest {
+        if !matches!(
+            item.media_type.as_str(),
+            "application/xhtml+xml" | "image/svg+xml"
+        ) {
+            continue;
+        }
+        let path = book.resource_key(&item.href);
+        if let Some(text) = book.resources.get_string(&path) {
+            let document = scan_document(&path, &text, &mut Vec::new());
+            documents.insert(path, document);
+        }
+    }
+
+    let manifest_paths: HashMap<String, &ManifestItem> = book
+        .manifest
+        .iter()
+        .map(|item| (book.resource_key(&item.href), item))
+        .collect();
+    let spine_paths: HashSet<String> = book
+        .spine
+        .iter()
+        .filter_map(|spine_item| book.manifest.iter().find(|m| m.id == spine_item.idref))
+        .map(|item| book.resource_key(&item.href))
+        .collect();
+    let resolve = |source: &str, href: &str| {
+        if url::is_external(href) {
+            return (None, LinkStatus::External);
+        }
+        let target = url::resolve(source, href);
+        let status = if !manifest_paths.contains_key(&target) {
+            if book.resources.contains_key(&target) {
+                LinkStatus::NotInManifest
+            } else {
+                LinkStatus::MissingResource
+            }
+        } else if check_fragment(&target, href, &documents).is_some() {
+            LinkStatus::MissingFragment
+        } else if target != source && !spine_paths.contains(&target) {
+            LinkStatus::NotInSpine
+        } else {
+            LinkStatus::Ok
+        };
+        (Some(target), status)
+    };
+
+    let mut links = Vec::new();
+    let graph = ReferenceGraph::build(book);
+    let mut texts: HashMap<&str, Option<String>> = HashMap::new();
+    let mut hyperlinks: Vec<_> = graph
+        .references
+        .iter()
+        .filter(|reference| {
+            reference.kind == ReferenceKind::Hyperlink && documents.contains_key(&reference.source)
+        })
+        .collect();
+    hyperlinks.sort_by(|a, b| a.source.cmp(&b.source));
+    for reference in hyperlinks {
+        let (target, status) = resolve(&reference.source, &reference.href);
+        let position = texts
+            .entry(&reference.source)
+            .or_insert_with(|| book.resources.get_string(&reference.source))
+            .as_deref()
+            .map(|text| line_column(text, reference.offset));
+        links.push(Link {
+            source: reference.source.clone(),
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            element: reference.element.clone().unwrap_or_default(),
+            href: reference.href.clone(),
+            target,
+            status,
+        });
+    }
+
+    // NCX hrefs are relative to the NCX itself
+    let ncx = book.manifest.iter().find(|item| item.is_ncx());
+    if let Some(ncx) = ncx {
+        let ncx_path = book.resource_key(&ncx.href);
+        if let Some(text) = book.resources.get_string(&ncx_path)
+            && let Ok((toc, page_list)) = navigation::parse_ncx(&text)
+        {
+            let mut points = Vec::new();
+            flatten(&toc, &mut points);
+            flatten(&page_list, &mut points);
+            for point in points.into_iter().filter(|point| !point.href.is_empty()) {
+                let (target, status) = resolve(&ncx_path, &point.href);
+                links.push(Link {
+                    source: ncx_path.clone(),
+                    line: None,
+                    column: None,
+                    element: "navPoint".to_string(),
+                    href: point.href.clone(),
+                    target,
+                    status,
+                });
+            }
+        }
+    }
+    links
+}
+
+fn flatten<'a>(points: &'a [NavPoint], out: &mut Vec<&'a NavPoint>) {
+    for point in points {
+        out.push(point);
+        flatten(&point.children, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::SpineItem;
+
+    fn item(id: &str, href: &str, media_type: &str) -> ManifestItem {
+        ManifestItem {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: media_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn classify_links() {
+        let mut book = EpubBook {
+            manifest: vec![
+                item("ch1", "ch1.xhtml", "application/xhtml+xml"),
+                item("ch2", "ch2.xhtml", "application/xhtml+xml"),
+                item("notes", "notes.xhtml", "application/xhtml+xml"),
+            ],
+            ..Default::default()
+        };
+        for idref in ["ch1", "ch2"] {
+            book.spine.push(SpineItem {
+                idref: idref.to_string(),
+                ..Default::default()
+            });
+        }
+        book.resources.insert(
+            "ch1.xhtml".to_string(),
+            br##"<html><body id="top">
+<a href="ch2.xhtml#end">1</a><a href="ch2.xhtml#gone">2</a><a href="#top">3</a>
+<a href="notes.xhtml">4</a><a href="extra.xhtml">5</a><a href="lost.xhtml">6</a>
+<a href="https://example.com/">7</a><img src="lost.png"/>
+</body></html>"##
+                .to_vec(),
+        );
+        book.resources.insert(
+            "ch2.xhtml".to_string(),
+            br#"<html><body><p id="end"/></body></html>"#.to_vec(),
+        );
+        book.resources
+            .insert("notes.xhtml".to_string(), b"<html/>".to_vec());
+        book.resources
+            .insert("extra.xhtml".to_string(), b"<html/>".to_vec());
+
+        let links = check_links(&book);
+        let statuses: Vec<LinkStatus> = links.iter().map(|link| link.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                LinkStatus::Ok,
+                LinkStatus::MissingFragment,
+                LinkStatus::Ok,
+                LinkStatus::NotInSpine,
+                LinkStatus::NotInManifest,
+                LinkStatus::MissingResource,
+                LinkStatus::External,
+            ]
+        );
+        assert_eq!(
+            links[1].to_string(),
+            "ch1.xhtml:2:39: <a> ch2.xhtml#gone: missing fragment"
+        );
+        assert_eq!(links[6].target, None);
+    }
+}
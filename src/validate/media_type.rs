@@ -0,0 +1,198 @@
+use crate::epub::EpubBook;
+use crate::validate::{Issue, rules};
+
+/// Media types every EPUB 3 reading system supports; anything else needs a
+/// fallback. Video is exempt.
+pub const CORE_MEDIA_TYPES: &[&str] = &[
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/svg+xml",
+    "image/webp",
+    "audio/mpeg",
+    "audio/mp4",
+    "audio/ogg; codecs=opus",
+    "text/css",
+    "font/ttf",
+    "font/otf",
+    "font/woff",
+    "font/woff2",
+    "application/font-sfnt",
+    "application/font-woff",
+    "application/vnd.ms-opentype",
+    "application/xhtml+xml",
+    "application/javascript",
+    "application/ecmascript",
+    "text/javascript",
+    "application/x-dtbncx+xml",
+    "application/smil+xml",
+    "application/pls+xml",
+];
+
+/// File formats recognisable from their first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Font,
+    Mpeg,
+    Mp4,
+    Ogg,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Gif => "GIF",
+            Format::Webp => "WebP",
+            Format::Font => "a font",
+            Format::Mpeg => "MP3",
+            Format::Mp4 => "MP4",
+            Format::Ogg => "Ogg",
+        }
+    }
+
+    fn media_type(self) -> Option<&'static str> {
+        Some(match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+            Format::Gif => "image/gif",
+            Format::Webp => "image/webp",
+            Format::Mpeg => "audio/mpeg",
+            Format::Mp4 => "audio/mp4",
+            Format::Font | Format::Ogg => return None,
+        })
+    }
+
+    /// The format a media type promises, `None` for types that cannot be
+    /// checked by signature, such as text formats
+    fn of_media_type(media_type: &str) -> Option<Self> {
+        let media_type = media_type.split(';').next().unwrap_or("").trim();
+        Some(match media_type {
+            "image/png" => Format::Png,
+            "image/jpeg" | "image/jpg" => Format::Jpeg,
+            "image/gif" => Format::Gif,
+            "image/webp" => Format::Webp,
+            "audio/mpeg" | "audio/mp3" => Format::Mpeg,
+            "audio/mp4" | "video/mp4" | "audio/x-m4a" | "audio/m4a" => Format::Mp4,
+            "audio/ogg" | "audio/opus" | "video/ogg" => Format::Ogg,
+            t if t.starts_with("font/")
+                || t == "application/font-sfnt"
+                || t == "application/font-woff"
+                || t == "application/vnd.ms-opentype"
+                || t == "application/x-font-ttf" =>
+            {
+                Format::Font
+            }
+            _ => return None,
+        })
+    }
+
+    fn sniff(data: &[u8]) -> Option<Self> {
+        Some(match data {
+            [0x89, b'P', b'N', b'G', ..] => Format::Png,
+            [0xFF, 0xD8, 0xFF, ..] => Format::Jpeg,
+            [b'G', b'I', b'F', b'8', ..] => Format::Gif,
+            _ if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") => Format::Webp,
+            [b'O', b'T', b'T', b'O', ..]
+            | [0x00, 0x01, 0x00, 0x00, ..]
+            | [b't', b'r', b'u', b'e', ..]
+            | [b't', b't', b'c', b'f', ..]
+            | [b'w', b'O', b'F', b'F', ..]
+            | [b'w', b'O', b'F', b'2', ..] => Format::Font,
+            [b'I', b'D', b'3', ..] | [0xFF, 0xFB | 0xF3 | 0xF2, ..] => Format::Mpeg,
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Format::Mp4,
+            [b'O', b'g', b'g', b'S', ..] => Format::Ogg,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether a media type is a core media type, ignoring parameters other
+/// than the Opus codec
+pub fn is_core_media_type(media_type: &str) -> bool {
+    let media_type = media_type.trim();
+    CORE_MEDIA_TYPES.contains(&media_type)
+        || CORE_MEDIA_TYPES.contains(&media_type.split(';').next().unwrap_or("").trim())
+}
+
+/// Compare the declared media type of each binary resource with its
+/// signature
+pub fn check_media_types(book: &EpubBook, issues: &mut Vec<Issue>) {
+    for item in &book.manifest {
+        let Some(expected) = Format::of_media_type(&item.media_type) else {
+            continue;
+        };
+        let path = book.resource_key(&item.href);
+        let Some(data) = book.resources.get(&path) else {
+            continue;
+        };
+        let found = Format::sniff(data);
+        if found == Some(expected) {
+            continue;
+        }
+        let content = match found {
+            Some(format) => format!("the content is {}", format.name()),
+            None => format!("the content is not {}", expected.name()),
+        };
+        let mut issue = Issue::new(
+            &rules::MEDIA_TYPE_MISMATCH,
+            format!("declared as {} but {content}", item.media_type),
+        )
+        .at(path);
+        if let Some(media_type) = found.and_then(Format::media_type) {
+            issue = issue.suggest(format!("declare the item as {media_type}"));
+        }
+        issues.push(issue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::ManifestItem;
+
+    #[test]
+    fn sniff_signatures() {
+        assert_eq!(Format::sniff(b"\x89PNG\r\n\x1a\n"), Some(Format::Png));
+        assert_eq!(Format::sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(Format::Webp));
+        assert_eq!(Format::sniff(b"wOF2\0\x01"), Some(Format::Font));
+        assert_eq!(Format::sniff(b"\0\0\0\x20ftypM4A "), Some(Format::Mp4));
+        assert_eq!(Format::sniff(b"<svg/>"), None);
+    }
+
+    #[test]
+    fn core_media_types() {
+        assert!(is_core_media_type("image/png"));
+        assert!(is_core_media_type("audio/ogg; codecs=opus"));
+        assert!(!is_core_media_type("image/tiff"));
+    }
+
+    #[test]
+    fn mismatched_media_type() {
+        let mut book = EpubBook::default();
+        for (id, media_type) in [("a", "image/jpeg"), ("b", "image/png"), ("c", "text/css")] {
+            book.manifest.push(ManifestItem {
+                id: id.to_string(),
+                href: format!("{id}.bin"),
+                media_type: media_type.to_string(),
+                ..Default::default()
+            });
+            book.resources
+                .insert(format!("{id}.bin"), b"\x89PNG\r\n\x1a\n".to_vec());
+        }
+        let mut issues = Vec::new();
+        check_media_types(&book, &mut issues);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].path.as_deref(), Some("a.bin"));
+        assert_eq!(
+            issues[0].message,
+            "declared as image/jpeg but the content is PNG"
+        );
+        assert_eq!(issues[0].suggestion, "declare the item as image/png");
+    }
+}
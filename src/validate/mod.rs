@@ -0,0 +1,162 @@
+pub mod content;
+pub mod fix;
+pub mod links;
+pub mod media_type;
+pub mod package;
+pub mod report;
+pub mod rules;
+
+use crate::epub::EpubBook;
+use rules::Rule;
+use serde::Serialize;
+
+/// How serious a validation issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem found in a book
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Issue {
+    /// Stable rule ID, e.g. `OPF-001`
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// ZIP path of the resource the issue is in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+    pub message: String,
+    /// How to fix the issue
+    pub suggestion: String,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl Issue {
+    pub fn new(rule: &Rule, message: impl Into<String>) -> Self {
+        Self {
+            rule: rule.id,
+            severity: rule.severity,
+            path: None,
+            line: None,
+            column: None,
+            message: message.into(),
+            suggestion: rule.fix.to_string(),
+        }
+    }
+
+    /// Replace the rule's generic fix with one specific to this issue
+    pub fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = suggestion.into();
+        self
+    }
+
+    /// The issue located in the resource at `path`
+    pub fn at(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// The issue located at a 1-based line and column
+    pub fn at_position(mut self, (line, column): (usize, usize)) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// `error[OPF-001] EPUB/ch1.xhtml:3:5: message`
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}] ", self.severity, self.rule)?;
+        if let Some(path) = &self.path {
+            write!(f, "{path}")?;
+            if let Some(line) = self.line {
+                write!(f, ":{line}")?;
+                if let Some(column) = self.column {
+                    write!(f, ":{column}")?;
+                }
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Check a book against every rule in [`rules::ALL`].
+///
+/// Issues come out grouped by check: the container and package document
+/// first, then the content documents.
+pub fn validate(book: &EpubBook) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = book
+        .repairs
+        .iter()
+        .map(|repair| Issue::new(&rules::CONTAINER_REPAIRED, repair.clone()))
+        .collect();
+    package::check_metadata(book, &mut issues);
+    package::check_manifest(book, &mut issues);
+    package::check_spine(book, &mut issues);
+    package::check_navigation(book, &mut issues);
+    media_type::check_media_types(book, &mut issues);
+    content::check_content(book, &mut issues);
+    issues
+}
+
+/// Byte offset to 1-based line and column
+pub(crate) fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(text.len());
+    let before = &text.as_bytes()[..offset];
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |pos| pos + 1);
+    let column = String::from_utf8_lossy(&before[line_start..])
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_issue() {
+        let issue = Issue::new(&rules::MISSING_TITLE, "missing dc:title");
+        assert_eq!(issue.to_string(), "error[OPF-001] missing dc:title");
+        let issue = Issue::new(&rules::NOT_WELL_FORMED, "unexpected end")
+            .at("EPUB/ch1.xhtml")
+            .at_position((3, 5));
+        assert_eq!(
+            issue.to_string(),
+            "error[HTM-001] EPUB/ch1.xhtml:3:5: unexpected end"
+        );
+    }
+
+    #[test]
+    fn line_and_column() {
+        let text = "<a>\n  <b>\n</a>";
+        assert_eq!(line_column(text, 0), (1, 1));
+        assert_eq!(line_column(text, 6), (2, 3));
+        assert_eq!(line_column(text, text.len()), (3, 5));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::epub::{EpubBook, EpubVersion, ManifestItem, url};
+use crate::validate::{Issue, rules};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+static MODIFIED_FORMAT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}Z$").expect("valid regex"));
+
+/// Required metadata and the unique identifier
+pub fn check_metadata(book: &EpubBook, issues: &mut Vec<Issue>) {
+    let opf = |issue| in_opf(book, issue);
+    let metadata = &book.metadata;
+
+    if metadata.titles.is_empty() {
+        issues.push(opf(Issue::new(&rules::MISSING_TITLE, "missing dc:title")));
+    }
+    if metadata.languages.is_empty() {
+        issues.push(opf(Issue::new(
+            &rules::MISSING_LANGUAGE,
+            "missing dc:language",
+        )));
+    }
+    if metadata.identifiers.is_empty() {
+        issues.push(opf(Issue::new(
+            &rules::MISSING_IDENTIFIER,
+            "missing dc:identifier",
+        )));
+    }
+
+    if book.version() == EpubVersion::V3 {
+        match &metadata.modified {
+            None => issues.push(opf(Issue::new(
+                &rules::MISSING_MODIFIED,
+                "missing dcterms:modified",
+            ))),
+            Some(modified) if !MODIFIED_FORMAT.is_match(modified.trim()) => {
+                issues.push(opf(Issue::new(
+                    &rules::INVALID_MODIFIED,
+                    format!(
+                        "dcterms:modified '{modified}' is not of the form CCYY-MM-DDThh:mm:ssZ"
+                    ),
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // The identifier the attribute points at is only known as read
+    if let Some(source) = &book.source {
+        match &book.opf_extras.unique_identifier_id {
+            None => issues.push(opf(Issue::new(
+                &rules::UNIQUE_IDENTIFIER,
+                "package has no unique-identifier attribute",
+            ))),
+            Some(id) if source.unique_identifier.is_none() => issues.push(opf(Issue::new(
+                &rules::UNIQUE_IDENTIFIER,
+                format!("unique-identifier '{id}' does not match the id of a dc:identifier"),
+            ))),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Duplicate and missing manifest entries, fallbacks and container files
+/// the manifest does not list
+pub fn check_manifest(book: &EpubBook, issues: &mut Vec<Issue>) {
+    let opf = |issue| in_opf(book, issue);
+
+    let mut ids = HashSet::new();
+    let mut paths = HashSet::new();
+    for item in &book.manifest {
+        if !ids.insert(item.id.as_str()) {
+            issues.push(opf(Issue::new(
+                &rules::DUPLICATE_MANIFEST_ID,
+                format!("duplicate manifest item id '{}'", item.id),
+            )));
+        }
+        if url::is_external(&item.href) {
+            continue;
+        }
+        let path = book.resource_key(&item.href);
+        if !paths.insert(path.clone()) {
+            issues.push(opf(Issue::new(
+                &rules::DUPLICATE_MANIFEST_HREF,
+                format!("{path} is listed in the manifest more than once"),
+            )));
+        }
+        if !book.resources.contains_key(&path) {
+            issues.push(opf(Issue::new(
+                &rules::RESOURCE_MISSING,
+                format!(
+                    "manifest item '{}' is missing from the container: {path}",
+                    item.id
+                ),
+            )));
+        }
+    }
+
+    for item in &book.manifest {
+        let Some(fallback) = &item.fallback else {
+            continue;
+        };
+        if !book.manifest.iter().any(|other| other.id == *fallback) {
+            issues.push(opf(Issue::new(
+                &rules::FALLBACK_NOT_FOUND,
+                format!(
+                    "fallback '{fallback}' of manifest item '{}' is not in the manifest",
+                    item.id
+                ),
+            )));
+        } else if fallback_chain(book, item).is_none() {
+            issues.push(opf(Issue::new(
+                &rules::FALLBACK_CYCLE,
+                format!("fallback chain of manifest item '{}' is circular", item.id),
+            )));
+        }
+    }
+
+    // Other renditions list their own files
+    if book.renditions.len() > 1 {
+        return;
+    }
+    let opf_path = opf_path(book);
+    for key in book.resources.keys() {
+        if !paths.contains(key) && opf_path != Some(key.as_str()) && !key.ends_with('/') {
+            issues.push(
+                Issue::new(
+                    &rules::RESOURCE_NOT_IN_MANIFEST,
+                    "file is not listed in the manifest",
+                )
+                .at(key.clone()),
+            );
+        }
+    }
+}
+
+/// Spine references and content document fallbacks
+pub fn check_spine(book: &EpubBook, issues: &mut Vec<Issue>) {
+    let opf = |issue| in_opf(book, issue);
+
+    if book.spine.is_empty() {
+        issues.push(opf(Issue::new(&rules::EMPTY_SPINE, "spine is empty")));
+    }
+
+    let mut seen = HashSet::new();
+    for spine_item in &book.spine {
+        if !seen.insert(spine_item.idref.as_str()) {
+            issues.push(opf(Issue::new(
+                &rules::DUPLICATE_SPINE_ITEM,
+                format!(
+                    "manifest item '{}' is in the spine more than once",
+                    spine_item.idref
+                ),
+            )));
+            continue;
+        }
+        let Some(item) = book.manifest.iter().find(|m| m.id == spine_item.idref) else {
+            issues.push(opf(Issue::new(
+                &rules::SPINE_ITEM_NOT_FOUND,
+                format!(
+                    "spine references missing manifest item: {}",
+                    spine_item.idref
+                ),
+            )));
+            continue;
+        };
+        let reaches_content = fallback_chain(book, item)
+            .is_some_and(|chain| chain.iter().any(|item| is_content_document(item)));
+        if !reaches_content {
+            issues.push(opf(Issue::new(
+                &rules::SPINE_FALLBACK,
+                format!(
+                    "spine item '{}' is {} with no content document fallback",
+                    item.id, item.media_type
+                ),
+            )));
+        }
+    }
+}
+
+/// A navigation document for EPUB 3, an NCX for EPUB 2
+pub fn check_navigation(book: &EpubBook, issues: &mut Vec<Issue>) {
+    let opf = |issue| in_opf(book, issue);
+
+    match book.version() {
+        EpubVersion::V3 => match book.manifest.iter().filter(|item| item.is_nav()).count() {
+            0 => issues.push(opf(Issue::new(
+                &rules::MISSING_NAV,
+                "no manifest item has the nav property",
+            ))),
+            1 => {}
+            count => issues.push(opf(Issue::new(
+                &rules::MULTIPLE_NAV,
+                format!("{count} manifest items have the nav property"),
+            ))),
+        },
+        EpubVersion::V2 => {
+            if !book.manifest.iter().any(|item| item.is_ncx()) {
+                issues.push(opf(Issue::new(
+                    &rules::MISSING_NCX,
+                    "no manifest item is an NCX (application/x-dtbncx+xml)",
+                )));
+            }
+        }
+    }
+}
+
+/// An item followed by its fallbacks, `None` when the chain is circular.
+///
+/// The chain stops at a fallback missing from the manifest.
+pub(crate) fn fallback_chain<'a>(
+    book: &'a EpubBook,
+    item: &'a ManifestItem,
+) -> Option<Vec<&'a ManifestItem>> {
+    let mut chain = vec![item];
+    let mut current = item;
+    while let Some(fallback) = &current.fallback {
+        let Some(next) = book.manifest.iter().find(|m| m.id == *fallback) else {
+            break;
+        };
+        if chain.iter().any(|seen| seen.id == next.id) {
+            return None;
+        }
+        chain.push(next);
+        current = next;
+    }
+    Some(chain)
+}
+
+/// Whether an item can be rendered from the spine on its own
+fn is_content_document(item: &ManifestItem) -> bool {
+    matches!(
+        item.media_type.as_str(),
+        "application/xhtml+xml" | "image/svg+xml" | "application/x-dtbook+xml"
+    )
+}
+
+/// The issue located in the package document, when the book was read
+pub(crate) fn in_opf(book: &EpubBook, issue: Issue) -> Issue {
+    match opf_path(book) {
+        Some(path) => issue.at(path),
+        None => issue,
+    }
+}
+
+fn opf_path(book: &EpubBook) -> Option<&str> {
+    book.source.as_ref().map(|source| source.opf_path.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::SpineItem;
+
+    fn item(id: &str, media_type: &str, fallback: Option<&str>) -> ManifestItem {
+        ManifestItem {
+            id: id.to_string(),
+            href: format!("{id}.bin"),
+            media_type: media_type.to_string(),
+            fallback: fallback.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn rules_of(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.rule).collect()
+    }
+
+    #[test]
+    fn missing_metadata() {
+        let book = EpubBook::default();
+        let mut issues = Vec::new();
+        check_metadata(&book, &mut issues);
+        assert_eq!(
+            rules_of(&issues),
+            vec!["OPF-001", "OPF-002", "OPF-003", "OPF-004"]
+        );
+    }
+
+    #[test]
+    fn modified_format() {
+        let mut book = EpubBook::default();
+        book.metadata.modified = Some("2024-01-01".to_string());
+        let mut issues = Vec::new();
+        check_metadata(&book, &mut issues);
+        assert!(rules_of(&issues).contains(&"OPF-005"));
+    }
+
+    #[test]
+    fn spine_needs_content_fallback() {
+        let mut book = EpubBook {
+            manifest: vec![
+                item("scan", "image/tiff", Some("page")),
+                item("page", "application/xhtml+xml", None),
+                item("raw", "image/png", None),
+            ],
+            ..Default::default()
+        };
+        for idref in ["scan", "raw", "raw", "gone"] {
+            book.spine.push(SpineItem {
+                idref: idref.to_string(),
+                ..Default::default()
+            });
+        }
+        let mut issues = Vec::new();
+        check_spine(&book, &mut issues);
+        assert_eq!(rules_of(&issues), vec!["OPF-017", "OPF-014", "OPF-012"]);
+        assert!(issues[0].message.contains("'raw' is image/png"));
+    }
+
+    #[test]
+    fn circular_fallbacks() {
+        let mut book = EpubBook {
+            manifest: vec![
+                item("a", "image/tiff", Some("b")),
+                item("b", "image/tiff", Some("a")),
+                item("c", "image/tiff", Some("gone")),
+            ],
+            ..Default::default()
+        };
+        book.resources.insert("a.bin".to_string(), Vec::new());
+        book.resources.insert("b.bin".to_string(), Vec::new());
+        book.resources.insert("c.bin".to_string(), Vec::new());
+        let mut issues = Vec::new();
+        check_manifest(&book, &mut issues);
+        assert_eq!(rules_of(&issues), vec!["OPF-016", "OPF-016", "OPF-015"]);
+    }
+
+    #[test]
+    fn manifest_and_container_agree() {
+        let mut book = EpubBook {
+            manifest: vec![
+                item("a", "image/png", None),
+                item("a", "image/png", None),
+                item("b", "image/png", None),
+            ],
+            ..Default::default()
+        };
+        book.resources.insert("a.bin".to_string(), Vec::new());
+        book.resources.insert("extra.css".to_string(), Vec::new());
+        let mut issues = Vec::new();
+        check_manifest(&book, &mut issues);
+        assert_eq!(
+            rules_of(&issues),
+            vec!["OPF-010", "OPF-011", "RSC-001", "RSC-002"]
+        );
+        assert_eq!(issues[3].path.as_deref(), Some("extra.css"));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::epub::writer::xml_escape;
+use crate::validate::{Issue, Severity, rules};
+use serde_json::json;
+
+/// How `book validate` prints its issues
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One line per issue
+    #[default]
+    Text,
+    /// Issues as objects with their rule, severity, location and fix
+    Json,
+    /// SARIF 2.1.0, for code scanning tools
+    Sarif,
+    /// JUnit XML, one test case per rule
+    Junit,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "sarif" => Ok(ReportFormat::Sarif),
+            "junit" => Ok(ReportFormat::Junit),
+            _ => Err(format!(
+                "unknown report format '{s}' (expected text, json, sarif or junit)"
+            )),
+        }
+    }
+}
+
+/// The least severe issue that makes `book validate` exit with an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailOn(pub Severity);
+
+impl FailOn {
+    /// How many issues are at or above the threshold
+    pub fn count(self, issues: &[Issue]) -> usize {
+        issues
+            .iter()
+            .filter(|issue| issue.severity >= self.0)
+            .count()
+    }
+}
+
+impl std::str::FromStr for FailOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "warning" => Ok(FailOn(Severity::Warning)),
+            "error" => Ok(FailOn(Severity::Error)),
+            _ => Err(format!(
+                "unknown severity '{s}' (expected warning or error)"
+            )),
+        }
+    }
+}
+
+fn error_count(issues: &[Issue]) -> usize {
+    issues.iter().filter(|issue| issue.is_error()).count()
+}
+
+/// `{valid, errors, warnings, issues}` with every field of each issue
+pub fn to_json(issues: &[Issue]) -> serde_json::Value {
+    let errors = error_count(issues);
+    json!({
+        "valid": errors == 0,
+        "errors": errors,
+        "warnings": issues.len() - errors,
+        "issues": issues,
+    })
+}
+
+/// A SARIF 2.1.0 log with one run. The book is the root artifact and each
+/// resource an issue is in is a nested artifact, so locations name both the
+/// file on disk and the path inside the container.
+pub fn to_sarif(issues: &[Issue], book: &str) -> serde_json::Value {
+    let rules: Vec<_> = rules::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id,
+                "shortDescription": { "text": rule.summary },
+                "help": { "text": rule.fix },
+                "defaultConfiguration": { "level": rule.severity.to_string() },
+            })
+        })
+        .collect();
+
+    let mut artifacts = vec![json!({ "location": { "uri": book } })];
+    let mut artifact_paths: Vec<&str> = Vec::new();
+    let results: Vec<_> = issues
+        .iter()
+        .map(|issue| {
+            let location = match &issue.path {
+                Some(path) => {
+                    let index = match artifact_paths.iter().position(|p| p == path) {
+                        Some(pos) => pos + 1,
+                        None => {
+                            artifact_paths.push(path.as_str());
+                            artifacts.push(json!({
+                                "location": { "uri": path },
+                                "parentIndex": 0,
+                            }));
+                            artifact_paths.len()
+                        }
+                    };
+                    let mut location = json!({
+                        "artifactLocation": { "uri": path, "index": index },
+                    });
+                    if let Some(line) = issue.line {
+                        let mut region = json!({ "startLine": line });
+                        if let Some(column) = issue.column {
+                            region["startColumn"] = json!(column);
+                        }
+                        location["region"] = region;
+                    }
+                    location
+                }
+                None => json!({ "artifactLocation": { "uri": book, "index": 0 } }),
+            };
+            let mut result = json!({
+                "ruleId": issue.rule,
+                "level": issue.severity.to_string(),
+                "message": { "text": issue.message },
+                "locations": [{ "physicalLocation": location }],
+                "properties": { "suggestion": issue.suggestion },
+            });
+            if let Some(index) = rules::ALL.iter().position(|rule| rule.id == issue.rule) {
+                result["ruleIndex"] = json!(index);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "epx",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules,
+                },
+            },
+            "artifacts": artifacts,
+            "results": results,
+        }],
+    })
+}
+
+/// A JUnit XML report with a test case per rule; a rule with issues fails
+/// and lists them
+pub fn to_junit(issues: &[Issue], book: &str) -> String {
+    let failures = rules::ALL
+        .iter()
+        .filter(|rule| issues.iter().any(|issue| issue.rule == rule.id))
+        .count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"epx validate\" tests=\"{0}\" failures=\"{failures}\">\n\
+         <testsuite name=\"{1}\" tests=\"{0}\" failures=\"{failures}\">\n",
+        rules::ALL.len(),
+        xml_escape(book)
+    ));
+    for rule in rules::ALL {
+        let found: Vec<&Issue> = issues.iter().filter(|i| i.rule == rule.id).collect();
+        let name = xml_escape(&format!("{}: {}", rule.id, rule.summary));
+        if found.is_empty() {
+            xml.push_str(&format!(
+                "  <testcase classname=\"{}\" name=\"{name}\"/>\n",
+                xml_escape(book)
+            ));
+            continue;
+        }
+        xml.push_str(&format!(
+            "  <testcase classname=\"{}\" name=\"{name}\">\n",
+            xml_escape(book)
+        ));
+        xml.push_str(&format!(
+            "    <failure type=\"{}\" message=\"{} issue(s)\">",
+            rule.severity,
+            found.len()
+        ));
+        for issue in found {
+            xml.push_str(&xml_escape(&issue.to_string()));
+            xml.push('\n');
+            xml.push_str(&xml_escape(&format!("  fix: {}", issue.suggestion)));
+            xml.push('\n');
+        }
+        xml.push_str("</failure>\n  </testcase>\n");
+    }
+    xml.push_str("</testsuite>\n</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issues() -> Vec<Issue> {
+        vec![
+            Issue::new(&rules::MISSING_TITLE, "no dc:title").at("EPUB/package.opf"),
+            Issue::new(&rules::DUPLICATE_ID, "duplicate id 'a'")
+                .at("EPUB/ch1.xhtml")
+                .at_position((4, 7)),
+            Issue::new(&rules::RESOURCE_NOT_IN_MANIFEST, "file is not listed").at("EPUB/ch1.xhtml"),
+        ]
+    }
+
+    #[test]
+    fn fail_on_threshold() {
+        let issues = issues();
+        assert_eq!("error".parse::<FailOn>().unwrap().count(&issues), 2);
+        assert_eq!("warning".parse::<FailOn>().unwrap().count(&issues), 3);
+        assert!("fatal".parse::<FailOn>().is_err());
+    }
+
+    #[test]
+    fn json_issue_fields() {
+        let json = to_json(&issues());
+        assert_eq!(json["valid"], false);
+        assert_eq!(json["errors"], 2);
+        assert_eq!(json["warnings"], 1);
+        let issue = &json["issues"][1];
+        assert_eq!(issue["rule"], "HTM-002");
+        assert_eq!(issue["severity"], "error");
+        assert_eq!(issue["path"], "EPUB/ch1.xhtml");
+        assert_eq!(issue["line"], 4);
+        assert_eq!(issue["column"], 7);
+        assert_eq!(issue["suggestion"], rules::DUPLICATE_ID.fix);
+    }
+
+    #[test]
+    fn sarif_locations() {
+        let sarif = to_sarif(&issues(), "book.epub");
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        // The book and two resources
+        assert_eq!(run["artifacts"].as_array().unwrap().len(), 3);
+        assert_eq!(run["artifacts"][2]["parentIndex"], 0);
+        let result = &run["results"][1];
+        assert_eq!(result["ruleId"], "HTM-002");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "EPUB/ch1.xhtml");
+        assert_eq!(location["artifactLocation"]["index"], 2);
+        assert_eq!(location["region"]["startLine"], 4);
+        assert_eq!(location["region"]["startColumn"], 7);
+        assert_eq!(run["results"][2]["level"], "warning");
+        let index = result["ruleIndex"].as_u64().unwrap() as usize;
+        assert_eq!(run["tool"]["driver"]["rules"][index]["id"], "HTM-002");
+    }
+
+    #[test]
+    fn junit_failures() {
+        let xml = to_junit(&issues(), "a&b.epub");
+        assert!(xml.contains(&format!("tests=\"{}\" failures=\"3\"", rules::ALL.len())));
+        assert!(xml.contains("<testsuite name=\"a&amp;b.epub\""));
+        assert!(xml.contains("<failure type=\"error\" message=\"1 issue(s)\">"));
+        assert!(xml.contains("error[HTM-002] EPUB/ch1.xhtml:4:7: duplicate id 'a'"));
+        let mut reader = quick_xml::Reader::from_str(&xml);
+        loop {
+            match reader.read_event().expect("well-formed report") {
+                quick_xml::events::Event::Eof => break,
+                _ => continue,
+            }
+        }
+    }
+}
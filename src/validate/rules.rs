@@ -0,0 +1,243 @@
+use crate::validate::Severity;
+use serde::Serialize;
+
+/// A validation rule. IDs are stable across releases so reports can be
+/// filtered and compared; a retired rule's ID is never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Rule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub summary: &'static str,
+    /// How issues of this rule are usually fixed
+    pub fix: &'static str,
+}
+
+const fn error(id: &'static str, summary: &'static str, fix: &'static str) -> Rule {
+    Rule {
+        id,
+        severity: Severity::Error,
+        summary,
+        fix,
+    }
+}
+
+const fn warning(id: &'static str, summary: &'static str, fix: &'static str) -> Rule {
+    Rule {
+        id,
+        severity: Severity::Warning,
+        summary,
+        fix,
+    }
+}
+
+// Container
+pub const CONTAINER_REPAIRED: Rule = error(
+    "PKG-001",
+    "the container is damaged",
+    "run `epx book repair` to write a conformant copy",
+);
+
+// Package metadata
+pub const MISSING_TITLE: Rule = error(
+    "OPF-001",
+    "dc:title is required",
+    "add a title with `epx metadata set --field title --value ...`",
+);
+pub const MISSING_LANGUAGE: Rule = error(
+    "OPF-002",
+    "dc:language is required",
+    "add a language with `epx metadata set --field language --value ...`",
+);
+pub const MISSING_IDENTIFIER: Rule = error(
+    "OPF-003",
+    "dc:identifier is required",
+    "add an identifier with `epx metadata set --field identifier --value ...`",
+);
+pub const MISSING_MODIFIED: Rule = error(
+    "OPF-004",
+    "EPUB 3 requires dcterms:modified",
+    "rewrite the book with epx, which sets dcterms:modified",
+);
+pub const INVALID_MODIFIED: Rule = error(
+    "OPF-005",
+    "dcterms:modified must be of the form CCYY-MM-DDThh:mm:ssZ",
+    "use a UTC timestamp such as 2024-01-31T12:00:00Z",
+);
+pub const UNIQUE_IDENTIFIER: Rule = error(
+    "OPF-006",
+    "package@unique-identifier must reference a dc:identifier",
+    "point unique-identifier at the id of a dc:identifier",
+);
+
+// Manifest
+pub const DUPLICATE_MANIFEST_ID: Rule = error(
+    "OPF-010",
+    "manifest item IDs must be unique",
+    "give each manifest item its own id",
+);
+pub const DUPLICATE_MANIFEST_HREF: Rule = error(
+    "OPF-011",
+    "a resource must be listed in the manifest once",
+    "remove the duplicate manifest item",
+);
+pub const FALLBACK_NOT_FOUND: Rule = error(
+    "OPF-015",
+    "a fallback must reference a manifest item",
+    "point the fallback at an existing manifest item",
+);
+pub const FALLBACK_CYCLE: Rule = error(
+    "OPF-016",
+    "fallback chains must not be circular",
+    "break the fallback cycle",
+);
+
+// Spine
+pub const SPINE_ITEM_NOT_FOUND: Rule = error(
+    "OPF-012",
+    "spine itemrefs must reference manifest items",
+    "remove the itemref or add the manifest item",
+);
+pub const EMPTY_SPINE: Rule = error(
+    "OPF-013",
+    "the spine must not be empty",
+    "add a content document to the spine",
+);
+pub const DUPLICATE_SPINE_ITEM: Rule = error(
+    "OPF-014",
+    "a manifest item may appear in the spine once",
+    "remove the duplicate itemref",
+);
+pub const SPINE_FALLBACK: Rule = error(
+    "OPF-017",
+    "spine items of non-content media types need a content document fallback",
+    "add a fallback to an XHTML content document",
+);
+
+// Resources
+pub const RESOURCE_MISSING: Rule = error(
+    "RSC-001",
+    "manifest items must exist in the container",
+    "add the file to the container or remove the manifest item",
+);
+pub const RESOURCE_NOT_IN_MANIFEST: Rule = warning(
+    "RSC-002",
+    "container files should be listed in the manifest",
+    "add the file to the manifest or remove it",
+);
+pub const MEDIA_TYPE_MISMATCH: Rule = error(
+    "RSC-003",
+    "the declared media type must match the resource content",
+    "declare the media type that matches the file",
+);
+pub const REFERENCE_NOT_FOUND: Rule = error(
+    "RSC-004",
+    "referenced resources must exist in the manifest",
+    "fix the reference or add the target to the book",
+);
+pub const FRAGMENT_NOT_FOUND: Rule = error(
+    "RSC-005",
+    "link fragments must match an ID in the target",
+    "fix the fragment or add the ID to the target",
+);
+pub const FOREIGN_WITHOUT_FALLBACK: Rule = error(
+    "RSC-006",
+    "foreign resources in content need a core media type fallback",
+    "use a core media type or add a manifest fallback",
+);
+
+// Content documents
+pub const NOT_WELL_FORMED: Rule = error(
+    "HTM-001",
+    "content documents must be well-formed XML",
+    "fix the markup so the document parses as XML",
+);
+pub const DUPLICATE_ID: Rule = error(
+    "HTM-002",
+    "IDs must be unique within a document",
+    "rename one of the elements",
+);
+
+// Navigation
+pub const MISSING_NAV: Rule = error(
+    "NAV-001",
+    "EPUB 3 requires a navigation document",
+    "add a navigation document with `epx book upgrade` or `epx toc generate`",
+);
+pub const MULTIPLE_NAV: Rule = error(
+    "NAV-002",
+    "only one manifest item may be the nav",
+    "keep the nav property on one manifest item",
+);
+pub const MISSING_TOC_NAV: Rule = error(
+    "NAV-003",
+    "the navigation document needs a nav of epub:type toc",
+    "add a <nav epub:type=\"toc\"> to the navigation document",
+);
+pub const TOC_NAV_STRUCTURE: Rule = error(
+    "NAV-004",
+    "the toc nav must contain an ol list",
+    "list the entries of the toc nav in an <ol>",
+);
+pub const MISSING_NCX: Rule = error(
+    "NAV-005",
+    "EPUB 2 requires an NCX",
+    "add an NCX to the manifest",
+);
+
+// Fixed layout and media overlays
+pub const MISSING_VIEWPORT: Rule = error(
+    "FXL-001",
+    "fixed-layout XHTML pages must declare a viewport",
+    "add <meta name=\"viewport\" content=\"width=..., height=...\"/> to the head",
+);
+pub const MEDIA_OVERLAY: Rule = error(
+    "MED-001",
+    "media overlays must match the content",
+    "fix the media overlay or its content document",
+);
+
+/// Every rule, in ID order within each group
+pub const ALL: &[Rule] = &[
+    CONTAINER_REPAIRED,
+    MISSING_TITLE,
+    MISSING_LANGUAGE,
+    MISSING_IDENTIFIER,
+    MISSING_MODIFIED,
+    INVALID_MODIFIED,
+    UNIQUE_IDENTIFIER,
+    DUPLICATE_MANIFEST_ID,
+    DUPLICATE_MANIFEST_HREF,
+    SPINE_ITEM_NOT_FOUND,
+    EMPTY_SPINE,
+    DUPLICATE_SPINE_ITEM,
+    FALLBACK_NOT_FOUND,
+    FALLBACK_CYCLE,
+    SPINE_FALLBACK,
+    RESOURCE_MISSING,
+    RESOURCE_NOT_IN_MANIFEST,
+    MEDIA_TYPE_MISMATCH,
+    REFERENCE_NOT_FOUND,
+    FRAGMENT_NOT_FOUND,
+    FOREIGN_WITHOUT_FALLBACK,
+    NOT_WELL_FORMED,
+    DUPLICATE_ID,
+    MISSING_NAV,
+    MULTIPLE_NAV,
+    MISSING_TOC_NAV,
+    TOC_NAV_STRUCTURE,
+    MISSING_NCX,
+    MISSING_VIEWPORT,
+    MEDIA_OVERLAY,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn rule_ids_are_unique() {
+        let ids: HashSet<&str> = ALL.iter().map(|rule| rule.id).collect();
+        assert_eq!(ids.len(), ALL.len());
+    }
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use predicates::prelude::*;
+
+fn epx() -> assert_cmd::Command {
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
+}
+
+const SECTION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <head><title>Section</title></head>
+  <body>
+    <h1>Title</h1>
+    <h3>Skipped</h3>
+    <img src="figure.png"/>
+    <table><tr><td>1</td></tr></table>
+  </body>
+</html>"#;
+
+fn opf_text(path: &std::path::Path) -> String {
+    let opf = common::read_entry(path, "EPUB/package.opf")
+        .or_else(|| common::read_entry(path, "OEBPS/package.opf"))
+        .expect("package document");
+    String::from_utf8(opf).unwrap()
+}
+
+#[test]
+fn a11y_reports_declared_metadata() {
+    let path = common::fixture_path("accessible_epub_3.epub");
+    let output = epx()
+        .args(["--json", "book", "a11y"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let metadata = &json["metadata"];
+    assert_eq!(metadata["access_modes"][0], "textual");
+    assert_eq!(metadata["hazards"][0], "none");
+    assert_eq!(metadata["certified_by"], "Matt Garrish");
+    assert_eq!(
+        metadata["conforms_to"][0],
+        "http://www.idpf.org/epub/a11y/accessibility-20170105.html#wcag-aa"
+    );
+    assert!(
+        metadata["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|f| f == "alternativeText")
+    );
+}
+
+#[test]
+fn a11y_audits_content() {
+    let (_tmp, path) = common::temp_copy_with_entries(
+        "minimal-v2.epub",
+        &[("OEBPS/xhtml/section0001.xhtml", SECTION.as_bytes())],
+    );
+    epx()
+        .args(["book", "a11y"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no accessibility metadata"))
+        .stdout(predicate::str::contains(
+            "OEBPS/xhtml/section0001.xhtml:7:5: image 'figure.png' has no alt text",
+        ))
+        .stdout(predicate::str::contains(
+            "OEBPS/xhtml/section0001.xhtml:2:1: no lang or xml:lang on <html>",
+        ))
+        .stdout(predicate::str::contains(
+            "section0001.xhtml:6:5: heading level skips from h1 to h3",
+        ))
+        .stdout(predicate::str::contains(
+            "section0001.xhtml:8:5: table has no header cells",
+        ))
+        .stdout(predicate::str::contains(
+            "no schema:accessibilitySummary declared",
+        ));
+}
+
+#[test]
+fn a11y_write_round_trips_through_the_opf() {
+    let (_tmp, path) = common::temp_copy("accessible_epub_3.epub");
+    epx()
+        .args(["book", "a11y", "--write", "--conforms-to"])
+        .arg("EPUB Accessibility 1.1 - WCAG 2.1 Level AA")
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Written metadata:"));
+
+    let opf = opf_text(&path);
+    assert!(
+        opf.contains(r#"<meta property="schema:accessibilityFeature">structuralNavigation</meta>"#)
+    );
+    assert!(opf.contains(
+        r#"<link rel="dcterms:conformsTo" href="http://www.idpf.org/epub/a11y/accessibility-20170105.html#wcag-aa"/>"#
+    ));
+    assert!(opf.contains(
+        r#"<meta property="dcterms:conformsTo">EPUB Accessibility 1.1 - WCAG 2.1 Level AA</meta>"#
+    ));
+    assert_eq!(opf.matches("schema:accessibilityHazard").count(), 1);
+
+    // The written metadata is read back as declared
+    let output = epx()
+        .args(["--json", "book", "a11y"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["metadata"], json["suggested"]);
+}
+
+#[test]
+fn a11y_write_epub2_uses_name_content_metas() {
+    let (_tmp, path) = common::temp_copy("minimal-v2.epub");
+    epx()
+        .args(["book", "a11y", "--write", "--summary", "Plain text only."])
+        .arg(&path)
+        .assert()
+        .success();
+
+    let opf = opf_text(&path);
+    assert!(opf.contains(r#"<meta name="schema:accessMode" content="textual"/>"#));
+    assert!(
+        opf.contains(r#"<meta name="schema:accessibilitySummary" content="Plain text only."/>"#)
+    );
+
+    let output = epx()
+        .args(["--json", "book", "a11y"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["metadata"]["summary"], "Plain text only.");
+    assert_eq!(json["metadata"]["hazards"][0], "unknown");
+}
+
+#[test]
+fn metadata_set_writes_accessibility_properties() {
+    let (_tmp, path) = common::temp_copy("minimal-v3.epub");
+    epx()
+        .args([
+            "metadata",
+            "set",
+            "--field",
+            "accessibilityHazard",
+            "--value",
+            "none",
+        ])
+        .arg(&path)
+        .assert()
+        .success();
+
+    let opf = opf_text(&path);
+    assert!(opf.contains(r#"<meta property="schema:accessibilityHazard">none</meta>"#));
+}
+
+#[test]
+fn page_list_claim_without_page_list() {
+    let (_tmp, path) = common::temp_copy("minimal-v3.epub");
+    epx()
+        .args([
+            "metadata",
+            "set",
+            "--field",
+            "schema:accessibilityFeature",
+            "--value",
+            "pageBreakMarkers",
+        ])
+        .arg(&path)
+        .assert()
+        .success();
+    epx()
+        .args(["book", "a11y"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "pageBreakMarkers is claimed but the navigation has no page list",
+        ));
+}
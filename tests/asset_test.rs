@@ -4,7 +4,7 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -156,3 +156,181 @@ fn test_asset_remove() {
         "removed asset should not appear in manifest"
     );
 }
+
+#[test]
+fn test_asset_add_obfuscated_font_roundtrip() {
+    let (tmp, copy) = common::temp_copy("minimal-v3.epub");
+    let font: Vec<u8> = (0..4096u32).map(|i| (i % 253) as u8).collect();
+    let font_path = tmp.path().join("body.ttf");
+    std::fs::write(&font_path, &font).unwrap();
+
+    epx()
+        .args([
+            "asset",
+            "add",
+            copy.to_str().unwrap(),
+            font_path.to_str().unwrap(),
+            "--obfuscate",
+        ])
+        .assert()
+        .success();
+
+    // Stored obfuscated, listed in encryption.xml
+    let stored = common::read_entry(&copy, "EPUB/body.ttf").unwrap();
+    assert_ne!(stored[..1040], font[..1040]);
+    assert_eq!(stored[1040..], font[1040..]);
+    let encryption = common::read_entry(&copy, "META-INF/encryption.xml").unwrap();
+    let encryption = String::from_utf8(encryption).unwrap();
+    assert!(encryption.contains("http://www.idpf.org/2008/embedding"));
+    assert!(encryption.contains("URI=\"EPUB/body.ttf\""));
+
+    // Changing the unique identifier re-keys the font
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "identifier",
+            "--value",
+            "urn:uuid:00000000-0000-0000-0000-000000000001",
+        ])
+        .assert()
+        .success();
+    let rekeyed = common::read_entry(&copy, "EPUB/body.ttf").unwrap();
+    assert_ne!(rekeyed, stored);
+
+    let out = tmp.path().join("extracted.ttf");
+    epx()
+        .args([
+            "asset",
+            "extract",
+            copy.to_str().unwrap(),
+            "body.ttf",
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&out).unwrap(), font);
+}
+
+#[test]
+fn test_asset_add_obfuscate_rejects_non_font() {
+    let (tmp, copy) = common::temp_copy("minimal-v3.epub");
+    let css = tmp.path().join("extra.css");
+    std::fs::write(&css, "p { margin: 0 }").unwrap();
+    epx()
+        .args([
+            "asset",
+            "add",
+            copy.to_str().unwrap(),
+            css.to_str().unwrap(),
+            "--obfuscate",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("only fonts can be obfuscated"));
+}
+
+#[test]
+fn test_drm_encrypted_book_is_rejected() {
+    let encryption = br#"<?xml version="1.0"?>
+<encryption xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:enc="http://www.w3.org/2001/04/xmlenc#">
+  <enc:EncryptedData>
+    <enc:EncryptionMethod Algorithm="http://www.w3.org/2001/04/xmlenc#aes128-cbc"/>
+    <enc:CipherData><enc:CipherReference URI="EPUB/xhtml/section0001.xhtml"/></enc:CipherData>
+  </enc:EncryptedData>
+</encryption>"#;
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[("META-INF/encryption.xml", encryption)],
+    );
+    epx()
+        .args(["book", "info", copy.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "EPUB/xhtml/section0001.xhtml is encrypted",
+        ));
+}
+
+#[test]
+fn test_asset_remove_refuses_css_referenced_font() {
+    let (_tmp, copy) = common::temp_copy("basic-v3plus2.epub");
+    epx()
+        .args([
+            "asset",
+            "remove",
+            copy.to_str().unwrap(),
+            "fonts/redacted-script-regular.ttf",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "EPUB/css/epub.css: url(../fonts/redacted-script-regular.ttf)",
+        ));
+    assert!(common::read_entry(&copy, "EPUB/fonts/redacted-script-regular.ttf").is_some());
+
+    epx()
+        .args([
+            "asset",
+            "remove",
+            copy.to_str().unwrap(),
+            "fonts/redacted-script-regular.ttf",
+            "--force",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Left 1 reference(s)"));
+    let css = common::read_entry(&copy, "EPUB/css/epub.css").unwrap();
+    assert!(String::from_utf8(css).unwrap().contains("url("));
+}
+
+#[test]
+fn test_asset_remove_cascade() {
+    let (_tmp, copy) = common::temp_copy("basic-v3plus2.epub");
+    epx()
+        .args([
+            "asset",
+            "remove",
+            copy.to_str().unwrap(),
+            "css/epub.css",
+            "--cascade",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Removed 2 reference(s) to css/epub.css",
+        ));
+    let section = common::read_entry(&copy, "EPUB/xhtml/section0002.xhtml").unwrap();
+    let section = String::from_utf8(section).unwrap();
+    assert!(!section.contains("epub.css"));
+    assert!(section.contains("<title>"));
+
+    // The font was only used by the removed stylesheet
+    epx()
+        .args(["asset", "list", copy.to_str().unwrap(), "--unused"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "fonts/redacted-script-regular.ttf",
+        ));
+}
+
+#[test]
+fn test_asset_list_unused() {
+    let (_tmp, copy) = common::temp_copy("basic-v3plus2.epub");
+    let output = epx()
+        .args([
+            "asset",
+            "list",
+            copy.to_str().unwrap(),
+            "--unused",
+            "--json",
+        ])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json, serde_json::json!([]));
+}
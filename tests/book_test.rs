@@ -5,7 +5,7 @@ use predicates::prelude::*;
 use tempfile::TempDir;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -220,3 +220,594 @@ fn test_book_info_epub2() {
         .success()
         .stdout(predicate::str::contains("Title:"));
 }
+
+const DISPLAY_OPTIONS: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?>
+<display_options>
+  <platform name="*">
+    <option name="specified-fonts">true</option>
+  </platform>
+</display_options>"#;
+
+#[test]
+fn test_book_info_shows_meta_inf_extras() {
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[(
+            "META-INF/com.apple.ibooks.display-options.xml",
+            DISPLAY_OPTIONS,
+        )],
+    );
+    epx()
+        .args(["book", "info", copy.to_str().unwrap(), "-v"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "META-INF/com.apple.ibooks.display-options.xml",
+        ))
+        .stdout(predicate::str::contains("[*] specified-fonts=true"));
+}
+
+#[test]
+fn test_edit_preserves_meta_inf_extras() {
+    let rights = b"<rights/>";
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("META-INF/rights.xml", rights),
+            (
+                "META-INF/com.apple.ibooks.display-options.xml",
+                DISPLAY_OPTIONS,
+            ),
+        ],
+    );
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "title",
+            "--value",
+            "Edited",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("signatures").not());
+
+    assert_eq!(
+        common::read_entry(&copy, "META-INF/rights.xml").as_deref(),
+        Some(&rights[..])
+    );
+    assert_eq!(
+        common::read_entry(&copy, "META-INF/com.apple.ibooks.display-options.xml").as_deref(),
+        Some(DISPLAY_OPTIONS)
+    );
+}
+
+#[test]
+fn test_edit_warns_about_invalidated_signatures() {
+    let signatures = b"<signatures xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\"/>";
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[("META-INF/signatures.xml", signatures)],
+    );
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "title",
+            "--value",
+            "Edited",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("signatures are no longer valid"));
+
+    assert_eq!(
+        common::read_entry(&copy, "META-INF/signatures.xml").as_deref(),
+        Some(&signatures[..])
+    );
+}
+
+#[test]
+fn test_book_assemble_obfuscate_fonts() {
+    let fixture = common::fixture_path("basic-v3plus2.epub");
+    let tmp = TempDir::new().unwrap();
+    let extract_dir = tmp.path().join("extracted");
+    let assembled = tmp.path().join("output.epub");
+
+    epx()
+        .args([
+            "book",
+            "extract",
+            fixture.to_str().unwrap(),
+            "-o",
+            extract_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    epx()
+        .args([
+            "book",
+            "assemble",
+            extract_dir.to_str().unwrap(),
+            "-o",
+            assembled.to_str().unwrap(),
+            "--obfuscate-fonts",
+        ])
+        .assert()
+        .success();
+
+    let encryption = common::read_entry(&assembled, "META-INF/encryption.xml")
+        .expect("encryption.xml should be written");
+    let encryption = String::from_utf8(encryption).unwrap();
+    assert!(encryption.contains("redacted-script-regular.ttf"));
+
+    // Reading back de-obfuscates to the original font
+    let original = common::read_epub_fixture("basic-v3plus2.epub");
+    let font = original
+        .resources
+        .get("EPUB/fonts/redacted-script-regular.ttf")
+        .unwrap()
+        .to_vec();
+    let book = epx::epub::reader::read_epub_with(&assembled, &Default::default()).unwrap();
+    let key = book
+        .resources
+        .keys()
+        .find(|k| k.ends_with("redacted-script-regular.ttf"))
+        .unwrap()
+        .clone();
+    assert_eq!(book.resources.get(&key).unwrap(), &font[..]);
+}
+
+#[test]
+fn test_book_assemble_epub2() {
+    let fixture = common::fixture_path("basic-v3plus2.epub");
+    let tmp = TempDir::new().unwrap();
+    let extract_dir = tmp.path().join("extracted");
+    let assembled = tmp.path().join("output.epub");
+
+    epx()
+        .args([
+            "book",
+            "extract",
+            fixture.to_str().unwrap(),
+            "-o",
+            extract_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    epx()
+        .args([
+            "book",
+            "assemble",
+            extract_dir.to_str().unwrap(),
+            "-o",
+            assembled.to_str().unwrap(),
+            "--epub-version",
+            "2.0.1",
+        ])
+        .assert()
+        .success();
+
+    common::assert_valid_epub(&assembled);
+    assert!(common::read_entry(&assembled, "OEBPS/toc.xhtml").is_none());
+    let opf =
+        String::from_utf8(common::read_entry(&assembled, "OEBPS/content.opf").unwrap()).unwrap();
+    assert!(opf.contains("version=\"2.0\""));
+    assert!(!opf.contains("properties="));
+
+    let book = epx::epub::reader::read_epub_with(&assembled, &Default::default()).unwrap();
+    assert_eq!(book.navigation.epub_version, epx::epub::EpubVersion::V2);
+    let chapter = &book.spine[0].idref;
+    let item = book.manifest.iter().find(|m| &m.id == chapter).unwrap();
+    let xhtml = book.resources.get(&book.resource_key(&item.href)).unwrap();
+    let xhtml = String::from_utf8(xhtml.to_vec()).unwrap();
+    assert!(xhtml.contains("XHTML 1.1"));
+
+    epx()
+        .args([
+            "book",
+            "assemble",
+            extract_dir.to_str().unwrap(),
+            "--epub-version",
+            "4",
+        ])
+        .assert()
+        .failure();
+}
+
+const TWO_RENDITIONS: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container" xmlns:rendition="http://www.idpf.org/2013/rendition">
+  <rootfiles>
+    <rootfile full-path="EPUB/package.opf" media-type="application/oebps-package+xml" rendition:label="English"/>
+    <rootfile full-path="EPUB/fr.opf" media-type="application/oebps-package+xml" rendition:language="fr"/>
+  </rootfiles>
+</container>"#;
+
+const FRENCH_OPF: &[u8] = br#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Titre</dc:title>
+    <dc:language>fr</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="section0001.xhtml" href="xhtml/section0001.xhtml" media-type="application/xhtml+xml" />
+  </manifest>
+  <spine>
+    <itemref idref="section0001.xhtml" />
+  </spine>
+</package>"#;
+
+fn two_rendition_copy() -> (TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("META-INF/container.xml", TWO_RENDITIONS),
+            ("EPUB/fr.opf", FRENCH_OPF),
+        ],
+    )
+}
+
+#[test]
+fn test_book_info_lists_renditions() {
+    let (_tmp, copy) = two_rendition_copy();
+    epx()
+        .args(["book", "info", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "* 0: EPUB/package.opf (label=English)",
+        ))
+        .stdout(predicate::str::contains("  1: EPUB/fr.opf (language=fr)"));
+
+    epx()
+        .args(["book", "info", copy.to_str().unwrap(), "--rendition", "fr"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Title:    Titre"))
+        .stdout(predicate::str::contains("* 1: EPUB/fr.opf"));
+
+    epx()
+        .args(["book", "info", copy.to_str().unwrap(), "--rendition", "de"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no rendition matches 'de'"));
+}
+
+#[test]
+fn test_edit_rendition_keeps_others() {
+    let (_tmp, copy) = two_rendition_copy();
+    let english_opf = common::read_entry(&copy, "EPUB/package.opf").unwrap();
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--rendition",
+            "1",
+            "--field",
+            "title",
+            "--value",
+            "Nouveau titre",
+        ])
+        .assert()
+        .success();
+
+    let container =
+        String::from_utf8(common::read_entry(&copy, "META-INF/container.xml").unwrap()).unwrap();
+    assert!(container.contains(
+        r#"<rootfile full-path="EPUB/package.opf" media-type="application/oebps-package+xml" rendition:label="English"/>"#
+    ));
+    assert!(container.contains(r#"rendition:language="fr""#));
+    assert_eq!(
+        common::read_entry(&copy, "EPUB/package.opf").unwrap(),
+        english_opf
+    );
+
+    let french = epx::epub::reader::read_epub_with(
+        &copy,
+        &epx::epub::reader::ReadOptions {
+            rendition: Some("fr".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(french.metadata.title(), Some("Nouveau titre"));
+    let english = epx::epub::reader::read_epub_with(&copy, &Default::default()).unwrap();
+    assert_eq!(english.metadata.title(), Some("Your title here"));
+}
+
+#[test]
+fn test_book_info_utf16_package() {
+    let opf = common::read_entry(
+        &common::fixture_path("minimal-v2.epub"),
+        "OEBPS/package.opf",
+    )
+    .unwrap();
+    let opf = String::from_utf8(opf)
+        .unwrap()
+        .replace("encoding=\"UTF-8\"", "encoding=\"UTF-16\"")
+        .replace("Your title here", "Café");
+    let mut utf16 = vec![0xFF, 0xFE];
+    for unit in opf.encode_utf16() {
+        utf16.extend_from_slice(&unit.to_le_bytes());
+    }
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v2.epub", &[("OEBPS/package.opf", &utf16)]);
+
+    epx()
+        .args(["book", "info", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Title:    Café"));
+}
+
+/// minimal-v3 with a deflated mimetype stored last, no container.xml, a
+/// chapter whose name differs in case from its manifest href and a
+/// stylesheet the manifest does not list
+fn broken_copy() -> (TempDir, std::path::PathBuf) {
+    use std::io::{Read, Write};
+
+    let src = std::fs::File::open(common::fixture_path("minimal-v3.epub")).unwrap();
+    let mut archive = zip::ZipArchive::new(src).unwrap();
+    let tmp = TempDir::new().unwrap();
+    let dest = tmp.path().join("broken.epub");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&dest).unwrap());
+    let deflate = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    let mut mimetype = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).unwrap();
+        let name = match entry.name() {
+            "META-INF/container.xml" => continue,
+            "EPUB/xhtml/section0001.xhtml" => "EPUB/xhtml/Section0001.xhtml".to_string(),
+            name if name.ends_with('/') => continue,
+            name => name.to_string(),
+        };
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        if name == "mimetype" {
+            mimetype = data;
+            continue;
+        }
+        zip.start_file(name, deflate).unwrap();
+        zip.write_all(&data).unwrap();
+    }
+    zip.start_file("EPUB/extra.css", deflate).unwrap();
+    zip.write_all(b"p { margin: 0 }").unwrap();
+    zip.start_file("mimetype", deflate).unwrap();
+    zip.write_all(&mimetype).unwrap();
+    zip.finish().unwrap();
+    (tmp, dest)
+}
+
+#[test]
+fn test_book_info_lenient() {
+    let (_tmp, copy) = broken_copy();
+
+    epx()
+        .args(["book", "info", copy.to_str().unwrap()])
+        .assert()
+        .failure();
+
+    epx()
+        .args(["book", "info", "--lenient", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Chapters: 1"))
+        .stderr(predicate::str::contains(
+            "warning: mimetype is not the first entry",
+        ))
+        .stderr(predicate::str::contains("container.xml is missing"));
+}
+
+#[test]
+fn test_book_repair() {
+    let (tmp, copy) = broken_copy();
+    let repaired = tmp.path().join("repaired.epub");
+
+    let output = epx()
+        .args([
+            "book",
+            "repair",
+            copy.to_str().unwrap(),
+            "-o",
+            repaired.to_str().unwrap(),
+            "--json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let repairs: Vec<&str> = json["repairs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r.as_str().unwrap())
+        .collect();
+    assert!(repairs.contains(&"mimetype is not the first entry"));
+    assert!(repairs.contains(&"mimetype is compressed"));
+    assert!(
+        repairs
+            .iter()
+            .any(|r| r.contains("xhtml/Section0001.xhtml"))
+    );
+    assert!(repairs.iter().any(|r| r.starts_with("EPUB/extra.css")));
+
+    common::assert_valid_epub(&repaired);
+    let opf =
+        String::from_utf8(common::read_entry(&repaired, "EPUB/package.opf").unwrap()).unwrap();
+    assert!(opf.contains(r#"href="xhtml/Section0001.xhtml""#));
+    assert!(opf.contains(r#"href="extra.css""#));
+
+    // The repaired book reads without --lenient and has nothing left to fix
+    epx()
+        .args(["book", "validate", repaired.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("valid"));
+    epx()
+        .args(["book", "repair", repaired.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("nothing to repair"));
+}
+
+#[test]
+fn test_book_repair_truncated_zip() {
+    let (_tmp, copy) = common::temp_copy("minimal-v3.epub");
+    let bytes = std::fs::read(&copy).unwrap();
+    let central_directory = bytes.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+    std::fs::write(&copy, &bytes[..central_directory + 20]).unwrap();
+
+    epx()
+        .args(["book", "repair", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "ZIP central directory is unreadable",
+        ));
+
+    common::assert_valid_epub(&copy);
+    epx()
+        .args(["chapter", "list", copy.to_str().unwrap()])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_book_upgrade() {
+    let (_tmp, copy) = common::temp_copy("minimal-v2.epub");
+    let before = epx::epub::reader::read_epub_with(&copy, &Default::default()).unwrap();
+
+    let output = epx()
+        .args(["book", "upgrade", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let changes: Vec<&str> = json["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c.as_str().unwrap())
+        .collect();
+    assert!(changes.iter().any(|c| c.starts_with("added nav document")));
+    assert!(changes.iter().any(|c| c.contains("doctype replaced")));
+
+    common::assert_valid_epub(&copy);
+    let opf = String::from_utf8(common::read_entry(&copy, "OEBPS/package.opf").unwrap()).unwrap();
+    assert!(opf.contains(r#"version="3.0""#));
+    assert!(opf.contains("dcterms:modified"));
+    assert!(!opf.contains("opf:scheme"));
+    let chapter =
+        String::from_utf8(common::read_entry(&copy, "OEBPS/xhtml/section0001.xhtml").unwrap())
+            .unwrap();
+    assert!(chapter.contains("<!DOCTYPE html>"));
+
+    let book = epx::epub::reader::read_epub_with(&copy, &Default::default()).unwrap();
+    assert_eq!(book.navigation.epub_version, epx::epub::EpubVersion::V3);
+    assert_eq!(book.navigation.toc, before.navigation.toc);
+    assert!(book.manifest.iter().any(|item| item.is_ncx()));
+
+    // Upgrading again has nothing left to do
+    epx()
+        .args(["book", "upgrade", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already EPUB 3"));
+}
+
+#[test]
+fn test_book_extract_endnotes_from_another_file() {
+    let opf = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Notes</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="section0001.xhtml" href="xhtml/section0001.xhtml" media-type="application/xhtml+xml" />
+    <item id="notes" href="xhtml/notes.xhtml" media-type="application/xhtml+xml" />
+  </manifest>
+  <spine>
+    <itemref idref="section0001.xhtml" />
+    <itemref idref="notes" />
+  </spine>
+</package>"#;
+    let section = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <head><title>Section 1</title></head>
+  <body>
+    <h1>Section 1</h1>
+    <p>Claim<a epub:type="noteref" id="r1" href="notes.xhtml#n1">1</a>.</p>
+  </body>
+</html>"#;
+    let notes = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <head><title>Notes</title></head>
+  <body>
+    <section epub:type="endnotes">
+      <h1>Notes</h1>
+      <ol>
+        <li id="n1">
+          <p><a href="section0001.xhtml#r1">1.</a> Source, <em>page 3</em>.</p>
+          <p>See also <a href="section0001.xhtml">section 1</a>.</p>
+        </li>
+      </ol>
+    </section>
+  </body>
+</html>"#;
+    let (tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", opf.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", section.as_bytes()),
+            ("EPUB/xhtml/notes.xhtml", notes.as_bytes()),
+        ],
+    );
+    let out_dir = tmp.path().join("extracted");
+    epx()
+        .args([
+            "book",
+            "extract",
+            copy.to_str().unwrap(),
+            "-o",
+            out_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    let chapters = out_dir.join("chapters");
+    let mut files: Vec<_> = std::fs::read_dir(&chapters)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    let chapter = std::fs::read_to_string(&files[0]).unwrap();
+    // The note links back to its reference, which keeps its anchor
+    assert!(chapter.contains("Claim[]{#r1}[^n1]."), "{chapter}");
+    let first_file = files[0].file_name().unwrap().to_str().unwrap();
+    assert!(
+        chapter.ends_with(&format!(
+            "[^n1]: Source, *page 3*.\n\n    See also [section 1]({first_file}).\n"
+        )),
+        "{chapter}"
+    );
+    let notes_chapter = std::fs::read_to_string(&files[1]).unwrap();
+    assert!(!notes_chapter.contains("Source"), "{notes_chapter}");
+}
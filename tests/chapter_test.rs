@@ -4,7 +4,7 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -23,11 +23,57 @@ pub fn temp_copy(fixture_name: &str) -> (tempfile::TempDir, PathBuf) {
     (tmp, dest)
 }
 
+/// Copy a fixture EPUB into a temp directory, appending extra entries.
+/// Existing entries are copied verbatim unless an extra entry of the same
+/// name replaces them. Returns (TempDir, path_to_copy).
+#[allow(dead_code)]
+pub fn temp_copy_with_entries(
+    fixture_name: &str,
+    extra: &[(&str, &[u8])],
+) -> (tempfile::TempDir, PathBuf) {
+    use std::io::Write;
+
+    let src = std::fs::File::open(fixture_path(fixture_name)).expect("open fixture");
+    let mut archive = zip::ZipArchive::new(src).expect("open zip");
+    let tmp = tempfile::TempDir::new().expect("create temp dir");
+    let dest = tmp.path().join(fixture_name);
+    let file = std::fs::File::create(&dest).expect("create copy");
+    let mut zip = zip::ZipWriter::new(file);
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).expect("raw entry");
+        if extra.iter().any(|(name, _)| *name == entry.name()) {
+            continue;
+        }
+        zip.raw_copy_file(entry).expect("copy entry");
+    }
+    let deflate = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    for (name, data) in extra {
+        zip.start_file(*name, deflate).expect("start entry");
+        zip.write_all(data).expect("write entry");
+    }
+    zip.finish().expect("finish zip");
+    (tmp, dest)
+}
+
+/// Read a single entry of an EPUB as bytes, if present
+#[allow(dead_code)]
+pub fn read_entry(path: &Path, name: &str) -> Option<Vec<u8>> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(path).expect("open epub");
+    let mut archive = zip::ZipArchive::new(file).expect("open zip");
+    let mut entry = archive.by_name(name).ok()?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).expect("read entry");
+    Some(data)
+}
+
 /// Shorthand: read a fixture EPUB into an EpubBook
 #[allow(dead_code)]
 pub fn read_epub_fixture(name: &str) -> epx::epub::EpubBook {
     let path = fixture_path(name);
-    epx::epub::reader::read_epub(&path).expect("read fixture epub")
+    epx::epub::reader::read_epub_with(&path, &Default::default()).expect("read fixture epub")
 }
 
 /// Create a minimal in-memory EpubBook for unit tests
@@ -47,8 +93,8 @@ pub fn create_minimal_book() -> epx::epub::EpubBook {
 
     EpubBook {
         metadata: EpubMetadata {
-            titles: vec!["Test Book".to_string()],
-            creators: vec!["Test Author".to_string()],
+            titles: vec![Title::new("Test Book")],
+            creators: vec![Contributor::new("Test Author")],
             identifiers: vec!["urn:uuid:12345678-1234-1234-1234-123456789abc".to_string()],
             languages: vec!["en".to_string()],
             ..Default::default()
@@ -58,21 +104,25 @@ pub fn create_minimal_book() -> epx::epub::EpubBook {
             href: "chapter1.xhtml".to_string(),
             media_type: "application/xhtml+xml".to_string(),
             properties: None,
+            ..Default::default()
         }],
         spine: vec![SpineItem {
             idref: "chapter1".to_string(),
             linear: true,
             properties: None,
+            ..Default::default()
         }],
         navigation: Navigation {
             toc: vec![NavPoint {
                 label: "Chapter 1".to_string(),
                 href: "chapter1.xhtml".to_string(),
                 children: Vec::new(),
+                ..Default::default()
             }],
             ..Default::default()
         },
-        resources,
+        resources: resources.into(),
+        ..Default::default()
     }
 }
 
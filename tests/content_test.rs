@@ -4,7 +4,7 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -151,3 +151,203 @@ fn test_content_headings_restructure() {
         "h1 headings should no longer exist after h1->h2 restructure"
     );
 }
+
+const LATIN1_CHAPTER: &[u8] = b"<?xml version=\"1.0\" encoding=\"iso-8859-1\"?>
+<html xmlns=\"http://www.w3.org/1999/xhtml\">
+  <head><title>Section 1</title></head>
+  <body>
+    <h1>Section 1</h1>
+    <p>Un caf\xE9 cr\xE8me.</p>
+  </body>
+</html>";
+
+fn latin1_copy() -> (tempfile::TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v2.epub",
+        &[("OEBPS/xhtml/section0001.xhtml", LATIN1_CHAPTER)],
+    )
+}
+
+#[test]
+fn test_content_search_legacy_encoding() {
+    let (_tmp, copy) = latin1_copy();
+    epx()
+        .args(["content", "search", copy.to_str().unwrap(), "café crème"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 match"));
+}
+
+#[test]
+fn test_content_replace_legacy_encoding() {
+    let (_tmp, copy) = latin1_copy();
+    epx()
+        .args([
+            "content",
+            "replace",
+            copy.to_str().unwrap(),
+            "crème",
+            "noir",
+        ])
+        .assert()
+        .success();
+
+    // The edited chapter is written as UTF-8 and says so
+    let chapter = common::read_entry(&copy, "OEBPS/xhtml/section0001.xhtml").unwrap();
+    let chapter = String::from_utf8(chapter).expect("chapter is UTF-8");
+    assert!(chapter.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+    assert!(chapter.contains("Un café noir."));
+}
+
+#[test]
+fn test_transcode_utf8_on_write() {
+    let (_tmp, copy) = latin1_copy();
+
+    // Unrelated edits leave the chapter in its original encoding
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "publisher",
+            "--value",
+            "Publisher",
+        ])
+        .assert()
+        .success();
+    assert_eq!(
+        common::read_entry(&copy, "OEBPS/xhtml/section0001.xhtml").unwrap(),
+        LATIN1_CHAPTER
+    );
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "publisher",
+            "--value",
+            "Publisher",
+            "--transcode-utf8",
+        ])
+        .assert()
+        .success();
+    let chapter = common::read_entry(&copy, "OEBPS/xhtml/section0001.xhtml").unwrap();
+    let chapter = String::from_utf8(chapter).expect("chapter is UTF-8");
+    assert!(chapter.contains("encoding=\"UTF-8\""));
+    assert!(chapter.contains("Un café crème."));
+}
+
+const LINKS_OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Your title here</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="section0001.xhtml" href="xhtml/section0001.xhtml" media-type="application/xhtml+xml" />
+    <item id="notes" href="xhtml/notes.xhtml" media-type="application/xhtml+xml" />
+  </manifest>
+  <spine>
+    <itemref idref="section0001.xhtml" />
+  </spine>
+</package>"#;
+
+const LINKS_SECTION: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head><title>Section 1</title></head>
+  <body>
+    <h1 id="start">Section 1</h1>
+    <p><a href="#start">Top</a> <a href="#nowhere">Nowhere</a></p>
+    <p><a href="notes.xhtml">Notes</a> <a href="gone.xhtml">Gone</a></p>
+    <p><a href="https://example.com/">Elsewhere</a></p>
+  </body>
+</html>"##;
+
+fn links_copy() -> (tempfile::TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", LINKS_OPF.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", LINKS_SECTION.as_bytes()),
+            (
+                "EPUB/xhtml/notes.xhtml",
+                b"<html xmlns=\"http://www.w3.org/1999/xhtml\"/>",
+            ),
+        ],
+    )
+}
+
+#[test]
+fn test_content_links() {
+    let (_tmp, copy) = links_copy();
+    epx()
+        .args(["content", "links", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "EPUB/xhtml/section0001.xhtml:7:42: <a> #nowhere: missing fragment",
+        ))
+        .stdout(predicate::str::contains(
+            "EPUB/xhtml/section0001.xhtml:8:17: <a> notes.xhtml: not in spine",
+        ))
+        .stdout(predicate::str::contains("<a> gone.xhtml: missing resource"))
+        .stdout(predicate::str::contains(
+            "<a> https://example.com/: external",
+        ))
+        .stdout(predicate::str::contains("<a> #start").not())
+        .stdout(predicate::str::contains(
+            "6 link(s): 2 ok, 2 broken, 1 not in spine, 1 external",
+        ));
+}
+
+#[test]
+fn test_content_links_json() {
+    let (_tmp, copy) = links_copy();
+    let output = epx()
+        .args(["content", "links", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["broken"], 2);
+    let link = json["links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|link| link["href"] == "#nowhere")
+        .unwrap();
+    assert_eq!(link["source"], "EPUB/xhtml/section0001.xhtml");
+    assert_eq!(link["target"], "EPUB/xhtml/section0001.xhtml");
+    assert_eq!(link["status"], "missing-fragment");
+    assert_eq!(link["line"], 7);
+}
+
+#[test]
+fn test_content_links_ncx() {
+    let ncx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head><meta name="dtb:uid" content="urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d"/></head>
+  <docTitle><text>Title</text></docTitle>
+  <navMap>
+    <navPoint id="n1" playOrder="1">
+      <navLabel><text>Section 1</text></navLabel>
+      <content src="xhtml/section0001.xhtml#nowhere"/>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v2.epub", &[("OEBPS/toc.ncx", ncx.as_bytes())]);
+    epx()
+        .args(["content", "links", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "OEBPS/toc.ncx: <navPoint> xhtml/section0001.xhtml#nowhere: missing fragment",
+        ));
+}
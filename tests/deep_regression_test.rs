@@ -4,7 +4,7 @@ use assert_cmd::Command;
 use std::path::PathBuf;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 /// Return the `_resources/` directory path.
@@ -43,7 +43,7 @@ fn discover_epubs() -> Vec<(String, PathBuf)> {
     let mut epubs: Vec<(String, PathBuf)> = std::fs::read_dir(&dir)
         .expect("read _resources/")
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "epub"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "epub"))
         .map(|e| {
             let path = e.path();
             let stem = path.file_stem().unwrap().to_string_lossy().to_string();
@@ -67,7 +67,7 @@ fn assert_extraction_structure(dir: &std::path::Path) {
     let md_count = std::fs::read_dir(&chapters)
         .expect("read chapters/")
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "md"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "md"))
         .count();
     assert!(
         md_count >= 1,
@@ -96,7 +96,7 @@ fn assert_extraction_quality(dir: &std::path::Path, stem: &str) {
     for entry in std::fs::read_dir(&chapters)
         .expect("read chapters/")
         .filter_map(|e| e.ok())
-        .filter(|e| e.path().extension().map_or(false, |ext| ext == "md"))
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "md"))
     {
         let content = std::fs::read_to_string(entry.path()).unwrap_or_default();
         let filename = entry.file_name().to_string_lossy().to_string();
@@ -0,0 +1,130 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+/// The regression corpus for XHTML preprocessing: every fixture book
+const FIXTURES: &[&str] = &[
+    "accessible_epub_3.epub",
+    "alice-in-wonderland.epub",
+    "basic-v3plus2.epub",
+    "childrens-literature.epub",
+    "minimal-v2.epub",
+    "minimal-v3.epub",
+    "minimal-v3plus2.epub",
+];
+
+/// Extract a fixture, returning the temp dir, the output dir and stderr
+fn extract(fixture: &str) -> (tempfile::TempDir, PathBuf, String) {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let out = tmp.path().join("out");
+    let output = assert_cmd::cargo::cargo_bin_cmd!("epx")
+        .args([
+            "book",
+            "extract",
+            common::fixture_path(fixture).to_str().unwrap(),
+            "-o",
+            out.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "extracting {fixture}");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (tmp, out, stderr)
+}
+
+/// Chapter file names and contents, in file name order
+fn chapters(dir: &Path) -> Vec<(String, String)> {
+    let mut chapters: Vec<(String, String)> = std::fs::read_dir(dir.join("chapters"))
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (name, std::fs::read_to_string(entry.path()).unwrap())
+        })
+        .collect();
+    chapters.sort();
+    chapters
+}
+
+#[test]
+fn test_corpus_has_no_preprocessing_leftovers() {
+    for fixture in FIXTURES {
+        let (_tmp, out, stderr) = extract(fixture);
+        for (name, markdown) in chapters(&out) {
+            // Code samples may show markup, but not the renamed attributes
+            for leftover in ["EPXANCHOR", "EPX_ID", "<<PENDING", "data-epub-", "![]("] {
+                assert!(
+                    !markdown.contains(leftover),
+                    "{fixture} {name} contains {leftover}"
+                );
+            }
+        }
+        // Links between chapters resolve; external URLs are reported as
+        // missing files
+        for warning in stderr.lines() {
+            assert!(warning.contains("'http"), "{fixture}: {warning}");
+        }
+    }
+}
+
+/// Headings, with their anchors, and the links, images and inline anchors
+/// of each chapter
+fn outline(dir: &Path) -> String {
+    let mut outline = String::new();
+    for (name, markdown) in chapters(dir) {
+        let body = markdown.splitn(3, "---\n").nth(2).unwrap_or(&markdown);
+        outline.push_str(&format!(
+            "{name}: {} link(s), {} image(s), {} anchor(s)\n",
+            body.matches("](").count() - body.matches("![").count(),
+            body.matches("![").count(),
+            body.matches("[]{#").count(),
+        ));
+        let mut in_code = false;
+        for line in body.lines() {
+            if line.starts_with("```") {
+                in_code = !in_code;
+            } else if !in_code && line.starts_with('#') {
+                outline.push_str(&format!("  {line}\n"));
+            }
+        }
+    }
+    outline
+}
+
+#[test]
+fn test_corpus_outlines() {
+    for fixture in FIXTURES {
+        let (_tmp, out, _) = extract(fixture);
+        let name = fixture.trim_end_matches(".epub");
+        insta::assert_snapshot!(name, outline(&out));
+    }
+}
+
+/// Every chapter, each under a `==> name <==` line
+fn concatenated(dir: &Path) -> String {
+    chapters(dir)
+        .into_iter()
+        .map(|(name, markdown)| format!("==> {name} <==\n{markdown}"))
+        .collect()
+}
+
+/// What extraction changed from the baseline in `tests/fixtures/extract-baseline`,
+/// the output of the string-based preprocessor the parsed tree replaced
+#[test]
+fn test_corpus_against_baseline() {
+    for fixture in FIXTURES {
+        let (_tmp, out, _) = extract(fixture);
+        let name = fixture.trim_end_matches(".epub");
+        let baseline = std::fs::read_to_string(
+            common::fixture_path("extract-baseline").join(format!("{name}.md")),
+        )
+        .unwrap();
+        let current = concatenated(&out);
+        let diff = similar::TextDiff::from_lines(&baseline, &current)
+            .unified_diff()
+            .context_radius(1)
+            .header("baseline", "current")
+            .to_string();
+        insta::assert_snapshot!(format!("{name}-baseline-diff"), diff);
+    }
+}
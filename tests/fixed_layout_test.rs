@@ -0,0 +1,236 @@
+mod common;
+
+use predicates::prelude::*;
+
+fn epx() -> assert_cmd::Command {
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
+}
+
+const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Picture Book</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+    <meta property="rendition:layout">pre-paginated</meta>
+    <meta property="rendition:spread">landscape</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="p1" href="xhtml/page1.xhtml" media-type="application/xhtml+xml" />
+    <item id="p2" href="xhtml/page2.xhtml" media-type="application/xhtml+xml" />
+    <item id="notes" href="xhtml/notes.xhtml" media-type="application/xhtml+xml" />
+    <item id="img1" href="images/page1.png" media-type="image/png" />
+  </manifest>
+  <spine page-progression-direction="ltr">
+    <itemref idref="p1" properties="page-spread-right" />
+    <itemref idref="p2" properties="page-spread-left" />
+    <itemref idref="notes" properties="rendition:layout-reflowable" />
+  </spine>
+</package>"#;
+
+const PAGE1: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head>
+    <title>Page 1</title>
+    <meta name="viewport" content="width=1200, height=1600"/>
+  </head>
+  <body>
+    <img src="../images/page1.png" alt="" style="position: absolute; top: 0; left: 0"/>
+    <a href="notes.xhtml">Notes</a>
+  </body>
+</html>"#;
+
+const PAGE2: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head>
+    <title>Page 2</title>
+  </head>
+  <body>
+    <div style="position: absolute; top: 100px">Text</div>
+  </body>
+</html>"#;
+
+const NOTES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head><title>Notes</title></head>
+  <body><h1>Notes</h1><p>About the pictures.</p></body>
+</html>"#;
+
+fn fixed_layout_copy() -> (tempfile::TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", OPF.as_bytes()),
+            ("EPUB/xhtml/page1.xhtml", PAGE1.as_bytes()),
+            ("EPUB/xhtml/page2.xhtml", PAGE2.as_bytes()),
+            ("EPUB/xhtml/notes.xhtml", NOTES.as_bytes()),
+            ("EPUB/images/page1.png", b"\x89PNG"),
+        ],
+    )
+}
+
+#[test]
+fn test_book_info_shows_layout() {
+    let (_tmp, copy) = fixed_layout_copy();
+    epx()
+        .args(["book", "info", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Layout:   pre-paginated (2 fixed-layout pages, 1200x1600)",
+        ));
+}
+
+#[test]
+fn test_spine_list_reports_properties_and_viewports() {
+    let (_tmp, copy) = fixed_layout_copy();
+    let output = epx()
+        .args(["spine", "list", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json[0]["properties"], "page-spread-right");
+    assert_eq!(json[0]["viewport"]["width"], 1200);
+    assert_eq!(json[1]["layout"], "pre-paginated");
+    assert!(json[1].get("viewport").is_none());
+    assert!(json[2].get("layout").is_none());
+}
+
+#[test]
+fn test_validate_reports_missing_viewport() {
+    let (_tmp, copy) = fixed_layout_copy();
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "error[FXL-001] EPUB/xhtml/page2.xhtml: fixed-layout page has no viewport meta",
+        ));
+}
+
+#[test]
+fn test_edit_preserves_spine_rendition_properties() {
+    let (_tmp, copy) = fixed_layout_copy();
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "title",
+            "--value",
+            "Renamed",
+        ])
+        .assert()
+        .success();
+
+    let opf = String::from_utf8(common::read_entry(&copy, "EPUB/package.opf").unwrap()).unwrap();
+    assert!(opf.contains("idref=\"p1\" properties=\"page-spread-right\""));
+    assert!(opf.contains("idref=\"notes\" properties=\"rendition:layout-reflowable\""));
+    assert!(opf.contains("<meta property=\"rendition:layout\">pre-paginated</meta>"));
+}
+
+#[test]
+fn test_extract_passes_fixed_layout_pages_through() {
+    let (tmp, copy) = fixed_layout_copy();
+    let out = tmp.path().join("out");
+    epx()
+        .args(["book", "extract", copy.to_str().unwrap(), "-o"])
+        .arg(&out)
+        .assert()
+        .success();
+
+    let pages: Vec<String> = std::fs::read_dir(out.join("pages"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(pages.len(), 2);
+    let page1 = pages.iter().find(|p| p.ends_with("page1.xhtml")).unwrap();
+    let page1 = std::fs::read_to_string(out.join("pages").join(page1)).unwrap();
+    assert!(page1.contains("width=1200, height=1600"));
+    assert!(page1.contains("src=\"../assets/images/page1.png\""));
+    assert!(page1.contains("href=\"../chapters/"));
+
+    // The reflowable notes are still converted
+    let chapters = std::fs::read_dir(out.join("chapters")).unwrap().count();
+    assert_eq!(chapters, 1);
+}
+
+#[test]
+fn test_extract_skips_fixed_layout_pages() {
+    let (tmp, copy) = fixed_layout_copy();
+    let out = tmp.path().join("out");
+    epx()
+        .args(["book", "extract", copy.to_str().unwrap(), "-o"])
+        .arg(&out)
+        .args(["--fixed-layout", "skip"])
+        .assert()
+        .success();
+    assert!(!out.join("pages").exists());
+    assert!(out.join("assets/images/page1.png").exists());
+}
+
+#[test]
+fn test_assemble_fixed_layout_from_images() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let dir = tmp.path().join("comic");
+    std::fs::create_dir_all(dir.join("pages")).unwrap();
+    std::fs::write(
+        dir.join("metadata.yml"),
+        "title: Comic\nlanguages:\n  - en\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("pages/001.png"), b"\x89PNG").unwrap();
+    std::fs::write(dir.join("pages/002.png"), b"\x89PNG").unwrap();
+    std::fs::write(dir.join("pages/003.xhtml"), PAGE2).unwrap();
+
+    let epub = tmp.path().join("comic.epub");
+    epx()
+        .args(["book", "assemble"])
+        .arg(&dir)
+        .arg("-o")
+        .arg(&epub)
+        .args(["--fixed-layout", "--viewport", "800x1200"])
+        .assert()
+        .success();
+    common::assert_valid_epub(&epub);
+
+    let book = epx::epub::reader::read_epub_with(&epub, &Default::default()).unwrap();
+    assert_eq!(
+        book.layout(),
+        epx::epub::fixed_layout::RenditionLayout::PrePaginated
+    );
+    let pages = book.fixed_layout_pages();
+    assert_eq!(pages.len(), 3);
+    assert!(
+        pages
+            .iter()
+            .all(|page| page.viewport.is_some_and(|v| v.width == 800))
+    );
+    assert_eq!(book.metadata.cover_id.as_deref(), Some("image-001-png"));
+}
+
+#[test]
+fn test_assemble_fixed_layout_needs_viewport_for_images() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let dir = tmp.path().join("comic");
+    std::fs::create_dir_all(dir.join("pages")).unwrap();
+    std::fs::write(dir.join("metadata.yml"), "title: Comic\n").unwrap();
+    std::fs::write(dir.join("pages/001.png"), b"\x89PNG").unwrap();
+
+    epx()
+        .args(["book", "assemble"])
+        .arg(&dir)
+        .arg("-o")
+        .arg(tmp.path().join("comic.epub"))
+        .arg("--fixed-layout")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--viewport"));
+}
@@ -0,0 +1,233 @@
+mod common;
+
+use predicates::prelude::*;
+
+fn epx() -> assert_cmd::Command {
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
+}
+
+const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Your title here</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="section0001.xhtml" href="xhtml/section0001.xhtml" media-type="application/xhtml+xml" media-overlay="s1-smil" />
+    <item id="s1-smil" href="smil/section0001.smil" media-type="application/smil+xml" />
+    <item id="s1-audio" href="audio/section0001.mp3" media-type="audio/mpeg" />
+  </manifest>
+  <spine>
+    <itemref idref="section0001.xhtml" />
+  </spine>
+</package>"#;
+
+const XHTML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head>
+    <title>Section 1</title>
+  </head>
+  <body>
+    <h1 id="h1">Section 1</h1>
+    <p id="p1">This is a paragraph.</p>
+  </body>
+</html>"#;
+
+const SMIL: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<smil xmlns="http://www.w3.org/ns/SMIL" xmlns:epub="http://www.idpf.org/2007/ops" version="3.0">
+  <body>
+    <seq epub:textref="../xhtml/section0001.xhtml">
+      <par id="par1">
+        <text src="../xhtml/section0001.xhtml#h1"/>
+        <audio src="../audio/section0001.mp3" clipBegin="0s" clipEnd="1.5s"/>
+      </par>
+      <par id="par2">
+        <text src="../xhtml/section0001.xhtml#p1"/>
+        <audio src="../audio/section0001.mp3" clipBegin="0:00:01.500" clipEnd="0:00:04.000"/>
+      </par>
+    </seq>
+  </body>
+</smil>"#;
+
+fn overlay_copy() -> (tempfile::TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", OPF.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", XHTML.as_bytes()),
+            ("EPUB/smil/section0001.smil", SMIL.as_bytes()),
+            ("EPUB/audio/section0001.mp3", b"ID3"),
+        ],
+    )
+}
+
+fn entry_string(path: &std::path::Path, name: &str) -> Option<String> {
+    common::read_entry(path, name).map(|data| String::from_utf8(data).unwrap())
+}
+
+#[test]
+fn test_media_overlay_list() {
+    let (_tmp, copy) = overlay_copy();
+    let output = epx()
+        .args(["media-overlay", "list", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json[0]["id"], "s1-smil");
+    assert_eq!(json[0]["documents"][0], "section0001.xhtml");
+    assert_eq!(json[0]["pars"], 2);
+    assert_eq!(json[0]["duration"], 4.0);
+}
+
+#[test]
+fn test_media_overlay_extract() {
+    let (_tmp, copy) = overlay_copy();
+    epx()
+        .args(["media-overlay", "extract", copy.to_str().unwrap(), "section0001.xhtml"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "EPUB/xhtml/section0001.xhtml#p1\tEPUB/audio/section0001.mp3\t0:00:01.500\t0:00:04.000\n",
+        ));
+}
+
+#[test]
+fn test_media_overlay_validate_and_update_durations() {
+    let (_tmp, copy) = overlay_copy();
+    epx()
+        .args(["media-overlay", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 issue(s)"))
+        .stdout(predicate::str::contains("no media:duration"));
+
+    epx()
+        .args(["media-overlay", "update-durations", copy.to_str().unwrap()])
+        .assert()
+        .success();
+    let opf = entry_string(&copy, "EPUB/package.opf").unwrap();
+    assert!(
+        opf.contains(r##"<meta property="media:duration" refines="#s1-smil">0:00:04.000</meta>"##)
+    );
+    assert!(opf.contains(r#"<meta property="media:duration">0:00:04.000</meta>"#));
+
+    epx()
+        .args(["media-overlay", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("media overlays valid"));
+}
+
+#[test]
+fn test_media_overlay_validate_broken_references() {
+    let smil = SMIL.replace("#p1", "#p9").replace(
+        "clipBegin=\"0s\" clipEnd=\"1.5s\"",
+        "clipBegin=\"2s\" clipEnd=\"1.5s\"",
+    );
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", OPF.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", XHTML.as_bytes()),
+            ("EPUB/smil/section0001.smil", smil.as_bytes()),
+        ],
+    );
+    let output = epx()
+        .args([
+            "media-overlay",
+            "validate",
+            copy.to_str().unwrap(),
+            "--json",
+        ])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["valid"], false);
+    let issues = json["issues"].to_string();
+    assert!(issues.contains("missing element 'p9'"));
+    assert!(issues.contains("is not after clipBegin"));
+    assert!(issues.contains("is not in the book"));
+
+    let output = epx()
+        .args(["book", "validate", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let issue = json["issues"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|issue| {
+            issue["message"]
+                .as_str()
+                .is_some_and(|message| message.contains("missing element 'p9'"))
+        })
+        .unwrap();
+    assert_eq!(issue["path"], "EPUB/smil/section0001.smil");
+    assert_eq!(issue["line"], 9);
+    assert_eq!(issue["column"], 7);
+}
+
+#[test]
+fn test_chapter_rename_updates_overlay() {
+    let (_tmp, copy) = overlay_copy();
+    epx()
+        .args([
+            "chapter",
+            "rename",
+            copy.to_str().unwrap(),
+            "section0001.xhtml",
+            "text/intro.xhtml",
+        ])
+        .assert()
+        .success();
+
+    assert!(common::read_entry(&copy, "EPUB/xhtml/section0001.xhtml").is_none());
+    assert!(common::read_entry(&copy, "EPUB/text/intro.xhtml").is_some());
+    let smil = entry_string(&copy, "EPUB/smil/section0001.smil").unwrap();
+    assert!(smil.contains(r#"epub:textref="../text/intro.xhtml""#));
+    assert!(smil.contains(r#"src="../text/intro.xhtml#p1""#));
+    let nav = entry_string(&copy, "EPUB/nav.xhtml").unwrap();
+    assert!(nav.contains(r#"href="text/intro.xhtml""#));
+
+    let output = epx()
+        .args([
+            "media-overlay",
+            "validate",
+            copy.to_str().unwrap(),
+            "--json",
+        ])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(!json["issues"].to_string().contains("text"));
+}
+
+#[test]
+fn test_chapter_remove_drops_overlay() {
+    let (_tmp, copy) = overlay_copy();
+    epx()
+        .args(["media-overlay", "update-durations", copy.to_str().unwrap()])
+        .assert()
+        .success();
+    epx()
+        .args([
+            "chapter",
+            "remove",
+            copy.to_str().unwrap(),
+            "section0001.xhtml",
+        ])
+        .assert()
+        .success();
+
+    assert!(common::read_entry(&copy, "EPUB/smil/section0001.smil").is_none());
+    assert!(common::read_entry(&copy, "EPUB/audio/section0001.mp3").is_none());
+    let opf = entry_string(&copy, "EPUB/package.opf").unwrap();
+    assert!(!opf.contains("smil"));
+    assert!(!opf.contains("media:duration"));
+}
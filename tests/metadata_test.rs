@@ -7,7 +7,7 @@ use predicates::prelude::*;
 use tempfile::TempDir;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -71,6 +71,40 @@ fn test_metadata_show_json() {
         .stdout(predicate::str::contains("{"));
 }
 
+#[test]
+fn test_metadata_show_creator_roles() {
+    let (_tmp, copy) = common::temp_copy("basic-v3plus2.epub");
+
+    // An unrelated edit keeps the creator's role refinement
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "title",
+            "--value",
+            "Edited",
+        ])
+        .assert()
+        .success();
+
+    let output = epx()
+        .args(["metadata", "show", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["creators"][0]["name"], "Hingle McCringleberry");
+    assert_eq!(json["creators"][0]["roles"][0], "aut");
+    assert!(json["custom"].get("role").is_none());
+
+    epx()
+        .args(["metadata", "show", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Hingle McCringleberry (aut)"));
+}
+
 #[test]
 fn test_metadata_custom_roundtrip() {
     let (_tmp, copy) = common::temp_copy("minimal-v3.epub");
@@ -242,3 +276,70 @@ fn test_metadata_import() {
                 .and(predicate::str::contains("Import Author")),
         );
 }
+
+#[test]
+fn test_metadata_set_preserves_unmodelled_opf() {
+    let (_tmp, copy) = common::temp_copy("alice-in-wonderland.epub");
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            copy.to_str().unwrap(),
+            "--field",
+            "publisher",
+            "--value",
+            "Edited Publisher",
+        ])
+        .assert()
+        .success();
+
+    let opf = common::read_entry(&copy, "OEBPS/content.opf").unwrap();
+    let opf = String::from_utf8(opf).unwrap();
+    assert!(opf.contains("Edited Publisher"));
+    assert!(
+        opf.contains("<dc:source>https://www.gutenberg.org/files/11/11-h/11-h.htm</dc:source>")
+    );
+}
+
+#[test]
+fn test_metadata_show_from_stdin() {
+    let data = std::fs::read(common::fixture_path("minimal-v3.epub")).unwrap();
+    epx()
+        .args(["metadata", "show", "-"])
+        .write_stdin(data)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Your title here"));
+}
+
+#[test]
+fn test_metadata_set_stdin_to_stdout() {
+    let data = std::fs::read(common::fixture_path("minimal-v3.epub")).unwrap();
+    let output = epx()
+        .args([
+            "metadata", "set", "-", "--field", "title", "--value", "Piped",
+        ])
+        .write_stdin(data)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Set title = Piped"));
+
+    let tmp = TempDir::new().unwrap();
+    let path = tmp.path().join("piped.epub");
+    std::fs::write(&path, &output.stdout).unwrap();
+    common::assert_valid_epub(&path);
+    let book = epx::epub::reader::read_epub_with(&path, &Default::default()).unwrap();
+    assert_eq!(book.metadata.title(), Some("Piped"));
+}
+
+#[test]
+fn test_metadata_export_to_stdout() {
+    let fixture = common::fixture_path("minimal-v3.epub");
+    epx()
+        .args(["metadata", "export", fixture.to_str().unwrap(), "-o", "-"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("title: Your title here"));
+}
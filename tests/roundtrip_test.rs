@@ -1,11 +1,11 @@
 mod common;
 
 use assert_cmd::Command;
-use epx::epub::reader::read_epub;
+use epx::epub::reader::read_epub_with;
 use tempfile::TempDir;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -71,7 +71,8 @@ fn test_roundtrip_preserves_custom_metadata() {
     common::assert_valid_epub(&assembled);
 
     // Read the assembled EPUB and verify the custom metadata field survived the round-trip
-    let book = epx::epub::reader::read_epub(&assembled).expect("read assembled epub");
+    let book = epx::epub::reader::read_epub_with(&assembled, &Default::default())
+        .expect("read assembled epub");
     assert_eq!(
         book.metadata.custom.get("rendition:layout"),
         Some(&"pre-paginated".to_string()),
@@ -183,7 +184,7 @@ fn test_roundtrip_childrens_literature() {
     let assembled = tmp.path().join("reassembled.epub");
 
     // Read original metadata before round-trip
-    let original = read_epub(&fixture).expect("read original epub");
+    let original = read_epub_with(&fixture, &Default::default()).expect("read original epub");
 
     // Extract to markdown
     epx()
@@ -213,14 +214,17 @@ fn test_roundtrip_childrens_literature() {
     common::assert_valid_epub(&assembled);
 
     // Verify key metadata survived the round-trip
-    let reassembled = read_epub(&assembled).expect("read reassembled epub");
+    let reassembled =
+        read_epub_with(&assembled, &Default::default()).expect("read reassembled epub");
     // The primary title should survive (subtitle may be stored in custom metadata)
     assert!(
         !reassembled.metadata.titles.is_empty(),
         "at least one title should survive round-trip"
     );
     assert!(
-        reassembled.metadata.titles[0].contains("Children's Literature"),
+        reassembled.metadata.titles[0]
+            .value
+            .contains("Children's Literature"),
         "primary title should contain 'Children's Literature', got: {:?}",
         reassembled.metadata.titles
     );
@@ -255,7 +259,7 @@ fn test_roundtrip_accessible_epub3() {
     let assembled = tmp.path().join("reassembled.epub");
 
     // Read original metadata before round-trip
-    let original = read_epub(&fixture).expect("read original epub");
+    let original = read_epub_with(&fixture, &Default::default()).expect("read original epub");
 
     // Extract to markdown
     epx()
@@ -285,7 +289,8 @@ fn test_roundtrip_accessible_epub3() {
     common::assert_valid_epub(&assembled);
 
     // Verify key metadata survived the round-trip
-    let reassembled = read_epub(&assembled).expect("read reassembled epub");
+    let reassembled =
+        read_epub_with(&assembled, &Default::default()).expect("read reassembled epub");
     assert_eq!(
         reassembled.metadata.titles, original.metadata.titles,
         "titles should survive round-trip"
@@ -322,7 +327,7 @@ fn test_roundtrip_alice_in_wonderland() {
     let assembled = tmp.path().join("reassembled.epub");
 
     // Read original metadata before round-trip
-    let original = read_epub(&fixture).expect("read original epub");
+    let original = read_epub_with(&fixture, &Default::default()).expect("read original epub");
 
     // Extract to markdown
     epx()
@@ -352,7 +357,8 @@ fn test_roundtrip_alice_in_wonderland() {
     common::assert_valid_epub(&assembled);
 
     // Verify key metadata survived the round-trip
-    let reassembled = read_epub(&assembled).expect("read reassembled epub");
+    let reassembled =
+        read_epub_with(&assembled, &Default::default()).expect("read reassembled epub");
     assert_eq!(
         reassembled.metadata.titles, original.metadata.titles,
         "titles should survive round-trip"
@@ -391,7 +397,7 @@ fn test_roundtrip_minimal_v3plus2() {
     let assembled = tmp.path().join("reassembled.epub");
 
     // Read original metadata before round-trip
-    let original = read_epub(&fixture).expect("read original epub");
+    let original = read_epub_with(&fixture, &Default::default()).expect("read original epub");
 
     // Extract to markdown
     epx()
@@ -421,7 +427,8 @@ fn test_roundtrip_minimal_v3plus2() {
     common::assert_valid_epub(&assembled);
 
     // Verify key metadata survived the round-trip
-    let reassembled = read_epub(&assembled).expect("read reassembled epub");
+    let reassembled =
+        read_epub_with(&assembled, &Default::default()).expect("read reassembled epub");
     assert_eq!(
         reassembled.metadata.titles, original.metadata.titles,
         "titles should survive round-trip"
@@ -440,3 +447,114 @@ fn test_roundtrip_minimal_v3plus2() {
         "spine item count should survive round-trip"
     );
 }
+
+#[test]
+fn test_metadata_edit_copies_untouched_entries_raw() {
+    let (_tmp, epub_copy) = common::temp_copy("alice-in-wonderland.epub");
+    let cover = "OEBPS/9040018069957981374_cover.jpg";
+
+    let raw_entry = |path: &std::path::Path| {
+        let file = std::fs::File::open(path).unwrap();
+        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let entry = archive.by_name(cover).unwrap();
+        (
+            entry.crc32(),
+            entry.compression(),
+            entry.compressed_size(),
+            entry.last_modified(),
+        )
+    };
+    let before = raw_entry(&epub_copy);
+
+    epx()
+        .args([
+            "metadata",
+            "set",
+            epub_copy.to_str().unwrap(),
+            "--field",
+            "title",
+            "--value",
+            "Renamed",
+        ])
+        .assert()
+        .success();
+
+    assert_eq!(raw_entry(&epub_copy), before);
+    common::assert_valid_epub(&epub_copy);
+}
+
+#[test]
+fn test_extract_resolves_escaped_and_relative_hrefs() {
+    let opf = br#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Your title here</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01T12:00:00Z</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav/nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="s1" href="xhtml/Section%20One.xhtml" media-type="application/xhtml+xml" />
+    <item id="s2" href="xhtml/two.xhtml" media-type="application/xhtml+xml" />
+    <item id="img" href="images/a%20b.png" media-type="image/png" />
+  </manifest>
+  <spine>
+    <itemref idref="s1" />
+    <itemref idref="s2" />
+  </spine>
+</package>"#;
+    let nav = br#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>Nav</title></head>
+<body><nav epub:type="toc"><ol>
+  <li><a href="../xhtml/Section%20One.xhtml">Opening</a></li>
+  <li><a href="../xhtml/./two.xhtml#start">Closing</a></li>
+</ol></nav></body>
+</html>"#;
+    let one = br#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>One</title></head>
+<body><h1>One</h1><p><img src="../images/a%20b.png" alt="pic"/></p>
+<p><a href="two.xhtml#start">Next</a></p></body>
+</html>"#;
+    let two = br#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>Two</title></head>
+<body><h1 id="start">Two</h1><p>End.</p></body>
+</html>"#;
+    let (tmp, path) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", opf),
+            ("EPUB/nav/nav.xhtml", nav),
+            ("EPUB/xhtml/Section One.xhtml", one),
+            ("EPUB/xhtml/two.xhtml", two),
+            ("EPUB/images/a b.png", b"\x89PNG"),
+        ],
+    );
+    let extract_dir = tmp.path().join("extracted");
+
+    epx()
+        .args([
+            "book",
+            "extract",
+            path.to_str().unwrap(),
+            "-o",
+            extract_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    let summary = std::fs::read_to_string(extract_dir.join("SUMMARY.md")).unwrap();
+    assert!(summary.contains("- [Opening](chapters/00-opening.md)"));
+    assert!(summary.contains("- [Closing](chapters/01-closing.md)"));
+
+    let chapter = std::fs::read_to_string(extract_dir.join("chapters/00-opening.md")).unwrap();
+    assert!(
+        chapter.contains("(../assets/images/a%20b.png)"),
+        "{chapter}"
+    );
+    assert!(chapter.contains("[Next](01-closing.md#start)"), "{chapter}");
+    assert!(extract_dir.join("assets/images/a b.png").exists());
+}
@@ -5,7 +5,7 @@ use predicates::prelude::*;
 use tempfile::TempDir;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
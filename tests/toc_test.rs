@@ -4,7 +4,7 @@ use assert_cmd::Command;
 use predicates::prelude::*;
 
 fn epx() -> Command {
-    Command::cargo_bin("epx").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
 }
 
 #[test]
@@ -116,3 +116,27 @@ fn test_toc_set() {
     // Verify the EPUB is still valid after modification
     common::assert_valid_epub(&copy);
 }
+
+#[test]
+fn test_toc_generate_keeps_landmarks_and_page_list() {
+    let (_tmp, copy) = common::temp_copy("childrens-literature.epub");
+    let original = epx::epub::reader::read_epub_with(&copy, &Default::default()).unwrap();
+    assert_eq!(original.navigation.landmarks.len(), 2);
+    assert!(!original.navigation.page_list.is_empty());
+
+    epx()
+        .args(["toc", "generate", copy.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let book = epx::epub::reader::read_epub_with(&copy, &Default::default()).unwrap();
+    assert_eq!(book.navigation.landmarks, original.navigation.landmarks);
+    assert_eq!(book.navigation.page_list, original.navigation.page_list);
+    assert_eq!(
+        book.navigation.landmarks[1].epub_type.as_deref(),
+        Some("bodymatter")
+    );
+
+    let ncx = String::from_utf8(common::read_entry(&copy, "EPUB/toc.ncx").unwrap()).unwrap();
+    assert!(ncx.contains("<pageList>"));
+}
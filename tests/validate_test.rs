@@ -0,0 +1,418 @@
+mod common;
+
+use predicates::prelude::*;
+
+fn epx() -> assert_cmd::Command {
+    assert_cmd::cargo::cargo_bin_cmd!("epx")
+}
+
+const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package version="3.0" unique-identifier="pub-id" xmlns="http://www.idpf.org/2007/opf">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="pub-id">urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d</dc:identifier>
+    <dc:title>Your title here</dc:title>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">2011-01-01</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav" />
+    <item id="section0001.xhtml" href="xhtml/section0001.xhtml" media-type="application/xhtml+xml" />
+    <item id="section0002.xhtml" href="xhtml/section0002.xhtml" media-type="application/xhtml+xml" />
+    <item id="photo" href="images/photo.jpg" media-type="image/jpeg" />
+    <item id="scan" href="images/scan.tiff" media-type="image/tiff" />
+  </manifest>
+  <spine>
+    <itemref idref="section0001.xhtml" />
+    <itemref idref="section0002.xhtml" />
+  </spine>
+</package>"#;
+
+const SECTION1: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head><title>One</title></head>
+  <body>
+    <h1 id="top">One</h1>
+    <p id="top">Again</p>
+    <p><a href="section0002.xhtml#missing">Two</a> <a href="gone.xhtml">Gone</a></p>
+    <img src="../images/photo.jpg" alt="Photo"/>
+    <img src="../images/scan.tiff" alt="Scan"/>
+    <img src="../images/unlisted.png" alt="Unlisted"/>
+    <p style="background: url(../images/missing-bg.png)">Styled</p>
+  </body>
+</html>"#;
+
+const SECTION2: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head><title>Two</title></head>
+  <body>
+    <p>Broken &nbsp; entity
+    <p>Unclosed</p>
+  </body>
+</html>"#;
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\0";
+
+fn broken_copy() -> (tempfile::TempDir, std::path::PathBuf) {
+    common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", OPF.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", SECTION1.as_bytes()),
+            ("EPUB/xhtml/section0002.xhtml", SECTION2.as_bytes()),
+            ("EPUB/images/photo.jpg", PNG),
+            ("EPUB/images/scan.tiff", b"II*\0"),
+            ("EPUB/images/unlisted.png", PNG),
+        ],
+    )
+}
+
+#[test]
+fn test_validate_reports_rule_ids() {
+    let (_tmp, copy) = broken_copy();
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("invalid"))
+        .stdout(predicate::str::contains(
+            "error[OPF-005] EPUB/package.opf: dcterms:modified '2011-01-01'",
+        ))
+        .stdout(predicate::str::contains(
+            "warning[RSC-002] EPUB/images/unlisted.png: file is not listed in the manifest",
+        ))
+        .stdout(predicate::str::contains(
+            "error[RSC-003] EPUB/images/photo.jpg: declared as image/jpeg but the content is PNG",
+        ))
+        .stdout(predicate::str::contains(
+            "error[HTM-002] EPUB/xhtml/section0001.xhtml:7:5: duplicate id 'top'",
+        ))
+        .stdout(predicate::str::contains(
+            "error[RSC-005] EPUB/xhtml/section0001.xhtml:8:17: <a> links to #missing",
+        ))
+        .stdout(predicate::str::contains(
+            "error[RSC-004] EPUB/xhtml/section0001.xhtml:8:61: <a> references missing resource gone.xhtml",
+        ))
+        .stdout(predicate::str::contains(
+            "<img> references EPUB/images/unlisted.png, which is not listed in the manifest",
+        ))
+        .stdout(predicate::str::contains(
+            "error[RSC-006] EPUB/xhtml/section0001.xhtml:10:15: <img> embeds EPUB/images/scan.tiff",
+        ))
+        .stdout(predicate::str::contains(
+            "error[RSC-004] EPUB/xhtml/section0001.xhtml:12:31: url() references missing resource ../images/missing-bg.png",
+        ))
+        .stdout(predicate::str::contains(
+            "error[HTM-001] EPUB/xhtml/section0002.xhtml:6:",
+        ));
+}
+
+#[test]
+fn test_validate_json_counts() {
+    let (_tmp, copy) = broken_copy();
+    let output = epx()
+        .args(["book", "validate", copy.to_str().unwrap(), "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["valid"], false);
+    assert_eq!(json["warnings"], 1);
+    assert!(json["errors"].as_u64().unwrap() >= 8);
+}
+
+#[test]
+fn test_validate_warnings_keep_book_valid() {
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v3.epub", &[("EPUB/notes.txt", b"notes")]);
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("valid, 0 error(s), 1 warning(s)"));
+}
+
+#[test]
+fn test_validate_nav_structure() {
+    let nav = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <head><title>Nav</title></head>
+  <body><nav epub:type="landmarks"><ol><li><a href="xhtml/section0001.xhtml">Start</a></li></ol></nav></body>
+</html>"#;
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v3.epub", &[("EPUB/nav.xhtml", nav.as_bytes())]);
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "error[NAV-003] EPUB/nav.xhtml: no nav element of epub:type toc",
+        ));
+}
+
+#[test]
+fn test_validate_epub2_ncx_links() {
+    let ncx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head><meta name="dtb:uid" content="urn:uuid:fe93046f-af57-475a-a0cb-a0d4bc99ba6d"/></head>
+  <docTitle><text>Title</text></docTitle>
+  <navMap>
+    <navPoint id="n1" playOrder="1">
+      <navLabel><text>Section 1</text></navLabel>
+      <content src="xhtml/section0001.xhtml#nowhere"/>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v2.epub", &[("OEBPS/toc.ncx", ncx.as_bytes())]);
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "error[RSC-005] OEBPS/toc.ncx: navPoint 'Section 1' links to #nowhere",
+        ));
+}
+
+#[test]
+fn test_validate_json_issue_fields() {
+    let (_tmp, copy) = broken_copy();
+    let output = epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--format",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let issue = json["issues"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|issue| issue["rule"] == "HTM-002")
+        .unwrap();
+    assert_eq!(issue["severity"], "error");
+    assert_eq!(issue["path"], "EPUB/xhtml/section0001.xhtml");
+    assert_eq!(issue["line"], 7);
+    assert_eq!(issue["column"], 5);
+    assert_eq!(issue["message"], "duplicate id 'top'");
+    assert!(issue["suggestion"].as_str().is_some());
+}
+
+#[test]
+fn test_validate_sarif() {
+    let (_tmp, copy) = broken_copy();
+    let output = epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--format",
+            "sarif",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let sarif: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(sarif["version"], "2.1.0");
+    let run = &sarif["runs"][0];
+    assert_eq!(run["tool"]["driver"]["name"], "epx");
+    assert_eq!(
+        run["artifacts"][0]["location"]["uri"],
+        copy.display().to_string()
+    );
+    let result = run["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|result| result["ruleId"] == "RSC-004")
+        .unwrap();
+    let location = &result["locations"][0]["physicalLocation"];
+    assert_eq!(
+        location["artifactLocation"]["uri"],
+        "EPUB/xhtml/section0001.xhtml"
+    );
+    assert_eq!(location["region"]["startLine"], 8);
+    assert_eq!(location["region"]["startColumn"], 61);
+}
+
+#[test]
+fn test_validate_junit() {
+    let (_tmp, copy) = broken_copy();
+    epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--format",
+            "junit",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("<?xml"))
+        .stdout(predicate::str::contains("<testcase classname=\""))
+        .stdout(predicate::str::contains(
+            "<failure type=\"warning\" message=\"1 issue(s)\">warning[RSC-002]",
+        ))
+        .stdout(predicate::str::contains(
+            "name=\"OPF-001: dc:title is required\"/>",
+        ));
+}
+
+#[test]
+fn test_validate_fail_on() {
+    let (_tmp, copy) =
+        common::temp_copy_with_entries("minimal-v3.epub", &[("EPUB/notes.txt", b"notes")]);
+    epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--fail-on",
+            "error",
+        ])
+        .assert()
+        .success();
+    epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--fail-on",
+            "warning",
+        ])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("warning[RSC-002]"))
+        .stderr(predicate::str::contains(
+            "1 issue(s) at or above warning severity",
+        ));
+    epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--fail-on",
+            "fatal",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected warning or error"));
+}
+
+#[test]
+fn test_validate_fix() {
+    let opf = OPF
+        .replace("    <dc:language>en</dc:language>\n", "")
+        .replace(
+            "<itemref idref=\"section0002.xhtml\" />",
+            "<itemref idref=\"section0002.xhtml\" />\n    <itemref idref=\"gone\" />",
+        );
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", opf.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", SECTION1.as_bytes()),
+            ("EPUB/images/photo.jpg", b"\xFF\xD8\xFF\xE0"),
+            ("EPUB/images/scan.tiff", b"II*\0"),
+            ("EPUB/images/unlisted.png", PNG),
+            ("EPUB/notes.txt", b"notes"),
+        ],
+    );
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap(), "--fix"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "OPF-012 EPUB/package.opf: removed spine itemref 'gone', which has no manifest item",
+        ))
+        .stdout(predicate::str::contains(
+            "OPF-002 EPUB/package.opf: set dc:language to 'en'",
+        ))
+        .stdout(predicate::str::contains(
+            "OPF-005 EPUB/package.opf: set dcterms:modified to",
+        ))
+        .stdout(predicate::str::contains(
+            "RSC-002 EPUB/images/unlisted.png: added to the manifest as 'unlisted' (image/png)",
+        ))
+        .stdout(predicate::str::contains(
+            "2 risky fix(es) skipped (use --aggressive)",
+        ))
+        .stdout(predicate::str::contains(
+            "HTM-002 EPUB/xhtml/section0001.xhtml: renamed duplicate id 'top' to 'top-2'",
+        ))
+        .stdout(predicate::str::contains(
+            "RSC-002 EPUB/notes.txt: deleted the file",
+        ));
+
+    let entry = |name| String::from_utf8(common::read_entry(&copy, name).unwrap()).unwrap();
+    let opf = entry("EPUB/package.opf");
+    assert!(!opf.contains("idref=\"gone\""));
+    assert!(opf.contains("href=\"images/unlisted.png\""));
+    assert!(entry("EPUB/xhtml/section0001.xhtml").contains("<p id=\"top\">"));
+
+    epx()
+        .args([
+            "book",
+            "validate",
+            copy.to_str().unwrap(),
+            "--fix",
+            "--aggressive",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("skipped").not())
+        .stdout(predicate::str::contains("HTM-002").count(1));
+    assert!(entry("EPUB/xhtml/section0001.xhtml").contains("<p id=\"top-2\">"));
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap(), "--json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OPF-012").not())
+        .stdout(predicate::str::contains("HTM-002").not())
+        .stdout(predicate::str::contains("RSC-002").not());
+}
+
+#[test]
+fn test_validate_fix_leaves_clean_book_untouched() {
+    let (_tmp, copy) = common::temp_copy("minimal-v3.epub");
+    let before = std::fs::read(&copy).unwrap();
+    epx()
+        .args(["book", "validate", copy.to_str().unwrap(), "--fix"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 fix(es) applied"));
+    assert_eq!(std::fs::read(&copy).unwrap(), before);
+}
+
+#[test]
+fn test_validate_fix_on_stdio_reports_fixed_book() {
+    let opf = OPF.replace(
+        "<itemref idref=\"section0002.xhtml\" />",
+        "<itemref idref=\"section0002.xhtml\" />\n    <itemref idref=\"gone\" />",
+    );
+    let (_tmp, copy) = common::temp_copy_with_entries(
+        "minimal-v3.epub",
+        &[
+            ("EPUB/package.opf", opf.as_bytes()),
+            ("EPUB/xhtml/section0001.xhtml", SECTION1.as_bytes()),
+        ],
+    );
+    let output = epx()
+        .args(["book", "validate", "-", "--fix", "--fail-on", "error"])
+        .write_stdin(std::fs::read(&copy).unwrap())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("OPF-012"))
+        .stderr(predicate::str::contains("error[HTM-002]"))
+        .stderr(predicate::str::contains(
+            "issue(s) at or above error severity",
+        ))
+        .get_output()
+        .clone();
+    assert!(output.stdout.starts_with(b"PK"));
+}